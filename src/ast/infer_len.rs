@@ -166,6 +166,24 @@ fn infer_expr(
                 if res.is_some() { return res; }
             }
         }
+        Match(ref match_expr) => {
+            let res = infer_expr(&match_expr.expr, name, decls);
+            if res.is_some() { return res; }
+            for arm in &match_expr.arms {
+                // Check for declaration of same name.
+                let mut bindings = vec![];
+                arm.pattern.bindings(&mut bindings);
+                if bindings.iter().any(|n| &***n == name) { continue; }
+                let st = decls.len();
+                decls.extend(bindings);
+                let res = {
+                    let res = arm.guard.as_ref().and_then(|guard| infer_expr(guard, name, decls));
+                    if res.is_some() { res } else { infer_expr(&arm.expr, name, decls) }
+                };
+                decls.truncate(st);
+                if res.is_some() { return res; }
+            }
+        }
         Compare(ref cmp_expr) => {
             let left = infer_expr(&cmp_expr.left, name, decls);
             if left.is_some() { return left; }
@@ -10,6 +10,7 @@ use Module;
 use Prelude;
 use Type;
 use Variable;
+use runtime::bytecode::Code;
 
 mod infer_len;
 mod replace;
@@ -37,20 +38,51 @@ pub fn convert(
         UseLookup::new()
     };
 
+    let new_functions = module.functions.len();
+    let mut records = HashMap::new();
     loop {
         if let Ok((range, function)) =
         Function::from_meta_data(&namespace, &file, &source, "fn", convert, ignored) {
             convert.update(range);
             module.register(function);
+        } else if let Ok((range, record)) = Type::record_from_meta_data(convert, ignored) {
+            convert.update(range);
+            let name = record.record().map(|(name, _)| name.clone()).unwrap();
+            records.insert(name, record);
+        } else if let Ok((range, ty)) = Type::enum_from_meta_data(convert, ignored) {
+            convert.update(range);
+            let name = ty.variants().map(|(name, _)| name.clone()).unwrap();
+            records.insert(name, ty);
         } else if convert.remaining_data_len() > 0 {
             return Err(());
         } else {
             break;
         }
     }
+    if records.len() > 0 {
+        // Records may refer to records declared in other scripts.
+        for (name, ty) in &module.records {
+            if !records.contains_key(name) {
+                records.insert(name.clone(), ty.clone());
+            }
+        }
+        Type::resolve_record_fields(&mut records);
+        module.records = records;
+    }
+    for f in &mut module.functions[new_functions..] {
+        for arg in &mut f.args {
+            arg.ty.resolve_records(&module.records);
+        }
+        f.ret.resolve_records(&module.records);
+    }
     for (i, f) in module.functions.iter().enumerate() {
         f.resolve_locals(i, module, &use_lookup);
     }
+    for f in &mut module.functions {
+        if f.code.is_none() {
+            f.code = Some(Arc::new(Code::compile(f)));
+        }
+    }
     Ok(())
 }
 
@@ -297,6 +329,10 @@ pub struct Function {
         ::std::sync::atomic::AtomicBool,
         ::std::sync::Mutex<Vec<::std::sync::mpsc::Sender<Variable>>>
     )>,
+    /// Compiled function body, set after resolving locals.
+    pub code: Option<Arc<Code>>,
+    /// Whether the function is declared with `test fn`.
+    pub test: bool,
 }
 
 impl Function {
@@ -321,13 +357,21 @@ impl Function {
         let mut block: Option<Block> = None;
         let mut expr: Option<Expression> = None;
         let mut ret: Option<Type> = None;
+        let mut test = false;
+        let mut generics: Vec<Arc<String>> = vec![];
         loop {
             if let Ok(range) = convert.end_node(node) {
                 convert.update(range);
                 break;
+            } else if let Ok((range, val)) = convert.meta_bool("test") {
+                convert.update(range);
+                test = val;
             } else if let Ok((range, val)) = convert.meta_string("name") {
                 convert.update(range);
                 name = Some(val);
+            } else if let Ok((range, val)) = convert.meta_string("generic") {
+                convert.update(range);
+                generics.push(val);
             } else if let Ok((range, val)) = Arg::from_meta_data(
                     convert, ignored) {
                 convert.update(range);
@@ -383,7 +427,13 @@ impl Function {
             name_plus_args.push(')');
             name = Arc::new(name_plus_args);
         }
-        let ret = try!(ret.ok_or(()));
+        let mut ret = try!(ret.ok_or(()));
+        if generics.len() > 0 {
+            for arg in &mut args {
+                arg.ty.resolve_generics(&generics);
+            }
+            ret.resolve_generics(&generics);
+        }
         Ok((convert.subtract(start), Function {
             namespace: namespace.clone(),
             resolved: Arc::new(AtomicBool::new(false)),
@@ -396,6 +446,8 @@ impl Function {
             ret: ret,
             source_range: convert.source(start).unwrap(),
             senders: Arc::new((AtomicBool::new(false), Mutex::new(vec![]))),
+            code: None,
+            test: test,
         }))
     }
 
@@ -847,6 +899,7 @@ pub enum Expression {
     All(Box<ForN>),
     LinkFor(Box<ForN>),
     If(Box<If>),
+    Match(Box<Match>),
     Compare(Box<Compare>),
     UnOp(Box<UnOpExpression>),
     Norm(Box<Norm>),
@@ -919,6 +972,10 @@ impl Expression {
                     file, source, "block", convert, ignored) {
                 convert.update(range);
                 result = Some(Expression::Block(val));
+            } else if let Ok((range, val)) = Bitwise::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                result = Some(val.to_expression());
             } else if let Ok((range, val)) = Add::from_meta_data(
                     file, source, convert, ignored) {
                 convert.update(range);
@@ -1047,6 +1104,10 @@ impl Expression {
                     file, source, convert, ignored) {
                 convert.update(range);
                 result = Some(Expression::If(Box::new(val)));
+            } else if let Ok((range, val)) = Match::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                result = Some(Expression::Match(Box::new(val)));
             } else if let Ok((range, val)) = Compare::from_meta_data(
                     file, source, convert, ignored) {
                 convert.update(range);
@@ -1143,6 +1204,7 @@ impl Expression {
             All(ref for_n_expr) => for_n_expr.source_range,
             LinkFor(ref for_n_expr) => for_n_expr.source_range,
             If(ref if_expr) => if_expr.source_range,
+            Match(ref match_expr) => match_expr.source_range,
             Compare(ref comp) => comp.source_range,
             Norm(ref norm) => norm.source_range,
             UnOp(ref unop) => unop.source_range,
@@ -1224,6 +1286,8 @@ impl Expression {
                 for_n_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             If(ref if_expr) =>
                 if_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
+            Match(ref match_expr) =>
+                match_expr.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             Compare(ref comp) =>
                 comp.resolve_locals(relative, stack, closure_stack, module, use_lookup),
             Norm(ref norm) =>
@@ -1563,6 +1627,85 @@ impl ArrayFill {
     }
 }
 
+/// Bitwise operators, which have lower precedence than `+` and `-`.
+#[derive(Debug, Clone)]
+pub struct Bitwise {
+    pub items: Vec<Expression>,
+    pub ops: Vec<BinOp>,
+    pub source_range: Range,
+}
+
+impl Bitwise {
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Bitwise), ()> {
+        let start = convert.clone();
+        let node = "bitwise";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut items = vec![];
+        let mut ops = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                    file, source, "expr", convert, ignored) {
+                convert.update(range);
+                items.push(val);
+            } else if let Ok((range, _)) = convert.meta_bool("&") {
+                convert.update(range);
+                ops.push(BinOp::BitAnd);
+            } else if let Ok((range, _)) = convert.meta_bool("|") {
+                convert.update(range);
+                ops.push(BinOp::BitOr);
+            } else if let Ok((range, _)) = convert.meta_bool("xor") {
+                convert.update(range);
+                ops.push(BinOp::BitXor);
+            } else if let Ok((range, _)) = convert.meta_bool("<<") {
+                convert.update(range);
+                ops.push(BinOp::Shl);
+            } else if let Ok((range, _)) = convert.meta_bool(">>") {
+                convert.update(range);
+                ops.push(BinOp::Shr);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        if items.len() == 0 {
+            return Err(())
+        }
+        Ok((convert.subtract(start), Bitwise {
+            items: items,
+            ops: ops,
+            source_range: convert.source(start).unwrap()
+        }))
+    }
+
+    pub fn to_expression(mut self) -> Expression {
+        if self.items.len() == 1 {
+            self.items[0].clone()
+        } else {
+            let op = self.ops.pop().expect("Expected a binary operation");
+            let last = self.items.pop().expect("Expected argument");
+            let source_range = self.source_range;
+            Expression::BinOp(Box::new(BinOpExpression {
+                op: op,
+                left: self.to_expression(),
+                right: last,
+                source_range: source_range,
+            }))
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Add {
     pub items: Vec<Expression>,
@@ -1795,6 +1938,16 @@ pub enum BinOp {
     Pow,
     OrElse,
     AndAlso,
+    /// Bitwise and of integers.
+    BitAnd,
+    /// Bitwise or of integers.
+    BitOr,
+    /// Bitwise exclusive or of integers.
+    BitXor,
+    /// Shift bits of integer left.
+    Shl,
+    /// Shift bits of integer right, preserving sign.
+    Shr,
 }
 
 impl BinOp {
@@ -1810,6 +1963,11 @@ impl BinOp {
             BinOp::Pow => "^",
             BinOp::OrElse => "||",
             BinOp::AndAlso => "&&",
+            BinOp::BitAnd => "&",
+            BinOp::BitOr => "|",
+            BinOp::BitXor => "xor",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
         }
     }
 
@@ -1821,14 +1979,23 @@ impl BinOp {
         }
     }
 
+    /// Returns `true` if the operator only works on integers.
+    pub fn is_bitwise(self) -> bool {
+        match self {
+            BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr => true,
+            _ => false
+        }
+    }
+
     /// Returns the operator precedence level.
     pub fn precedence(self) -> u8 {
         match self {
             BinOp::OrElse | BinOp::AndAlso => 0,
-            BinOp::Add | BinOp::Sub => 1,
+            BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr => 1,
+            BinOp::Add | BinOp::Sub => 2,
             BinOp::Mul | BinOp::Dot | BinOp::Cross
-            | BinOp::Div | BinOp::Rem => 2,
-            BinOp::Pow => 3,
+            | BinOp::Div | BinOp::Rem => 3,
+            BinOp::Pow => 4,
         }
     }
 }
@@ -2251,6 +2418,10 @@ impl Call {
         let f_index = if let Some(ref alias) = self.alias {
             if let Some(&i) = use_lookup.aliases.get(alias).and_then(|map| map.get(&self.name)) {
                 FnIndex::Loaded(i as isize - relative as isize)
+            } else if module.records.get(alias).and_then(|ty| ty.variants())
+                .map(|(_, variants)| variants.iter().any(|&(ref name, _)| name == &self.name))
+                .unwrap_or(false) {
+                FnIndex::Variant
             } else {
                 FnIndex::None
             }
@@ -2269,7 +2440,7 @@ impl Call {
                 // Don't push return since last value in block
                 // is used as return value.
             }
-            FnIndex::Intrinsic(_) => {}
+            FnIndex::Intrinsic(_) | FnIndex::Variant => {}
             FnIndex::None => {}
         }
         for arg in &self.args {
@@ -3174,10 +3345,24 @@ impl For {
 pub struct ForN {
     pub name: Arc<String>,
     pub start: Option<Expression>,
+    /// The end of the counter, or the collection when iterating with `in`.
     pub end: Expression,
     pub block: Block,
     pub label: Option<Arc<String>>,
     pub source_range: Range,
+    /// Set when iterating over items of a collection.
+    ///
+    /// The counter is then hidden and counts up to the number of items.
+    pub for_in: Option<ForIn>,
+}
+
+/// Names declared by a loop iterating over items of a collection.
+#[derive(Debug, Clone)]
+pub struct ForIn {
+    /// Name of key or index.
+    pub key: Option<Arc<String>>,
+    /// Name of value.
+    pub value: Arc<String>,
 }
 
 impl ForN {
@@ -3195,6 +3380,7 @@ impl ForN {
         let mut indices: Vec<(Arc<String>, Option<Expression>, Option<Expression>)> = vec![];
         let mut block: Option<Block> = None;
         let mut label: Option<Arc<String>> = None;
+        let mut collection: Option<Expression> = None;
         loop {
             if let Ok(range) = convert.end_node(node) {
                 convert.update(range);
@@ -3203,6 +3389,10 @@ impl ForN {
                     file, source, "block", convert, ignored) {
                 convert.update(range);
                 block = Some(val);
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                    file, source, "collection", convert, ignored) {
+                convert.update(range);
+                collection = Some(val);
             } else if let Ok((range, val)) = convert.meta_string("label") {
                 convert.update(range);
                 label = Some(val);
@@ -3228,6 +3418,26 @@ impl ForN {
             }
         }
 
+        if let Some(collection) = collection {
+            let for_in = match indices.len() {
+                1 => ForIn { key: None, value: indices[0].0.clone() },
+                2 => ForIn {
+                    key: Some(indices[0].0.clone()),
+                    value: indices[1].0.clone()
+                },
+                _ => return Err(())
+            };
+            return Ok((convert.subtract(start), ForN {
+                name: Arc::new(String::new()),
+                start: None,
+                end: collection,
+                block: try!(block.ok_or(())),
+                label: label,
+                source_range: convert.source(start).unwrap(),
+                for_in: Some(for_in),
+            }));
+        }
+
         ForN::create(
             node,
             convert.subtract(start),
@@ -3295,6 +3505,7 @@ impl ForN {
             block: block,
             label: label,
             source_range: source_range,
+            for_in: None,
         }))
     }
 
@@ -3313,7 +3524,16 @@ impl ForN {
         }
         self.end.resolve_locals(relative, stack, closure_stack, module, use_lookup);
         stack.truncate(st);
-        stack.push(Some(self.name.clone()));
+        if let Some(ref for_in) = self.for_in {
+            // The counter is hidden.
+            stack.push(None);
+            if let Some(ref key) = for_in.key {
+                stack.push(Some(key.clone()));
+            }
+            stack.push(Some(for_in.value.clone()));
+        } else {
+            stack.push(Some(self.name.clone()));
+        }
         self.block.resolve_locals(relative, stack, closure_stack, module, use_lookup);
         stack.truncate(st);
     }
@@ -3561,6 +3781,422 @@ impl If {
     }
 }
 
+/// Match expression, e.g. `match x { some(y) => y, none() => 0 }`.
+///
+/// Arms are tried in order, and the first arm with a matching pattern
+/// and a guard that is `true` is evaluated.
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub expr: Expression,
+    pub arms: Vec<MatchArm>,
+    pub source_range: Range,
+}
+
+impl Match {
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Match), ()> {
+        let start = convert.clone();
+        let node = "match";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut expr: Option<Expression> = None;
+        let mut arms: Vec<MatchArm> = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "expr", convert, ignored) {
+                convert.update(range);
+                expr = Some(val);
+            } else if let Ok((range, val)) = MatchArm::from_meta_data(
+                    file, source, convert, ignored) {
+                convert.update(range);
+                arms.push(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let expr = try!(expr.ok_or(()));
+        Ok((convert.subtract(start), Match {
+            expr: expr,
+            arms: arms,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+
+    pub fn resolve_locals(
+        &self,
+        relative: usize,
+        stack: &mut Vec<Option<Arc<String>>>,
+        closure_stack: &mut Vec<usize>,
+        module: &Module,
+        use_lookup: &UseLookup,
+    ) {
+        let st = stack.len();
+        self.expr.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+        stack.truncate(st);
+        for arm in &self.arms {
+            // Bindings are pushed in the same order as when matching.
+            let mut bindings = vec![];
+            arm.pattern.bindings(&mut bindings);
+            for name in bindings {
+                stack.push(Some(name));
+            }
+            let arm_st = stack.len();
+            if let Some(ref guard) = arm.guard {
+                guard.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+                stack.truncate(arm_st);
+            }
+            arm.expr.resolve_locals(relative, stack, closure_stack, module, use_lookup);
+            stack.truncate(st);
+        }
+    }
+}
+
+/// An arm in a match expression, e.g. `some(x) if x > 0 => x`.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub expr: Expression,
+    pub source_range: Range,
+}
+
+impl MatchArm {
+    pub fn from_meta_data(
+        file: &Arc<String>,
+        source: &Arc<String>,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, MatchArm), ()> {
+        let start = convert.clone();
+        let node = "match_arm";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut pattern: Option<Pattern> = None;
+        let mut guard: Option<Expression> = None;
+        let mut expr: Option<Expression> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                "pattern", convert, ignored) {
+                convert.update(range);
+                pattern = Some(val);
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "guard", convert, ignored) {
+                convert.update(range);
+                guard = Some(val);
+            } else if let Ok((range, val)) = Expression::from_meta_data(
+                file, source, "expr", convert, ignored) {
+                convert.update(range);
+                expr = Some(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let pattern = try!(pattern.ok_or(()));
+        let expr = try!(expr.ok_or(()));
+        Ok((convert.subtract(start), MatchArm {
+            pattern: pattern,
+            guard: guard,
+            expr: expr,
+            source_range: convert.source(start).unwrap(),
+        }))
+    }
+}
+
+/// Pattern in a match arm.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Matches anything, `_`.
+    Wildcard,
+    /// Matches anything and binds the value to a name.
+    Bind(Arc<String>),
+    /// Matches a number, string or bool.
+    Value(Variable),
+    /// Matches `some(_)`.
+    Some(Box<Pattern>),
+    /// Matches `none()`.
+    None,
+    /// Matches `ok(_)`.
+    Ok(Box<Pattern>),
+    /// Matches `err(_)`, where the pattern is matched against the error message.
+    Err(Box<Pattern>),
+    /// Matches an object with the keys, e.g. `{name: n, ..}`.
+    ///
+    /// Without `..`, the object can not have other keys.
+    Object(Vec<(Arc<String>, Pattern)>, bool),
+    /// Matches an array with the items, e.g. `[a, b, ..]` or `[first, .., last]`.
+    ///
+    /// Stores the position of `..` among the items.
+    /// Items before `..` match the start of the array and items after `..` match the end.
+    /// Without `..`, the array must have the same length.
+    Array(Vec<Pattern>, Option<usize>),
+    /// Matches a variant of an enum, e.g. `Shape::Rect(w, h)`.
+    Variant(Arc<String>, Arc<String>, Vec<Pattern>),
+}
+
+impl Pattern {
+    pub fn from_meta_data(
+        node: &str,
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Pattern), ()> {
+        let start = convert.clone();
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut result: Option<Pattern> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                "some_pattern", convert, ignored) {
+                convert.update(range);
+                result = Some(Pattern::Some(Box::new(val)));
+            } else if let Ok(range) = convert.start_node("none_pattern") {
+                convert.update(range);
+                let range = try!(convert.end_node("none_pattern"));
+                convert.update(range);
+                result = Some(Pattern::None);
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                "ok_pattern", convert, ignored) {
+                convert.update(range);
+                result = Some(Pattern::Ok(Box::new(val)));
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                "err_pattern", convert, ignored) {
+                convert.update(range);
+                result = Some(Pattern::Err(Box::new(val)));
+            } else if let Ok((range, val)) = Pattern::variant_from_meta_data(
+                convert, ignored) {
+                convert.update(range);
+                result = Some(val);
+            } else if let Ok((range, val)) = Pattern::object_from_meta_data(
+                convert, ignored) {
+                convert.update(range);
+                result = Some(val);
+            } else if let Ok((range, val)) = Pattern::array_from_meta_data(
+                convert, ignored) {
+                convert.update(range);
+                result = Some(val);
+            } else if let Ok((range, _)) = convert.meta_bool("_") {
+                convert.update(range);
+                result = Some(Pattern::Wildcard);
+            } else if let Ok((range, val)) = convert.meta_f64("num") {
+                convert.update(range);
+                result = Some(Pattern::Value(Variable::f64(val)));
+            } else if let Ok((range, val)) = convert.meta_string("text") {
+                convert.update(range);
+                result = Some(Pattern::Value(Variable::Text(val)));
+            } else if let Ok((range, val)) = convert.meta_bool("bool") {
+                convert.update(range);
+                result = Some(Pattern::Value(Variable::bool(val)));
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                result = Some(Pattern::Bind(val));
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let result = try!(result.ok_or(()));
+        Ok((convert.subtract(start), result))
+    }
+
+    fn object_from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Pattern), ()> {
+        let start = convert.clone();
+        let node = "object_pattern";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut key: Option<Arc<String>> = None;
+        let mut key_patterns: Vec<(Arc<String>, Pattern)> = vec![];
+        let mut rest = false;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("key") {
+                convert.update(range);
+                key = Some(val);
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                "pattern", convert, ignored) {
+                convert.update(range);
+                key_patterns.push((try!(key.take().ok_or(())), val));
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                // Binds a key to a variable with the same name.
+                convert.update(range);
+                key_patterns.push((val.clone(), Pattern::Bind(val)));
+            } else if let Ok((range, _)) = convert.meta_bool("..") {
+                convert.update(range);
+                rest = true;
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        Ok((convert.subtract(start), Pattern::Object(key_patterns, rest)))
+    }
+
+    fn array_from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Pattern), ()> {
+        let start = convert.clone();
+        let node = "array_pattern";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut items: Vec<Pattern> = vec![];
+        let mut rest: Option<usize> = None;
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                "pattern", convert, ignored) {
+                convert.update(range);
+                items.push(val);
+            } else if let Ok((range, _)) = convert.meta_bool("..") {
+                convert.update(range);
+                // Only one `..` is allowed in an array pattern.
+                if rest.is_some() { return Err(()); }
+                rest = Some(items.len());
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        Ok((convert.subtract(start), Pattern::Array(items, rest)))
+    }
+
+    fn variant_from_meta_data(
+        mut convert: Convert,
+        ignored: &mut Vec<Range>)
+    -> Result<(Range, Pattern), ()> {
+        let start = convert.clone();
+        let node = "variant_pattern";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut ty: Option<Arc<String>> = None;
+        let mut name: Option<Arc<String>> = None;
+        let mut items: Vec<Pattern> = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("enum") {
+                convert.update(range);
+                ty = Some(val);
+            } else if let Ok((range, val)) = convert.meta_string("variant") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok((range, val)) = Pattern::from_meta_data(
+                "pattern", convert, ignored) {
+                convert.update(range);
+                items.push(val);
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let ty = try!(ty.ok_or(()));
+        let name = try!(name.ok_or(()));
+        Ok((convert.subtract(start), Pattern::Variant(ty, name, items)))
+    }
+
+    /// Gets the names bound by the pattern, in the order they are matched.
+    pub fn bindings(&self, names: &mut Vec<Arc<String>>) {
+        match *self {
+            Pattern::Wildcard | Pattern::Value(_) | Pattern::None => {}
+            Pattern::Bind(ref name) => names.push(name.clone()),
+            Pattern::Some(ref pat) | Pattern::Ok(ref pat) | Pattern::Err(ref pat) =>
+                pat.bindings(names),
+            Pattern::Object(ref key_patterns, _) => {
+                for &(_, ref pat) in key_patterns {
+                    pat.bindings(names);
+                }
+            }
+            Pattern::Array(ref items, _) | Pattern::Variant(_, _, ref items) => {
+                for pat in items {
+                    pat.bindings(names);
+                }
+            }
+        }
+    }
+
+    /// Matches a resolved value against the pattern.
+    ///
+    /// Pushes the bound values in the same order as `bindings`.
+    pub fn matches(&self, val: &Variable, bound: &mut Vec<(Arc<String>, Variable)>) -> bool {
+        match (self, val) {
+            (&Pattern::Wildcard, _) => true,
+            (&Pattern::Bind(ref name), _) => {
+                bound.push((name.clone(), val.clone()));
+                true
+            }
+            (&Pattern::Value(ref a), b) => a == b,
+            (&Pattern::Some(ref pat), &Variable::Option(Some(ref v))) => pat.matches(v, bound),
+            (&Pattern::None, &Variable::Option(None)) => true,
+            (&Pattern::Ok(ref pat), &Variable::Result(Ok(ref v))) => pat.matches(v, bound),
+            (&Pattern::Err(ref pat), &Variable::Result(Err(ref err))) =>
+                pat.matches(&err.message, bound),
+            (&Pattern::Object(ref key_patterns, rest), &Variable::Object(ref obj)) => {
+                if !rest && obj.len() != key_patterns.len() { return false; }
+                for &(ref key, ref pat) in key_patterns {
+                    match obj.get(key) {
+                        None => return false,
+                        Some(v) => if !pat.matches(v, bound) { return false; }
+                    }
+                }
+                true
+            }
+            (&Pattern::Array(ref items, rest), &Variable::Array(ref arr)) => {
+                if arr.len() < items.len() || rest.is_none() && arr.len() != items.len() {
+                    return false;
+                }
+                let (start, end) = items.split_at(rest.unwrap_or(items.len()));
+                start.iter().zip(arr.iter()).all(|(pat, v)| pat.matches(v, bound)) &&
+                end.iter().zip(arr[arr.len() - end.len()..].iter())
+                    .all(|(pat, v)| pat.matches(v, bound))
+            }
+            (&Pattern::Variant(ref ty, ref name, ref items), &Variable::Variant(ref v)) => {
+                ty == &v.ty && name == &v.name && items.len() == v.payload.len() &&
+                items.iter().zip(v.payload.iter()).all(|(pat, v)| pat.matches(v, bound))
+            }
+            _ => false
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Compare {
     pub op: CompareOp,
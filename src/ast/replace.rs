@@ -18,6 +18,8 @@ use super::{
     If,
     Item,
     Link,
+    Match,
+    MatchArm,
     Object,
     Norm,
     Swizzle,
@@ -227,6 +229,29 @@ pub fn number(expr: &Expression, name: &Arc<String>, val: f64) -> Expression {
                 source_range: if_expr.source_range,
             }))
         }
+        E::Match(ref match_expr) => {
+            let mut new_arms: Vec<MatchArm> = vec![];
+            for arm in &match_expr.arms {
+                // Keep the arm when the pattern binds a variable with same name.
+                let mut bindings = vec![];
+                arm.pattern.bindings(&mut bindings);
+                if bindings.iter().any(|n| n == name) {
+                    new_arms.push(arm.clone());
+                    continue;
+                }
+                new_arms.push(MatchArm {
+                    pattern: arm.pattern.clone(),
+                    guard: arm.guard.as_ref().map(|guard| number(guard, name, val)),
+                    expr: number(&arm.expr, name, val),
+                    source_range: arm.source_range,
+                });
+            }
+            E::Match(Box::new(Match {
+                expr: number(&match_expr.expr, name, val),
+                arms: new_arms,
+                source_range: match_expr.source_range,
+            }))
+        }
         E::Compare(ref cmp_expr) => {
             E::Compare(Box::new(Compare {
                 op: cmp_expr.op.clone(),
@@ -341,8 +366,14 @@ fn number_for_n(for_n_expr: &ForN, name: &Arc<String>, val: f64) -> ForN {
             start: for_n_expr.start.as_ref()
                 .map(|start| number(start, name, val)),
             end: number(&for_n_expr.end, name, val),
-            block: number_block(&for_n_expr.block, name, val),
+            block: match for_n_expr.for_in {
+                // Key or value shadows the name inside the block.
+                Some(ref for_in) if &for_in.value == name ||
+                    for_in.key.as_ref() == Some(name) => for_n_expr.block.clone(),
+                _ => number_block(&for_n_expr.block, name, val),
+            },
             source_range: for_n_expr.source_range,
+            for_in: for_n_expr.for_in.clone(),
         }
     }
 }
@@ -0,0 +1,12 @@
+extern crate dyon;
+
+use std::io;
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(err) = dyon::lsp::run(stdin.lock(), stdout.lock()) {
+        eprintln!("dyon-lsp: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,398 @@
+extern crate dyon;
+extern crate range;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::sync::Arc;
+
+use range::Range;
+
+use dyon::{ast, error, load, load_str, DyonError, Module, Runtime, Type, Variable};
+use dyon::intrinsics::functions::list_functions;
+use dyon::testing;
+use dyon::write::{write_variable, EscapeString};
+
+const HELP: &'static str = "\
+Enter function declarations or expressions to evaluate.
+Variables declared with `:=` are kept between inputs.
+
+:load <file>          Load functions from file
+:type <expr>          Show the type of expression
+:functions [filter]   List functions containing filter in name
+:help                 Show this message
+:quit                 Exit";
+
+const TEST_USAGE: &'static str = "\
+Usage: dyon test [--filter <text>] [--threads <n>] <file.dyon>...
+
+Runs functions declared with `test fn`.
+
+--filter <text>   Only run tests with names containing text
+--threads <n>     Number of tests to run in parallel";
+
+/// The function used to evaluate expressions.
+const REPL_FN: &'static str = "__repl";
+/// The argument that receives variables declared by the input.
+const REPL_NEW: &'static str = "__repl_new";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(|x| &**x) == Some("test") {
+        process::exit(test(&args[1..]));
+    }
+
+    let mut module = Module::new();
+    let mut rt = Runtime::new();
+    let mut env = Env { names: vec![] };
+
+    println!("Dyon {} (type `:help` for help)", env!("CARGO_PKG_VERSION"));
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        let input = match read_input(&mut lines) {
+            None => break,
+            Some(input) => input,
+        };
+        let input = input.trim();
+        if input == "" { continue }
+
+        if input.starts_with(':') {
+            let (cmd, arg) = match input.find(char::is_whitespace) {
+                None => (input, ""),
+                Some(i) => (&input[..i], input[i..].trim()),
+            };
+            match cmd {
+                ":q" | ":quit" => break,
+                ":h" | ":help" => println!("{}", HELP),
+                ":load" => {
+                    // Keep the old functions if loading fails.
+                    let mut m = module.clone();
+                    if !error(load(arg, &mut m)) {
+                        module = m;
+                    }
+                }
+                ":type" => {
+                    match compile_expr(&module, &env, arg) {
+                        Ok(ref c) if c.returns => {
+                            let f = c.module.functions.last().expect("Expected function");
+                            println!("{}", f.ret.description());
+                        }
+                        Ok(_) => println!("void"),
+                        Err(err) => { error(Err(err)); }
+                    }
+                }
+                ":functions" => print_functions(&module, arg),
+                _ => eprintln!("Unknown command `{}`, type `:help` for help", cmd),
+            }
+        } else if input.starts_with("fn ") {
+            let mut m = module.clone();
+            if !error(load_str("repl", Arc::new(input.into()), &mut m)) {
+                module = m;
+            }
+        } else if let Some(m) = load_definitions(&module, input) {
+            module = m;
+        } else {
+            match compile_expr(&module, &env, input) {
+                Ok(c) => {
+                    match run(&mut rt, &mut env, &c) {
+                        Ok(Some(v)) => print_variable(&rt, &v),
+                        Ok(None) => {}
+                        Err(err) => { error(Err(c.input_error(err))); }
+                    }
+                }
+                Err(err) => { error(Err(err)); }
+            }
+        }
+    }
+}
+
+/// Runs tests in files, returns the exit code.
+fn test(args: &[String]) -> i32 {
+    let mut filter = String::new();
+    let mut threads = 4;
+    let mut files = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &**arg {
+            "--filter" => match args.next() {
+                Some(x) => filter = x.clone(),
+                None => { eprintln!("{}", TEST_USAGE); return 2 }
+            },
+            "--threads" => match args.next().and_then(|x| x.parse().ok()) {
+                Some(x) => threads = x,
+                None => { eprintln!("{}", TEST_USAGE); return 2 }
+            },
+            "-h" | "--help" => { println!("{}", TEST_USAGE); return 0 }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        eprintln!("{}", TEST_USAGE);
+        return 2;
+    }
+
+    let mut module = Module::new();
+    for file in files {
+        if error(load(file, &mut module)) { return 1 }
+    }
+    let module = Arc::new(module);
+    let tests = testing::collect(&module, &filter);
+    let results = testing::run(&module, &tests, threads);
+    print!("{}", testing::report(&results));
+    if results.iter().all(|res| res.passed()) { 0 } else { 1 }
+}
+
+/// Reads lines until brackets are balanced.
+/// Returns `None` at end of input.
+fn read_input<I>(lines: &mut I) -> Option<String>
+    where I: Iterator<Item = io::Result<String>>
+{
+    let mut input = String::new();
+    let mut prompt = "> ";
+    loop {
+        print!("{}", prompt);
+        io::stdout().flush().unwrap();
+        match lines.next() {
+            Some(Ok(line)) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            _ => {
+                return if input == "" { None } else { Some(input) };
+            }
+        }
+        if open_brackets(&input) <= 0 { return Some(input) }
+        prompt = "... ";
+    }
+}
+
+/// Counts brackets that are not closed, ignoring those in strings.
+fn open_brackets(input: &str) -> i32 {
+    let mut open = 0;
+    let mut in_string = false;
+    let mut escape = false;
+    for c in input.chars() {
+        if in_string {
+            if escape { escape = false }
+            else if c == '\\' { escape = true }
+            else if c == '"' { in_string = false }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '(' | '[' => open += 1,
+            '}' | ')' | ']' => open -= 1,
+            _ => {}
+        }
+    }
+    open
+}
+
+/// Loads functions declared in mathematical notation, e.g. `f(x) = x + 1`.
+/// Returns `None` if the input is not a function declaration.
+fn load_definitions(module: &Module, input: &str) -> Option<Module> {
+    let mut m = module.clone();
+    match load_str("repl", Arc::new(input.into()), &mut m) {
+        Ok(()) => Some(m),
+        Err(_) => None,
+    }
+}
+
+/// Variables declared in the REPL.
+///
+/// The values are stored at the bottom of the runtime stack,
+/// and passed by reference to the function that evaluates the input.
+struct Env {
+    names: Vec<Arc<String>>,
+}
+
+/// Input wrapped in a function.
+struct Compiled {
+    /// Copy of the module with the function.
+    module: Module,
+    /// Name of the function, including `mut` arguments.
+    name: String,
+    /// Whether the function returns a value.
+    returns: bool,
+    /// Variables declared by the input, stored after running it.
+    declared: Vec<Arc<String>>,
+    /// The generated source.
+    source: Arc<String>,
+    /// The input.
+    input: Arc<String>,
+    /// Number of bytes before the input in the generated source.
+    offset: usize,
+}
+
+impl Compiled {
+    /// Loads input wrapped in a function into a copy of the module.
+    fn load(
+        module: &Module,
+        input: &str,
+        prefix: String,
+        suffix: &str
+    ) -> Result<Compiled, DyonError> {
+        let mut m = module.clone();
+        let source = Arc::new(format!("{}{}{}", prefix, input, suffix));
+        let c = Compiled {
+            module: Module::new(),
+            name: String::new(),
+            returns: false,
+            declared: vec![],
+            source: source.clone(),
+            input: Arc::new(input.into()),
+            offset: prefix.len(),
+        };
+        match load_str("repl", source, &mut m) {
+            Ok(()) => {
+                let (name, returns) = {
+                    let f = m.functions.last().expect("Expected function");
+                    ((*f.name).clone(), f.ret != Type::Void)
+                };
+                Ok(Compiled { module: m, name: name, returns: returns, ..c })
+            }
+            Err(err) => Err(c.input_error(err)),
+        }
+    }
+
+    /// Points error to the input instead of the generated source.
+    fn input_error(&self, mut err: DyonError) -> DyonError {
+        err.call_stack.retain(|frame| *frame.fn_name != self.name);
+        if err.source.as_ref() != Some(&self.source) { return err }
+        err.source = Some(self.input.clone());
+        err.range = match err.range {
+            Some(range) if range.offset >= self.offset &&
+                range.offset + range.length <= self.offset + self.input.len() =>
+                Some(Range::new(range.offset - self.offset, range.length)),
+            _ => None,
+        };
+        err
+    }
+}
+
+/// Loads expression wrapped in a function into a copy of the module.
+///
+/// Variables in the environment are arguments of the function.
+/// Tries to return the value of the expression first,
+/// then runs it as statements when it has no value.
+/// Variables declared by the statements are passed back in an extra argument.
+fn compile_expr(module: &Module, env: &Env, expr: &str) -> Result<Compiled, DyonError> {
+    let mut args: Vec<String> = env.names.iter()
+        .map(|name| format!("mut {}: 'return", name)).collect();
+    let err = match Compiled::load(module, expr,
+        format!("{}({}) = ", REPL_FN, args.join(", ")), "") {
+        Ok(c) => {
+            if c.returns { return Ok(c) }
+            None
+        }
+        Err(err) => Some(err),
+    };
+
+    let prefix = format!("fn {}({}) {{\n", REPL_FN, args.join(", "));
+    let c = match Compiled::load(module, expr, prefix, "\n}") {
+        Ok(c) => c,
+        Err(stmt_err) => return Err(err.unwrap_or(stmt_err)),
+    };
+    let declared = declared_variables(c.module.functions.last().expect("Expected function"));
+    if declared.is_empty() { return Ok(c) }
+
+    args.push(format!("mut {}", REPL_NEW));
+    let values: Vec<String> = declared.iter().map(|name| format!("clone({})", name)).collect();
+    let prefix = format!("fn {}({}) {{\n", REPL_FN, args.join(", "));
+    let suffix = format!("\n{} = [{}]\n}}", REPL_NEW, values.join(", "));
+    let c = try!(Compiled::load(module, expr, prefix, &suffix));
+    Ok(Compiled { declared: declared, ..c })
+}
+
+/// Returns the variables declared at the top level of a function.
+fn declared_variables(f: &ast::Function) -> Vec<Arc<String>> {
+    let mut names: Vec<Arc<String>> = vec![];
+    for expr in &f.block.expressions {
+        if let ast::Expression::Assign(ref assign) = *expr {
+            if assign.op != ast::AssignOp::Assign { continue }
+            if let ast::Expression::Item(ref item) = assign.left {
+                if item.ids.is_empty() && !names.contains(&item.name) {
+                    names.push(item.name.clone());
+                }
+            }
+        }
+    }
+    names
+}
+
+/// Runs compiled input and stores the declared variables in the environment.
+fn run(rt: &mut Runtime, env: &mut Env, c: &Compiled) -> Result<Option<Variable>, DyonError> {
+    let n = env.names.len();
+    let mut args: Vec<Variable> = (0..n).map(Variable::Ref).collect();
+    if !c.declared.is_empty() {
+        rt.stack.push(Variable::Array(Arc::new(vec![])));
+        args.push(Variable::Ref(n));
+    }
+    let m = Arc::new(c.module.clone());
+    let res = if c.returns {
+        rt.call_str_ret(&c.name, &args, &m).map(Some)
+    } else {
+        rt.call_str(&c.name, &args, &m).map(|()| None)
+    };
+    // Keep the environment when the stacks are cleared.
+    let mut values: Vec<Variable> = rt.stack.iter().take(n + 1).cloned().collect();
+    rt.clear_stacks();
+    let declared = if values.len() > n { values.pop() } else { None };
+    rt.stack.extend(values);
+    let v = try!(res);
+    if let Some(Variable::Array(arr)) = declared {
+        for (name, value) in c.declared.iter().zip(arr.iter()) {
+            match env.names.iter().position(|n| n == name) {
+                Some(i) => rt.stack[i] = value.clone(),
+                None => {
+                    env.names.push(name.clone());
+                    rt.stack.push(value.clone());
+                }
+            }
+        }
+    }
+    Ok(v)
+}
+
+fn print_variable(rt: &Runtime, v: &Variable) {
+    let mut stdout = io::stdout();
+    write_variable(&mut stdout, rt, rt.resolve(v), EscapeString::Json, 0).unwrap();
+    println!("");
+}
+
+/// Prints functions with names containing filter.
+fn print_functions(module: &Module, filter: &str) {
+    let text = |obj: &Variable, key: &str| -> Arc<String> {
+        if let Variable::Object(ref obj) = *obj {
+            if let Some(&Variable::Text(ref t)) = obj.get(&Arc::new(key.into())) {
+                return t.clone();
+            }
+        }
+        panic!("Expected `{}` in function info", key)
+    };
+
+    let functions = list_functions(module);
+    for (i, f) in functions.iter().enumerate() {
+        let name = text(f, "name");
+        if !name.contains(filter) { continue }
+        // Functions are sorted by name, the last one shadows the others.
+        if let Some(next) = functions.get(i + 1) {
+            if text(next, "name") == name { continue }
+        }
+        let mut args = vec![];
+        if let Variable::Object(ref obj) = *f {
+            if let Some(&Variable::Array(ref arr)) = obj.get(&Arc::new("arguments".into())) {
+                for arg in arr.iter() {
+                    args.push(format!("{}: {}", text(arg, "name"), text(arg, "takes")));
+                }
+            }
+        }
+        let ret = text(f, "returns");
+        if &**ret == "void" {
+            println!("{}({})", name, args.join(", "));
+        } else {
+            println!("{}({}) -> {}", name, args.join(", "), ret);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+extern crate dyon;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process;
+use std::sync::Arc;
+
+use dyon::format::format_str;
+
+const USAGE: &'static str = "\
+Usage: dyonfmt [--check] <file.dyon>...
+
+Formats Dyon files in place.
+
+--check   Do not write files, fail if any file is not formatted";
+
+fn main() {
+    let mut check = false;
+    let mut files = vec![];
+    for arg in env::args().skip(1) {
+        match &*arg {
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let mut failed = false;
+    for file in &files {
+        let mut source = String::new();
+        if let Err(err) = File::open(file).and_then(|mut f| f.read_to_string(&mut source)) {
+            eprintln!("Could not read `{}`: {}", file, err);
+            failed = true;
+            continue;
+        }
+        let source = Arc::new(source);
+        let formatted = match format_str(file, &source) {
+            Ok(x) => x,
+            Err(err) => {
+                eprintln!("{}", err);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == *source { continue }
+        if check {
+            println!("Not formatted: {}", file);
+            failed = true;
+        } else if let Err(err) = File::create(file)
+            .and_then(|mut f| f.write_all(formatted.as_bytes())) {
+            eprintln!("Could not write `{}`: {}", file, err);
+            failed = true;
+        }
+    }
+    if failed { process::exit(1) }
+}
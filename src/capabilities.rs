@@ -0,0 +1,122 @@
+//! Permissions for scripts to access the world outside the runtime.
+//!
+//! Denied capabilities are reported when loading a script,
+//! pointing out the call that requires it.
+
+/// A group of intrinsics that can be denied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Reading and writing files.
+    File,
+    /// Downloading from urls.
+    Net,
+    /// Reading from standard input.
+    Stdin,
+    /// Starting threads with `go`.
+    Threads,
+    /// Loading new modules.
+    Load,
+    /// Reading command line arguments.
+    Args,
+}
+
+impl Capability {
+    /// Returns capabilities required by intrinsic.
+    pub fn of_intrinsic(name: &str) -> &'static [Capability] {
+        use self::Capability::*;
+
+        match name {
+            "load_string__file" | "save__string_file" |
+            "load_data__file" | "save__data_file" |
+            "load__meta_file" => &[File],
+            "load_string__url" | "load__meta_url" => &[Net],
+            "download__url_file" => &[Net, File],
+            "read_line" | "read_number" => &[Stdin],
+            "load" | "load__source_imports" | "module__in_string_imports" => &[Load],
+            "args_os" => &[Args],
+            _ => &[]
+        }
+    }
+
+    /// Returns the name used in error messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::File => "file",
+            Capability::Net => "net",
+            Capability::Stdin => "stdin",
+            Capability::Threads => "threads",
+            Capability::Load => "load",
+            Capability::Args => "args",
+        }
+    }
+}
+
+/// Stores which capabilities scripts are allowed to use.
+///
+/// Modules loaded by a script get the same capabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub file: bool,
+    pub net: bool,
+    pub stdin: bool,
+    pub threads: bool,
+    pub load: bool,
+    pub args: bool,
+}
+
+impl Capabilities {
+    /// Allows everything.
+    pub fn all() -> Capabilities {
+        Capabilities {
+            file: true,
+            net: true,
+            stdin: true,
+            threads: true,
+            load: true,
+            args: true,
+        }
+    }
+
+    /// Denies everything.
+    pub fn none() -> Capabilities {
+        Capabilities {
+            file: false,
+            net: false,
+            stdin: false,
+            threads: false,
+            load: false,
+            args: false,
+        }
+    }
+
+    /// Returns `true` if capability is allowed.
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::File => self.file,
+            Capability::Net => self.net,
+            Capability::Stdin => self.stdin,
+            Capability::Threads => self.threads,
+            Capability::Load => self.load,
+            Capability::Args => self.args,
+        }
+    }
+
+    /// Allows or denies capability.
+    pub fn set(&mut self, capability: Capability, allow: bool) {
+        match capability {
+            Capability::File => self.file = allow,
+            Capability::Net => self.net = allow,
+            Capability::Stdin => self.stdin = allow,
+            Capability::Threads => self.threads = allow,
+            Capability::Load => self.load = allow,
+            Capability::Args => self.args = allow,
+        }
+    }
+
+    /// Returns the first denied capability required by intrinsic.
+    pub fn denied_intrinsic(&self, name: &str) -> Option<Capability> {
+        Capability::of_intrinsic(name).iter()
+            .cloned()
+            .find(|&c| !self.allows(c))
+    }
+}
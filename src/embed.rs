@@ -118,6 +118,16 @@ impl PopVariable for f64 {
     }
 }
 
+impl PopVariable for i64 {
+    fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
+        if let &Variable::I64(n) = var {
+            Ok(n)
+        } else {
+            Err(rt.expected(var, "i64"))
+        }
+    }
+}
+
 impl<T: PopVariable> PopVariable for Option<T> {
     fn pop_var(rt: &Runtime, var: &Variable) -> Result<Self, String> {
         if let &Variable::Option(ref s) = var {
@@ -272,6 +282,10 @@ impl PushVariable for f64 {
     fn push_var(&self) -> Variable { Variable::f64(*self) }
 }
 
+impl PushVariable for i64 {
+    fn push_var(&self) -> Variable { Variable::i64(*self) }
+}
+
 impl PushVariable for str {
     fn push_var(&self) -> Variable { Variable::Text(Arc::new(self.into())) }
 }
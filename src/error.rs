@@ -0,0 +1,185 @@
+//! Errors reported when loading or running programs.
+
+use std::fmt;
+use std::sync::Arc;
+use range::Range;
+
+use runtime::Call;
+use runtime::limits::Limit;
+use Capability;
+
+/// Returns line and column of a byte offset in source, counting from 1.
+///
+/// The column counts characters.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut column = 1;
+    for (i, c) in source.char_indices() {
+        if i >= offset { break }
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    (line, column)
+}
+
+/// The kind of error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Could not read source file.
+    Io,
+    /// The source does not match the syntax.
+    Syntax,
+    /// The meta data could not be converted to AST.
+    Conversion,
+    /// Reported by the lifetime checker.
+    Lifetime,
+    /// Reported by the type checker.
+    Type,
+    /// Reported when running a program.
+    Runtime,
+    /// Reported when running a program reaches a limit.
+    Limit(Limit),
+    /// A script uses a capability that is denied.
+    Capability(Capability),
+    /// Formatting would change the meaning of the program.
+    Format,
+}
+
+/// A function call in the stack trace of an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub fn_name: Arc<String>,
+    pub file: Option<Arc<String>>,
+}
+
+/// Stores information about an error.
+///
+/// Displays the same text as `ParseErrorHandler`, pointing out the range.
+#[derive(Debug, Clone)]
+pub struct DyonError {
+    pub kind: ErrorKind,
+    /// The source file.
+    pub file: Option<Arc<String>>,
+    /// The source text, used to point out the range.
+    pub source: Option<Arc<String>>,
+    pub range: Option<Range>,
+    pub message: String,
+    /// Extra lines shown after the message.
+    pub notes: Vec<String>,
+    /// Function calls when the error happened, innermost last.
+    pub call_stack: Vec<Frame>,
+}
+
+impl DyonError {
+    /// Creates a new error without a location.
+    pub fn new(kind: ErrorKind, message: String) -> DyonError {
+        DyonError {
+            kind: kind,
+            file: None,
+            source: None,
+            range: None,
+            message: message,
+            notes: vec![],
+            call_stack: vec![],
+        }
+    }
+
+    /// Sets the range in source.
+    pub fn range(mut self, range: Range) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Sets the source file and text.
+    pub fn source(mut self, file: Arc<String>, source: Arc<String>) -> Self {
+        self.file = Some(file);
+        self.source = Some(source);
+        self
+    }
+
+    /// Adds a note shown after the message.
+    pub fn note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    /// Captures the call stack.
+    pub fn call_stack(mut self, call_stack: &[Call]) -> Self {
+        self.call_stack = call_stack.iter().map(|call| Frame {
+            fn_name: call.fn_name.clone(),
+            file: call.file.clone(),
+        }).collect();
+        self
+    }
+
+    /// Returns line and column where the range starts, counting from 1.
+    pub fn line_column(&self) -> Option<(usize, usize)> {
+        match (self.range, self.source.as_ref()) {
+            (Some(range), Some(source)) => Some(line_column(source, range.offset)),
+            _ => None
+        }
+    }
+
+    /// Returns the call stack as text, one function per line.
+    pub fn stack_trace(&self) -> String {
+        let mut s = String::new();
+        for frame in &self.call_stack {
+            s.push_str(&frame.fn_name);
+            if let Some(ref file) = frame.file {
+                s.push_str(" (");
+                s.push_str(file);
+                s.push(')');
+            }
+            s.push('\n')
+        }
+        s
+    }
+}
+
+impl fmt::Display for DyonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use piston_meta::ParseErrorHandler;
+
+        let mut msg = String::new();
+        if !self.call_stack.is_empty() {
+            msg.push_str(&self.stack_trace());
+            msg.push('\n');
+        }
+        if self.kind == ErrorKind::Syntax {
+            msg.push_str("Error ");
+        }
+        msg.push_str(&self.message);
+        for note in &self.notes {
+            msg.push('\n');
+            msg.push_str(note);
+        }
+        match (self.range, self.source.as_ref()) {
+            (Some(range), Some(source)) => {
+                // Runtime errors show the file in the stack trace.
+                let header = match self.kind {
+                    ErrorKind::Runtime | ErrorKind::Limit(_) => false,
+                    _ => true
+                };
+                if header {
+                    if let Some(ref file) = self.file {
+                        try!(write!(f, "In `{}`:\n\n", file));
+                    }
+                }
+                let mut w: Vec<u8> = vec![];
+                ParseErrorHandler::new(source).write_msg(&mut w, range, &msg).unwrap();
+                write!(f, "{}", String::from_utf8_lossy(&w))
+            }
+            _ => write!(f, "{}", msg)
+        }
+    }
+}
+
+impl ::std::error::Error for DyonError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
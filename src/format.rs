@@ -0,0 +1,1297 @@
+//! Formats source code.
+//!
+//! The formatter prints the meta data from the parser back to source,
+//! keeping comments and literals as written.
+//! At most one blank line between items is preserved.
+//! Lists, such as arrays and arguments, are put on separate lines
+//! when the source breaks lines between the items.
+//!
+//! The output is parsed again to make sure it gives the same meta data as the input,
+//! so formatting never changes the meaning of a program.
+//!
+//! This is a separate printer from `write::write_expr`, which prints closures at runtime.
+//! `write_expr` works on the AST, where the syntax is already lowered:
+//!
+//! - Comments, number literals such as `100_000` and escapes in strings are gone
+//! - Unicode spellings such as `∑` and `×` are replaced by the ASCII operator
+//! - Named calls such as `foo(a: x)` are turned into calls to `foo__a`
+//! - `vec4 i f(i)` is unrolled into `(f(0), f(1), f(2), f(3))`
+//! - Parentheses are dropped and put back by precedence
+//!
+//! Printing the AST would therefore not parse back to the same meta data.
+//! New syntax must be added to both printers: here to format files,
+//! and to `write.rs` if it can appear inside a closure.
+
+use std::sync::Arc;
+
+use piston_meta::MetaData;
+use range::Range;
+
+use {parse_str, DyonError, ErrorKind};
+
+/// Formats source.
+///
+/// Returns an error if the source has a syntax error,
+/// or if the output does not parse to the same meta data.
+pub fn format_str(file: &str, source: &Arc<String>) -> Result<String, DyonError> {
+    let data = try!(parse_str(file, source));
+    let doc = Node::from_meta_data(&data, source.len());
+    let mut printer = Printer::new(source);
+    printer.document(&doc);
+    let res = printer.out;
+
+    let changed = |range: Range| {
+        DyonError::new(ErrorKind::Format,
+                       "Formatting would change the meaning of the program".into())
+            .range(range)
+            .source(Arc::new(file.into()), source.clone())
+    };
+    let formatted = Arc::new(res.clone());
+    let new_data = match parse_str(file, &formatted) {
+        Ok(x) => x,
+        Err(err) => return Err(changed(Range::empty(0)).note(err.message)),
+    };
+    for (i, d) in data.iter().enumerate() {
+        if new_data.get(i).map(|n| &n.data) != Some(&d.data) {
+            return Err(changed(d.range()));
+        }
+    }
+    if new_data.len() != data.len() {
+        return Err(changed(Range::empty(source.len())));
+    }
+    let texts = |text: &str| -> Vec<String> {
+        comments(text).iter()
+            .map(|c| text[c.offset..c.next_offset()].trim_end().into())
+            .collect()
+    };
+    if texts(source) != texts(&res) {
+        return Err(changed(Range::empty(0)).note("Comments were not preserved".into()));
+    }
+    Ok(res)
+}
+
+/// Returns `true` if source is already formatted.
+pub fn is_formatted(file: &str, source: &Arc<String>) -> Result<bool, DyonError> {
+    Ok(try!(format_str(file, source)) == **source)
+}
+
+/// Finds ranges of comments outside strings.
+fn comments(source: &str) -> Vec<Range> {
+    let bytes = source.as_bytes();
+    let n = bytes.len();
+    let mut res = vec![];
+    let mut i = 0;
+    while i < n {
+        let next = if i + 1 < n { bytes[i + 1] } else { 0 };
+        match (bytes[i], next) {
+            (b'"', _) => {
+                i += 1;
+                while i < n {
+                    match bytes[i] {
+                        b'\\' => i += 2,
+                        b'"' => { i += 1; break }
+                        _ => i += 1,
+                    }
+                }
+            }
+            (b'/', b'/') => {
+                let start = i;
+                while i < n && bytes[i] != b'\n' { i += 1 }
+                res.push(Range::new(start, i - start));
+            }
+            (b'/', b'*') => {
+                // Multi-line comments can be nested.
+                let start = i;
+                let mut depth = 0;
+                while i < n {
+                    if bytes[i..].starts_with(b"/*") {
+                        depth += 1;
+                        i += 2;
+                    } else if bytes[i..].starts_with(b"*/") {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 { break }
+                    } else {
+                        i += 1;
+                    }
+                }
+                let i = i.min(n);
+                res.push(Range::new(start, i - start));
+            }
+            _ => i += 1,
+        }
+    }
+    res
+}
+
+/// A node in the meta data tree.
+struct Node {
+    name: Arc<String>,
+    range: Range,
+    children: Vec<Child>,
+}
+
+/// A child of a node in the meta data tree.
+enum Child {
+    Node(Node),
+    Bool(Arc<String>, bool, Range),
+    F64(Arc<String>, Range),
+    Str(Arc<String>, Arc<String>, Range),
+}
+
+impl Child {
+    fn name(&self) -> &str {
+        match *self {
+            Child::Node(ref node) => &node.name,
+            Child::Bool(ref name, _, _) |
+            Child::F64(ref name, _) |
+            Child::Str(ref name, _, _) => name,
+        }
+    }
+
+    fn range(&self) -> Range {
+        match *self {
+            Child::Node(ref node) => node.range,
+            Child::Bool(_, _, range) |
+            Child::F64(_, range) |
+            Child::Str(_, _, range) => range,
+        }
+    }
+
+    fn node(&self) -> Option<&Node> {
+        match *self {
+            Child::Node(ref node) => Some(node),
+            _ => None
+        }
+    }
+}
+
+impl Node {
+    /// Builds a tree with a root node containing the whole document.
+    fn from_meta_data(data: &[Range<MetaData>], len: usize) -> Node {
+        let mut stack = vec![Node {
+            name: Arc::new("document".into()),
+            range: Range::new(0, len),
+            children: vec![],
+        }];
+        for d in data {
+            let range = d.range();
+            let child = match d.data {
+                MetaData::StartNode(ref name) => {
+                    stack.push(Node {
+                        name: name.clone(),
+                        range: range,
+                        children: vec![],
+                    });
+                    continue;
+                }
+                MetaData::EndNode(_) => {
+                    let mut node = stack.pop().expect("Expected node");
+                    // The end node covers the whole node.
+                    node.range = range;
+                    Child::Node(node)
+                }
+                MetaData::Bool(ref name, val) => Child::Bool(name.clone(), val, range),
+                MetaData::F64(ref name, _) => Child::F64(name.clone(), range),
+                MetaData::String(ref name, ref val) => Child::Str(name.clone(), val.clone(), range),
+            };
+            stack.last_mut().expect("Expected node").children.push(child);
+        }
+        stack.pop().expect("Expected document")
+    }
+
+    fn start(&self) -> usize { self.range.offset }
+
+    fn end(&self) -> usize { self.range.next_offset() }
+
+    /// Returns the first child node with name.
+    fn get(&self, name: &str) -> Option<&Node> {
+        self.children.iter().filter_map(|c| c.node()).find(|n| &**n.name == name)
+    }
+
+    /// Returns the first string with name.
+    fn str(&self, name: &str) -> Option<&Arc<String>> {
+        for c in &self.children {
+            if let Child::Str(ref n, ref val, _) = *c {
+                if **n == *name { return Some(val) }
+            }
+        }
+        None
+    }
+
+    /// Returns the first boolean with name.
+    fn bool(&self, name: &str) -> Option<bool> {
+        for c in &self.children {
+            if let Child::Bool(ref n, val, _) = *c {
+                if **n == *name { return Some(val) }
+            }
+        }
+        None
+    }
+
+    /// Returns the child nodes with name.
+    fn nodes<'b>(&'b self, name: &'b str) -> Vec<&'b Node> {
+        self.children.iter().filter_map(|c| c.node()).filter(|n| &**n.name == name).collect()
+    }
+}
+
+/// Returns `true` if expression must be wrapped in parentheses
+/// where the syntax expects a left expression, e.g. operands of `*`.
+fn needs_parens(child: &Child) -> bool {
+    match child.name() {
+        "bitwise" | "add" | "compare" | "assign" | "if" | "match" | "for" | "for_n" | "loop" |
+        "return" | "return_void" | "break" | "continue" | "in" | "swizzle" => true,
+        _ => false
+    }
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    out: String,
+    tabs: usize,
+    comments: Vec<Range>,
+    /// The next comment to print.
+    next_comment: usize,
+    /// The end of the last source item printed, used to find blank lines.
+    last: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str) -> Printer<'a> {
+        Printer {
+            source: source,
+            out: String::new(),
+            tabs: 0,
+            comments: comments(source),
+            next_comment: 0,
+            last: 0,
+        }
+    }
+
+    fn slice(&self, range: Range) -> &'a str {
+        &self.source[range.offset..range.next_offset()]
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn push(&mut self, s: &str) {
+        if s.is_empty() { return }
+        if self.at_line_start() && !self.out.is_empty() {
+            for _ in 0..self.tabs { self.out.push_str("    ") }
+        }
+        self.out.push_str(s);
+    }
+
+    fn newline(&mut self) {
+        while self.out.ends_with(' ') { self.out.pop(); }
+        self.out.push('\n');
+    }
+
+    /// Starts a new line before an item at offset,
+    /// keeping a blank line from the source.
+    fn line_break(&mut self, offset: usize) {
+        if !self.at_line_start() { self.newline() }
+        let blank = self.last <= offset &&
+            self.source[self.last..offset].matches('\n').count() >= 2;
+        let after_open = self.out.trim_end_matches('\n').ends_with(|c| "{[(".contains(c));
+        if blank && !self.out.is_empty() && !self.out.ends_with("\n\n") && !after_open {
+            self.newline();
+        }
+    }
+
+    /// Returns `true` if there are no line breaks between the last item and offset.
+    fn same_line(&self, offset: usize) -> bool {
+        self.last <= offset && !self.source[self.last..offset].contains('\n')
+    }
+
+    fn has_comments(&self, range: Range) -> bool {
+        self.comments[self.next_comment..].iter()
+            .any(|c| c.offset >= range.offset && c.offset < range.next_offset())
+    }
+
+    fn write_comment(&mut self) {
+        let c = self.comments[self.next_comment];
+        let text = self.slice(c).trim_end();
+        self.push(text);
+        self.next_comment += 1;
+        self.last = c.next_offset();
+        if text.starts_with("//") { self.newline() }
+    }
+
+    /// Prints comments before offset.
+    fn flush_comments(&mut self, offset: usize) {
+        while self.next_comment < self.comments.len() &&
+              self.comments[self.next_comment].offset < offset {
+            let start = self.comments[self.next_comment].offset;
+            if self.same_line(start) && !self.at_line_start() {
+                self.push(" ");
+            } else {
+                self.line_break(start);
+            }
+            self.write_comment();
+        }
+    }
+
+    /// Starts an item on its own line.
+    fn begin_item(&mut self, start: usize) {
+        self.flush_comments(start);
+        self.line_break(start);
+    }
+
+    /// Ends an item on its own line.
+    ///
+    /// Comments inside the item that were not printed,
+    /// and comments after it on the same line, are put at the end.
+    fn end_item(&mut self, end: usize) {
+        if end > self.last { self.last = end }
+        while self.next_comment < self.comments.len() {
+            let start = self.comments[self.next_comment].offset;
+            if !(start < end || self.same_line(start)) { break }
+            if !self.at_line_start() { self.push(" ") }
+            self.write_comment();
+        }
+    }
+
+    /// Returns `true` if the source breaks lines between items.
+    fn multiline(&self, start: usize, end: usize, items: &[(usize, usize)]) -> bool {
+        if items.is_empty() { return false }
+        let mut prev = start;
+        for &(a, b) in items {
+            if prev < a && self.source[prev..a].contains('\n') { return true }
+            prev = b;
+        }
+        prev < end && self.source[prev..end].contains('\n')
+    }
+
+    /// Prints a list of items, one per line if the source breaks lines between them.
+    fn list<F>(
+        &mut self,
+        open: &str,
+        close: &str,
+        sep: &str,
+        start: usize,
+        end: usize,
+        items: &[(usize, usize)],
+        mut f: F
+    )
+        where F: FnMut(&mut Printer<'a>, usize)
+    {
+        self.push(open);
+        if self.multiline(start, end, items) {
+            self.tabs += 1;
+            // Items on the same line in the source are kept together.
+            for (i, &(a, b)) in items.iter().enumerate() {
+                if i > 0 && self.same_line(a) && !self.at_line_start() {
+                    self.push(" ");
+                } else {
+                    self.begin_item(a);
+                }
+                f(self, i);
+                if i + 1 < items.len() { self.push(sep.trim_end()) }
+                let next_same_line = items.get(i + 1).map(|&(c, _)| {
+                    !self.source[b..c].contains('\n') && !self.has_comments(Range::new(b, c - b))
+                }).unwrap_or(false);
+                if next_same_line { self.last = b } else { self.end_item(b) }
+            }
+            self.flush_comments(end);
+            self.tabs -= 1;
+            if !self.at_line_start() { self.newline() }
+        } else {
+            for i in 0..items.len() {
+                if i > 0 { self.push(sep) }
+                f(self, i);
+            }
+        }
+        self.push(close);
+    }
+
+    fn document(&mut self, doc: &Node) {
+        for child in &doc.children {
+            let node = match child.node() {
+                None => continue,
+                Some(x) => x
+            };
+            match &**node.name {
+                "ns" => {
+                    self.begin_item(node.start());
+                    self.push("ns ");
+                    self.path(node);
+                    self.end_item(node.end());
+                }
+                "uses" => {
+                    for u in node.nodes("use") {
+                        self.begin_item(u.start());
+                        self.use_(u);
+                        self.end_item(u.end());
+                    }
+                }
+                "fn" => {
+                    self.begin_item(node.start());
+                    self.function(node);
+                    self.end_item(node.end());
+                }
+                "record" => {
+                    self.begin_item(node.start());
+                    self.record(node);
+                    self.end_item(node.end());
+                }
+                "enum" => {
+                    self.begin_item(node.start());
+                    self.enum_(node);
+                    self.end_item(node.end());
+                }
+                _ => {}
+            }
+        }
+        self.flush_comments(self.source.len());
+        if !self.at_line_start() { self.newline() }
+    }
+
+    /// Prints names separated by `::`.
+    fn path(&mut self, node: &Node) {
+        let mut first = true;
+        for c in &node.children {
+            if let Child::Str(ref name, ref val, _) = *c {
+                if &**name != "name" { continue }
+                if !first { self.push("::") }
+                self.push(val);
+                first = false;
+            }
+        }
+    }
+
+    fn use_(&mut self, node: &Node) {
+        self.push("use ");
+        self.path(node);
+        let mut fns = 0;
+        for c in &node.children {
+            if let Child::Str(ref name, ref val, _) = *c {
+                match &***name {
+                    "use_fn" => {
+                        self.push(if fns == 0 { "::{" } else { ", " });
+                        self.push(val);
+                        fns += 1;
+                    }
+                    "use_fn_alias" => {
+                        self.push(" as ");
+                        self.push(val);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if fns > 0 { self.push("}") }
+        if let Some(alias) = node.str("alias") {
+            self.push(" as ");
+            self.push(alias);
+        }
+    }
+
+    fn function(&mut self, node: &Node) {
+        let block = node.get("block");
+        if node.bool("test") == Some(true) { self.push("test ") }
+        if block.is_some() { self.push("fn ") }
+        self.push(node.str("name").map(|x| &***x).unwrap_or(""));
+        let mut generics = 0;
+        for c in &node.children {
+            if let Child::Str(ref name, ref val, _) = *c {
+                if &**name != "generic" { continue }
+                self.push(if generics == 0 { "<" } else { ", " });
+                self.push(val);
+                generics += 1;
+            }
+        }
+        if generics > 0 { self.push(">") }
+        let args = node.nodes("arg");
+        let items: Vec<(usize, usize)> = args.iter().map(|a| (a.start(), a.end())).collect();
+        let end = items.last()
+            .and_then(|&(_, b)| self.source[b..].find(')').map(|i| b + i))
+            .unwrap_or(node.end());
+        self.list("(", ")", ", ", node.start(), end, &items, |p, i| p.arg(args[i]));
+        self.currents(node);
+        match block {
+            Some(block) => {
+                if node.bool("returns") == Some(true) {
+                    self.push(" ->");
+                    if let Some(ty) = node.get("ret_type") {
+                        self.push(" ");
+                        self.ty(ty);
+                    }
+                }
+                self.push(" ");
+                self.block(block);
+            }
+            None => {
+                if let Some(expr) = node.get("expr") {
+                    self.push(" = ");
+                    self.expr(expr);
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, node: &Node) {
+        self.push("type ");
+        self.push(node.str("name").map(|x| &***x).unwrap_or(""));
+        self.push(" ");
+        let fields = node.nodes("field");
+        let items: Vec<(usize, usize)> = fields.iter().map(|a| (a.start(), a.end())).collect();
+        self.list("{", "}", ", ", node.start(), node.end(), &items, |p, i| {
+            p.push(fields[i].str("name").map(|x| &***x).unwrap_or(""));
+            p.push(": ");
+            if let Some(ty) = fields[i].get("type") { p.ty(ty) }
+        });
+    }
+
+    fn enum_(&mut self, node: &Node) {
+        self.push("enum ");
+        self.push(node.str("name").map(|x| &***x).unwrap_or(""));
+        self.push(" ");
+        let variants = node.nodes("variant");
+        let items: Vec<(usize, usize)> = variants.iter().map(|a| (a.start(), a.end())).collect();
+        self.list("{", "}", ", ", node.start(), node.end(), &items, |p, i| {
+            p.push(variants[i].str("name").map(|x| &***x).unwrap_or(""));
+            let payload = variants[i].nodes("payload");
+            if payload.is_empty() { return }
+            p.push("(");
+            for (j, ty) in payload.iter().enumerate() {
+                if j > 0 { p.push(", ") }
+                if let Some(ty) = ty.get("type") { p.ty(ty) }
+            }
+            p.push(")");
+        });
+    }
+
+    fn arg(&mut self, node: &Node) {
+        if node.bool("mut") == Some(true) { self.push("mut ") }
+        self.push(node.str("name").map(|x| &***x).unwrap_or(""));
+        let lifetime = node.str("lifetime");
+        let ty = node.get("type");
+        if lifetime.is_some() || ty.is_some() { self.push(": ") }
+        if let Some(lifetime) = lifetime {
+            self.push("'");
+            self.push(lifetime);
+            if ty.is_some() { self.push(" ") }
+        }
+        if let Some(ty) = ty { self.ty(ty) }
+    }
+
+    fn currents(&mut self, node: &Node) {
+        let currents = node.nodes("current");
+        if currents.is_empty() { return }
+        self.push(" ~ ");
+        for (i, c) in currents.iter().enumerate() {
+            if i > 0 { self.push(", ") }
+            self.arg(c);
+        }
+    }
+
+    fn ty(&mut self, node: &Node) {
+        for c in &node.children {
+            match *c {
+                Child::Bool(ref name, true, _) => {
+                    let text = match &***name {
+                        "opt_any" => "opt",
+                        "res_any" => "res",
+                        "thr_any" => "thr",
+                        "in_any" => "in",
+                        "iter_any" => "iter",
+                        "map_any" => "map",
+                        "arr_any" => "[]",
+                        "obj_any" => "{}",
+                        "sec_bool" => "sec[bool]",
+                        "sec_f64" => "sec[f64]",
+                        x => x,
+                    };
+                    self.push(text);
+                }
+                Child::Str(ref name, ref val, _) if &**name == "ad_hoc" => {
+                    self.push(val);
+                }
+                Child::Node(ref n) => {
+                    match &**n.name {
+                        "arr" => {
+                            self.push("[");
+                            self.ty(n);
+                            self.push("]");
+                        }
+                        "opt" | "res" | "thr" | "in" | "iter" => {
+                            self.push(&n.name);
+                            self.push("[");
+                            self.ty(n);
+                            self.push("]");
+                        }
+                        "map_key" => {
+                            self.push("map[");
+                            self.ty(n);
+                            self.push(", ");
+                        }
+                        "map_value" => {
+                            self.ty(n);
+                            self.push("]");
+                        }
+                        "ad_hoc_ty" => {
+                            self.push(" ");
+                            self.ty(n);
+                        }
+                        "closure_type" => {
+                            self.push("\\(");
+                            for (i, arg) in n.nodes("cl_arg").iter().enumerate() {
+                                if i > 0 { self.push(", ") }
+                                self.ty(arg);
+                            }
+                            self.push(") -> ");
+                            if let Some(ret) = n.get("cl_ret") { self.ty(ret) }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn block(&mut self, node: &Node) {
+        let exprs: Vec<&Node> = node.children.iter().filter_map(|c| c.node()).collect();
+        let comments = self.has_comments(node.range);
+        if exprs.is_empty() && !comments {
+            self.push("{}");
+            return;
+        }
+        if exprs.len() == 1 && !comments && !self.slice(node.range).contains('\n') {
+            self.push("{ ");
+            self.expr(exprs[0]);
+            self.push(" }");
+            return;
+        }
+        self.push("{");
+        self.last = node.start() + 1;
+        self.tabs += 1;
+        for expr in exprs {
+            self.begin_item(expr.start());
+            self.expr(expr);
+            self.end_item(expr.end());
+        }
+        self.flush_comments(node.end());
+        self.tabs -= 1;
+        if !self.at_line_start() { self.newline() }
+        self.push("}");
+    }
+
+    fn expr(&mut self, node: &Node) {
+        self.expr_with(node, false)
+    }
+
+    /// Prints expression where the syntax expects a left expression.
+    fn lexpr(&mut self, node: &Node) {
+        self.expr_with(node, true)
+    }
+
+    fn expr_with(&mut self, node: &Node, lexpr: bool) {
+        for c in &node.children {
+            match *c {
+                Child::Bool(ref name, val, _) if &**name == "try" => {
+                    if val { self.push("?") }
+                }
+                Child::Bool(ref name, val, _) if &**name == "mut" => {
+                    if val { self.push("mut ") }
+                }
+                _ => {
+                    let parens = lexpr && needs_parens(c);
+                    if parens { self.push("(") }
+                    self.kind(c);
+                    if parens { self.push(")") }
+                }
+            }
+        }
+    }
+
+    fn label(&mut self, node: &Node) {
+        if let Some(label) = node.str("label") {
+            self.push("'");
+            self.push(label);
+            self.push(": ");
+        }
+    }
+
+    fn kind(&mut self, c: &Child) {
+        let node = match *c {
+            Child::Node(ref node) => node,
+            Child::Bool(ref name, val, _) => {
+                match &***name {
+                    "return_void" => self.push("return"),
+                    _ => self.push(if val { "true" } else { "false" }),
+                }
+                return;
+            }
+            Child::F64(_, range) => {
+                let text = self.slice(range);
+                self.push(text);
+                return;
+            }
+            Child::Str(ref name, ref val, range) => {
+                if &***name == "color" {
+                    self.push("#");
+                    self.push(val);
+                } else if &***name == "text" {
+                    let text = self.slice(range);
+                    self.push(text);
+                } else {
+                    self.push(val);
+                }
+                return;
+            }
+        };
+        match &**node.name {
+            "bitwise" | "add" | "mul" => {
+                for c in &node.children {
+                    match *c {
+                        Child::Bool(ref op, _, range) => self.op(op, range),
+                        Child::Node(ref n) => {
+                            match &**n.name {
+                                "val" => self.lexpr(n),
+                                "expr" => self.expr(n),
+                                _ => self.kind(c),
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            "unop" => {
+                let not = node.bool("!").is_some();
+                self.push(if not { "!" } else { "-" });
+                if let Some(expr) = node.get("expr") {
+                    if not { self.lexpr(expr) } else { self.expr(expr) }
+                }
+            }
+            "pow" => {
+                if let Some(base) = node.get("base") { self.lexpr(base) }
+                self.push(" ^ ");
+                if let Some(exp) = node.get("exp") { self.lexpr(exp) }
+            }
+            "item" => self.item(node),
+            "call" => {
+                self.alias(node);
+                self.push(node.str("name").map(|x| &***x).unwrap_or(""));
+                self.call_args(node);
+            }
+            "named_call" => {
+                self.alias(node);
+                self.named_call_args(node, 1);
+            }
+            "call_closure" => {
+                self.push("\\");
+                if let Some(item) = node.get("item") { self.item(item) }
+                self.call_args(node);
+            }
+            "named_call_closure" => {
+                self.push("\\");
+                if let Some(item) = node.get("item") { self.item(item) }
+                self.named_call_args(node, 0);
+            }
+            "swizzle" => {
+                for sw in &node.children {
+                    if let Child::Node(ref n) = *sw {
+                        if &**n.name == "expr" {
+                            self.push(" ");
+                            self.expr(n);
+                        } else {
+                            for b in &n.children {
+                                if let Child::Bool(ref name, true, _) = *b { self.push(name) }
+                            }
+                        }
+                    }
+                }
+            }
+            "go" => {
+                self.push("go ");
+                for c in &node.children { self.kind(c) }
+            }
+            "in" => {
+                self.push("in ");
+                self.alias(node);
+                self.push(node.str("name").map(|x| &***x).unwrap_or(""));
+            }
+            "closure" => {
+                let args = node.nodes("arg");
+                let items: Vec<(usize, usize)> = args.iter()
+                    .map(|a| (a.start(), a.end())).collect();
+                self.push("\\");
+                self.list("(", ")", ", ", node.start(), node.start(), &items,
+                          |p, i| p.arg(args[i]));
+                self.currents(node);
+                self.push(" = ");
+                if let Some(expr) = node.get("expr") { self.expr(expr) }
+            }
+            "object" => {
+                let kvs = node.nodes("key_value");
+                let items: Vec<(usize, usize)> = kvs.iter()
+                    .map(|a| (a.start(), a.end())).collect();
+                self.list("{", "}", ", ", node.start(), node.end(), &items, |p, i| {
+                    for c in &kvs[i].children {
+                        match *c {
+                            Child::Str(_, _, range) => {
+                                let key = p.slice(range);
+                                p.push(key);
+                                p.push(": ");
+                            }
+                            Child::Node(ref val) => p.expr(val),
+                            _ => {}
+                        }
+                    }
+                });
+            }
+            "array" => {
+                let items = node.nodes("array_item");
+                let ranges: Vec<(usize, usize)> = items.iter()
+                    .map(|a| (a.start(), a.end())).collect();
+                self.list("[", "]", ", ", node.start(), node.end(), &ranges,
+                          |p, i| p.expr(items[i]));
+            }
+            "array_fill" => {
+                self.push("[");
+                if let Some(fill) = node.get("fill") { self.expr(fill) }
+                self.push("; ");
+                if let Some(n) = node.get("n") { self.expr(n) }
+                self.push("]");
+            }
+            "return" => {
+                self.push("return ");
+                self.expr(node);
+            }
+            "break" | "continue" => {
+                self.push(&node.name);
+                if let Some(label) = node.str("label") {
+                    self.push(" '");
+                    self.push(label);
+                }
+            }
+            "for" => {
+                self.label(node);
+                self.push("for ");
+                if let Some(init) = node.get("init") { self.expr(init) }
+                self.push("; ");
+                if let Some(cond) = node.get("cond") { self.expr(cond) }
+                self.push("; ");
+                if let Some(step) = node.get("step") { self.expr(step) }
+                self.push(" ");
+                if let Some(block) = node.get("block") { self.block(block) }
+            }
+            "for_n" => {
+                self.label(node);
+                self.push("for ");
+                self.short_loop(node);
+            }
+            "sum" | "prod" | "sum_vec4" | "prod_vec4" |
+            "min" | "max" | "sift" | "any" | "all" => {
+                self.label(node);
+                self.loop_keyword(node);
+                self.push(" ");
+                self.short_loop(node);
+            }
+            "link_for" => {
+                self.label(node);
+                self.push("link ");
+                self.short_loop(node);
+            }
+            "loop" => {
+                self.label(node);
+                self.push("loop ");
+                if let Some(block) = node.get("block") { self.block(block) }
+            }
+            "vec4_un_loop" => {
+                for n in &["4", "3", "2"] {
+                    if node.bool(n) == Some(true) {
+                        self.push("vec");
+                        self.push(n);
+                    }
+                }
+                self.push(" ");
+                self.push(node.str("name").map(|x| &***x).unwrap_or(""));
+                self.push(" ");
+                if let Some(expr) = node.get("expr") { self.expr(expr) }
+            }
+            "if" => {
+                self.push("if ");
+                for c in &node.children {
+                    if let Child::Node(ref n) = *c {
+                        match &**n.name {
+                            "cond" => self.expr(n),
+                            "else_if_cond" => {
+                                self.push(" else if ");
+                                self.expr(n);
+                            }
+                            "else_block" => {
+                                self.push(" else ");
+                                self.block(n);
+                            }
+                            _ => {
+                                self.push(" ");
+                                self.block(n);
+                            }
+                        }
+                    }
+                }
+            }
+            "match" => {
+                self.push("match ");
+                let start = match node.get("expr") {
+                    Some(expr) => {
+                        self.expr(expr);
+                        expr.end()
+                    }
+                    None => node.start()
+                };
+                self.push(" ");
+                self.match_arms(node, start);
+            }
+            "block" => self.block(node),
+            "assign" | "compare" => {
+                for c in &node.children {
+                    match *c {
+                        Child::Bool(ref op, true, range) => self.op(op, range),
+                        Child::Node(ref n) if &**n.name == "left" => self.lexpr(n),
+                        Child::Node(ref n) => self.expr(n),
+                        _ => {}
+                    }
+                }
+            }
+            "link" => {
+                self.push("link ");
+                self.link_items(node);
+            }
+            "vec4" => {
+                let comps: Vec<&Node> = node.children.iter().filter_map(|c| c.node()).collect();
+                if comps.len() == 1 {
+                    self.push("(");
+                    self.expr(comps[0]);
+                    self.push(",)");
+                } else {
+                    let items: Vec<(usize, usize)> = comps.iter()
+                        .map(|a| (a.start(), a.end())).collect();
+                    self.list("(", ")", ", ", node.start(), node.end(), &items,
+                              |p, i| p.expr(comps[i]));
+                }
+            }
+            "grab" => {
+                self.push("grab ");
+                for c in &node.children {
+                    match *c {
+                        Child::F64(_, range) => {
+                            let level = self.slice(range);
+                            self.push("'");
+                            self.push(level);
+                            self.push(" ");
+                        }
+                        Child::Node(ref n) => self.expr(n),
+                        _ => {}
+                    }
+                }
+            }
+            "try_expr" => {
+                self.push("try ");
+                if let Some(expr) = node.get("expr") { self.expr(expr) }
+            }
+            "norm" => {
+                self.push("|");
+                if let Some(expr) = node.get("expr") { self.expr(expr) }
+                self.push("|");
+            }
+            _ => self.expr(node),
+        }
+    }
+
+    /// Prints binary operator as written, e.g. `*` or `⨯`.
+    fn op(&mut self, name: &str, range: Range) {
+        let text = if range.length > 0 { self.slice(range) } else { name };
+        self.push(" ");
+        self.push(text);
+        self.push(" ");
+    }
+
+    /// Prints the keyword of a mathematical loop as written, e.g. `sum` or `∑`.
+    fn loop_keyword(&mut self, node: &Node) {
+        let unicode = match &**node.name {
+            "sum" => "∑",
+            "prod" => "∏",
+            "sum_vec4" => "∑vec4",
+            "prod_vec4" => "∏vec4",
+            "any" => "∃",
+            "all" => "∀",
+            _ => "",
+        };
+        let start = node.children.iter()
+            .find(|c| c.name() != "label")
+            .map(|c| c.range().offset)
+            .unwrap_or(node.start());
+        let keyword = self.source[node.start()..start].trim_end();
+        if !unicode.is_empty() && keyword.ends_with(unicode) {
+            self.push(unicode);
+        } else {
+            self.push(&node.name);
+        }
+    }
+
+    fn alias(&mut self, node: &Node) {
+        if let Some(alias) = node.str("alias") {
+            self.push(alias);
+            self.push("::");
+        }
+    }
+
+    fn item(&mut self, node: &Node) {
+        for c in &node.children {
+            match *c {
+                Child::Bool(ref name, true, _) if &**name == "current" => self.push("~ "),
+                Child::Bool(ref name, true, _) if &**name == "try_item" => self.push("?"),
+                Child::Str(ref name, ref val, _) if &**name == "name" => self.push(val),
+                Child::Node(ref extra) if &**extra.name == "item_extra" => {
+                    for id in &extra.children {
+                        match *id {
+                            Child::Str(_, ref val, range) => {
+                                if self.slice(range).starts_with('"') {
+                                    let text = self.slice(range);
+                                    self.push("[");
+                                    self.push(text);
+                                    self.push("]");
+                                } else {
+                                    self.push(".");
+                                    self.push(val);
+                                }
+                            }
+                            Child::F64(_, range) => {
+                                let text = self.slice(range);
+                                self.push("[");
+                                self.push(text);
+                                self.push("]");
+                            }
+                            Child::Node(ref n) => {
+                                self.push("[");
+                                self.expr(n);
+                                self.push("]");
+                            }
+                            Child::Bool(_, true, _) => self.push("?"),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn call_args(&mut self, node: &Node) {
+        let args = node.nodes("call_arg");
+        let items: Vec<(usize, usize)> = args.iter().map(|a| (a.start(), a.end())).collect();
+        let start = items.first().map(|&(a, _)| a).unwrap_or(node.start());
+        let start = self.source[..start].rfind('(').unwrap_or(start);
+        self.list("(", ")", ", ", start, node.end(), &items, |p, i| p.expr(args[i]));
+    }
+
+    /// Prints a call with named arguments, e.g. `foo(bar: x)`.
+    ///
+    /// The `skip` first words are printed before the arguments.
+    fn named_call_args(&mut self, node: &Node, skip: usize) {
+        let mut words = vec![];
+        let mut args = vec![];
+        for c in &node.children {
+            match *c {
+                Child::Str(ref name, ref val, range) if &**name == "word" => {
+                    words.push((val.clone(), range))
+                }
+                Child::Node(ref n) if &**n.name == "call_arg" => args.push(n),
+                _ => {}
+            }
+        }
+        for &(ref word, _) in words.iter().take(skip) { self.push(word) }
+        let words = &words[skip..];
+        let items: Vec<(usize, usize)> = words.iter().zip(args.iter())
+            .map(|(&(_, range), arg)| (range.offset, arg.end())).collect();
+        let start = items.first().map(|&(a, _)| a).unwrap_or(node.start());
+        let start = self.source[..start].rfind('(').unwrap_or(start);
+        self.list("(", ")", ", ", start, node.end(), &items, |p, i| {
+            p.push(&words[i].0);
+            p.push(": ");
+            p.expr(args[i]);
+        });
+    }
+
+    /// Prints ranges and body of a loop, e.g. `i, j [1, n) { ... }`.
+    fn short_loop(&mut self, node: &Node) {
+        let mut first = true;
+        let mut has_start = false;
+        for c in &node.children {
+            match *c {
+                Child::Str(ref name, ref val, _) if &**name == "name" => {
+                    if !first { self.push(", ") }
+                    self.push(val);
+                    first = false;
+                }
+                Child::Node(ref n) => {
+                    match &**n.name {
+                        "start" => {
+                            self.push(" [");
+                            self.expr(n);
+                            self.push(", ");
+                            has_start = true;
+                        }
+                        "end" => {
+                            if has_start {
+                                has_start = false;
+                                self.expr(n);
+                                self.push(")");
+                            } else {
+                                self.push(" ");
+                                self.expr(n);
+                            }
+                        }
+                        "collection" => {
+                            self.push(" in ");
+                            self.expr(n);
+                        }
+                        "block" => {
+                            self.push(" ");
+                            if &**node.name == "link_for" {
+                                match n.get("expr").and_then(|e| e.get("link")) {
+                                    Some(link) => self.link_items(link),
+                                    None => self.block(n),
+                                }
+                            } else {
+                                self.block(n);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Prints the arms of a match expression, e.g. `{ some(x) => x, none() => 0 }`.
+    fn match_arms(&mut self, node: &Node, start: usize) {
+        let arms = node.nodes("match_arm");
+        let ranges: Vec<(usize, usize)> = arms.iter().map(|a| (a.start(), a.end())).collect();
+        if arms.is_empty() {
+            self.push("{}");
+        } else if self.multiline(start, node.end(), &ranges) {
+            self.list("{", "}", ", ", start, node.end(), &ranges, |p, i| p.match_arm(arms[i]));
+        } else {
+            self.push("{ ");
+            for (i, arm) in arms.iter().enumerate() {
+                if i > 0 { self.push(", ") }
+                self.match_arm(arm);
+            }
+            self.push(" }");
+        }
+    }
+
+    fn match_arm(&mut self, node: &Node) {
+        if let Some(pattern) = node.get("pattern") { self.pattern(pattern) }
+        if let Some(guard) = node.get("guard") {
+            self.push(" if ");
+            self.expr(guard);
+        }
+        self.push(" => ");
+        if let Some(expr) = node.get("expr") { self.expr(expr) }
+    }
+
+    fn pattern(&mut self, node: &Node) {
+        for c in &node.children {
+            match *c {
+                Child::Node(ref n) => {
+                    match &**n.name {
+                        "some_pattern" | "ok_pattern" | "err_pattern" => {
+                            let name = n.name.trim_end_matches("_pattern");
+                            self.push(name);
+                            self.push("(");
+                            self.pattern(n);
+                            self.push(")");
+                        }
+                        "none_pattern" => self.push("none()"),
+                        "variant_pattern" => {
+                            self.push(n.str("enum").map(|x| &***x).unwrap_or(""));
+                            self.push("::");
+                            self.push(n.str("variant").map(|x| &***x).unwrap_or(""));
+                            self.push("(");
+                            for (i, p) in n.nodes("pattern").iter().enumerate() {
+                                if i > 0 { self.push(", ") }
+                                self.pattern(p);
+                            }
+                            self.push(")");
+                        }
+                        "object_pattern" => {
+                            self.push("{");
+                            self.pattern_items(n);
+                            self.push("}");
+                        }
+                        "array_pattern" => {
+                            self.push("[");
+                            self.pattern_items(n);
+                            self.push("]");
+                        }
+                        _ => {}
+                    }
+                }
+                Child::Bool(ref name, _, _) if &**name == "_" => self.push("_"),
+                Child::Str(ref name, ref val, _) if &**name == "name" => self.push(val),
+                _ => self.kind(c),
+            }
+        }
+    }
+
+    /// Prints the items of an object or array pattern, e.g. `name: n, ..`.
+    fn pattern_items(&mut self, node: &Node) {
+        let mut first = true;
+        for c in &node.children {
+            match *c {
+                Child::Str(ref name, _, range) if &**name == "key" => {
+                    if !first { self.push(", ") }
+                    let key = self.slice(range);
+                    self.push(key);
+                    self.push(": ");
+                    first = false;
+                    continue;
+                }
+                Child::Node(ref n) => {
+                    // Patterns after keys are already separated.
+                    if &**node.name != "object_pattern" {
+                        if !first { self.push(", ") }
+                    }
+                    self.pattern(n);
+                }
+                Child::Str(_, ref val, _) => {
+                    if !first { self.push(", ") }
+                    self.push(val);
+                }
+                Child::Bool(_, _, _) => {
+                    if !first { self.push(", ") }
+                    self.push("..");
+                }
+                _ => {}
+            }
+            first = false;
+        }
+    }
+
+    /// Prints the items of a link separated by space, e.g. `{ a "b" c }`.
+    fn link_items(&mut self, node: &Node) {
+        let items = node.nodes("link_item");
+        let ranges: Vec<(usize, usize)> = items.iter().map(|a| (a.start(), a.end())).collect();
+        if items.is_empty() {
+            self.push("{}");
+        } else if self.multiline(node.start(), node.end(), &ranges) {
+            self.list("{", "}", " ", node.start(), node.end(), &ranges, |p, i| p.expr(items[i]));
+        } else {
+            self.push("{ ");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 { self.push(" ") }
+                self.expr(item);
+            }
+            self.push(" }");
+        }
+    }
+}
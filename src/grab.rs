@@ -6,6 +6,7 @@ are constants.
 use std::sync::Arc;
 use ast;
 use runtime::{Flow, Runtime, Side};
+use DyonError;
 use Module;
 use Variable;
 
@@ -24,7 +25,7 @@ pub fn grab_expr(
     expr: &ast::Expression,
     side: Side,
     module: &Arc<Module>,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), DyonError> {
     use ast::Expression as E;
 
     match expr {
@@ -35,8 +36,7 @@ pub fn grab_expr(
                     (Some(x), Flow::Continue) => x,
                     (x, Flow::Return) => { return Ok((Grabbed::Variable(x), Flow::Return)); }
                     _ => return Err(module.error(expr.source_range(),
-                                    &format!("{}\nExpected something",
-                                        rt.stack_trace()), rt))
+                                    "Expected something", rt))
                 };
                 Ok((Grabbed::Expression(E::Variable(expr.source_range(),
                     v.deep_clone(&rt.stack))), Flow::Continue))
@@ -175,6 +175,38 @@ pub fn grab_expr(
                 source_range: if_expr.source_range.clone(),
             }))), Flow::Continue))
         },
+        &E::Match(ref match_expr) => {
+            Ok((Grabbed::Expression(E::Match(Box::new(ast::Match {
+                expr: match grab_expr(level, rt, &match_expr.expr, side, module) {
+                    Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                    x => return x,
+                },
+                arms: {
+                    let mut new_arms = vec![];
+                    for arm in &match_expr.arms {
+                        new_arms.push(ast::MatchArm {
+                            pattern: arm.pattern.clone(),
+                            guard: match arm.guard {
+                                None => None,
+                                Some(ref guard) => {
+                                    match grab_expr(level, rt, guard, side, module) {
+                                        Ok((Grabbed::Expression(x), Flow::Continue)) => Some(x),
+                                        x => return x,
+                                    }
+                                }
+                            },
+                            expr: match grab_expr(level, rt, &arm.expr, side, module) {
+                                Ok((Grabbed::Expression(x), Flow::Continue)) => x,
+                                x => return x,
+                            },
+                            source_range: arm.source_range.clone(),
+                        });
+                    }
+                    new_arms
+                },
+                source_range: match_expr.source_range.clone(),
+            }))), Flow::Continue))
+        },
         &E::Go(ref go) => {
             let call = &go.call;
             Ok((Grabbed::Expression(E::Go(Box::new(ast::Go {
@@ -451,7 +483,7 @@ fn grab_block(
     block: &ast::Block,
     side: Side,
     module: &Arc<Module>,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), DyonError> {
     Ok((Grabbed::Block(ast::Block {
         expressions: {
             let mut new_expressions = vec![];
@@ -473,7 +505,7 @@ fn grab_item(
     item: &ast::Item,
     side: Side,
     module: &Arc<Module>,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), DyonError> {
     Ok((Grabbed::Item(ast::Item {
         name: item.name.clone(),
         stack_id: item.stack_id.clone(),
@@ -507,7 +539,7 @@ fn grab_for_n(
     for_n: &ast::ForN,
     side: Side,
     module: &Arc<Module>,
-) -> Result<(Grabbed, Flow), String> {
+) -> Result<(Grabbed, Flow), DyonError> {
     Ok((Grabbed::ForN(ast::ForN {
         name: for_n.name.clone(),
         start: match for_n.start {
@@ -528,6 +560,7 @@ fn grab_for_n(
             x => return x,
         },
         label: for_n.label.clone(),
-        source_range: for_n.source_range.clone()
+        source_range: for_n.source_range.clone(),
+        for_in: for_n.for_in.clone(),
     }), Flow::Continue))
 }
@@ -0,0 +1,234 @@
+//! Helper functions for encoding and decoding bytes.
+
+/// Describes how a number is stored in bytes, e.g. `"u16le"` or `"f64be"`.
+#[derive(Clone, Copy)]
+pub struct Format {
+    /// Number of bytes.
+    pub size: usize,
+    /// Whether the number is signed.
+    pub signed: bool,
+    /// Whether the number is a float.
+    pub float: bool,
+    /// Whether the most significant byte comes first.
+    pub big_endian: bool,
+}
+
+impl Format {
+    /// Parses format from a string.
+    ///
+    /// Integers are `u8`, `i8`, and `u16`, `i16`, `u32`, `i32`, `u64`, `i64`
+    /// followed by `le` (little-endian) or `be` (big-endian).
+    /// Floats are `f32` and `f64` followed by `le` or `be`.
+    pub fn from_str(format: &str) -> Result<Format, String> {
+        let (ty, big_endian) = if format.ends_with("le") {
+            (&format[..format.len() - 2], false)
+        } else if format.ends_with("be") {
+            (&format[..format.len() - 2], true)
+        } else {
+            (format, false)
+        };
+        let (size, signed, float) = match ty {
+            "u8" => (1, false, false),
+            "i8" => (1, true, false),
+            "u16" => (2, false, false),
+            "i16" => (2, true, false),
+            "u32" => (4, false, false),
+            "i32" => (4, true, false),
+            "u64" => (8, false, false),
+            "i64" => (8, true, false),
+            "f32" => (4, true, true),
+            "f64" => (8, true, true),
+            _ => return Err(format!("Unknown format `{}`", format))
+        };
+        if size > 1 && ty.len() == format.len() {
+            return Err(format!("Expected `{}le` or `{}be`, found `{}`", ty, ty, format));
+        }
+        if size == 1 && ty.len() != format.len() {
+            return Err(format!("Expected `{}`, found `{}`", ty, format));
+        }
+        Ok(Format {
+            size: size,
+            signed: signed,
+            float: float,
+            big_endian: big_endian,
+        })
+    }
+
+    /// Reads bits at position.
+    fn read_bits(self, bytes: &[u8], pos: usize) -> Result<u64, String> {
+        if pos + self.size > bytes.len() {
+            return Err(format!("Out of bounds, can not read {} bytes at `{}` from {} bytes",
+                               self.size, pos, bytes.len()));
+        }
+        let mut bits: u64 = 0;
+        for i in 0..self.size {
+            let b = if self.big_endian {
+                bytes[pos + i]
+            } else {
+                bytes[pos + self.size - 1 - i]
+            };
+            bits = (bits << 8) | b as u64;
+        }
+        Ok(bits)
+    }
+
+    /// Writes bits at position, extending the bytes at the end.
+    fn write_bits(self, bytes: &mut Vec<u8>, pos: usize, bits: u64) -> Result<(), String> {
+        if pos > bytes.len() {
+            return Err(format!("Out of bounds, can not write at `{}` to {} bytes",
+                               pos, bytes.len()));
+        }
+        if pos + self.size > bytes.len() {
+            bytes.resize(pos + self.size, 0);
+        }
+        for i in 0..self.size {
+            let b = (bits >> (8 * i)) as u8;
+            if self.big_endian {
+                bytes[pos + self.size - 1 - i] = b;
+            } else {
+                bytes[pos + i] = b;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads an integer.
+    ///
+    /// `u64` is read as the `i64` with the same bits.
+    pub fn read_int(self, bytes: &[u8], pos: usize) -> Result<i64, String> {
+        if self.float {
+            return Err("Expected integer format".into());
+        }
+        let bits = try!(self.read_bits(bytes, pos));
+        Ok(if self.signed && self.size < 8 {
+            // Extend sign.
+            let shift = 64 - 8 * self.size;
+            ((bits << shift) as i64) >> shift
+        } else {
+            bits as i64
+        })
+    }
+
+    /// Writes an integer, checking that it fits.
+    ///
+    /// `u64` is written from the `i64` with the same bits.
+    pub fn write_int(self, bytes: &mut Vec<u8>, pos: usize, val: i64) -> Result<(), String> {
+        if self.float {
+            return Err("Expected integer format".into());
+        }
+        if self.size < 8 {
+            let bits = 8 * self.size as u32;
+            let (min, max) = if self.signed {
+                (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+            } else {
+                (0, (1 << bits) - 1)
+            };
+            if val < min || val > max {
+                return Err(format!("`{}` does not fit in {} bytes", val, self.size));
+            }
+        }
+        self.write_bits(bytes, pos, val as u64)
+    }
+
+    /// Reads a float.
+    pub fn read_float(self, bytes: &[u8], pos: usize) -> Result<f64, String> {
+        if !self.float {
+            return Err("Expected float format".into());
+        }
+        let bits = try!(self.read_bits(bytes, pos));
+        Ok(if self.size == 4 {
+            f32::from_bits(bits as u32) as f64
+        } else {
+            f64::from_bits(bits)
+        })
+    }
+
+    /// Writes a float.
+    pub fn write_float(self, bytes: &mut Vec<u8>, pos: usize, val: f64) -> Result<(), String> {
+        if !self.float {
+            return Err("Expected float format".into());
+        }
+        let bits = if self.size == 4 {
+            (val as f32).to_bits() as u64
+        } else {
+            val.to_bits()
+        };
+        self.write_bits(bytes, pos, bits)
+    }
+}
+
+/// Converts bytes to lower case hexadecimal.
+pub fn to_hex(bytes: &[u8]) -> String {
+    const DIGITS: &'static [u8] = b"0123456789abcdef";
+
+    let mut res = String::with_capacity(bytes.len() * 2);
+    for &b in bytes {
+        res.push(DIGITS[(b >> 4) as usize] as char);
+        res.push(DIGITS[(b & 0xf) as usize] as char);
+    }
+    res
+}
+
+/// Converts hexadecimal to bytes.
+pub fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    fn digit(c: u8) -> Option<u8> {
+        (c as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let text = text.as_bytes();
+    if text.len() % 2 != 0 {
+        return Err("Expected even number of hexadecimal digits".into());
+    }
+    let mut res = Vec::with_capacity(text.len() / 2);
+    for (i, pair) in text.chunks(2).enumerate() {
+        match (digit(pair[0]), digit(pair[1])) {
+            (Some(a), Some(b)) => res.push((a << 4) | b),
+            _ => return Err(format!("Expected hexadecimal digits at `{}`", 2 * i))
+        }
+    }
+    Ok(res)
+}
+
+const BASE64: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Converts bytes to base64 with padding.
+pub fn to_base64(bytes: &[u8]) -> String {
+    let mut res = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let mut bits: u32 = 0;
+        for i in 0..3 {
+            bits = (bits << 8) | *chunk.get(i).unwrap_or(&0) as u32;
+        }
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(BASE64[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+/// Converts base64 to bytes, with or without padding.
+pub fn from_base64(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim_end_matches('=').as_bytes();
+    if text.len() % 4 == 1 {
+        return Err("Invalid length of base64".into());
+    }
+    let mut res = Vec::with_capacity(text.len() * 3 / 4);
+    for (i, chunk) in text.chunks(4).enumerate() {
+        let mut bits: u32 = 0;
+        for (j, &c) in chunk.iter().enumerate() {
+            let val = match BASE64.iter().position(|&b| b == c) {
+                None => return Err(format!("Expected base64 character at `{}`", 4 * i + j)),
+                Some(x) => x as u32
+            };
+            bits |= val << (18 - 6 * j);
+        }
+        for j in 0..chunk.len() - 1 {
+            res.push((bits >> (16 - 8 * j)) as u8);
+        }
+    }
+    Ok(res)
+}
@@ -1,6 +1,6 @@
 use std::fs::File;
 use std::io::Read;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use range::Range;
@@ -143,8 +143,6 @@ fn object(
     strings: &mut Strings,
     data: &str
 ) -> Result<Variable, String> {
-    use std::collections::HashMap;
-
     let mut res: HashMap<Arc<String>, Variable> = HashMap::new();
     let mut was_comma = false;
     loop {
@@ -206,9 +204,67 @@ fn object(
 
         was_comma = comma(read);
     }
+    if let Some(v) = variant(&res) {
+        return Ok(v);
+    }
+    if let Some(v) = map(&res) {
+        return Ok(v);
+    }
     Ok(Variable::Object(Arc::new(res)))
 }
 
+/// Reads an enum variant from its tagged encoding.
+///
+/// An object with exactly the keys `"$enum"`, `"$variant"` and `"$payload"`,
+/// e.g. `{"$enum": "Shape", "$variant": "Rect", "$payload": [2, 3]}`,
+/// is read as a variant.
+/// Keys starting with `$` must be quoted, so objects written as `{enum: ...}` are kept.
+fn variant(obj: &HashMap<Arc<String>, Variable>) -> Option<Variable> {
+    use Variant;
+
+    if obj.len() != 3 { return None; }
+    match (obj.get(&Arc::new("$enum".into())), obj.get(&Arc::new("$variant".into())),
+           obj.get(&Arc::new("$payload".into()))) {
+        (Some(&Variable::Text(ref ty)), Some(&Variable::Text(ref name)),
+         Some(&Variable::Array(ref payload))) => {
+            Some(Variable::Variant(Box::new(Variant {
+                ty: ty.clone(),
+                name: name.clone(),
+                payload: (**payload).clone(),
+            })))
+        }
+        _ => None
+    }
+}
+
+/// Reads a map from its tagged encoding.
+///
+/// An object with only the key `"$map"` containing pairs of keys and values,
+/// e.g. `{"$map": [[1, "one"], [2, "two"]]}`, is read as a map.
+/// Keys starting with `$` must be quoted, so objects written as `{map: ...}` are kept.
+fn map(obj: &HashMap<Arc<String>, Variable>) -> Option<Variable> {
+    use MapKey;
+
+    if obj.len() != 1 { return None; }
+    let entries = match obj.get(&Arc::new("$map".into())) {
+        Some(&Variable::Array(ref entries)) => entries,
+        _ => return None
+    };
+    let mut res = HashMap::new();
+    for entry in entries.iter() {
+        match *entry {
+            Variable::Array(ref pair) if pair.len() == 2 => {
+                match MapKey::from_variable(&pair[0]) {
+                    Ok(key) => { res.insert(key, pair[1].clone()); }
+                    Err(_) => return None
+                }
+            }
+            _ => return None
+        }
+    }
+    Some(Variable::Map(Arc::new(res)))
+}
+
 fn array(
     read: &mut ReadToken,
     strings: &mut Strings,
@@ -0,0 +1,101 @@
+//! Structural differences between values, used by assertions.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use write::{write_variable, EscapeString};
+use MapKey;
+use Runtime;
+use Variable;
+
+/// The maximum number of differences to list.
+const MAX_DIFFERENCES: usize = 10;
+
+/// Writes value as Dyon source.
+pub fn show(rt: &Runtime, v: &Variable) -> String {
+    let mut w: Vec<u8> = vec![];
+    write_variable(&mut w, rt, rt.resolve(v), EscapeString::Json, 0).unwrap();
+    String::from_utf8(w).unwrap()
+}
+
+/// Returns the left and right value,
+/// followed by the paths where they differ.
+pub fn diff(rt: &Runtime, left: &Variable, right: &Variable) -> String {
+    let mut differences = vec![];
+    differences_at(rt, String::new(), rt.resolve(left), rt.resolve(right), &mut differences);
+    let mut s = format!("left:  {}\nright: {}", show(rt, left), show(rt, right));
+    // Differences are only listed inside arrays, objects, maps, links, options and results.
+    if differences.iter().any(|d| d.0 != "") {
+        for &(ref path, ref msg) in differences.iter().take(MAX_DIFFERENCES) {
+            s.push_str(&format!("\nat {}: {}", path, msg));
+        }
+        if differences.len() > MAX_DIFFERENCES {
+            s.push_str(&format!("\n... and {} more", differences.len() - MAX_DIFFERENCES));
+        }
+    }
+    s
+}
+
+fn differences_at(
+    rt: &Runtime,
+    path: String,
+    left: &Variable,
+    right: &Variable,
+    res: &mut Vec<(String, String)>
+) {
+    if left == right { return }
+    let missing = |res: &mut Vec<(String, String)>, path: String, left: Option<&Variable>,
+                   right: Option<&Variable>| {
+        res.push((path, match (left, right) {
+            (Some(v), _) => format!("{} only in left", show(rt, v)),
+            (_, Some(v)) => format!("{} only in right", show(rt, v)),
+            _ => unreachable!()
+        }));
+    };
+    let items = |res: &mut Vec<(String, String)>, a: &[Variable], b: &[Variable]| {
+        for i in 0..a.len().max(b.len()) {
+            let item_path = format!("{}[{}]", path, i);
+            match (a.get(i), b.get(i)) {
+                (Some(a), Some(b)) => differences_at(rt, item_path, a, b, res),
+                (a, b) => missing(res, item_path, a, b),
+            }
+        }
+    };
+    match (left, right) {
+        (&Variable::Array(ref a), &Variable::Array(ref b)) => items(res, a, b),
+        (&Variable::Link(ref a), &Variable::Link(ref b)) => items(res, &a.items(), &b.items()),
+        (&Variable::Object(ref a), &Variable::Object(ref b)) => {
+            let keys: BTreeSet<&Arc<String>> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let key_path = if is_ident(key) { format!("{}.{}", path, key) }
+                    else { format!("{}[{:?}]", path, key) };
+                match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => differences_at(rt, key_path, a, b, res),
+                    (a, b) => missing(res, key_path, a, b),
+                }
+            }
+        }
+        (&Variable::Map(ref a), &Variable::Map(ref b)) => {
+            let keys: BTreeSet<&MapKey> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let key_path = format!("{}[{}]", path, key);
+                match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => differences_at(rt, key_path, a, b, res),
+                    (a, b) => missing(res, key_path, a, b),
+                }
+            }
+        }
+        (&Variable::Option(Some(ref a)), &Variable::Option(Some(ref b))) =>
+            differences_at(rt, format!("{}.some", path), a, b, res),
+        (&Variable::Result(Ok(ref a)), &Variable::Result(Ok(ref b))) =>
+            differences_at(rt, format!("{}.ok", path), a, b, res),
+        (&Variable::Result(Err(ref a)), &Variable::Result(Err(ref b))) =>
+            differences_at(rt, format!("{}.err", path), &a.message, &b.message, res),
+        _ => res.push((path, format!("{} != {}", show(rt, left), show(rt, right)))),
+    }
+}
+
+fn is_ident(key: &str) -> bool {
+    key.chars().next().map(|c| c.is_alphabetic() || c == '_').unwrap_or(false) &&
+    key.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
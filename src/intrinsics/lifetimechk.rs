@@ -59,8 +59,10 @@ fn min_ref(v: &Variable, min: &mut Option<usize>) {
         &Variable::Return => {}
         &Variable::Bool(_, _) => {}
         &Variable::F64(_, _) => {}
+        &Variable::I64(_) => {}
         &Variable::Vec4(_) => {}
         &Variable::Text(_) => {}
+        &Variable::Bytes(_) => {}
         &Variable::Link(_) => {}
         &Variable::UnsafeRef(_) => {}
         &Variable::RustObject(_) => {}
@@ -77,7 +79,14 @@ fn min_ref(v: &Variable, min: &mut Option<usize>) {
                 min_ref(v, min);
             }
         }
+        &Variable::Map(ref map) => {
+            for v in map.values() {
+                min_ref(v, min);
+            }
+        }
         &Variable::Closure(_, _) => {}
         &Variable::In(_) => {}
+        &Variable::Variant(_) => {}
+        &Variable::Iter(_) => {}
     }
 }
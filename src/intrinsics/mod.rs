@@ -1,14 +1,22 @@
 #![allow(non_snake_case)]
 
 use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::cmp::Ordering;
 use rand::Rng;
+use regex::Regex;
+use range::Range;
 
-use runtime::{Flow, Runtime, Side};
+use runtime::{limits, Flow, Runtime, Side};
 use ast;
 use prelude::{Lt, Prelude, Dfn};
 
+use DyonError;
 use FnIndex;
 use Error;
+use Iter;
+use MapKey;
 use Module;
 use Variable;
 use Type;
@@ -18,7 +26,10 @@ mod io;
 mod meta;
 mod data;
 mod lifetimechk;
-mod functions;
+mod diff;
+mod bytes;
+mod text;
+pub mod functions;
 
 #[cfg(not(feature = "http"))]
 const HTTP_SUPPORT_DISABLED: &'static str = "Http support is disabled";
@@ -126,12 +137,88 @@ const NEXT: usize = 96;
 const WAIT_NEXT: usize = 97;
 const LOAD_DATA__STRING: usize = 98;
 const ARGS_OS: usize = 99;
+const ASSERT: usize = 100;
+const ASSERT_EQ: usize = 101;
+const ASSERT_NE: usize = 102;
+const I64: usize = 103;
+const F64: usize = 104;
+const PARSE_I64: usize = 105;
+const BYTES: usize = 106;
+const BYTES__STRING: usize = 107;
+const STR__BYTES: usize = 108;
+const BYTES__HEX: usize = 109;
+const HEX: usize = 110;
+const BYTES__BASE64: usize = 111;
+const BASE64: usize = 112;
+const SLICE__BYTES_START_END: usize = 113;
+const LOAD_BYTES__FILE: usize = 114;
+const SAVE__BYTES_FILE: usize = 115;
+const READ_INT: usize = 116;
+const READ_FLOAT: usize = 117;
+const WRITE_INT: usize = 118;
+const WRITE_FLOAT: usize = 119;
+const NEW_MAP: usize = 120;
+const ITER: usize = 121;
+const MAP: usize = 122;
+const FILTER: usize = 123;
+const FOLD: usize = 124;
+const TAKE: usize = 125;
+const SKIP: usize = 126;
+const ZIP: usize = 127;
+const ENUMERATE: usize = 128;
+const COLLECT: usize = 129;
+const FLAT_MAP: usize = 130;
+const SPLIT: usize = 131;
+const JOIN: usize = 132;
+const STARTS_WITH: usize = 133;
+const ENDS_WITH: usize = 134;
+const CONTAINS: usize = 135;
+const FIND: usize = 136;
+const REPLACE: usize = 137;
+const SUBSTR: usize = 138;
+const UPPER: usize = 139;
+const LOWER: usize = 140;
+const PAD: usize = 141;
+const REPEAT: usize = 142;
+const LINES: usize = 143;
+const FORMAT: usize = 144;
+const MATCH: usize = 145;
+const CAPTURES: usize = 146;
+const REPLACE_ALL: usize = 147;
+const SORT: usize = 148;
+const SORT_BY: usize = 149;
+const BINARY_SEARCH: usize = 150;
+const DEDUP: usize = 151;
+const SLICE: usize = 152;
+const CONCAT: usize = 153;
+const EXTEND: usize = 154;
+const INDEX_OF: usize = 155;
+const TRUNCATE: usize = 156;
+const CHUNKS: usize = 157;
+const SINH: usize = 158;
+const COSH: usize = 159;
+const TANH: usize = 160;
+const CLAMP: usize = 161;
+const LERP: usize = 162;
+const SMOOTHSTEP: usize = 163;
+const SIGN: usize = 164;
+const FRACT: usize = 165;
+const MOD: usize = 166;
+const HYPOT: usize = 167;
+const CBRT: usize = 168;
+const DEG: usize = 169;
+const RAD: usize = 170;
+const PI: usize = 171;
+const TAU: usize = 172;
+const E: usize = 173;
+const RANDOM_RANGE: usize = 174;
+const SEED: usize = 175;
 
 const TABLE: &'static [(usize, fn(
         &mut Runtime,
         &ast::Call,
         &Arc<Module>,
-    ) -> Result<Option<Variable>, String>)]
+    ) -> Result<Option<Variable>, DyonError>)]
 = &[
     (X, x),
     (Y, y),
@@ -233,6 +320,82 @@ const TABLE: &'static [(usize, fn(
     (WAIT_NEXT, wait_next),
     (LOAD_DATA__STRING, load_data__string),
     (ARGS_OS, args_os),
+    (ASSERT, assert),
+    (ASSERT_EQ, assert_eq),
+    (ASSERT_NE, assert_ne),
+    (I64, i64),
+    (F64, f64),
+    (PARSE_I64, parse_i64),
+    (BYTES, bytes),
+    (BYTES__STRING, bytes__string),
+    (STR__BYTES, str__bytes),
+    (BYTES__HEX, bytes__hex),
+    (HEX, hex),
+    (BYTES__BASE64, bytes__base64),
+    (BASE64, base64),
+    (SLICE__BYTES_START_END, slice__bytes_start_end),
+    (LOAD_BYTES__FILE, load_bytes__file),
+    (SAVE__BYTES_FILE, save__bytes_file),
+    (READ_INT, read_int),
+    (READ_FLOAT, read_float),
+    (WRITE_INT, write_int),
+    (WRITE_FLOAT, write_float),
+    (NEW_MAP, new_map),
+    (ITER, iter),
+    (MAP, map),
+    (FILTER, filter),
+    (FOLD, fold),
+    (TAKE, take),
+    (SKIP, skip),
+    (ZIP, zip),
+    (ENUMERATE, enumerate),
+    (COLLECT, collect),
+    (FLAT_MAP, flat_map),
+    (SPLIT, split),
+    (JOIN, join),
+    (STARTS_WITH, starts_with),
+    (ENDS_WITH, ends_with),
+    (CONTAINS, contains),
+    (FIND, find),
+    (REPLACE, replace),
+    (SUBSTR, substr),
+    (UPPER, upper),
+    (LOWER, lower),
+    (PAD, pad),
+    (REPEAT, repeat),
+    (LINES, lines),
+    (FORMAT, format),
+    (MATCH, _match),
+    (CAPTURES, captures),
+    (REPLACE_ALL, replace_all),
+    (SORT, sort),
+    (SORT_BY, sort_by),
+    (BINARY_SEARCH, binary_search),
+    (DEDUP, dedup),
+    (SLICE, slice),
+    (CONCAT, concat),
+    (EXTEND, extend),
+    (INDEX_OF, index_of),
+    (TRUNCATE, truncate),
+    (CHUNKS, chunks),
+    (SINH, sinh),
+    (COSH, cosh),
+    (TANH, tanh),
+    (CLAMP, clamp),
+    (LERP, lerp),
+    (SMOOTHSTEP, smoothstep),
+    (SIGN, sign),
+    (FRACT, fract),
+    (MOD, _mod),
+    (HYPOT, hypot),
+    (CBRT, cbrt),
+    (DEG, deg),
+    (RAD, rad),
+    (PI, pi),
+    (TAU, tau),
+    (E, e),
+    (RANDOM_RANGE, random_range),
+    (SEED, seed),
 ];
 
 pub fn standard(f: &mut Prelude) {
@@ -243,6 +406,32 @@ pub fn standard(f: &mut Prelude) {
             ret: ret
         });
     };
+    let t = || Type::Generic(Arc::new("T".into()));
+    let u = || Type::Generic(Arc::new("U".into()));
+    let iter = |ty: Type| Type::Iter(Box::new(ty));
+    let map_ty = |key: Type, value: Type| Type::Map(Box::new(key), Box::new(value));
+    // Math functions apply component-wise to vec4,
+    // so they return vec4 when some argument is vec4.
+    let math = |f: &mut Prelude, name: &str, index: usize, n: usize| {
+        f.intrinsic(Arc::new(name.into()), index, Dfn {
+            lts: vec![Lt::Default; n],
+            tys: vec![Type::F64; n],
+            ret: Type::F64
+        });
+        for vec4s in 1..(1 << n) {
+            f.overload(index, Dfn {
+                lts: vec![Lt::Default; n],
+                tys: (0..n).map(|i| if vec4s & (1 << i) != 0 { Type::Vec4 } else { Type::F64 })
+                    .collect(),
+                ret: Type::Vec4
+            });
+        }
+    };
+    let closure = |tys: Vec<Type>, ret: Type| Type::Closure(Box::new(Dfn {
+        lts: vec![Lt::Default; tys.len()],
+        tys: tys,
+        ret: ret
+    }));
 
     sarg(f, "x", X, Type::Vec4, Type::F64);
     sarg(f, "y", Y, Type::Vec4, Type::F64);
@@ -270,7 +459,7 @@ pub fn standard(f: &mut Prelude) {
     });
     sarg(f, "println", PRINTLN, Type::Any, Type::Void);
     sarg(f, "print", PRINT, Type::Any, Type::Void);
-    sarg(f, "clone", CLONE, Type::Any, Type::Any);
+    sarg(f, "clone", CLONE, t(), t());
     f.intrinsic(Arc::new("debug".into()), DEBUG, Dfn {
         lts: vec![],
         tys: vec![],
@@ -296,7 +485,7 @@ pub fn standard(f: &mut Prelude) {
         tys: vec![],
         ret: Type::Text
     });
-    sarg(f, "len", LEN, Type::array(), Type::F64);
+    sarg(f, "len", LEN, Type::Any, Type::F64);
     f.intrinsic(Arc::new("push_ref(mut,_)".into()), PUSH_REF, Dfn {
         lts: vec![Lt::Default, Lt::Arg(0)],
         tys: vec![Type::array(), Type::Any],
@@ -369,10 +558,10 @@ pub fn standard(f: &mut Prelude) {
         tys: vec![],
         ret: Type::option()
     });
-    sarg(f, "some", SOME, Type::Any, Type::option());
+    sarg(f, "some", SOME, t(), Type::Option(Box::new(t())));
     sarg(f, "unwrap", UNWRAP, Type::Any, Type::Any);
     sarg(f, "unwrap_err", UNWRAP_ERR, Type::Any, Type::Any);
-    sarg(f, "ok", OK, Type::Any, Type::result());
+    sarg(f, "ok", OK, t(), Type::Result(Box::new(t())));
     sarg(f, "err", ERR, Type::Any, Type::result());
     sarg(f, "is_err", IS_ERR, Type::result(), Type::Bool);
     sarg(f, "is_ok", IS_OK, Type::result(), Type::Bool);
@@ -417,6 +606,11 @@ pub fn standard(f: &mut Prelude) {
         tys: vec![Type::Object, Type::Text],
         ret: Type::Bool
     });
+    f.overload(HAS, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![map_ty(t(), u()), t()],
+        ret: Type::Bool
+    });
     sarg(f, "chars", CHARS, Type::Text, Type::Array(Box::new(Type::Text)));
     f.intrinsic(Arc::new("now".into()), NOW, Dfn {
         lts: vec![],
@@ -439,6 +633,11 @@ pub fn standard(f: &mut Prelude) {
     sarg(f, "load_data__file", LOAD_DATA__FILE, Type::Text, Type::Result(Box::new(Type::Any)));
     sarg(f, "functions__module", FUNCTIONS__MODULE, Type::Any, Type::Any);
     sarg(f, "keys", KEYS, Type::Object, Type::Array(Box::new(Type::Text)));
+    f.overload(KEYS, Dfn {
+        lts: vec![Lt::Default],
+        tys: vec![map_ty(t(), u())],
+        ret: Type::Array(Box::new(t()))
+    });
     f.intrinsic(Arc::new("errstr__string_start_len_msg".into()),
         ERRSTR__STRING_START_LEN_MSG, Dfn {
             lts: vec![Lt::Default; 4],
@@ -469,6 +668,11 @@ pub fn standard(f: &mut Prelude) {
         tys: vec![Type::array(), Type::F64, Type::Any],
         ret: Type::Void
     });
+    f.overload(INSERT, Dfn {
+        lts: vec![Lt::Default; 3],
+        tys: vec![map_ty(t(), u()), t(), u()],
+        ret: Type::Void
+    });
     f.intrinsic(Arc::new("insert_ref(mut,_,_)".into()), INSERT_REF, Dfn {
         lts: vec![Lt::Default, Lt::Default, Lt::Arg(0)],
         tys: vec![Type::array(), Type::F64, Type::Any],
@@ -479,6 +683,11 @@ pub fn standard(f: &mut Prelude) {
         tys: vec![Type::array(), Type::F64],
         ret: Type::Any
     });
+    f.overload(REMOVE, Dfn {
+        lts: vec![Lt::Return, Lt::Default],
+        tys: vec![map_ty(t(), u()), t()],
+        ret: u()
+    });
     f.intrinsic(Arc::new("next".into()), NEXT, Dfn {
         lts: vec![Lt::Default],
         tys: vec![Type::in_ty()],
@@ -495,6 +704,255 @@ pub fn standard(f: &mut Prelude) {
         tys: vec![],
         ret: Type::Array(Box::new(Type::Text))
     });
+    sarg(f, "assert", ASSERT, Type::Bool, Type::Void);
+    f.intrinsic(Arc::new("assert_eq".into()), ASSERT_EQ, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Any; 2],
+        ret: Type::Void
+    });
+    f.intrinsic(Arc::new("assert_ne".into()), ASSERT_NE, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Any; 2],
+        ret: Type::Void
+    });
+    sarg(f, "i64", I64, Type::F64, Type::I64);
+    sarg(f, "f64", F64, Type::I64, Type::F64);
+    sarg(f, "parse_i64", PARSE_I64, Type::Text, Type::Option(Box::new(Type::I64)));
+    sarg(f, "bytes", BYTES, Type::Array(Box::new(Type::F64)), Type::Bytes);
+    sarg(f, "bytes__string", BYTES__STRING, Type::Text, Type::Bytes);
+    sarg(f, "str__bytes", STR__BYTES, Type::Bytes, Type::Result(Box::new(Type::Text)));
+    sarg(f, "bytes__hex", BYTES__HEX, Type::Text, Type::Result(Box::new(Type::Bytes)));
+    sarg(f, "hex", HEX, Type::Bytes, Type::Text);
+    sarg(f, "bytes__base64", BYTES__BASE64, Type::Text, Type::Result(Box::new(Type::Bytes)));
+    sarg(f, "base64", BASE64, Type::Bytes, Type::Text);
+    f.intrinsic(Arc::new("slice__bytes_start_end".into()), SLICE__BYTES_START_END, Dfn {
+        lts: vec![Lt::Default; 3],
+        tys: vec![Type::Bytes, Type::F64, Type::F64],
+        ret: Type::Bytes
+    });
+    sarg(f, "load_bytes__file", LOAD_BYTES__FILE, Type::Text, Type::Result(Box::new(Type::Bytes)));
+    f.intrinsic(Arc::new("save__bytes_file".into()), SAVE__BYTES_FILE, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Bytes, Type::Text],
+        ret: Type::Result(Box::new(Type::Text))
+    });
+    f.intrinsic(Arc::new("read_int".into()), READ_INT, Dfn {
+        lts: vec![Lt::Default; 3],
+        tys: vec![Type::Bytes, Type::F64, Type::Text],
+        ret: Type::I64
+    });
+    f.intrinsic(Arc::new("read_float".into()), READ_FLOAT, Dfn {
+        lts: vec![Lt::Default; 3],
+        tys: vec![Type::Bytes, Type::F64, Type::Text],
+        ret: Type::F64
+    });
+    f.intrinsic(Arc::new("write_int(mut,_,_,_)".into()), WRITE_INT, Dfn {
+        lts: vec![Lt::Default; 4],
+        tys: vec![Type::Bytes, Type::F64, Type::Text, Type::I64],
+        ret: Type::Void
+    });
+    f.intrinsic(Arc::new("write_float(mut,_,_,_)".into()), WRITE_FLOAT, Dfn {
+        lts: vec![Lt::Default; 4],
+        tys: vec![Type::Bytes, Type::F64, Type::Text, Type::F64],
+        ret: Type::Void
+    });
+    f.intrinsic(Arc::new("new_map".into()), NEW_MAP, Dfn {
+        lts: vec![],
+        tys: vec![],
+        ret: Type::map()
+    });
+    sarg(f, "iter", ITER, Type::Any, Type::iter());
+    f.intrinsic(Arc::new("map".into()), MAP, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![iter(t()), closure(vec![t()], u())],
+        ret: iter(u())
+    });
+    f.intrinsic(Arc::new("filter".into()), FILTER, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![iter(t()), closure(vec![t()], Type::Bool)],
+        ret: iter(t())
+    });
+    f.intrinsic(Arc::new("fold".into()), FOLD, Dfn {
+        lts: vec![Lt::Default; 3],
+        tys: vec![iter(t()), u(), closure(vec![u(), t()], u())],
+        ret: u()
+    });
+    f.intrinsic(Arc::new("take".into()), TAKE, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![iter(t()), Type::F64],
+        ret: iter(t())
+    });
+    f.intrinsic(Arc::new("skip".into()), SKIP, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![iter(t()), Type::F64],
+        ret: iter(t())
+    });
+    f.intrinsic(Arc::new("zip".into()), ZIP, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![iter(t()), iter(u())],
+        ret: iter(Type::array())
+    });
+    sarg(f, "enumerate", ENUMERATE, iter(t()), iter(Type::array()));
+    sarg(f, "collect", COLLECT, iter(t()), Type::Array(Box::new(t())));
+    f.intrinsic(Arc::new("flat_map".into()), FLAT_MAP, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![iter(t()), closure(vec![t()], iter(u()))],
+        ret: iter(u())
+    });
+    f.intrinsic(Arc::new("split".into()), SPLIT, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::Text],
+        ret: Type::Array(Box::new(Type::Text))
+    });
+    f.intrinsic(Arc::new("join".into()), JOIN, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Array(Box::new(Type::Text)), Type::Text],
+        ret: Type::Text
+    });
+    f.intrinsic(Arc::new("starts_with".into()), STARTS_WITH, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::Text],
+        ret: Type::Bool
+    });
+    f.intrinsic(Arc::new("ends_with".into()), ENDS_WITH, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::Text],
+        ret: Type::Bool
+    });
+    f.intrinsic(Arc::new("contains".into()), CONTAINS, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::Text],
+        ret: Type::Bool
+    });
+    f.overload(CONTAINS, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Array(Box::new(t())), t()],
+        ret: Type::Bool
+    });
+    f.intrinsic(Arc::new("find".into()), FIND, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::Text],
+        ret: Type::Option(Box::new(Type::F64))
+    });
+    f.intrinsic(Arc::new("replace".into()), REPLACE, Dfn {
+        lts: vec![Lt::Default; 3],
+        tys: vec![Type::Text, Type::Text, Type::Text],
+        ret: Type::Text
+    });
+    f.intrinsic(Arc::new("substr".into()), SUBSTR, Dfn {
+        lts: vec![Lt::Default; 3],
+        tys: vec![Type::Text, Type::F64, Type::F64],
+        ret: Type::Text
+    });
+    sarg(f, "upper", UPPER, Type::Text, Type::Text);
+    sarg(f, "lower", LOWER, Type::Text, Type::Text);
+    f.intrinsic(Arc::new("pad".into()), PAD, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::F64],
+        ret: Type::Text
+    });
+    f.intrinsic(Arc::new("repeat".into()), REPEAT, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::F64],
+        ret: Type::Text
+    });
+    sarg(f, "lines", LINES, Type::Text, Type::Array(Box::new(Type::Text)));
+    f.intrinsic(Arc::new("format".into()), FORMAT, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::Any],
+        ret: Type::Text
+    });
+    f.intrinsic(Arc::new("match".into()), MATCH, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::Text],
+        ret: Type::Bool
+    });
+    f.intrinsic(Arc::new("captures".into()), CAPTURES, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Text, Type::Text],
+        ret: Type::Option(Box::new(Type::Array(Box::new(Type::Text))))
+    });
+    f.intrinsic(Arc::new("replace_all".into()), REPLACE_ALL, Dfn {
+        lts: vec![Lt::Default; 3],
+        tys: vec![Type::Text, Type::Text, Type::Text],
+        ret: Type::Text
+    });
+    sarg(f, "sort(mut)", SORT, Type::array(), Type::Void);
+    f.intrinsic(Arc::new("sort_by(mut,_)".into()), SORT_BY, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Array(Box::new(t())), closure(vec![t(), t()], Type::F64)],
+        ret: Type::Void
+    });
+    f.intrinsic(Arc::new("binary_search".into()), BINARY_SEARCH, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Array(Box::new(t())), t()],
+        ret: Type::Option(Box::new(Type::F64))
+    });
+    sarg(f, "dedup(mut)", DEDUP, Type::array(), Type::Void);
+    f.intrinsic(Arc::new("slice".into()), SLICE, Dfn {
+        lts: vec![Lt::Default; 3],
+        tys: vec![Type::Array(Box::new(t())), Type::F64, Type::F64],
+        ret: Type::Array(Box::new(t()))
+    });
+    f.intrinsic(Arc::new("concat".into()), CONCAT, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Array(Box::new(t())), Type::Array(Box::new(t()))],
+        ret: Type::Array(Box::new(t()))
+    });
+    f.intrinsic(Arc::new("extend(mut,_)".into()), EXTEND, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Array(Box::new(t())), Type::Array(Box::new(t()))],
+        ret: Type::Void
+    });
+    f.intrinsic(Arc::new("index_of".into()), INDEX_OF, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Array(Box::new(t())), t()],
+        ret: Type::Option(Box::new(Type::F64))
+    });
+    f.intrinsic(Arc::new("truncate(mut,_)".into()), TRUNCATE, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::array(), Type::F64],
+        ret: Type::Void
+    });
+    f.intrinsic(Arc::new("chunks".into()), CHUNKS, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::Array(Box::new(t())), Type::F64],
+        ret: Type::Array(Box::new(Type::Array(Box::new(t()))))
+    });
+    math(f, "sinh", SINH, 1);
+    math(f, "cosh", COSH, 1);
+    math(f, "tanh", TANH, 1);
+    math(f, "clamp", CLAMP, 3);
+    math(f, "lerp", LERP, 3);
+    math(f, "smoothstep", SMOOTHSTEP, 3);
+    math(f, "sign", SIGN, 1);
+    math(f, "fract", FRACT, 1);
+    math(f, "mod", MOD, 2);
+    math(f, "hypot", HYPOT, 2);
+    math(f, "cbrt", CBRT, 1);
+    math(f, "deg", DEG, 1);
+    math(f, "rad", RAD, 1);
+    f.intrinsic(Arc::new("pi".into()), PI, Dfn {
+        lts: vec![],
+        tys: vec![],
+        ret: Type::F64
+    });
+    f.intrinsic(Arc::new("tau".into()), TAU, Dfn {
+        lts: vec![],
+        tys: vec![],
+        ret: Type::F64
+    });
+    f.intrinsic(Arc::new("e".into()), E, Dfn {
+        lts: vec![],
+        tys: vec![],
+        ret: Type::F64
+    });
+    f.intrinsic(Arc::new("random_range".into()), RANDOM_RANGE, Dfn {
+        lts: vec![Lt::Default; 2],
+        tys: vec![Type::F64, Type::F64],
+        ret: Type::F64
+    });
+    sarg(f, "seed", SEED, Type::F64, Type::Void);
 }
 
 pub fn call_standard(
@@ -502,28 +960,37 @@ pub fn call_standard(
     index: usize,
     call: &ast::Call,
     module: &Arc<Module>
-) -> Result<(Option<Variable>, Flow), String> {
+) -> Result<(Option<Variable>, Flow), DyonError> {
     for arg in &call.args {
         match try!(rt.expression(arg, Side::Right, module)) {
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             (Some(v), Flow::Continue) => rt.stack.push(v),
             _ => return Err(module.error(arg.source_range(),
-                    &format!("{}\nExpected something. \
-                    Expression did not return a value.",
-                    rt.stack_trace()), rt))
+                    "Expected something. \
+                    Expression did not return a value.", rt))
         };
     }
+    let expect = try!(call_standard_pushed(rt, index, call, module));
+    Ok((expect, Flow::Continue))
+}
+
+/// Calls intrinsic with arguments already pushed on the stack.
+pub fn call_standard_pushed(
+    rt: &mut Runtime,
+    index: usize,
+    call: &ast::Call,
+    module: &Arc<Module>
+) -> Result<Option<Variable>, DyonError> {
     let (ind, f) = TABLE[index];
     debug_assert!(ind == index);
-    let expect = try!((f)(rt, call, module));
-    Ok((expect, Flow::Continue))
+    (f)(rt, call, module)
 }
 
 fn x(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(match rt.resolve(&v) {
         &Variable::Vec4(ref vec4) => Variable::f64(vec4[0] as f64),
@@ -536,7 +1003,7 @@ fn y(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(match rt.resolve(&v) {
         &Variable::Vec4(ref vec4) => Variable::f64(vec4[1] as f64),
@@ -549,7 +1016,7 @@ fn z(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(match rt.resolve(&v) {
         &Variable::Vec4(ref vec4) => Variable::f64(vec4[2] as f64),
@@ -562,7 +1029,7 @@ fn w(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(match rt.resolve(&v) {
         &Variable::Vec4(ref vec4) => Variable::f64(vec4[3] as f64),
@@ -575,7 +1042,7 @@ fn s(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let ind = rt.stack.pop().expect(TINVOTS);
     let ind = match rt.resolve(&ind) {
         &Variable::F64(val, _) => val,
@@ -589,8 +1056,7 @@ fn s(
                 Some(&s) => s as f64,
                 None => {
                     return Err(module.error(call.source_range,
-                        &format!("{}\nIndex out of bounds `{}`",
-                            rt.stack_trace(), ind), rt))
+                        &format!("Index out of bounds `{}`", ind), rt))
                 }
             }
         }
@@ -602,18 +1068,23 @@ fn s(
 
 fn clone(
     rt: &mut Runtime,
-    _call: &ast::Call,
-    _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
-    Ok(Some(rt.resolve(&v).deep_clone(&rt.stack)))
+    let v = rt.resolve(&v).deep_clone(&rt.stack);
+    if rt.limits.heap.is_some() {
+        let bytes = limits::heap_size(Some(&v));
+        try!(rt.alloc(bytes, call.source_range, module));
+    }
+    Ok(Some(v))
 }
 
 fn why(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = Variable::Array(Arc::new(match rt.resolve(&v) {
         &Variable::Bool(true, Some(ref sec)) => {
@@ -623,13 +1094,11 @@ fn why(
         }
         &Variable::Bool(true, None) => {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nThis does not make sense, perhaps an array is empty?",
-                    rt.stack_trace()), rt))
+                "This does not make sense, perhaps an array is empty?", rt))
         }
         &Variable::Bool(false, _) => {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nMust be `true` to have meaning, try add or remove `!`",
-                    rt.stack_trace()), rt))
+                "Must be `true` to have meaning, try add or remove `!`", rt))
         }
         x => return Err(module.error(call.args[0].source_range(),
             &rt.expected(x, "bool"), rt))
@@ -641,14 +1110,13 @@ fn _where(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = Variable::Array(Arc::new(match rt.resolve(&v) {
         &Variable::F64(val, Some(ref sec)) => {
             if val.is_nan() {
                 return Err(module.error(call.args[0].source_range(),
-                    &format!("{}\nExpected number, found `NaN`",
-                        rt.stack_trace()), rt))
+                    "Expected number, found `NaN`", rt))
             } else {
                 let mut sec = (**sec).clone();
                 sec.reverse();
@@ -657,8 +1125,7 @@ fn _where(
         }
         &Variable::F64(_, None) => {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nThis does not make sense, perhaps an array is empty?",
-                    rt.stack_trace()), rt))
+                "This does not make sense, perhaps an array is empty?", rt))
         }
         x => return Err(module.error(call.args[0].source_range(),
             &rt.expected(x, "f64"), rt))
@@ -670,7 +1137,7 @@ fn explain_why(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let why = rt.stack.pop().expect(TINVOTS);
     let val = rt.stack.pop().expect(TINVOTS);
     let (val, why) = match rt.resolve(&val) {
@@ -694,7 +1161,7 @@ fn explain_where(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let wh = rt.stack.pop().expect(TINVOTS);
     let val = rt.stack.pop().expect(TINVOTS);
     let (val, wh) = match rt.resolve(&val) {
@@ -718,7 +1185,7 @@ fn println(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use write::{print_variable, EscapeString};
 
     let x = rt.stack.pop().expect(TINVOTS);
@@ -731,7 +1198,7 @@ fn print(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use write::{print_variable, EscapeString};
 
     let x = rt.stack.pop().expect(TINVOTS);
@@ -743,7 +1210,7 @@ fn sqrt(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.sqrt())
 }
 
@@ -751,7 +1218,7 @@ fn sin(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.sin())
 }
 
@@ -759,7 +1226,7 @@ fn asin(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.asin())
 }
 
@@ -767,7 +1234,7 @@ fn cos(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.cos())
 }
 
@@ -775,7 +1242,7 @@ fn acos(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.acos())
 }
 
@@ -783,7 +1250,7 @@ fn tan(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.tan())
 }
 
@@ -791,7 +1258,7 @@ fn atan(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.atan())
 }
 
@@ -799,7 +1266,7 @@ fn atan2(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let x = rt.stack.pop().expect(TINVOTS);
     let x = match rt.resolve(&x) {
         &Variable::F64(b, _) => b,
@@ -819,7 +1286,7 @@ fn exp(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.exp())
 }
 
@@ -827,7 +1294,7 @@ fn ln(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.ln())
 }
 
@@ -835,7 +1302,7 @@ fn log2(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.log2())
 }
 
@@ -843,7 +1310,7 @@ fn log10(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.log10())
 }
 
@@ -851,7 +1318,7 @@ fn round(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.round())
 }
 
@@ -859,7 +1326,7 @@ fn abs(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.abs())
 }
 
@@ -867,7 +1334,7 @@ fn floor(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.floor())
 }
 
@@ -875,7 +1342,7 @@ fn ceil(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     rt.unary_f64(call, module, |a| a.ceil())
 }
 
@@ -883,7 +1350,7 @@ fn sleep(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use std::thread::sleep;
     use std::time::Duration;
 
@@ -903,7 +1370,7 @@ fn head(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = Variable::Option(match rt.resolve(&v) {
         &Variable::Link(ref link) => link.head(),
@@ -917,7 +1384,7 @@ fn tip(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = Variable::Option(match rt.resolve(&v) {
         &Variable::Link(ref link) => link.tip(),
@@ -931,7 +1398,7 @@ fn tail(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = Variable::Link(Box::new(match rt.resolve(&v) {
         &Variable::Link(ref link) => link.tail(),
@@ -945,7 +1412,7 @@ fn neck(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = Variable::Link(Box::new(match rt.resolve(&v) {
         &Variable::Link(ref link) => link.neck(),
@@ -959,7 +1426,7 @@ fn is_empty(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(Variable::bool(match rt.resolve(&v) {
         &Variable::Link(ref link) => link.is_empty(),
@@ -972,7 +1439,7 @@ fn random(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     Ok(Some(Variable::f64(rt.rng.gen())))
 }
 
@@ -980,19 +1447,21 @@ fn len(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = match rt.stack.pop() {
         Some(v) => v,
         None => panic!(TINVOTS)
     };
 
     let v = {
-        let arr = match rt.resolve(&v) {
-            &Variable::Array(ref arr) => arr,
+        let len = match rt.resolve(&v) {
+            &Variable::Array(ref arr) => arr.len(),
+            &Variable::Bytes(ref bytes) => bytes.len(),
+            &Variable::Map(ref map) => map.len(),
             x => return Err(module.error(call.args[0].source_range(),
                             &rt.expected(x, "array"), rt))
         };
-        Variable::f64(arr.len() as f64)
+        Variable::f64(len as f64)
     };
     Ok(Some(v))
 }
@@ -1001,8 +1470,9 @@ fn push_ref(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let item = rt.stack.pop().expect(TINVOTS);
+    try!(rt.alloc(size_of::<Variable>(), call.source_range, module));
     let v = rt.stack.pop().expect(TINVOTS);
 
     if let Variable::Ref(ind) = v {
@@ -1014,13 +1484,11 @@ fn push_ref(
         };
         if !ok {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nExpected reference to array",
-                    rt.stack_trace()), rt));
+                "Expected reference to array", rt));
         }
     } else {
         return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected reference to array",
-                rt.stack_trace()), rt));
+            "Expected reference to array", rt));
     }
     Ok(None)
 }
@@ -1029,14 +1497,14 @@ fn insert_ref(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let item = rt.stack.pop().expect(TINVOTS);
+    try!(rt.alloc(size_of::<Variable>(), call.source_range, module));
     let index = rt.stack.pop().expect(TINVOTS);
     let index = match *rt.resolve(&index) {
         Variable::F64(index, _) => index,
         _ => return Err(module.error(call.args[1].source_range(),
-                        &format!("{}\nExpected number",
-                            rt.stack_trace()), rt))
+                        "Expected number", rt))
     };
     let v = rt.stack.pop().expect(TINVOTS);
 
@@ -1045,8 +1513,7 @@ fn insert_ref(
             let index = index as usize;
             if index > arr.len() {
                 return Err(module.error(call.source_range,
-                            &format!("{}\nIndex out of bounds",
-                            rt.stack_trace()), rt))
+                            "Index out of bounds", rt))
             }
         }
         let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
@@ -1057,13 +1524,11 @@ fn insert_ref(
         };
         if !ok {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nExpected reference to array",
-                    rt.stack_trace()), rt));
+                "Expected reference to array", rt));
         }
     } else {
         return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected reference to array",
-                rt.stack_trace()), rt));
+            "Expected reference to array", rt));
     }
     Ok(None)
 }
@@ -1072,9 +1537,11 @@ fn push(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let item = rt.stack.pop().expect(TINVOTS);
     let item = rt.resolve(&item).deep_clone(&rt.stack);
+    let bytes = size_of::<Variable>() + limits::alloc_size(&item);
+    try!(rt.alloc(bytes, call.source_range, module));
     let v = rt.stack.pop().expect(TINVOTS);
 
     if let Variable::Ref(ind) = v {
@@ -1086,13 +1553,11 @@ fn push(
         };
         if !ok {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nExpected reference to array",
-                    rt.stack_trace()), rt));
+                "Expected reference to array", rt));
         }
     } else {
         return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected reference to array",
-                rt.stack_trace()), rt));
+            "Expected reference to array", rt));
     }
     Ok(None)
 }
@@ -1101,25 +1566,37 @@ fn insert(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let item = rt.stack.pop().expect(TINVOTS);
     let item = rt.resolve(&item).deep_clone(&rt.stack);
+    let bytes = size_of::<Variable>() + limits::alloc_size(&item);
+    try!(rt.alloc(bytes, call.source_range, module));
     let index = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    if let Variable::Ref(ind) = v {
+        if let Variable::Map(_) = rt.stack[ind] {
+            let key = match MapKey::from_variable(rt.resolve(&index)) {
+                Ok(key) => key,
+                Err(err) => return Err(module.error(call.args[1].source_range(), &err, rt))
+            };
+            if let Variable::Map(ref mut map) = rt.stack[ind] {
+                Arc::make_mut(map).insert(key, item);
+            }
+            return Ok(None);
+        }
+    }
     let index = match *rt.resolve(&index) {
         Variable::F64(index, _) => index,
         _ => return Err(module.error(call.args[1].source_range(),
-                        &format!("{}\nExpected number",
-                            rt.stack_trace()), rt))
+                        "Expected number", rt))
     };
-    let v = rt.stack.pop().expect(TINVOTS);
 
     if let Variable::Ref(ind) = v {
         if let Variable::Array(ref arr) = rt.stack[ind] {
             let index = index as usize;
             if index > arr.len() {
                 return Err(module.error(call.source_range,
-                            &format!("{}\nIndex out of bounds",
-                            rt.stack_trace()), rt))
+                            "Index out of bounds", rt))
             }
         }
         let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
@@ -1130,13 +1607,11 @@ fn insert(
         };
         if !ok {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nExpected reference to array",
-                    rt.stack_trace()), rt));
+                "Expected reference to array", rt));
         }
     } else {
         return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected reference to array",
-                rt.stack_trace()), rt));
+            "Expected reference to array", rt));
     }
     Ok(None)
 }
@@ -1145,7 +1620,7 @@ fn pop(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let arr = rt.stack.pop().expect(TINVOTS);
     let mut v: Option<Variable> = None;
     if let Variable::Ref(ind) = arr {
@@ -1157,18 +1632,15 @@ fn pop(
         };
         if !ok {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nExpected reference to array",
-                    rt.stack_trace()), rt));
+                "Expected reference to array", rt));
         }
     } else {
         return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected reference to array",
-                rt.stack_trace()), rt));
+            "Expected reference to array", rt));
     }
     let v = match v {
         None => return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected non-empty array",
-                rt.stack_trace()), rt)),
+            "Expected non-empty array", rt)),
         Some(val) => val
     };
     Ok(Some(v))
@@ -1178,22 +1650,38 @@ fn remove(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let index = rt.stack.pop().expect(TINVOTS);
+    let arr = rt.stack.pop().expect(TINVOTS);
+    if let Variable::Ref(ind) = arr {
+        if let Variable::Map(_) = rt.stack[ind] {
+            let key = match MapKey::from_variable(rt.resolve(&index)) {
+                Ok(key) => key,
+                Err(err) => return Err(module.error(call.args[1].source_range(), &err, rt))
+            };
+            let v = if let Variable::Map(ref mut map) = rt.stack[ind] {
+                Arc::make_mut(map).remove(&key)
+            } else {
+                None
+            };
+            return match v {
+                Some(v) => Ok(Some(v)),
+                None => Err(module.error(call.args[1].source_range(),
+                            &format!("Map has no key `{}`", key), rt))
+            };
+        }
+    }
     let index = match *rt.resolve(&index) {
         Variable::F64(index, _) => index,
         _ => return Err(module.error(call.args[1].source_range(),
-                        &format!("{}\nExpected number",
-                            rt.stack_trace()), rt))
+                        "Expected number", rt))
     };
-    let arr = rt.stack.pop().expect(TINVOTS);
     if let Variable::Ref(ind) = arr {
         if let Variable::Array(ref arr) = rt.stack[ind] {
             let index = index as usize;
             if index >= arr.len() {
                 return Err(module.error(call.source_range,
-                            &format!("{}\nIndex out of bounds",
-                            rt.stack_trace()), rt))
+                            "Index out of bounds", rt))
             }
         }
         if let Variable::Array(ref mut arr) = rt.stack[ind] {
@@ -1202,12 +1690,10 @@ fn remove(
             false
         };
         return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected reference to array",
-                rt.stack_trace()), rt));
+            "Expected reference to array", rt));
     } else {
         return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected reference to array",
-                rt.stack_trace()), rt));
+            "Expected reference to array", rt));
     }
 }
 
@@ -1215,7 +1701,7 @@ fn reverse(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     if let Variable::Ref(ind) = v {
         let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
@@ -1226,13 +1712,11 @@ fn reverse(
         };
         if !ok {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nExpected reference to array",
-                    rt.stack_trace()), rt));
+                "Expected reference to array", rt));
         }
     } else {
         return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected reference to array",
-                rt.stack_trace()), rt));
+            "Expected reference to array", rt));
     }
     Ok(None)
 }
@@ -1241,7 +1725,7 @@ fn clear(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     if let Variable::Ref(ind) = v {
         let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
@@ -1252,13 +1736,11 @@ fn clear(
         };
         if !ok {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nExpected reference to array",
-                    rt.stack_trace()), rt));
+                "Expected reference to array", rt));
         }
     } else {
         return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected reference to array",
-                rt.stack_trace()), rt));
+            "Expected reference to array", rt));
     }
     Ok(None)
 }
@@ -1267,7 +1749,7 @@ fn swap(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let j = rt.stack.pop().expect(TINVOTS);
     let i = rt.stack.pop().expect(TINVOTS);
     let j = match rt.resolve(&j) {
@@ -1290,13 +1772,11 @@ fn swap(
         };
         if !ok {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nExpected reference to array",
-                    rt.stack_trace()), rt));
+                "Expected reference to array", rt));
         }
     } else {
         return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected reference to array",
-                rt.stack_trace()), rt));
+            "Expected reference to array", rt));
     }
     Ok(None)
 }
@@ -1305,7 +1785,7 @@ fn read_line(
     _rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use std::io::{self, Write};
 
     let mut input = String::new();
@@ -1328,7 +1808,7 @@ fn read_number(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use std::io::{self, Write};
 
     let err = rt.stack.pop().expect(TINVOTS);
@@ -1370,7 +1850,7 @@ fn parse_number(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let text = rt.stack.pop().expect(TINVOTS);
     let text= match rt.resolve(&text) {
         &Variable::Text(ref t) => t.clone(),
@@ -1383,11 +1863,61 @@ fn parse_number(
     })))
 }
 
+fn parse_i64(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let text = rt.stack.pop().expect(TINVOTS);
+    let text= match rt.resolve(&text) {
+        &Variable::Text(ref t) => t.clone(),
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "text"), rt))
+    };
+    Ok(Some(Variable::Option(match text.trim().parse::<i64>() {
+        Ok(v) => Some(Box::new(Variable::i64(v))),
+        Err(_) => None
+    })))
+}
+
+fn i64(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = match rt.resolve(&v) {
+        &Variable::F64(v, _) => v,
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "number"), rt))
+    };
+    // The range of `i64` is `[-2^63, 2^63)`.
+    if v.fract() != 0.0 || !(v >= -9223372036854775808.0 && v < 9223372036854775808.0) {
+        return Err(module.error(call.args[0].source_range(),
+            &format!("Can not convert `{}` to `i64` without losing precision", v), rt));
+    }
+    Ok(Some(Variable::i64(v as i64)))
+}
+
+fn f64(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = match rt.resolve(&v) {
+        &Variable::I64(v) => v,
+        x => return Err(module.error(call.args[0].source_range(),
+                &rt.expected(x, "i64"), rt))
+    };
+    Ok(Some(Variable::f64(v as f64)))
+}
+
 fn trim(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::Text(ref t) => t.clone(),
@@ -1402,7 +1932,7 @@ fn trim_left(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::Text(ref t) => t.clone(),
@@ -1417,7 +1947,7 @@ fn trim_right(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let mut v = match rt.resolve(&v) {
         &Variable::Text(ref t) => t.clone(),
@@ -1437,7 +1967,7 @@ fn _str(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use write::{write_variable, EscapeString};
 
     let v = rt.stack.pop().expect(TINVOTS);
@@ -1451,7 +1981,7 @@ fn json_string(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use write::{write_variable, EscapeString};
 
     let v = rt.stack.pop().expect(TINVOTS);
@@ -1464,7 +1994,7 @@ fn str__color(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::Vec4(val) => val,
@@ -1499,7 +2029,7 @@ fn srgb_to_linear__color(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::Vec4(val) => val,
@@ -1522,7 +2052,7 @@ fn linear_to_srgb__color(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::Vec4(val) => val,
@@ -1545,15 +2075,18 @@ fn _typeof(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(match rt.resolve(&v) {
         &Variable::Text(_) => rt.text_type.clone(),
         &Variable::F64(_, _) => rt.f64_type.clone(),
+        &Variable::I64(_) => rt.i64_type.clone(),
+        &Variable::Bytes(_) => rt.bytes_type.clone(),
         &Variable::Vec4(_) => rt.vec4_type.clone(),
         &Variable::Return => rt.return_type.clone(),
         &Variable::Bool(_, _) => rt.bool_type.clone(),
         &Variable::Object(_) => rt.object_type.clone(),
+        &Variable::Map(_) => rt.map_type.clone(),
         &Variable::Array(_) => rt.array_type.clone(),
         &Variable::Link(_) => rt.link_type.clone(),
         &Variable::Ref(_) => rt.ref_type.clone(),
@@ -1564,6 +2097,8 @@ fn _typeof(
         &Variable::Thread(_) => rt.thread_type.clone(),
         &Variable::Closure(_, _) => rt.closure_type.clone(),
         &Variable::In(_) => rt.in_type.clone(),
+        &Variable::Iter(_) => rt.iter_type.clone(),
+        &Variable::Variant(ref v) => Variable::Text(v.ty.clone()),
     }))
 }
 
@@ -1571,7 +2106,7 @@ fn debug(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     println!("Stack {:#?}", rt.stack);
     println!("Locals {:#?}", rt.local_stack);
     println!("Currents {:#?}", rt.current_stack);
@@ -1582,7 +2117,7 @@ fn backtrace(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     println!("{:#?}", rt.call_stack);
     Ok(None)
 }
@@ -1591,20 +2126,21 @@ fn load(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use load;
 
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::Text(ref text) => {
             let mut m = Module::new_intrinsics(module.intrinsics.clone());
+            m.capabilities = module.capabilities;
             for f in &module.ext_prelude {
                 m.add(f.name.clone(), f.f, f.p.clone());
             }
             if let Err(err) = load(text, &mut m) {
                 Variable::Result(Err(Box::new(Error {
                     message: Variable::Text(Arc::new(
-                        format!("{}\n{}\n{}", rt.stack_trace(), err,
+                        format!("{}\n{}", err,
                             module.error(call.args[0].source_range(),
                             "When attempting to load module:", rt)))),
                     trace: vec![]
@@ -1624,12 +2160,13 @@ fn load__source_imports(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use load;
 
     let modules = rt.stack.pop().expect(TINVOTS);
     let source = rt.stack.pop().expect(TINVOTS);
     let mut new_module = Module::new_intrinsics(module.intrinsics.clone());
+    new_module.capabilities = module.capabilities;
     for f in &module.ext_prelude {
         new_module.add(f.name.clone(), f.f, f.p.clone());
     }
@@ -1655,8 +2192,7 @@ fn load__source_imports(
                             }
                             None => return Err(module.error(
                                 call.args[1].source_range(),
-                                &format!("{}\nExpected `Module`",
-                                    rt.stack_trace()), rt))
+                                "Expected `Module`", rt))
                         }
                     }
                     x => return Err(module.error(
@@ -1673,7 +2209,7 @@ fn load__source_imports(
             if let Err(err) = load(text, &mut new_module) {
                 Variable::Result(Err(Box::new(Error {
                     message: Variable::Text(Arc::new(
-                        format!("{}\n{}\n{}", rt.stack_trace(), err,
+                        format!("{}\n{}", err,
                             module.error(call.args[0].source_range(),
                             "When attempting to load module:", rt)))),
                     trace: vec![]
@@ -1694,7 +2230,7 @@ fn module__in_string_imports(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use load_str;
 
     let modules = rt.stack.pop().expect(TINVOTS);
@@ -1711,6 +2247,7 @@ fn module__in_string_imports(
                 &rt.expected(x, "str"), rt))
     };
     let mut new_module = Module::new_intrinsics(module.intrinsics.clone());
+    new_module.capabilities = module.capabilities;
     for f in &module.ext_prelude {
         new_module.add(f.name.clone(), f.f, f.p.clone());
     }
@@ -1736,8 +2273,7 @@ fn module__in_string_imports(
                             }
                             None => return Err(module.error(
                                 call.args[2].source_range(),
-                                &format!("{}\nExpected `Module`",
-                                    rt.stack_trace()), rt))
+                                "Expected `Module`", rt))
                         }
                     }
                     x => return Err(module.error(
@@ -1752,7 +2288,7 @@ fn module__in_string_imports(
     let v = if let Err(err) = load_str(&name, source, &mut new_module) {
             Variable::Result(Err(Box::new(Error {
                 message: Variable::Text(Arc::new(
-                    format!("{}\n{}\n{}", rt.stack_trace(), err,
+                    format!("{}\n{}", err,
                         module.error(call.args[0].source_range(),
                         "When attempting to load module:", rt)))),
                 trace: vec![]
@@ -1769,7 +2305,7 @@ fn _call(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     // Use the source from calling function.
     let source = module.functions[rt.call_stack.last().unwrap().index].source.clone();
     let args = rt.stack.pop().expect(TINVOTS);
@@ -1803,23 +2339,17 @@ fn _call(
                     if f.args.len() != args.len() {
                         return Err(module.error(
                             call.args[2].source_range(),
-                            &format!(
-                                "{}\nExpected `{}` arguments, found `{}`",
-                                rt.stack_trace(),
-                                f.args.len(), args.len()), rt))
+                            &format!("Expected `{}` arguments, found `{}`", f.args.len(), args.len()), rt))
                     }
                     try!(lifetimechk::check(f, &args).map_err(|err|
                         module.error(call.args[2].source_range(),
-                        &format!("{}\n{}", err, rt.stack_trace()), rt)));
+                        &err, rt)));
                 }
-                FnIndex::Intrinsic(_) | FnIndex::None |
+                FnIndex::Intrinsic(_) | FnIndex::None | FnIndex::Variant |
                 FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) =>
                     return Err(module.error(
                             call.args[1].source_range(),
-                            &format!(
-                                "{}\nCould not find function `{}`",
-                                rt.stack_trace(),
-                                fn_name), rt))
+                            &format!("Could not find function `{}`", fn_name), rt))
             }
             let call = ast::Call {
                 alias: None,
@@ -1835,8 +2365,7 @@ fn _call(
             try!(rt.call(&call, &m));
         }
         None => return Err(module.error(call.args[0].source_range(),
-                    &format!("{}\nExpected `Module`",
-                        rt.stack_trace()), rt))
+                    "Expected `Module`", rt))
     }
 
     Ok(None)
@@ -1846,7 +2375,7 @@ fn call_ret(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     // Use the source from calling function.
     let source = module.functions[rt.call_stack.last().unwrap().index].source.clone();
     let args = rt.stack.pop().expect(TINVOTS);
@@ -1880,23 +2409,17 @@ fn call_ret(
                     if f.args.len() != args.len() {
                         return Err(module.error(
                             call.args[2].source_range(),
-                            &format!(
-                                "{}\nExpected `{}` arguments, found `{}`",
-                                rt.stack_trace(),
-                                f.args.len(), args.len()), rt))
+                            &format!("Expected `{}` arguments, found `{}`", f.args.len(), args.len()), rt))
                     }
                     try!(lifetimechk::check(f, &args).map_err(|err|
                         module.error(call.args[2].source_range(),
-                        &format!("{}\n{}", err, rt.stack_trace()), rt)));
+                        &err, rt)));
                 }
-                FnIndex::Intrinsic(_) | FnIndex::None |
+                FnIndex::Intrinsic(_) | FnIndex::None | FnIndex::Variant |
                 FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) =>
                     return Err(module.error(
                         call.args[1].source_range(),
-                        &format!(
-                            "{}\nCould not find function `{}`",
-                            rt.stack_trace(),
-                            fn_name), rt))
+                        &format!("Could not find function `{}`", fn_name), rt))
             }
             let call = ast::Call {
                 alias: None,
@@ -1912,7 +2435,7 @@ fn call_ret(
             try!(rt.call(&call, &m)).0
         }
         None => return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected `Module`", rt.stack_trace()), rt))
+            "Expected `Module`", rt))
     };
 
     Ok(v)
@@ -1922,7 +2445,7 @@ fn functions(
     _rt: &mut Runtime,
     _call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     // List available functions in scope.
     let v = Variable::Array(Arc::new(functions::list_functions(module)));
     Ok(Some(v))
@@ -1932,7 +2455,7 @@ fn functions__module(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     // List available functions in scope.
     let m = rt.stack.pop().expect(TINVOTS);
     let m = match rt.resolve(&m) {
@@ -1945,7 +2468,7 @@ fn functions__module(
         .downcast_ref::<Arc<Module>>() {
         Some(m) => functions::list_functions(m),
         None => return Err(module.error(call.args[0].source_range(),
-            &format!("{}\nExpected `Module`", rt.stack_trace()), rt))
+            "Expected `Module`", rt))
     };
 
     let v = Variable::Array(Arc::new(functions));
@@ -1956,7 +2479,7 @@ fn none(
     _rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     Ok(Some(Variable::Option(None)))
 }
 
@@ -1964,7 +2487,7 @@ fn some(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(Variable::Option(Some(Box::new(
         rt.resolve(&v).deep_clone(&rt.stack)
@@ -1975,7 +2498,7 @@ fn ok(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(Variable::Result(Ok(Box::new(
         rt.resolve(&v).deep_clone(&rt.stack)
@@ -1986,7 +2509,7 @@ fn err(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(Variable::Result(Err(Box::new(
         Error {
@@ -1999,7 +2522,7 @@ fn is_err(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(match rt.resolve(&v) {
         &Variable::Result(Err(_)) => Variable::bool(true),
@@ -2015,7 +2538,7 @@ fn is_ok(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(match rt.resolve(&v) {
         &Variable::Result(Err(_)) => Variable::bool(false),
@@ -2031,7 +2554,7 @@ fn min(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::Array(ref arr) => {
@@ -2055,7 +2578,7 @@ fn max(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::Array(ref arr) => {
@@ -2079,7 +2602,7 @@ fn unwrap(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use write::{write_variable, EscapeString};
 
     // Return value does not depend on lifetime of argument since
@@ -2089,8 +2612,7 @@ fn unwrap(
         &Variable::Option(Some(ref v)) => (**v).clone(),
         &Variable::Option(None) => {
             return Err(module.error(call.args[0].source_range(),
-                &format!("{}\nExpected `some(_)`",
-                    rt.stack_trace()), rt));
+                "Expected `some(_)`", rt));
         }
         &Variable::Result(Ok(ref ok)) => (**ok).clone(),
         &Variable::Result(Err(ref err)) => {
@@ -2098,8 +2620,6 @@ fn unwrap(
 
             // Print out error message.
             let mut w: Vec<u8> = vec![];
-            w.extend_from_slice(rt.stack_trace().as_bytes());
-            w.extend_from_slice("\n".as_bytes());
             write_variable(&mut w, rt, &err.message,
                            EscapeString::None, 0).unwrap();
             for t in &err.trace {
@@ -2121,7 +2641,7 @@ fn unwrap_or(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     // Return value does not depend on lifetime of argument since
     // `ok(x)` and `some(x)` perform a deep clone.
     let def = rt.stack.pop().expect(TINVOTS);
@@ -2143,7 +2663,7 @@ fn unwrap_err(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(match rt.resolve(&v) {
         &Variable::Result(Err(ref err)) => err.message.clone(),
@@ -2158,7 +2678,7 @@ fn dir__angle(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     Ok(Some(match rt.resolve(&v) {
         &Variable::F64(val, _) => Variable::Vec4([val.cos() as f32, val.sin() as f32, 0.0, 0.0]),
@@ -2173,7 +2693,7 @@ fn load__meta_file(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let file = rt.stack.pop().expect(TINVOTS);
     let meta = rt.stack.pop().expect(TINVOTS);
     let file = match rt.resolve(&file) {
@@ -2200,7 +2720,7 @@ fn load__meta_url(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let url = rt.stack.pop().expect(TINVOTS);
     let meta = rt.stack.pop().expect(TINVOTS);
     let url = match rt.resolve(&url) {
@@ -2227,7 +2747,7 @@ fn syntax__in_string(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use piston_meta::syntax_errstr;
 
     let text = rt.stack.pop().expect(TINVOTS);
@@ -2257,7 +2777,7 @@ fn meta__syntax_in_string(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use piston_meta::Syntax;
 
     let text = rt.stack.pop().expect(TINVOTS);
@@ -2297,7 +2817,7 @@ fn download__url_file(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let file = rt.stack.pop().expect(TINVOTS);
     let url = rt.stack.pop().expect(TINVOTS);
     let file = match rt.resolve(&file) {
@@ -2326,7 +2846,7 @@ fn save__string_file(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use std::fs::File;
     use std::io::Write;
     use std::error::Error as StdError;
@@ -2366,7 +2886,7 @@ fn save__string_file(
     _: &mut Runtime,
     _: &ast::Call,
     _: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     Err(FILE_SUPPORT_DISABLED.into())
 }
 
@@ -2375,7 +2895,7 @@ fn load_string__file(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use std::fs::File;
     use std::io::Read;
     use std::error::Error as StdError;
@@ -2414,7 +2934,7 @@ fn load_string__file(
     _: &mut Runtime,
     _: &ast::Call,
     _: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     Err(FILE_SUPPORT_DISABLED.into())
 }
 
@@ -2422,7 +2942,7 @@ fn load_string__url(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let url = rt.stack.pop().expect(TINVOTS);
     let url = match rt.resolve(&url) {
         &Variable::Text(ref url) => url.clone(),
@@ -2447,7 +2967,7 @@ fn join__thread(
     rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use Thread;
 
     let thread = rt.stack.pop().expect(TINVOTS);
@@ -2459,7 +2979,7 @@ fn join__thread(
                     Ok(res) => match res {
                         Ok(res) => Ok(Box::new(res)),
                         Err(err) => Err(Box::new(Error {
-                            message: Variable::Text(Arc::new(err)),
+                            message: Variable::Text(Arc::new(format!("{}", err))),
                             trace: vec![]
                         }))
                     },
@@ -2485,7 +3005,7 @@ fn load_data__file(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let file = rt.stack.pop().expect(TINVOTS);
     let file = match rt.resolve(&file) {
         &Variable::Text(ref t) => t.clone(),
@@ -2508,7 +3028,7 @@ fn load_data__string(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let text = rt.stack.pop().expect(TINVOTS);
     let text = match rt.resolve(&text) {
         &Variable::Text(ref t) => t.clone(),
@@ -2531,7 +3051,7 @@ fn args_os(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let mut arr: Vec<Variable> = vec![];
     for arg in ::std::env::args_os() {
         if let Ok(t) = arg.into_string() {
@@ -2549,7 +3069,7 @@ fn save__data_file(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use std::error::Error;
     use std::fs::File;
     use std::io::BufWriter;
@@ -2567,8 +3087,7 @@ fn save__data_file(
         Ok(f) => BufWriter::new(f),
         Err(err) => {
             return Err(module.error(call.args[0].source_range(),
-                       &format!("{}\nError when creating file `{}`:\n{}",
-                        rt.stack_trace(), file, err.description()), rt))
+                       &format!("Error when creating file `{}`:\n{}", file, err.description()), rt))
         }
     };
     let res = match write_variable(&mut f, rt, &data, EscapeString::Json, 0) {
@@ -2590,7 +3109,7 @@ fn save__data_file(
     _: &mut Runtime,
     _: &ast::Call,
     _: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     Err(FILE_SUPPORT_DISABLED.into())
 }
 
@@ -2598,16 +3117,15 @@ fn json_from_meta_data(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use std::error::Error;
 
     let meta_data = rt.stack.pop().expect(TINVOTS);
     let json = match rt.resolve(&meta_data) {
         &Variable::Array(ref arr) => {
             try!(meta::json_from_meta_data(arr).map_err(|err| {
-                format!("{}\nError when generating JSON:\n{}",
-                        rt.stack_trace(),
-                        err.description())
+                module.error(call.args[0].source_range(),
+                    &format!("Error when generating JSON:\n{}", err.description()), rt)
             }))
         }
         x => return Err(module.error(call.args[0].source_range(),
@@ -2620,9 +3138,8 @@ fn errstr__string_start_len_msg(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use piston_meta::ParseErrorHandler;
-    use range::Range;
 
     let msg = rt.stack.pop().expect(TINVOTS);
     let msg = match rt.resolve(&msg) {
@@ -2660,18 +3177,21 @@ fn has(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let key = rt.stack.pop().expect(TINVOTS);
-    let key = match rt.resolve(&key) {
-        &Variable::Text(ref t) => t.clone(),
-        x => return Err(module.error(call.args[1].source_range(),
-                        &rt.expected(x, "str"), rt))
-    };
     let obj = rt.stack.pop().expect(TINVOTS);
     let res = match rt.resolve(&obj) {
-        &Variable::Object(ref obj) => obj.contains_key(&key),
+        &Variable::Object(ref obj) => match rt.resolve(&key) {
+            &Variable::Text(ref t) => obj.contains_key(t),
+            x => return Err(module.error(call.args[1].source_range(),
+                            &rt.expected(x, "str"), rt))
+        },
+        &Variable::Map(ref map) => match MapKey::from_variable(rt.resolve(&key)) {
+            Ok(key) => map.contains_key(&key),
+            Err(err) => return Err(module.error(call.args[1].source_range(), &err, rt))
+        },
         x => return Err(module.error(call.args[0].source_range(),
-                        &rt.expected(x, "object"), rt))
+                        &rt.expected(x, "object or map"), rt))
     };
     Ok(Some(Variable::bool(res)))
 }
@@ -2680,14 +3200,20 @@ fn keys(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let obj = rt.stack.pop().expect(TINVOTS);
     let res = Variable::Array(Arc::new(match rt.resolve(&obj) {
         &Variable::Object(ref obj) => {
             obj.keys().map(|k| Variable::Text(k.clone())).collect()
         }
+        &Variable::Map(ref map) => {
+            // Sort keys to make iteration deterministic.
+            let mut keys: Vec<&MapKey> = map.keys().collect();
+            keys.sort();
+            keys.into_iter().map(|k| k.to_variable()).collect()
+        }
         x => return Err(module.error(call.args[0].source_range(),
-                        &rt.expected(x, "object"), rt))
+                        &rt.expected(x, "object or map"), rt))
     }));
     Ok(Some(res))
 }
@@ -2696,7 +3222,7 @@ fn chars(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let t = rt.stack.pop().expect(TINVOTS);
     let t = match rt.resolve(&t) {
         &Variable::Text(ref t) => t.clone(),
@@ -2717,7 +3243,7 @@ fn now(
     _rt: &mut Runtime,
     _call: &ast::Call,
     _module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use std::time::{SystemTime, UNIX_EPOCH};
 
     let val = match SystemTime::now().duration_since(UNIX_EPOCH) {
@@ -2732,11 +3258,50 @@ fn now(
     Ok(Some(val))
 }
 
+fn assert(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::Bool(true, _) => Ok(None),
+        &Variable::Bool(false, _) => Err(module.error(call.source_range,
+                                         "Assertion failed", rt)),
+        x => Err(module.error(call.args[0].source_range(),
+                              &rt.expected(x, "bool"), rt))
+    }
+}
+
+fn assert_eq(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    let a = rt.stack.pop().expect(TINVOTS);
+    if rt.resolve(&a) == rt.resolve(&b) { return Ok(None) }
+    Err(module.error(call.source_range,
+        &format!("Assertion `left == right` failed\n{}", diff::diff(rt, &a, &b)), rt))
+}
+
+fn assert_ne(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let b = rt.stack.pop().expect(TINVOTS);
+    let a = rt.stack.pop().expect(TINVOTS);
+    if rt.resolve(&a) != rt.resolve(&b) { return Ok(None) }
+    Err(module.error(call.source_range,
+        &format!("Assertion `left != right` failed\nboth: {}", diff::show(rt, &a)), rt))
+}
+
 fn is_nan(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>,
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     let v = rt.stack.pop().expect(TINVOTS);
     let v = match rt.resolve(&v) {
         &Variable::F64(ref v, _) => v.clone(),
@@ -2750,7 +3315,7 @@ fn wait_next(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use std::error::Error;
 
     let v = rt.stack.pop().expect(TINVOTS);
@@ -2776,7 +3341,7 @@ fn next(
     rt: &mut Runtime,
     call: &ast::Call,
     module: &Arc<Module>
-) -> Result<Option<Variable>, String> {
+) -> Result<Option<Variable>, DyonError> {
     use std::error::Error;
 
     let v = rt.stack.pop().expect(TINVOTS);
@@ -2797,3 +3362,1350 @@ fn next(
                         &rt.expected(x, "in"), rt))
     }))
 }
+
+/// Pops bytes from the stack.
+fn pop_bytes(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    arg: usize,
+    module: &Arc<Module>,
+) -> Result<Arc<Vec<u8>>, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::Bytes(ref b) => Ok(b.clone()),
+        x => Err(module.error(call.args[arg].source_range(),
+                 &rt.expected(x, "bytes"), rt))
+    }
+}
+
+/// Pops text from the stack.
+fn pop_text(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    arg: usize,
+    module: &Arc<Module>,
+) -> Result<Arc<String>, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::Text(ref t) => Ok(t.clone()),
+        x => Err(module.error(call.args[arg].source_range(),
+                 &rt.expected(x, "str"), rt))
+    }
+}
+
+/// Pops a position in bytes from the stack.
+fn pop_pos(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    arg: usize,
+    module: &Arc<Module>,
+) -> Result<usize, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::F64(v, _) if v >= 0.0 && v.fract() == 0.0 => Ok(v as usize),
+        &Variable::F64(v, _) => Err(module.error(call.args[arg].source_range(),
+            &format!("Expected position, found `{}`", v), rt)),
+        x => Err(module.error(call.args[arg].source_range(),
+                 &rt.expected(x, "f64"), rt))
+    }
+}
+
+fn bytes_result(res: Result<Vec<u8>, String>) -> Variable {
+    Variable::Result(match res {
+        Ok(b) => Ok(Box::new(Variable::Bytes(Arc::new(b)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Text(Arc::new(err)),
+            trace: vec![]
+        }))
+    })
+}
+
+fn bytes(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let arr = match rt.resolve(&v) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(module.error(call.args[0].source_range(),
+                        &rt.expected(x, "array"), rt))
+    };
+    let mut res = Vec::with_capacity(arr.len());
+    for it in arr.iter() {
+        match rt.resolve(it) {
+            &Variable::F64(v, _) if v >= 0.0 && v <= 255.0 && v.fract() == 0.0 => {
+                res.push(v as u8)
+            }
+            x => return Err(module.error(call.args[0].source_range(),
+                &format!("Expected integers from 0 to 255, found `{}`",
+                         rt.typeof_var(x)), rt))
+        }
+    }
+    Ok(Some(Variable::Bytes(Arc::new(res))))
+}
+
+fn bytes__string(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let text = try!(pop_text(rt, call, 0, module));
+    Ok(Some(Variable::Bytes(Arc::new(text.as_bytes().to_vec()))))
+}
+
+fn str__bytes(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let b = try!(pop_bytes(rt, call, 0, module));
+    Ok(Some(Variable::Result(match String::from_utf8((*b).clone()) {
+        Ok(text) => Ok(Box::new(Variable::Text(Arc::new(text)))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Text(Arc::new(format!("{}", err))),
+            trace: vec![]
+        }))
+    })))
+}
+
+fn bytes__hex(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let text = try!(pop_text(rt, call, 0, module));
+    Ok(Some(bytes_result(bytes::from_hex(&text))))
+}
+
+fn hex(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let b = try!(pop_bytes(rt, call, 0, module));
+    Ok(Some(Variable::Text(Arc::new(bytes::to_hex(&b)))))
+}
+
+fn bytes__base64(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let text = try!(pop_text(rt, call, 0, module));
+    Ok(Some(bytes_result(bytes::from_base64(&text))))
+}
+
+fn base64(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let b = try!(pop_bytes(rt, call, 0, module));
+    Ok(Some(Variable::Text(Arc::new(bytes::to_base64(&b)))))
+}
+
+fn slice__bytes_start_end(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let end = try!(pop_pos(rt, call, 2, module));
+    let start = try!(pop_pos(rt, call, 1, module));
+    let b = try!(pop_bytes(rt, call, 0, module));
+    if start > end || end > b.len() {
+        return Err(module.error(call.source_range,
+            &format!("Out of bounds, can not slice `{}..{}` from {} bytes",
+                     start, end, b.len()), rt));
+    }
+    Ok(Some(Variable::Bytes(Arc::new(b[start..end].to_vec()))))
+}
+
+#[cfg(feature = "file")]
+fn load_bytes__file(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    use std::fs::File;
+    use std::io::Read;
+
+    let file = try!(pop_text(rt, call, 0, module));
+    let mut b = vec![];
+    Ok(Some(bytes_result(File::open(&**file)
+        .and_then(|mut f| f.read_to_end(&mut b))
+        .map(|_| b)
+        .map_err(|err| io::io_error("open", &file, &err)))))
+}
+
+#[cfg(not(feature = "file"))]
+fn load_bytes__file(
+    _: &mut Runtime,
+    _: &ast::Call,
+    _: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+#[cfg(feature = "file")]
+fn save__bytes_file(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    use std::fs::File;
+    use std::io::Write;
+
+    let file = try!(pop_text(rt, call, 1, module));
+    let b = try!(pop_bytes(rt, call, 0, module));
+    Ok(Some(Variable::Result(match File::create(&**file).and_then(|mut f| f.write_all(&b)) {
+        Ok(()) => Ok(Box::new(Variable::Text(file))),
+        Err(err) => Err(Box::new(Error {
+            message: Variable::Text(Arc::new(io::io_error("save", &file, &err))),
+            trace: vec![]
+        }))
+    })))
+}
+
+#[cfg(not(feature = "file"))]
+fn save__bytes_file(
+    _: &mut Runtime,
+    _: &ast::Call,
+    _: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    Err(FILE_SUPPORT_DISABLED.into())
+}
+
+fn read_int(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let format = try!(pop_text(rt, call, 2, module));
+    let pos = try!(pop_pos(rt, call, 1, module));
+    let b = try!(pop_bytes(rt, call, 0, module));
+    match bytes::Format::from_str(&format).and_then(|f| f.read_int(&b, pos)) {
+        Ok(x) => Ok(Some(Variable::i64(x))),
+        Err(err) => Err(module.error(call.source_range, &err, rt))
+    }
+}
+
+fn read_float(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let format = try!(pop_text(rt, call, 2, module));
+    let pos = try!(pop_pos(rt, call, 1, module));
+    let b = try!(pop_bytes(rt, call, 0, module));
+    match bytes::Format::from_str(&format).and_then(|f| f.read_float(&b, pos)) {
+        Ok(x) => Ok(Some(Variable::f64(x))),
+        Err(err) => Err(module.error(call.source_range, &err, rt))
+    }
+}
+
+/// Writes to bytes referenced by first argument.
+fn write_bytes<F>(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+    f: F
+) -> Result<Option<Variable>, DyonError>
+    where F: FnOnce(bytes::Format, usize, &mut Vec<u8>) -> Result<(), String>
+{
+    let format = try!(pop_text(rt, call, 2, module));
+    let pos = try!(pop_pos(rt, call, 1, module));
+    let format = match bytes::Format::from_str(&format) {
+        Ok(x) => x,
+        Err(err) => return Err(module.error(call.args[2].source_range(), &err, rt))
+    };
+    let v = rt.stack.pop().expect(TINVOTS);
+    let ind = match v {
+        Variable::Ref(ind) => ind,
+        _ => return Err(module.error(call.args[0].source_range(),
+                        "Expected reference to bytes", rt))
+    };
+    let len = match rt.stack[ind] {
+        Variable::Bytes(ref b) => b.len(),
+        _ => return Err(module.error(call.args[0].source_range(),
+                        "Expected reference to bytes", rt))
+    };
+    if pos <= len && pos + format.size > len {
+        try!(rt.alloc(pos + format.size - len, call.source_range, module));
+    }
+    let res = if let Variable::Bytes(ref mut b) = rt.stack[ind] {
+        f(format, pos, Arc::make_mut(b))
+    } else {
+        unreachable!()
+    };
+    match res {
+        Ok(()) => Ok(None),
+        Err(err) => Err(module.error(call.source_range, &err, rt))
+    }
+}
+
+fn write_int(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let val = rt.stack.pop().expect(TINVOTS);
+    let val = match rt.resolve(&val) {
+        &Variable::I64(v) => v,
+        x => return Err(module.error(call.args[3].source_range(),
+                        &rt.expected(x, "i64"), rt))
+    };
+    write_bytes(rt, call, module, |format, pos, b| format.write_int(b, pos, val))
+}
+
+fn write_float(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let val = rt.stack.pop().expect(TINVOTS);
+    let val = match rt.resolve(&val) {
+        &Variable::F64(v, _) => v,
+        x => return Err(module.error(call.args[3].source_range(),
+                        &rt.expected(x, "f64"), rt))
+    };
+    write_bytes(rt, call, module, |format, pos, b| format.write_float(b, pos, val))
+}
+
+fn new_map(
+    _rt: &mut Runtime,
+    _call: &ast::Call,
+    _module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    Ok(Some(Variable::Map(Arc::new(HashMap::new()))))
+}
+
+/// Pops iterator from the stack.
+fn pop_iter(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    arg: usize,
+    module: &Arc<Module>,
+) -> Result<Iter, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::Iter(ref iter) => Ok((**iter).clone()),
+        x => Err(module.error(call.args[arg].source_range(),
+                 &rt.expected(x, "iter"), rt))
+    }
+}
+
+/// Pops closure from the stack.
+fn pop_closure(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    arg: usize,
+    module: &Arc<Module>,
+) -> Result<Variable, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        x @ &Variable::Closure(_, _) => Ok(x.clone()),
+        x => Err(module.error(call.args[arg].source_range(),
+                 &rt.expected(x, "closure"), rt))
+    }
+}
+
+/// Pops a number of items from the stack.
+fn pop_count(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    arg: usize,
+    module: &Arc<Module>,
+) -> Result<usize, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::F64(x, _) if x >= 0.0 => Ok(x as usize),
+        &Variable::F64(_, _) => Err(module.error(call.args[arg].source_range(),
+                                    "Expected non-negative number", rt)),
+        x => Err(module.error(call.args[arg].source_range(),
+                 &rt.expected(x, "number"), rt))
+    }
+}
+
+fn iter(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let v = rt.resolve(&v);
+    // The iterator shares the collection, but literals can hold references
+    // to the stack that must be copied since the iterator can outlive them.
+    let iter = if has_refs(v) {
+        Iter::from_variable(&v.deep_clone(&rt.stack))
+    } else {
+        Iter::from_variable(v)
+    };
+    match iter {
+        Some(iter) => Ok(Some(Variable::Iter(Box::new(iter)))),
+        None => Err(module.error(call.args[0].source_range(),
+                &rt.expected(v, "array, object, map, link or in"), rt))
+    }
+}
+
+/// Returns `true` if a value holds references to the stack.
+fn has_refs(v: &Variable) -> bool {
+    match *v {
+        Variable::Ref(_) => true,
+        Variable::Array(ref arr) => arr.iter().any(has_refs),
+        Variable::Object(ref obj) => obj.values().any(has_refs),
+        Variable::Map(ref map) => map.values().any(has_refs),
+        _ => false
+    }
+}
+
+fn map(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let f = try!(pop_closure(rt, call, 1, module));
+    let iter = try!(pop_iter(rt, call, 0, module));
+    Ok(Some(Variable::Iter(Box::new(Iter::MapWith(Box::new(iter), f)))))
+}
+
+fn filter(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let f = try!(pop_closure(rt, call, 1, module));
+    let iter = try!(pop_iter(rt, call, 0, module));
+    Ok(Some(Variable::Iter(Box::new(Iter::Filter(Box::new(iter), f)))))
+}
+
+fn fold(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let f = try!(pop_closure(rt, call, 2, module));
+    let init = rt.stack.pop().expect(TINVOTS);
+    let mut acc = rt.resolve(&init).deep_clone(&rt.stack);
+    let mut iter = try!(pop_iter(rt, call, 0, module));
+    while let Some(x) = try!(iter.next(rt, &call.name, call.source_range, module)) {
+        acc = match try!(rt.call_closure_args(&f, &[acc, x], call.name.clone(),
+                                              call.source_range, module)) {
+            Some(x) => rt.resolve(&x).clone(),
+            None => return Err(module.error(call.args[2].source_range(),
+                               "Expected closure to return a value", rt))
+        };
+    }
+    Ok(Some(acc))
+}
+
+fn take(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let n = try!(pop_count(rt, call, 1, module));
+    let iter = try!(pop_iter(rt, call, 0, module));
+    Ok(Some(Variable::Iter(Box::new(Iter::Take(Box::new(iter), n)))))
+}
+
+fn skip(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let n = try!(pop_count(rt, call, 1, module));
+    let iter = try!(pop_iter(rt, call, 0, module));
+    Ok(Some(Variable::Iter(Box::new(Iter::Skip(Box::new(iter), n)))))
+}
+
+fn zip(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let b = try!(pop_iter(rt, call, 1, module));
+    let a = try!(pop_iter(rt, call, 0, module));
+    Ok(Some(Variable::Iter(Box::new(Iter::Zip(Box::new(a), Box::new(b))))))
+}
+
+fn enumerate(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let iter = try!(pop_iter(rt, call, 0, module));
+    Ok(Some(Variable::Iter(Box::new(Iter::Enumerate(Box::new(iter), 0)))))
+}
+
+fn collect(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let mut iter = try!(pop_iter(rt, call, 0, module));
+    let mut arr = vec![];
+    while let Some(x) = try!(iter.next(rt, &call.name, call.source_range, module)) {
+        arr.push(x);
+    }
+    Ok(Some(Variable::Array(Arc::new(arr))))
+}
+
+fn flat_map(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let f = try!(pop_closure(rt, call, 1, module));
+    let iter = try!(pop_iter(rt, call, 0, module));
+    Ok(Some(Variable::Iter(Box::new(Iter::FlatMap(Box::new(iter), f, None)))))
+}
+
+fn split(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let sep = try!(pop_text(rt, call, 1, module));
+    let text = try!(pop_text(rt, call, 0, module));
+    if sep.len() == 0 {
+        return Err(module.error(call.args[1].source_range(),
+                   "Expected non-empty separator", rt));
+    }
+    let res = text.split(&**sep)
+        .map(|s| Variable::Text(Arc::new(s.into())))
+        .collect::<Vec<_>>();
+    Ok(Some(Variable::Array(Arc::new(res))))
+}
+
+fn join(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let sep = try!(pop_text(rt, call, 1, module));
+    let v = rt.stack.pop().expect(TINVOTS);
+    let arr = match rt.resolve(&v) {
+        &Variable::Array(ref arr) => arr.clone(),
+        x => return Err(module.error(call.args[0].source_range(),
+                        &rt.expected(x, "[str]"), rt))
+    };
+    let mut size = Some(0);
+    for (i, it) in arr.iter().enumerate() {
+        if i > 0 { size = size.and_then(|n: usize| n.checked_add(sep.len())); }
+        match rt.resolve(it) {
+            &Variable::Text(ref t) => size = size.and_then(|n| n.checked_add(t.len())),
+            x => return Err(module.error(call.args[0].source_range(),
+                            &rt.expected(x, "str"), rt))
+        }
+    }
+    let mut res = try!(alloc_text(rt, size, call.source_range, module));
+    for (i, it) in arr.iter().enumerate() {
+        if i > 0 { res.push_str(&sep); }
+        if let &Variable::Text(ref t) = rt.resolve(it) { res.push_str(t); }
+    }
+    Ok(Some(Variable::Text(Arc::new(res))))
+}
+
+fn starts_with(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let pat = try!(pop_text(rt, call, 1, module));
+    let text = try!(pop_text(rt, call, 0, module));
+    Ok(Some(Variable::bool(text.starts_with(&**pat))))
+}
+
+fn ends_with(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let pat = try!(pop_text(rt, call, 1, module));
+    let text = try!(pop_text(rt, call, 0, module));
+    Ok(Some(Variable::bool(text.ends_with(&**pat))))
+}
+
+fn contains(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let x = rt.stack.pop().expect(TINVOTS);
+    let v = rt.stack.pop().expect(TINVOTS);
+    let res = match rt.resolve(&v) {
+        &Variable::Text(ref text) => match rt.resolve(&x) {
+            &Variable::Text(ref pat) => text.contains(&***pat),
+            x => return Err(module.error(call.args[1].source_range(),
+                            &rt.expected(x, "str"), rt))
+        },
+        &Variable::Array(ref arr) => {
+            let x = rt.resolve(&x);
+            arr.iter().any(|it| rt.resolve(it) == x)
+        }
+        x => return Err(module.error(call.args[0].source_range(),
+                        &rt.expected(x, "str or array"), rt))
+    };
+    Ok(Some(Variable::bool(res)))
+}
+
+fn find(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let pat = try!(pop_text(rt, call, 1, module));
+    let text = try!(pop_text(rt, call, 0, module));
+    Ok(Some(Variable::Option(text::find(&text, &pat)
+        .map(|i| Box::new(Variable::f64(i as f64))))))
+}
+
+fn replace(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let to = try!(pop_text(rt, call, 2, module));
+    let from = try!(pop_text(rt, call, 1, module));
+    let text = try!(pop_text(rt, call, 0, module));
+    let count = text.matches(&**from).count();
+    let size = count.checked_mul(to.len())
+        .and_then(|n| n.checked_add(text.len() - count * from.len()));
+    let mut res = try!(alloc_text(rt, size, call.source_range, module));
+    let mut last = 0;
+    for (i, m) in text.match_indices(&**from) {
+        res.push_str(&text[last..i]);
+        res.push_str(&to);
+        last = i + m.len();
+    }
+    res.push_str(&text[last..]);
+    Ok(Some(Variable::Text(Arc::new(res))))
+}
+
+fn substr(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let end = try!(pop_pos(rt, call, 2, module));
+    let start = try!(pop_pos(rt, call, 1, module));
+    let text = try!(pop_text(rt, call, 0, module));
+    match text::substr(&text, start, end) {
+        Some(s) => Ok(Some(Variable::Text(Arc::new(s.into())))),
+        None => Err(module.error(call.source_range,
+            &format!("Out of bounds, can not take `{}..{}` from {} characters",
+                     start, end, text.chars().count()), rt))
+    }
+}
+
+fn upper(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let text = try!(pop_text(rt, call, 0, module));
+    Ok(Some(Variable::Text(Arc::new(text.to_uppercase()))))
+}
+
+fn lower(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let text = try!(pop_text(rt, call, 0, module));
+    Ok(Some(Variable::Text(Arc::new(text.to_lowercase()))))
+}
+
+fn pad(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    let width = match rt.resolve(&v) {
+        &Variable::F64(x, _) => x,
+        x => return Err(module.error(call.args[1].source_range(),
+                        &rt.expected(x, "f64"), rt))
+    };
+    let text = try!(pop_text(rt, call, 0, module));
+    let mut res = try!(alloc_text(rt, text::pad_len(&text, width), call.source_range, module));
+    text::pad(&text, width, &mut res);
+    Ok(Some(Variable::Text(Arc::new(res))))
+}
+
+fn repeat(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let n = try!(pop_count(rt, call, 1, module));
+    let text = try!(pop_text(rt, call, 0, module));
+    let mut res = try!(alloc_text(rt, text.len().checked_mul(n), call.source_range, module));
+    for _ in 0..n { res.push_str(&text); }
+    Ok(Some(Variable::Text(Arc::new(res))))
+}
+
+fn lines(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let text = try!(pop_text(rt, call, 0, module));
+    let res = text.lines()
+        .map(|s| Variable::Text(Arc::new(s.into())))
+        .collect::<Vec<_>>();
+    Ok(Some(Variable::Array(Arc::new(res))))
+}
+
+fn format(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    use self::text::Part;
+
+    let args = rt.stack.pop().expect(TINVOTS);
+    let template = try!(pop_text(rt, call, 0, module));
+    let args = rt.resolve(&args).clone();
+    match args {
+        Variable::Array(_) | Variable::Object(_) => {}
+        ref x => return Err(module.error(call.args[1].source_range(),
+                            &rt.expected(x, "array or object"), rt))
+    }
+    let parts = match text::format(&template) {
+        Ok(parts) => parts,
+        Err(err) => return Err(module.error(call.source_range, &err, rt))
+    };
+    // Arguments are written twice, first to compute the size of the text.
+    let mut size = Some(0);
+    let mut buf = String::new();
+    for part in &parts {
+        let n = match *part {
+            Part::Text(s) => s.len(),
+            Part::Arg(ref key, precision) => {
+                buf.clear();
+                if let Err(err) = format_arg(rt, &args, key, precision, &mut buf) {
+                    return Err(module.error(call.source_range, &err, rt));
+                }
+                buf.len()
+            }
+        };
+        size = size.and_then(|size: usize| size.checked_add(n));
+    }
+    let mut res = try!(alloc_text(rt, size, call.source_range, module));
+    for part in &parts {
+        match *part {
+            Part::Text(s) => res.push_str(s),
+            Part::Arg(ref key, precision) => {
+                let _ = format_arg(rt, &args, key, precision, &mut res);
+            }
+        }
+    }
+    Ok(Some(Variable::Text(Arc::new(res))))
+}
+
+/// Writes an argument of a format string.
+fn format_arg(
+    rt: &Runtime,
+    args: &Variable,
+    key: &text::Key,
+    precision: Option<usize>,
+    res: &mut String
+) -> Result<(), String> {
+    use write::{write_variable, EscapeString};
+    use self::text::Key;
+
+    let v = match (key, args) {
+        (&Key::Index(i), &Variable::Array(ref arr)) => match arr.get(i) {
+            Some(v) => v,
+            None => return Err(format!("There is no argument `{}`", i))
+        },
+        (&Key::Name(name), &Variable::Object(ref obj)) => match obj.get(&Arc::new(name.into())) {
+            Some(v) => v,
+            None => return Err(format!("There is no argument `{}`", name))
+        },
+        (&Key::Index(_), _) => return Err("Expected array for arguments by position".into()),
+        (&Key::Name(_), _) => return Err("Expected object for arguments by name".into()),
+    };
+    match (rt.resolve(v), precision) {
+        (&Variable::F64(x, _), Some(p)) => res.push_str(&format!("{:.*}", p, x)),
+        (x, Some(_)) => return Err(format!("Precision requires `f64`, found `{}`",
+                                           rt.typeof_var(x))),
+        (x, None) => {
+            let mut buf: Vec<u8> = vec![];
+            write_variable(&mut buf, rt, x, EscapeString::None, 0).unwrap();
+            res.push_str(&String::from_utf8(buf).unwrap());
+        }
+    }
+    Ok(())
+}
+
+/// Creates text with capacity for `size` bytes, charged to the heap limit.
+///
+/// Reports an error if the size overflows or the memory can not be allocated.
+fn alloc_text(
+    rt: &mut Runtime,
+    size: Option<usize>,
+    range: Range,
+    module: &Arc<Module>,
+) -> Result<String, DyonError> {
+    let size = match size {
+        Some(size) => size,
+        None => return Err(module.error(range, "Text is too large", rt))
+    };
+    try!(rt.alloc(size, range, module));
+    let mut res = String::new();
+    if res.try_reserve_exact(size).is_err() {
+        return Err(module.error(range,
+            &format!("Can not allocate {} bytes for text", size), rt));
+    }
+    Ok(res)
+}
+
+/// Pops a regular expression pattern from the stack.
+fn pop_regex(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    arg: usize,
+    module: &Arc<Module>,
+) -> Result<Regex, DyonError> {
+    let pattern = try!(pop_text(rt, call, arg, module));
+    Regex::new(&pattern).map_err(|err| module.error(call.args[arg].source_range(),
+        &format!("Invalid regular expression:\n{}", err), rt))
+}
+
+fn _match(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let re = try!(pop_regex(rt, call, 1, module));
+    let text = try!(pop_text(rt, call, 0, module));
+    Ok(Some(Variable::bool(re.is_match(&text))))
+}
+
+fn captures(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let re = try!(pop_regex(rt, call, 1, module));
+    let text = try!(pop_text(rt, call, 0, module));
+    Ok(Some(Variable::Option(re.captures(&text).map(|caps| {
+        let res = caps.iter()
+            .map(|m| Variable::Text(Arc::new(m.map(|m| m.as_str()).unwrap_or("").into())))
+            .collect::<Vec<_>>();
+        Box::new(Variable::Array(Arc::new(res)))
+    }))))
+}
+
+fn replace_all(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let with = try!(pop_text(rt, call, 2, module));
+    let re = try!(pop_regex(rt, call, 1, module));
+    let text = try!(pop_text(rt, call, 0, module));
+    // Replacements are expanded twice, first to compute the size of the text.
+    let all_caps: Vec<_> = re.captures_iter(&text).collect();
+    let mut size = Some(text.len());
+    let mut buf = String::new();
+    for caps in &all_caps {
+        buf.clear();
+        caps.expand(&with, &mut buf);
+        let m = caps.get(0).unwrap();
+        size = size.and_then(|size| (size - m.as_str().len()).checked_add(buf.len()));
+    }
+    let mut res = try!(alloc_text(rt, size, call.source_range, module));
+    let mut last = 0;
+    for caps in &all_caps {
+        let m = caps.get(0).unwrap();
+        res.push_str(&text[last..m.start()]);
+        caps.expand(&with, &mut res);
+        last = m.end();
+    }
+    res.push_str(&text[last..]);
+    Ok(Some(Variable::Text(Arc::new(res))))
+}
+
+/// Pops array from the stack.
+fn pop_array(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    arg: usize,
+    module: &Arc<Module>,
+) -> Result<Arc<Vec<Variable>>, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::Array(ref arr) => Ok(arr.clone()),
+        x => Err(module.error(call.args[arg].source_range(),
+                 &rt.expected(x, "array"), rt))
+    }
+}
+
+/// Pops reference to array from the stack and returns its stack index.
+fn pop_array_ref(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    arg: usize,
+    module: &Arc<Module>,
+) -> Result<usize, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    if let Variable::Ref(ind) = v {
+        if let Variable::Array(_) = rt.stack[ind] {
+            return Ok(ind);
+        }
+    }
+    Err(module.error(call.args[arg].source_range(),
+        "Expected reference to array", rt))
+}
+
+/// Compares two numbers or two strings.
+///
+/// Numbers are totally ordered, with NaN after all other numbers.
+fn compare_items(a: &Variable, b: &Variable) -> Option<Ordering> {
+    match (a, b) {
+        (&Variable::F64(a, _), &Variable::F64(b, _)) => Some(
+            a.partial_cmp(&b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))),
+        (&Variable::Text(ref a), &Variable::Text(ref b)) => Some(a.cmp(b)),
+        _ => None
+    }
+}
+
+/// Sorts items, keeping the order of equal items, with a comparison that can fail.
+fn merge_sort<F, E>(items: &mut Vec<Variable>, cmp: &mut F) -> Result<(), E>
+    where F: FnMut(&Variable, &Variable) -> Result<Ordering, E>
+{
+    let n = items.len();
+    if n <= 1 { return Ok(()); }
+    let mut right = items.split_off(n / 2);
+    try!(merge_sort(items, cmp));
+    try!(merge_sort(&mut right, cmp));
+    let left = ::std::mem::replace(items, Vec::with_capacity(n));
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    loop {
+        let take_left = match (left.peek(), right.peek()) {
+            (Some(a), Some(b)) => try!(cmp(a, b)) != Ordering::Greater,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        items.push(if take_left { left.next().unwrap() } else { right.next().unwrap() });
+    }
+    Ok(())
+}
+
+fn sort(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let ind = try!(pop_array_ref(rt, call, 0, module));
+    let ok = if let Variable::Array(ref mut arr) = rt.stack[ind] {
+        let ok = arr.windows(2).all(|w| compare_items(&w[0], &w[1]).is_some());
+        if ok {
+            Arc::make_mut(arr).sort_by(|a, b| compare_items(a, b).unwrap());
+        }
+        ok
+    } else { true };
+    if !ok {
+        return Err(module.error(call.args[0].source_range(),
+            "Expected array of numbers or array of strings", rt));
+    }
+    Ok(None)
+}
+
+fn sort_by(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let f = try!(pop_closure(rt, call, 1, module));
+    let ind = try!(pop_array_ref(rt, call, 0, module));
+    let mut items = if let Variable::Array(ref arr) = rt.stack[ind] {
+        (**arr).clone()
+    } else { vec![] };
+    try!(merge_sort(&mut items, &mut |a, b| {
+        match try!(rt.call_closure_args(&f, &[a.clone(), b.clone()], call.name.clone(),
+                                        call.source_range, module)) {
+            Some(x) => match *rt.resolve(&x) {
+                Variable::F64(x, _) if x < 0.0 => Ok(Ordering::Less),
+                Variable::F64(x, _) if x > 0.0 => Ok(Ordering::Greater),
+                Variable::F64(_, _) => Ok(Ordering::Equal),
+                ref x => Err(module.error(call.args[1].source_range(),
+                             &rt.expected(x, "f64"), rt))
+            },
+            None => Err(module.error(call.args[1].source_range(),
+                        "Expected closure to return a value", rt))
+        }
+    }));
+    rt.stack[ind] = Variable::Array(Arc::new(items));
+    Ok(None)
+}
+
+fn binary_search(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let x = rt.stack.pop().expect(TINVOTS);
+    let x = rt.resolve(&x).clone();
+    let arr = try!(pop_array(rt, call, 0, module));
+    let mut failed = false;
+    let res = arr.binary_search_by(|it| {
+        compare_items(rt.resolve(it), &x).unwrap_or_else(|| {
+            failed = true;
+            Ordering::Equal
+        })
+    });
+    if failed {
+        return Err(module.error(call.source_range,
+            "Expected array of numbers or array of strings, and item of same type", rt));
+    }
+    Ok(Some(Variable::Option(res.ok().map(|i| Box::new(Variable::f64(i as f64))))))
+}
+
+fn dedup(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let ind = try!(pop_array_ref(rt, call, 0, module));
+    if let Variable::Array(ref mut arr) = rt.stack[ind] {
+        Arc::make_mut(arr).dedup();
+    }
+    Ok(None)
+}
+
+fn slice(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let end = try!(pop_pos(rt, call, 2, module));
+    let start = try!(pop_pos(rt, call, 1, module));
+    let arr = try!(pop_array(rt, call, 0, module));
+    if start > end || end > arr.len() {
+        return Err(module.error(call.source_range,
+            &format!("Out of bounds, can not slice `{}..{}` from {} items",
+                     start, end, arr.len()), rt));
+    }
+    let v = Variable::Array(Arc::new(arr[start..end].iter()
+        .map(|it| rt.resolve(it).deep_clone(&rt.stack)).collect()));
+    try!(rt.alloc_var(&v, call.source_range, module));
+    Ok(Some(v))
+}
+
+fn concat(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let b = try!(pop_array(rt, call, 1, module));
+    let a = try!(pop_array(rt, call, 0, module));
+    let v = Variable::Array(Arc::new(a.iter().chain(b.iter())
+        .map(|it| rt.resolve(it).deep_clone(&rt.stack)).collect()));
+    try!(rt.alloc_var(&v, call.source_range, module));
+    Ok(Some(v))
+}
+
+fn extend(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let items = try!(pop_array(rt, call, 1, module));
+    let items = items.iter().map(|it| rt.resolve(it).deep_clone(&rt.stack)).collect::<Vec<_>>();
+    let bytes = items.iter().map(|it| size_of::<Variable>() + limits::alloc_size(it)).sum();
+    try!(rt.alloc(bytes, call.source_range, module));
+    let ind = try!(pop_array_ref(rt, call, 0, module));
+    if let Variable::Array(ref mut arr) = rt.stack[ind] {
+        Arc::make_mut(arr).extend(items);
+    }
+    Ok(None)
+}
+
+fn index_of(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let x = rt.stack.pop().expect(TINVOTS);
+    let arr = try!(pop_array(rt, call, 0, module));
+    let x = rt.resolve(&x);
+    Ok(Some(Variable::Option(arr.iter().position(|it| rt.resolve(it) == x)
+        .map(|i| Box::new(Variable::f64(i as f64))))))
+}
+
+fn truncate(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let n = try!(pop_count(rt, call, 1, module));
+    let ind = try!(pop_array_ref(rt, call, 0, module));
+    if let Variable::Array(ref mut arr) = rt.stack[ind] {
+        if n < arr.len() {
+            Arc::make_mut(arr).truncate(n);
+        }
+    }
+    Ok(None)
+}
+
+fn chunks(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let n = try!(pop_count(rt, call, 1, module));
+    let arr = try!(pop_array(rt, call, 0, module));
+    if n == 0 {
+        return Err(module.error(call.args[1].source_range(),
+                   "Expected chunk size greater than zero", rt));
+    }
+    let v = Variable::Array(Arc::new(arr.chunks(n)
+        .map(|chunk| Variable::Array(Arc::new(chunk.iter()
+            .map(|it| rt.resolve(it).deep_clone(&rt.stack)).collect())))
+        .collect()));
+    try!(rt.alloc_var(&v, call.source_range, module));
+    Ok(Some(v))
+}
+
+/// Number or vec4, for math functions that apply component-wise.
+#[derive(Clone, Copy)]
+enum Num {
+    F64(f64),
+    Vec4([f32; 4]),
+}
+
+impl Num {
+    /// Gets component, where a number is the same for all components.
+    fn get(&self, i: usize) -> f64 {
+        match *self {
+            Num::F64(x) => x,
+            Num::Vec4(v) => v[i] as f64,
+        }
+    }
+}
+
+/// Pops a number or vec4 from the stack.
+fn pop_num(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    arg: usize,
+    module: &Arc<Module>,
+) -> Result<Num, DyonError> {
+    let v = rt.stack.pop().expect(TINVOTS);
+    match rt.resolve(&v) {
+        &Variable::F64(x, _) => Ok(Num::F64(x)),
+        &Variable::Vec4(v) => Ok(Num::Vec4(v)),
+        x => Err(module.error(call.args[arg].source_range(),
+                 &rt.expected(x, "f64 or vec4"), rt))
+    }
+}
+
+/// Pops arguments that are numbers or vec4s and applies a function.
+///
+/// Returns a vec4 when some argument is a vec4,
+/// by applying the function per component.
+fn component_wise<F: Fn(&[f64]) -> f64>(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+    f: F
+) -> Result<Option<Variable>, DyonError> {
+    let n = call.args.len();
+    let mut args = [Num::F64(0.0); 3];
+    for i in (0..n).rev() {
+        args[i] = try!(pop_num(rt, call, i, module));
+    }
+    let args = &args[..n];
+    let mut xs = [0.0; 3];
+    if args.iter().any(|a| if let Num::Vec4(_) = *a { true } else { false }) {
+        let mut res = [0.0; 4];
+        for (i, r) in res.iter_mut().enumerate() {
+            for (x, a) in xs.iter_mut().zip(args) { *x = a.get(i); }
+            *r = f(&xs[..n]) as f32;
+        }
+        Ok(Some(Variable::Vec4(res)))
+    } else {
+        for (x, a) in xs.iter_mut().zip(args) { *x = a.get(0); }
+        Ok(Some(Variable::f64(f(&xs[..n]))))
+    }
+}
+
+fn sinh(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0].sinh())
+}
+
+fn cosh(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0].cosh())
+}
+
+fn tanh(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0].tanh())
+}
+
+fn clamp(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0].max(x[1]).min(x[2]))
+}
+
+fn lerp(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0] + (x[1] - x[0]) * x[2])
+}
+
+fn smoothstep(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| {
+        let t = ((x[2] - x[0]) / (x[1] - x[0])).max(0.0).min(1.0);
+        t * t * (3.0 - 2.0 * t)
+    })
+}
+
+fn sign(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| {
+        if x[0] > 0.0 { 1.0 } else if x[0] < 0.0 { -1.0 } else { x[0] }
+    })
+}
+
+fn fract(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0] - x[0].floor())
+}
+
+fn _mod(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0] - x[1] * (x[0] / x[1]).floor())
+}
+
+fn hypot(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0].hypot(x[1]))
+}
+
+fn cbrt(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0].cbrt())
+}
+
+fn deg(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0].to_degrees())
+}
+
+fn rad(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    component_wise(rt, call, module, |x| x[0].to_radians())
+}
+
+fn pi(
+    _rt: &mut Runtime,
+    _call: &ast::Call,
+    _module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    Ok(Some(Variable::f64(::std::f64::consts::PI)))
+}
+
+fn tau(
+    _rt: &mut Runtime,
+    _call: &ast::Call,
+    _module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    Ok(Some(Variable::f64(2.0 * ::std::f64::consts::PI)))
+}
+
+fn e(
+    _rt: &mut Runtime,
+    _call: &ast::Call,
+    _module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    Ok(Some(Variable::f64(::std::f64::consts::E)))
+}
+
+fn random_range(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    let max = rt.stack.pop().expect(TINVOTS);
+    let max = match rt.resolve(&max) {
+        &Variable::F64(x, _) => x,
+        x => return Err(module.error(call.args[1].source_range(),
+                        &rt.expected(x, "f64"), rt))
+    };
+    let min = rt.stack.pop().expect(TINVOTS);
+    let min = match rt.resolve(&min) {
+        &Variable::F64(x, _) => x,
+        x => return Err(module.error(call.args[0].source_range(),
+                        &rt.expected(x, "f64"), rt))
+    };
+    let r: f64 = rt.rng.gen();
+    Ok(Some(Variable::f64(min + (max - min) * r)))
+}
+
+fn seed(
+    rt: &mut Runtime,
+    call: &ast::Call,
+    module: &Arc<Module>,
+) -> Result<Option<Variable>, DyonError> {
+    use runtime::rng;
+
+    let v = rt.stack.pop().expect(TINVOTS);
+    let seed = match rt.resolve(&v) {
+        &Variable::F64(x, _) if x >= 0.0 && x.fract() == 0.0 => x as u64,
+        &Variable::F64(x, _) => return Err(module.error(call.args[0].source_range(),
+            &format!("Expected non-negative integer seed, found `{}`", x), rt)),
+        x => return Err(module.error(call.args[0].source_range(),
+                        &rt.expected(x, "f64"), rt))
+    };
+    rt.rng = rng::Rng::new(seed);
+    Ok(None)
+}
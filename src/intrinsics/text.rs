@@ -0,0 +1,150 @@
+//! Helper functions for text.
+
+/// Refers to an argument of a format string.
+pub enum Key<'a> {
+    /// Position of argument in array.
+    Index(usize),
+    /// Name of argument in object.
+    Name(&'a str),
+}
+
+/// The largest precision of numbers in a format string.
+pub const MAX_PRECISION: usize = 1000;
+
+/// Part of a format string.
+pub enum Part<'a> {
+    /// Text that is written as it is.
+    Text(&'a str),
+    /// Argument with optional precision.
+    Arg(Key<'a>, Option<usize>),
+}
+
+/// Parses a format string into parts of text and placeholders for arguments.
+///
+/// A placeholder `{}` refers to the next argument, `{0}` to an argument by position
+/// and `{name}` to an argument by name.
+/// The precision of numbers is set with `{:.2}`, `{0:.2}` or `{name:.2}`,
+/// up to `MAX_PRECISION` digits.
+/// Use `{{` and `}}` to write braces.
+pub fn format<'a>(template: &'a str) -> Result<Vec<Part<'a>>, String> {
+    let mut res = vec![];
+    let mut next = 0;
+    // Start of text that is not written yet.
+    let mut start = 0;
+    let mut chars = template.char_indices();
+    loop {
+        match chars.next() {
+            None => break,
+            Some((i, '{')) => {
+                push_text(&mut res, &template[start..i]);
+                loop {
+                    match chars.next() {
+                        None => return Err("Expected `}` in format string".into()),
+                        Some((j, '{')) if j == i + 1 => {
+                            // Write the second brace as text.
+                            start = j;
+                            break;
+                        }
+                        Some((j, '}')) => {
+                            let placeholder = &template[i + 1..j];
+                            let (name, spec) = match placeholder.find(':') {
+                                None => (placeholder, ""),
+                                Some(k) => (&placeholder[..k], &placeholder[k + 1..]),
+                            };
+                            let precision = if spec == "" { None } else {
+                                let n = if spec.starts_with('.') {
+                                    spec[1..].parse::<usize>().ok()
+                                } else { None };
+                                match n {
+                                    Some(n) if n <= MAX_PRECISION => Some(n),
+                                    Some(_) => return Err(format!(
+                                        "Precision in `{{{}}}` is larger than {}",
+                                        placeholder, MAX_PRECISION)),
+                                    None => return Err(format!(
+                                        "Unknown format `{{{}}}`, expected precision like `{{:.2}}`",
+                                        placeholder))
+                                }
+                            };
+                            let key = if name == "" {
+                                next += 1;
+                                Key::Index(next - 1)
+                            } else if let Ok(k) = name.parse::<usize>() {
+                                Key::Index(k)
+                            } else {
+                                Key::Name(name)
+                            };
+                            res.push(Part::Arg(key, precision));
+                            start = j + 1;
+                            break;
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+            Some((i, '}')) => {
+                match chars.next() {
+                    Some((j, '}')) => {
+                        // Write the second brace as text.
+                        push_text(&mut res, &template[start..i]);
+                        start = j;
+                    }
+                    _ => return Err("Unmatched `}` in format string, use `}}` to write `}`".into())
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    push_text(&mut res, &template[start..]);
+    Ok(res)
+}
+
+fn push_text<'a>(parts: &mut Vec<Part<'a>>, text: &'a str) {
+    if text.len() > 0 { parts.push(Part::Text(text)); }
+}
+
+/// Returns the characters from `start` to `end` (exclusive).
+///
+/// Returns `None` if the range is out of bounds.
+pub fn substr(text: &str, start: usize, end: usize) -> Option<&str> {
+    if start > end { return None; }
+    let mut indices = text.char_indices().map(|(i, _)| i).chain(Some(text.len()));
+    let a = match indices.nth(start) {
+        None => return None,
+        Some(a) => a,
+    };
+    let b = if start == end { a } else {
+        match indices.nth(end - start - 1) {
+            None => return None,
+            Some(b) => b,
+        }
+    };
+    Some(&text[a..b])
+}
+
+/// Returns the length in bytes of text padded to `width` characters.
+///
+/// Returns `None` if the length overflows.
+pub fn pad_len(text: &str, width: f64) -> Option<usize> {
+    let n = text.chars().count();
+    let w = width.abs();
+    if !(w > n as f64) { return Some(text.len()); }
+    if w >= usize::MAX as f64 { return None; }
+    (w as usize - n).checked_add(text.len())
+}
+
+/// Pads text with spaces to at least `width` characters.
+///
+/// A positive width aligns text to the right, a negative width aligns text to the left.
+pub fn pad(text: &str, width: f64, res: &mut String) {
+    let n = text.chars().count();
+    let w = width.abs() as usize;
+    let spaces = if n >= w { 0 } else { w - n };
+    if width < 0.0 { res.push_str(text); }
+    for _ in 0..spaces { res.push(' '); }
+    if !(width < 0.0) { res.push_str(text); }
+}
+
+/// Returns the character position of the first occurence of `pat`.
+pub fn find(text: &str, pat: &str) -> Option<usize> {
+    text.find(pat).map(|i| text[..i].chars().count())
+}
@@ -0,0 +1,223 @@
+//! Lazy sequences of items.
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
+use range::Range;
+
+use runtime::Runtime;
+use Array;
+use DyonError;
+use Link;
+use Map;
+use MapKey;
+use Module;
+use Object;
+use Variable;
+
+/// Lazy sequence of items.
+///
+/// Items are computed one at a time when the iterator is consumed,
+/// without building intermediate arrays.
+/// Since an iterator is a value, consuming a copy leaves the original unchanged.
+///
+/// Objects and maps give items `[key, value]` sorted by key.
+/// An `in` receiver gives the messages that are waiting, without blocking.
+#[derive(Debug, Clone)]
+pub enum Iter {
+    /// Items of an array, with index of next item.
+    Array(Array, usize),
+    /// Items of an object, with sorted keys and index of next key.
+    Object(Object, Arc<Vec<Arc<String>>>, usize),
+    /// Items of a map, with sorted keys and index of next key.
+    Map(Map, Arc<Vec<MapKey>>, usize),
+    /// Remaining items of a link.
+    Link(Link),
+    /// Messages of an `in` receiver.
+    In(Arc<Mutex<Receiver<Variable>>>),
+    /// Items passed through a closure.
+    MapWith(Box<Iter>, Variable),
+    /// Items for which a closure returns `true`.
+    Filter(Box<Iter>, Variable),
+    /// At most a number of items.
+    Take(Box<Iter>, usize),
+    /// Items after skipping a number of items.
+    Skip(Box<Iter>, usize),
+    /// Pairs `[a, b]` of items, until one of the iterators ends.
+    Zip(Box<Iter>, Box<Iter>),
+    /// Pairs `[index, item]`, with index of next item.
+    Enumerate(Box<Iter>, usize),
+    /// Items of sequences returned by a closure, with the current sequence.
+    FlatMap(Box<Iter>, Variable, Option<Box<Iter>>),
+}
+
+impl Iter {
+    /// Creates an iterator over items of a collection.
+    ///
+    /// Returns `None` if the variable can not be iterated.
+    /// The variable must be resolved.
+    pub fn from_variable(v: &Variable) -> Option<Iter> {
+        Some(match *v {
+            Variable::Array(ref arr) => Iter::Array(arr.clone(), 0),
+            Variable::Object(ref obj) => {
+                let mut keys: Vec<Arc<String>> = obj.keys().cloned().collect();
+                keys.sort();
+                Iter::Object(obj.clone(), Arc::new(keys), 0)
+            }
+            Variable::Map(ref map) => {
+                let mut keys: Vec<MapKey> = map.keys().cloned().collect();
+                keys.sort();
+                Iter::Map(map.clone(), Arc::new(keys), 0)
+            }
+            Variable::Link(ref link) => Iter::Link((**link).clone()),
+            Variable::In(ref receiver) => Iter::In(receiver.clone()),
+            Variable::Iter(ref iter) => (**iter).clone(),
+            _ => return None
+        })
+    }
+
+    /// Computes the next item.
+    ///
+    /// Closures are called with `name` on the call stack,
+    /// and errors are reported at `range`.
+    pub fn next(
+        &mut self,
+        rt: &mut Runtime,
+        name: &Arc<String>,
+        range: Range,
+        module: &Arc<Module>
+    ) -> Result<Option<Variable>, DyonError> {
+        use std::error::Error;
+
+        Ok(match *self {
+            Iter::Array(ref arr, ref mut i) => {
+                if *i < arr.len() {
+                    *i += 1;
+                    Some(arr[*i - 1].clone())
+                } else { None }
+            }
+            Iter::Object(ref obj, ref keys, ref mut i) => {
+                if *i < keys.len() {
+                    *i += 1;
+                    let key = &keys[*i - 1];
+                    Some(pair(Variable::Text(key.clone()), obj[key].clone()))
+                } else { None }
+            }
+            Iter::Map(ref map, ref keys, ref mut i) => {
+                if *i < keys.len() {
+                    *i += 1;
+                    let key = &keys[*i - 1];
+                    Some(pair(key.to_variable(), map[key].clone()))
+                } else { None }
+            }
+            Iter::Link(ref mut link) => {
+                match link.head() {
+                    None => None,
+                    Some(x) => {
+                        *link = link.tail();
+                        Some(*x)
+                    }
+                }
+            }
+            Iter::In(ref mutex) => {
+                match mutex.lock() {
+                    Ok(x) => x.try_recv().ok(),
+                    Err(err) => return Err(module.error(range,
+                        &format!("Can not lock In mutex:\n{}", err.description()), rt))
+                }
+            }
+            Iter::MapWith(ref mut iter, ref f) => {
+                match try!(iter.next(rt, name, range, module)) {
+                    None => None,
+                    Some(x) => Some(try!(call(rt, f, &[x], name, range, module)))
+                }
+            }
+            Iter::Filter(ref mut iter, ref f) => {
+                loop {
+                    let x = match try!(iter.next(rt, name, range, module)) {
+                        None => break None,
+                        Some(x) => x
+                    };
+                    match try!(call(rt, f, &[x.clone()], name, range, module)) {
+                        Variable::Bool(true, _) => break Some(x),
+                        Variable::Bool(false, _) => {}
+                        ref x => return Err(module.error(range,
+                            &rt.expected(x, "bool"), rt))
+                    }
+                }
+            }
+            Iter::Take(ref mut iter, ref mut n) => {
+                if *n == 0 { None } else {
+                    *n -= 1;
+                    try!(iter.next(rt, name, range, module))
+                }
+            }
+            Iter::Skip(ref mut iter, ref mut n) => {
+                while *n > 0 {
+                    *n -= 1;
+                    if try!(iter.next(rt, name, range, module)).is_none() {
+                        *n = 0;
+                        return Ok(None);
+                    }
+                }
+                try!(iter.next(rt, name, range, module))
+            }
+            Iter::Zip(ref mut a, ref mut b) => {
+                match try!(a.next(rt, name, range, module)) {
+                    None => None,
+                    Some(x) => match try!(b.next(rt, name, range, module)) {
+                        None => None,
+                        Some(y) => Some(pair(x, y))
+                    }
+                }
+            }
+            Iter::Enumerate(ref mut iter, ref mut i) => {
+                match try!(iter.next(rt, name, range, module)) {
+                    None => None,
+                    Some(x) => {
+                        *i += 1;
+                        Some(pair(Variable::f64((*i - 1) as f64), x))
+                    }
+                }
+            }
+            Iter::FlatMap(ref mut iter, ref f, ref mut inner) => {
+                loop {
+                    if let Some(ref mut inner) = *inner {
+                        if let Some(x) = try!(inner.next(rt, name, range, module)) {
+                            return Ok(Some(x));
+                        }
+                    }
+                    let x = match try!(iter.next(rt, name, range, module)) {
+                        None => break None,
+                        Some(x) => x
+                    };
+                    let v = try!(call(rt, f, &[x], name, range, module));
+                    *inner = match Iter::from_variable(&v) {
+                        Some(x) => Some(Box::new(x)),
+                        None => return Err(module.error(range,
+                            &rt.expected(&v, "iter"), rt))
+                    };
+                }
+            }
+        })
+    }
+}
+
+/// Creates an item `[a, b]`.
+fn pair(a: Variable, b: Variable) -> Variable {
+    Variable::Array(Arc::new(vec![a, b]))
+}
+
+/// Calls closure and returns its value.
+fn call(
+    rt: &mut Runtime,
+    f: &Variable,
+    args: &[Variable],
+    name: &Arc<String>,
+    range: Range,
+    module: &Arc<Module>
+) -> Result<Variable, DyonError> {
+    match try!(rt.call_closure_args(f, args, name.clone(), range, module)) {
+        Some(x) => Ok(rt.resolve(&x).clone()),
+        None => Err(module.error(range, "Expected closure to return a value", rt))
+    }
+}
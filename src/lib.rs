@@ -8,6 +8,7 @@ extern crate read_token;
 extern crate reqwest;
 #[macro_use]
 extern crate lazy_static;
+extern crate regex;
 
 use std::any::Any;
 use std::fmt;
@@ -25,23 +26,47 @@ pub mod prelude;
 pub mod embed;
 pub mod ty;
 pub mod link;
+pub mod map;
+pub mod iter;
 pub mod macros;
 pub mod vec4;
 pub mod write;
+pub mod error;
+pub mod capabilities;
+pub mod lsp;
+pub mod format;
+pub mod testing;
 
 mod grab;
 
 pub use runtime::Runtime;
+pub use runtime::limits::{Limit, Limits};
+pub use runtime::debugger::Debugger;
 pub use prelude::{Lt, Prelude, Dfn};
 pub use ty::Type;
 pub use link::Link;
+pub use map::MapKey;
+pub use iter::Iter;
 pub use vec4::Vec4;
+pub use error::{DyonError, ErrorKind, Frame};
+pub use capabilities::{Capabilities, Capability};
 
 /// A common error message when there is no value on the stack.
 pub const TINVOTS: &'static str = "There is no value on the stack";
 
 pub type Array = Arc<Vec<Variable>>;
 pub type Object = Arc<HashMap<Arc<String>, Variable>>;
+/// Map with keys of type `bool`, `f64`, `str` or `vec4`.
+///
+/// Data uses a tagged encoding for maps, with entries sorted by key,
+/// which is read back by `load_data`:
+///
+/// ```ignore
+/// {"$map": [[1, "one"], [2, "two"]]}
+/// ```
+///
+/// Objects with other keys, such as `{map: [...]}`, are read as objects.
+pub type Map = Arc<HashMap<MapKey, Variable>>;
 pub type RustObject = Arc<Mutex<Any>>;
 
 #[derive(Debug, Clone)]
@@ -54,11 +79,11 @@ pub struct Error {
 
 #[derive(Clone)]
 pub struct Thread {
-    pub handle: Option<Arc<Mutex<JoinHandle<Result<Variable, String>>>>>,
+    pub handle: Option<Arc<Mutex<JoinHandle<Result<Variable, DyonError>>>>>,
 }
 
 impl Thread {
-    pub fn new(handle: JoinHandle<Result<Variable, String>>) -> Thread {
+    pub fn new(handle: JoinHandle<Result<Variable, DyonError>>) -> Thread {
         Thread {
             handle: Some(Arc::new(Mutex::new(handle)))
         }
@@ -69,7 +94,7 @@ impl Thread {
     pub fn invalidate_handle(
         rt: &mut Runtime,
         var: Variable
-    ) -> Result<JoinHandle<Result<Variable, String>>, String> {
+    ) -> Result<JoinHandle<Result<Variable, DyonError>>, String> {
         use std::error::Error;
 
         let thread = match var {
@@ -106,6 +131,25 @@ impl fmt::Debug for Thread {
 #[derive(Debug, Clone)]
 pub struct UnsafeRef(*mut Variable);
 
+/// Value of an enum type declared in a script.
+///
+/// Data uses a tagged encoding for variants,
+/// which is read back by `load_data`:
+///
+/// ```ignore
+/// {"$enum": "Shape", "$variant": "Rect", "$payload": [2, 3]}
+/// ```
+///
+/// Objects with other keys, such as `{enum: "Shape", ...}`, are read as objects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    /// The name of the enum type.
+    pub ty: Arc<String>,
+    /// The name of the variant.
+    pub name: Arc<String>,
+    pub payload: Vec<Variable>,
+}
+
 #[derive(Clone)]
 pub struct ClosureEnvironment {
     pub module: Arc<Module>,
@@ -124,10 +168,18 @@ pub enum Variable {
     Return,
     Bool(bool, Option<Box<Vec<Variable>>>),
     F64(f64, Option<Box<Vec<Variable>>>),
+    /// Integer created with `i64(x)` or `parse_i64(text)`.
+    ///
+    /// There is no literal for `i64`, since number literals are `f64`.
+    /// Integers above 2^53 can not be written exactly as numbers,
+    /// so use `unwrap(parse_i64("9007199254740993"))` instead.
+    I64(i64),
     Vec4([f32; 4]),
     Text(Arc<String>),
+    Bytes(Arc<Vec<u8>>),
     Array(Array),
     Object(Object),
+    Map(Map),
     Link(Box<Link>),
     UnsafeRef(UnsafeRef),
     RustObject(RustObject),
@@ -137,6 +189,9 @@ pub enum Variable {
     // Stores closure AST, relative function index.
     Closure(Arc<ast::Closure>, Box<ClosureEnvironment>),
     In(Arc<Mutex<::std::sync::mpsc::Receiver<Variable>>>),
+    Variant(Box<Variant>),
+    /// Lazy sequence of items.
+    Iter(Box<Iter>),
 }
 
 /// This is requires because `UnsafeRef(*mut Variable)` can not be sent across threads.
@@ -153,15 +208,21 @@ impl Variable {
         Variable::Bool(val, None)
     }
 
+    pub fn i64(val: i64) -> Variable {
+        Variable::I64(val)
+    }
+
     fn deep_clone(&self, stack: &Vec<Variable>) -> Variable {
         use Variable::*;
 
         match *self {
             F64(_, _) => self.clone(),
+            I64(_) => self.clone(),
             Vec4(_) => self.clone(),
             Return => self.clone(),
             Bool(_, _) => self.clone(),
             Text(_) => self.clone(),
+            Bytes(_) => self.clone(),
             Object(ref obj) => {
                 let mut res = obj.clone();
                 for (_, val) in Arc::make_mut(&mut res) {
@@ -176,6 +237,13 @@ impl Variable {
                 }
                 Array(res)
             }
+            Map(ref map) => {
+                let mut res = map.clone();
+                for (_, val) in Arc::make_mut(&mut res) {
+                    *val = val.deep_clone(stack);
+                }
+                Map(res)
+            }
             Link(_) => self.clone(),
             Ref(ind) => {
                 stack[ind].deep_clone(stack)
@@ -192,19 +260,36 @@ impl Variable {
             Thread(_) => self.clone(),
             Closure(_, _) => self.clone(),
             In(_) => self.clone(),
+            Iter(_) => self.clone(),
+            // Variant constructors always use deep clone, so it does not contain references.
+            Variant(_) => self.clone(),
         }
     }
 }
 
+/// Compares values deeply, ignoring secrets.
+///
+/// Errors are equal when their messages are equal, ignoring the trace.
+/// References, Rust objects, threads, closures and in-types are never equal.
 impl PartialEq for Variable {
     fn eq(&self, other: &Variable) -> bool {
         match (self, other) {
             (&Variable::Return, _) => false,
             (&Variable::Bool(a, _), &Variable::Bool(b, _)) => a == b,
             (&Variable::F64(a, _), &Variable::F64(b, _)) => a == b,
+            (&Variable::I64(a), &Variable::I64(b)) => a == b,
+            (&Variable::Vec4(a), &Variable::Vec4(b)) => a == b,
             (&Variable::Text(ref a), &Variable::Text(ref b)) => a == b,
+            (&Variable::Bytes(ref a), &Variable::Bytes(ref b)) => a == b,
             (&Variable::Object(ref a), &Variable::Object(ref b)) => a == b,
+            (&Variable::Map(ref a), &Variable::Map(ref b)) => a == b,
             (&Variable::Array(ref a), &Variable::Array(ref b)) => a == b,
+            (&Variable::Link(ref a), &Variable::Link(ref b)) => a == b,
+            (&Variable::Option(ref a), &Variable::Option(ref b)) => a == b,
+            (&Variable::Result(Ok(ref a)), &Variable::Result(Ok(ref b))) => a == b,
+            (&Variable::Result(Err(ref a)), &Variable::Result(Err(ref b))) =>
+                a.message == b.message,
+            (&Variable::Variant(ref a), &Variable::Variant(ref b)) => a == b,
             (&Variable::Ref(_), _) => false,
             (&Variable::UnsafeRef(_), _) => false,
             (&Variable::RustObject(_), _) => false,
@@ -221,6 +306,8 @@ pub enum FnIndex {
     Loaded(isize),
     ExternalVoid(FnExternalRef),
     ExternalReturn(FnExternalRef),
+    /// Constructs a variant of an enum declared in a script, e.g. `Shape::Rect(2, 3)`.
+    Variant,
 }
 
 /// Used to store direct reference to external function.
@@ -260,6 +347,10 @@ pub struct Module {
     pub functions: Vec<ast::Function>,
     pub ext_prelude: Vec<FnExternal>,
     pub intrinsics: Arc<HashMap<Arc<String>, usize>>,
+    /// Capabilities allowed for scripts loaded into this module.
+    pub capabilities: Capabilities,
+    /// Record and enum types declared in loaded scripts.
+    pub records: HashMap<Arc<String>, Type>,
 }
 
 impl Module {
@@ -272,6 +363,8 @@ impl Module {
             functions: vec![],
             ext_prelude: vec![],
             intrinsics: intrinsics,
+            capabilities: Capabilities::all(),
+            records: HashMap::new(),
         }
     }
 
@@ -301,23 +394,48 @@ impl Module {
         }
     }
 
-    pub fn error(&self, range: Range, msg: &str, rt: &Runtime) -> String {
-        self.error_fnindex(range, msg, rt.call_stack.last().unwrap().index)
-    }
-
-    pub fn error_fnindex(&self, range: Range, msg: &str, fnindex: usize) -> String {
-        let source = &self.functions[fnindex].source;
-        self.error_source(range, msg, source)
-    }
-
-    pub fn error_source(&self, range: Range, msg: &str, source: &Arc<String>) -> String {
-        use piston_meta::ParseErrorHandler;
-
-        let mut w: Vec<u8> = vec![];
-        ParseErrorHandler::new(source)
-            .write_msg(&mut w, range, &format!("{}", msg))
-            .unwrap();
-        String::from_utf8(w).unwrap()
+    pub fn error(&self, range: Range, msg: &str, rt: &Runtime) -> DyonError {
+        self.error_call_stack(range, msg, &rt.call_stack)
+    }
+
+    /// Creates runtime error in the last function on the call stack.
+    pub fn error_call_stack(
+        &self,
+        range: Range,
+        msg: &str,
+        call_stack: &[runtime::Call]
+    ) -> DyonError {
+        let f = &self.functions[call_stack.last().unwrap().index];
+        self.error_source(range, msg, &f.source, call_stack)
+    }
+
+    /// Creates a line in the trace of an `err` value.
+    ///
+    /// The trace lists one function per line, so the call stack is left out.
+    pub fn error_trace(
+        &self,
+        range: Range,
+        msg: &str,
+        call_stack: &[runtime::Call]
+    ) -> String {
+        let mut err = self.error_call_stack(range, msg, call_stack);
+        err.call_stack.clear();
+        format!("{}", err)
+    }
+
+    pub fn error_source(
+        &self,
+        range: Range,
+        msg: &str,
+        source: &Arc<String>,
+        call_stack: &[runtime::Call]
+    ) -> DyonError {
+        let file = call_stack.last().and_then(|call| call.file.clone())
+            .unwrap_or_else(|| Arc::new(String::new()));
+        DyonError::new(ErrorKind::Runtime, msg.into())
+            .range(range)
+            .source(file, source.clone())
+            .call_stack(call_stack)
     }
 
     /// Adds a new extended prelude function.
@@ -336,7 +454,7 @@ impl Module {
 }
 
 /// Runs a program using a source file.
-pub fn run(source: &str) -> Result<(), String> {
+pub fn run(source: &str) -> Result<(), DyonError> {
     let mut module = Module::new_intrinsics(Arc::new(Prelude::new_intrinsics().functions));
     try!(load(source, &mut module));
     let mut runtime = runtime::Runtime::new();
@@ -345,7 +463,7 @@ pub fn run(source: &str) -> Result<(), String> {
 }
 
 /// Runs a program from a string.
-pub fn run_str(source: &str, d: Arc<String>) -> Result<(), String> {
+pub fn run_str(source: &str, d: Arc<String>) -> Result<(), DyonError> {
     let mut module = Module::new_intrinsics(Arc::new(Prelude::new_intrinsics().functions));
     try!(load_str(source, d, &mut module));
     let mut runtime = runtime::Runtime::new();
@@ -387,46 +505,57 @@ impl Call {
     }
 
     /// Run call without any return value.
-    pub fn run(&self, runtime: &mut Runtime, module: &Arc<Module>) -> Result<(), String> {
+    pub fn run(&self, runtime: &mut Runtime, module: &Arc<Module>) -> Result<(), DyonError> {
         runtime.call_str(&self.name, &self.args, module)
     }
 
     /// Run call with return value.
-    pub fn run_ret<T: embed::PopVariable>(&self, runtime: &mut Runtime, module: &Arc<Module>) -> Result<T, String> {
+    pub fn run_ret<T: embed::PopVariable>(
+        &self,
+        runtime: &mut Runtime,
+        module: &Arc<Module>
+    ) -> Result<T, DyonError> {
         let val = runtime.call_str_ret(&self.name, &self.args, module)?;
         T::pop_var(runtime, runtime.resolve(&val))
+            .map_err(|err| DyonError::new(ErrorKind::Runtime, err))
     }
 
     /// Convert return value to a Vec4 convertible type.
-    pub fn run_vec4<T: embed::ConvertVec4>(&self, runtime: &mut Runtime, module: &Arc<Module>) -> Result<T, String> {
+    pub fn run_vec4<T: embed::ConvertVec4>(
+        &self,
+        runtime: &mut Runtime,
+        module: &Arc<Module>
+    ) -> Result<T, DyonError> {
         let val = runtime.call_str_ret(&self.name, &self.args, module)?;
         match runtime.resolve(&val) {
             &Variable::Vec4(val) => Ok(T::from(val)),
-            x => Err(runtime.expected(x, "vec4"))
+            x => Err(DyonError::new(ErrorKind::Runtime, runtime.expected(x, "vec4")))
         }
     }
 }
 
 /// Loads source from file.
-pub fn load(source: &str, module: &mut Module) -> Result<(), String> {
+pub fn load(source: &str, module: &mut Module) -> Result<(), DyonError> {
     use std::fs::File;
     use std::io::Read;
 
-    let mut data_file = try!(File::open(source).map_err(|err|
-        format!("Could not open `{}`, {}", source, err)));
+    let mut data_file = try!(File::open(source).map_err(|err| {
+        let mut err = DyonError::new(ErrorKind::Io,
+            format!("Could not open `{}`, {}", source, err));
+        err.file = Some(Arc::new(source.into()));
+        err
+    }));
     let mut data = Arc::new(String::new());
     data_file.read_to_string(Arc::make_mut(&mut data)).unwrap();
     load_str(source, data, module)
 }
 
-/// Loads a source from string.
+/// Parses a source from string into meta data.
 ///
 /// - source - The name of source file
 /// - d - The data of source file
-/// - module - The module to load the source
-pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(), String> {
-    use std::thread;
-    use piston_meta::{parse_errstr, syntax_errstr, Syntax};
+pub fn parse_str(source: &str, d: &Arc<String>) -> Result<Vec<Range<MetaData>>, DyonError> {
+    use piston_meta::{parse, syntax_errstr, ParseError, Syntax};
 
     lazy_static! {
         static ref SYNTAX_RULES: Result<Syntax, String> = {
@@ -436,12 +565,44 @@ pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(),
     }
 
     let syntax_rules = try!(SYNTAX_RULES.as_ref()
-        .map_err(|err| err.clone()));
+        .map_err(|err| DyonError::new(ErrorKind::Syntax, err.clone())));
 
+    let file: Arc<String> = Arc::new(source.into());
     let mut data = vec![];
-    try!(parse_errstr(syntax_rules, &d, &mut data).map_err(
-        |err| format!("In `{}:`\n{}", source, err)
-    ));
+    if let Err(range_err) = parse(syntax_rules, d, &mut data) {
+        let (range, parse_err) = range_err.decouple();
+        let mut err = DyonError::new(ErrorKind::Syntax, format!("{}", parse_err))
+            .range(range)
+            .source(file, d.clone());
+        if let ParseError::ExpectedTag(_, _) = parse_err {
+            // Show lines before the error when forgetting a token at end of a line.
+            let lines: Vec<&str> = d.lines().collect();
+            if let Some((line, _)) = err.line_column() {
+                let mut prev_line = line - 1;
+                while prev_line > 0 {
+                    prev_line -= 1;
+                    if !lines[prev_line].chars().all(|c| c.is_whitespace()) { break; }
+                }
+                for i in prev_line..(line - 1) {
+                    err.notes.push(format!("{}: {}", i + 1, lines[i]));
+                }
+            }
+        }
+        return Err(err);
+    }
+    Ok(data)
+}
+
+/// Loads a source from string.
+///
+/// - source - The name of source file
+/// - d - The data of source file
+/// - module - The module to load the source
+pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(), DyonError> {
+    use std::thread;
+
+    let file: Arc<String> = Arc::new(source.into());
+    let data = try!(parse_str(source, &d));
 
     let check_data = data.clone();
     let prelude = Arc::new(Prelude::from_module(module));
@@ -466,19 +627,7 @@ pub fn load_str(source: &str, d: Arc<String>, module: &mut Module) -> Result<(),
                 }
             }
         }
-        Err(err_msg) => {
-            use std::io::Write;
-            use piston_meta::ParseErrorHandler;
-
-            let (range, msg) = err_msg.decouple();
-
-            let mut buf: Vec<u8> = vec![];
-            writeln!(&mut buf, "In `{}`:\n", source).unwrap();
-            ParseErrorHandler::new(&d)
-                .write_msg(&mut buf, range, &msg)
-                .unwrap();
-            return Err(String::from_utf8(buf).unwrap())
-        }
+        Err(err) => return Err(err.source(file, d.clone()))
     }
 
     check_ignored_meta_data(&conv_res, source, &d, &data, &ignored)
@@ -491,7 +640,7 @@ pub fn load_meta(
     d: Arc<String>,
     data: &[Range<MetaData>],
     module: &mut Module
-) -> Result<(), String> {
+) -> Result<(), DyonError> {
     // Convert to AST.
     let mut ignored = vec![];
     let conv_res = ast::convert(Arc::new(source.into()), d.clone(), &data, &mut ignored, module);
@@ -505,38 +654,32 @@ fn check_ignored_meta_data(
     d: &Arc<String>,
     data: &[Range<MetaData>],
     ignored: &[Range],
-) -> Result<(), String> {
+) -> Result<(), DyonError> {
     use piston_meta::json;
 
-    if ignored.len() > 0 || conv_res.is_err() {
-        use std::io::Write;
-        use piston_meta::ParseErrorHandler;
-
+    if ignored.len() > 0 {
         let mut buf: Vec<u8> = vec![];
-        if ignored.len() > 0 {
-            writeln!(&mut buf, "Some meta data was ignored in the syntax").unwrap();
-            writeln!(&mut buf, "START IGNORED").unwrap();
-            json::write(&mut buf, &data[ignored[0].iter()]).unwrap();
-            writeln!(&mut buf, "END IGNORED").unwrap();
-
-            writeln!(&mut buf, "In `{}`:\n", source).unwrap();
-            ParseErrorHandler::new(&d)
-                .write_msg(&mut buf,
-                           data[ignored[0].iter()][0].range(),
-                           "Could not understand this")
-                .unwrap();
-        }
+        json::write(&mut buf, &data[ignored[0].iter()]).unwrap();
+        let mut err = DyonError::new(ErrorKind::Conversion, "Could not understand this".into())
+            .range(data[ignored[0].iter()][0].range())
+            .source(Arc::new(source.into()), d.clone())
+            .note("Some meta data was ignored in the syntax".into())
+            .note(format!("START IGNORED\n{}\nEND IGNORED", String::from_utf8_lossy(&buf)));
         if let &Err(()) = conv_res {
-            writeln!(&mut buf, "Conversion error").unwrap();
+            err = err.note("Conversion error".into());
         }
-        return Err(String::from_utf8(buf).unwrap());
+        return Err(err);
+    } else if let &Err(()) = conv_res {
+        let mut err = DyonError::new(ErrorKind::Conversion, "Conversion error".into());
+        err.file = Some(Arc::new(source.into()));
+        return Err(err);
     }
 
     Ok(())
 }
 
 /// Reports and error to standard output.
-pub fn error(res: Result<(), String>) -> bool {
+pub fn error(res: Result<(), DyonError>) -> bool {
     match res {
         Err(err) => {
             println!("");
@@ -566,10 +709,13 @@ mod tests {
         Return,
         Bool(bool, Option<Box<Vec<Variable>>>),
         F64(f64, Option<Box<Vec<Variable>>>),
+        I64(i64),
         Vec4([f32; 4]),
         Text(Arc<String>),
+        Bytes(Arc<Vec<u8>>),
         Array(Array),
         Object(Object),
+        Map(Map),
         Link(Box<Link>),
         UnsafeRef(UnsafeRef),
         RustObject(RustObject),
@@ -628,6 +774,11 @@ mod tests {
         b.iter(|| run_bench("source/bench/call.dyon"));
     }
 
+    #[bench]
+    fn bench_collatz(b: &mut Bencher) {
+        b.iter(|| run_bench("source/bench/collatz.dyon"));
+    }
+
     #[bench]
     fn bench_n_body(b: &mut Bencher) {
         b.iter(|| run_bench("source/bench/n_body.dyon"));
@@ -9,6 +9,7 @@ pub enum Kind {
     Current,
     Block,
     Expr,
+    Bitwise,
     Add,
     Mul,
     Pow,
@@ -44,6 +45,7 @@ pub enum Kind {
     Vec4UnLoop,
     Start,
     End,
+    Collection,
     Init,
     Cond,
     ElseIfCond,
@@ -87,6 +89,22 @@ pub enum Kind {
     Grab,
     TryExpr,
     In,
+    Match,
+    MatchArm,
+    Guard,
+    Pattern,
+    SomePattern,
+    NonePattern,
+    OkPattern,
+    ErrPattern,
+    ObjectPattern,
+    ArrayPattern,
+    VariantPattern,
+    Record,
+    Field,
+    Enum,
+    Variant,
+    Payload,
 }
 
 impl Kind {
@@ -100,6 +118,7 @@ impl Kind {
             "current" => Kind::Current,
             "block" => Kind::Block,
             "expr" => Kind::Expr,
+            "bitwise" => Kind::Bitwise,
             "add" => Kind::Add,
             "mul" => Kind::Mul,
             "pow" => Kind::Pow,
@@ -136,6 +155,7 @@ impl Kind {
             "all" => Kind::All,
             "vec4_un_loop" => Kind::Vec4UnLoop,
             "end" => Kind::End,
+            "collection" => Kind::Collection,
             "init" => Kind::Init,
             "cond" => Kind::Cond,
             "else_if_cond" => Kind::ElseIfCond,
@@ -180,6 +200,22 @@ impl Kind {
             "grab" => Kind::Grab,
             "try_expr" => Kind::TryExpr,
             "in" => Kind::In,
+            "match" => Kind::Match,
+            "match_arm" => Kind::MatchArm,
+            "guard" => Kind::Guard,
+            "pattern" => Kind::Pattern,
+            "some_pattern" => Kind::SomePattern,
+            "none_pattern" => Kind::NonePattern,
+            "ok_pattern" => Kind::OkPattern,
+            "err_pattern" => Kind::ErrPattern,
+            "object_pattern" => Kind::ObjectPattern,
+            "array_pattern" => Kind::ArrayPattern,
+            "variant_pattern" => Kind::VariantPattern,
+            "record" => Kind::Record,
+            "field" => Kind::Field,
+            "enum" => Kind::Enum,
+            "variant" => Kind::Variant,
+            "payload" => Kind::Payload,
             _ => return None
         })
     }
@@ -203,6 +239,17 @@ impl Kind {
         }
     }
 
+    /// A pattern in a match arm, which can bind variables.
+    pub fn is_pattern(&self) -> bool {
+        use self::Kind::*;
+
+        match *self {
+            Pattern | SomePattern | NonePattern | OkPattern | ErrPattern |
+            ObjectPattern | ArrayPattern | VariantPattern => true,
+            _ => false
+        }
+    }
+
     pub fn is_block(&self) -> bool {
         use self::Kind::*;
 
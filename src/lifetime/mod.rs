@@ -13,6 +13,8 @@ use prelude::{Lt, Prelude};
 use ast::{AssignOp, UseLookup};
 
 use Type;
+use DyonError;
+use ErrorKind;
 
 mod kind;
 mod node;
@@ -24,9 +26,70 @@ mod typecheck;
 pub fn check(
     data: &[Range<MetaData>],
     prelude: &Prelude
-) -> Result<HashMap<Arc<String>, Type>, Range<String>> {
+) -> Result<HashMap<Arc<String>, Type>, DyonError> {
+    fn error(kind: ErrorKind, err: Range<String>) -> DyonError {
+        let (range, msg) = err.decouple();
+        DyonError::new(kind, msg).range(range)
+    }
+
     let mut nodes: Vec<Node> = vec![];
-    try!(convert_meta_data(&mut nodes, data));
+    let (functions, function_lookup, use_lookup) =
+        try!(check_lifetimes(&mut nodes, data, prelude)
+            .map_err(|err| error(ErrorKind::Lifetime, err)));
+
+    try!(check_capabilities(&nodes, prelude));
+
+    try!(typecheck::run(&mut nodes, prelude, &use_lookup)
+        .map_err(|err| error(ErrorKind::Type, err)));
+
+    // Copy refined return types to use in AST.
+    let mut refined_rets: HashMap<Arc<String>, Type> = HashMap::new();
+    for (name, &ind) in &function_lookup {
+        if let Some(ref ty) = nodes[functions[ind]].ty {
+            refined_rets.insert(name.clone(), ty.clone());
+        }
+    }
+
+    Ok(refined_rets)
+}
+
+/// Checks that calls to intrinsics and `go` are allowed by the capabilities.
+fn check_capabilities(nodes: &[Node], prelude: &Prelude) -> Result<(), DyonError> {
+    use Capability;
+
+    fn denied(cap: Capability, range: Range, msg: String) -> DyonError {
+        DyonError::new(ErrorKind::Capability(cap), msg).range(range)
+    }
+
+    for node in nodes {
+        match node.kind {
+            Kind::Call if node.declaration.is_none() && node.alias.is_none() => {
+                let name = node.name().expect("Expected name");
+                if let Some(cap) = prelude.capabilities.denied_intrinsic(name) {
+                    return Err(denied(cap, node.source, format!(
+                        "`{}` requires capability `{}`, which is denied",
+                        name, cap.name())));
+                }
+            }
+            Kind::Go if !prelude.capabilities.threads => {
+                return Err(denied(Capability::Threads, node.source, format!(
+                    "`go` requires capability `{}`, which is denied",
+                    Capability::Threads.name())));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks lifetime constraints.
+/// Returns functions, function lookup and use lookup for type checking.
+fn check_lifetimes(
+    nodes: &mut Vec<Node>,
+    data: &[Range<MetaData>],
+    prelude: &Prelude
+) -> Result<(Vec<usize>, HashMap<Arc<String>, usize>, UseLookup), Range<String>> {
+    try!(convert_meta_data(nodes, data));
 
     // Add mutability information to function names.
     for i in 0..nodes.len() {
@@ -184,7 +247,8 @@ pub fn check(
     let inferred: Vec<usize> = nodes.iter().enumerate()
         .filter(|&(_, n)| {
             n.kind.is_decl_loop() &&
-            n.find_child_by_kind(&nodes, Kind::End).is_none()
+            n.find_child_by_kind(&nodes, Kind::End).is_none() &&
+            n.find_child_by_kind(&nodes, Kind::Collection).is_none()
         })
         .map(|(i, _)| i)
         .collect();
@@ -205,7 +269,8 @@ pub fn check(
 
         'search: loop {
             if nodes[parent].kind.is_decl_loop() ||
-               nodes[parent].kind.is_decl_un_loop() {
+               nodes[parent].kind.is_decl_un_loop() ||
+               nodes[parent].kind == Kind::MatchArm {
                 let my_name = nodes[i].name().unwrap();
                 for name in &nodes[parent].names {
                     if name == my_name {
@@ -306,9 +371,18 @@ pub fn check(
                         nodes[i].declaration = Some(j);
                     }
                     None => {
+                        let name = nodes[i].name().expect("Expected name");
+                        // Numbers that can not be represented exactly by `f64`
+                        // are read as names.
+                        if name.chars().all(|c| c.is_digit(10)) {
+                            return Err(nodes[i].source.wrap(
+                                format!("Could not find declaration of `{}`\n\
+                                Number literals are `f64` and must be exact, \
+                                use `unwrap(parse_i64(\"{}\"))` for a larger `i64`",
+                                name, name)));
+                        }
                         return Err(nodes[i].source.wrap(
-                            format!("Could not find declaration of `{}`",
-                            nodes[i].name().expect("Expected name"))));
+                            format!("Could not find declaration of `{}`", name)));
                     }
                 }
             }
@@ -325,6 +399,8 @@ pub fn check(
                 let mut ch = i;
                 while let Some(parent) = nodes[ch].parent {
                     if nodes[parent].kind == Kind::Pow { continue 'item; }
+                    if nodes[parent].kind == Kind::Bitwise &&
+                       nodes[parent].children.len() > 1 { continue 'item; }
                     if nodes[parent].kind == Kind::Mul &&
                        nodes[parent].children.len() > 1 { continue 'item; }
                     if nodes[parent].kind == Kind::Add &&
@@ -344,6 +420,14 @@ pub fn check(
         }
     }
 
+    // Check that match expressions cover all cases of `opt` and `res`.
+    for node in nodes.iter().filter(|n| n.kind == Kind::Match) {
+        if let Some(missing) = node.missing_match_arm(&nodes, None) {
+            return Err(node.source.wrap(
+                format!("Non-exhaustive `match`, missing `{}`", missing)));
+        }
+    }
+
     // Check for duplicate function arguments.
     let mut arg_names: HashSet<Arc<String>> = HashSet::new();
     for &f in &functions {
@@ -388,6 +472,25 @@ pub fn check(
         }
     }
 
+    // Number of payload values of enum variants declared in this script or in the prelude.
+    let mut variants: HashMap<(Arc<String>, Arc<String>), usize> = HashMap::new();
+    for ty in prelude.records.values() {
+        if let Some((name, vs)) = ty.variants() {
+            for &(ref variant, ref tys) in vs {
+                variants.insert((name.clone(), variant.clone()), tys.len());
+            }
+        }
+    }
+    for node in nodes.iter().filter(|n| n.kind == Kind::Enum) {
+        let name = node.name().expect("Expected name");
+        for &ch in node.children.iter().filter(|&&ch| nodes[ch].kind == Kind::Variant) {
+            let variant = nodes[ch].name().expect("Expected name");
+            let n = nodes[ch].children.iter()
+                .filter(|&&p| nodes[p].kind == Kind::Payload).count();
+            variants.insert((name.clone(), variant.clone()), n);
+        }
+    }
+
     // Link call nodes to functions.
     for &c in &calls {
         let n = {
@@ -414,6 +517,18 @@ pub fn check(
             if let Some(&i) = use_lookup.aliases.get(alias).and_then(|map| map.get(&name)) {
                 node.lts = prelude.list[i].lts.clone();
                 continue;
+            } else if let Some(&len) = variants.get(&(alias.clone(), name.clone())) {
+                // Constructs an enum variant, which copies the payload.
+                if len != n {
+                    return Err(node.source.wrap(
+                        format!("{}::{}: Expected {} arguments, found {}",
+                        alias, name, len, n)));
+                }
+                node.lts = vec![Lt::Default; n];
+                continue;
+            } else if variants.keys().any(|&(ref ty, _)| ty == alias) {
+                return Err(node.source.wrap(
+                    format!("`{}` has no variant `{}`", alias, name)));
             } else {
                 return Err(node.source.wrap(
                     format!("Could not find function `{}::{}`", alias, name)));
@@ -809,17 +924,7 @@ pub fn check(
         }
     }
 
-    try!(typecheck::run(&mut nodes, prelude, &use_lookup));
-
-    // Copy refined return types to use in AST.
-    let mut refined_rets: HashMap<Arc<String>, Type> = HashMap::new();
-    for (name, &ind) in &function_lookup {
-        if let Some(ref ty) = nodes[functions[ind]].ty {
-            refined_rets.insert(name.clone(), ty.clone());
-        }
-    }
-
-    Ok(refined_rets)
+    Ok((functions, function_lookup, use_lookup))
 }
 
 // Search for suggestions using matching function signature.
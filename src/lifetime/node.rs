@@ -46,6 +46,8 @@ pub struct Node {
     /// The argument lifetime constraints, one for each argument to a function.
     /// Just using an empty vector for nodes that are not functions.
     pub lts: Vec<Lt>,
+    /// Type variables declared by a generic function.
+    pub generics: Vec<Arc<String>>,
 }
 
 impl Node {
@@ -90,6 +92,39 @@ impl Node {
         }
     }
 
+    /// Returns the names bound by a pattern.
+    pub fn bindings(&self) -> &[Arc<String>] {
+        // The first name of a variant pattern is the variant.
+        if self.kind == Kind::VariantPattern { &self.names[1..] } else { &self.names }
+    }
+
+    /// Returns `true` if the pattern matches any value.
+    pub fn is_irrefutable(&self) -> bool {
+        self.children.len() == 0 && self.ty.is_none()
+    }
+
+    /// Returns the first case of `opt`, `res` or an enum that is not covered
+    /// by arms without guards in a match expression.
+    ///
+    /// Patterns inside `some`, `ok`, `err` and enum variants are checked as well,
+    /// e.g. `ok(some(x))` and `ok(none())` together cover `ok(_)`.
+    /// When the type of matched value is unknown,
+    /// it is inferred from the patterns, except for enums.
+    pub fn missing_match_arm(&self, nodes: &[Node], ty: Option<&Type>) -> Option<String> {
+        let mut rows: Vec<Vec<Option<usize>>> = vec![];
+        for &arm in &self.children {
+            if nodes[arm].kind != Kind::MatchArm { continue; }
+            if nodes[arm].find_child_by_kind(nodes, Kind::Guard).is_some() { continue; }
+            if let Some(pattern) = nodes[arm].find_child_by_kind(nodes, Kind::Pattern) {
+                rows.push(vec![Some(pattern)]);
+            }
+        }
+        let ty = ty.cloned();
+        // Only `opt`, `res` and enums must be covered.
+        if constructors(nodes, &rows, &ty).is_none() { return None; }
+        missing_patterns(nodes, &rows, &[ty]).map(|mut missing| missing.remove(0))
+    }
+
     pub fn has_lifetime(&self) -> bool {
         use super::kind::Kind::*;
 
@@ -98,7 +133,7 @@ impl Node {
             Vec4 | Vec4UnLoop | Swizzle |
             Assign | For | ForN | Link | LinkFor |
             Closure | CallClosure | Grab | TryExpr | Norm | In => false,
-            Add | Mul | Compare => self.children.len() == 1,
+            Bitwise | Add | Mul | Compare => self.children.len() == 1,
             _ => true
         }
     }
@@ -116,6 +151,13 @@ impl Node {
                     return arg_lifetime(declaration, &arg, nodes, arg_names);
                 } else if arg.kind == Kind::Current {
                     return Some(Lifetime::Current(declaration));
+                } else if arg.kind == Kind::MatchArm {
+                    // Bindings in a pattern are parts of the matched value.
+                    let m = arg.parent.expect("Expected match");
+                    return match nodes[m].find_child_by_kind(nodes, Kind::Expr) {
+                        Some(expr) => nodes[expr].lifetime(nodes, arg_names),
+                        None => None
+                    };
                 } else {
                     return Some(Lifetime::Local(declaration));
                 }
@@ -182,10 +224,12 @@ impl Node {
                 (_, Kind::Vec4) => {}
                 (_, Kind::Start) => { continue }
                 (_, Kind::End) => { continue }
+                (_, Kind::Collection) => { continue }
                 (_, Kind::Assign) => {}
                 (_, Kind::Object) => {}
                 (_, Kind::KeyValue) => {}
                 (_, Kind::Val) => {}
+                (_, Kind::Bitwise) => {}
                 (_, Kind::Add) => {}
                 (_, Kind::Mul) => {}
                 (_, Kind::Call) => {}
@@ -209,6 +253,18 @@ impl Node {
                     // on the lifetime of the arguments.
                     continue
                 }
+                (Kind::Match, Kind::Expr) => {
+                    // The matched value is only used through bindings.
+                    continue
+                }
+                (_, Kind::Match) => {}
+                (_, Kind::MatchArm) => {}
+                (_, Kind::Pattern) => { continue }
+                (_, Kind::Guard) => {
+                    // A guard controls the flow, but the result does not
+                    // depend on its lifetime.
+                    continue
+                }
                 (_, Kind::Left) => {}
                 (_, Kind::Right) => {}
                 (_, Kind::Expr) => {}
@@ -320,6 +376,14 @@ pub fn convert_meta_data(
                 };
 
                 let parent = parents.last().map(|i| *i);
+                if kind == Kind::Id {
+                    if let Some(parent) = parent {
+                        // Expressions used as index are not fields.
+                        if nodes[parent].kind == Kind::ItemExtra {
+                            nodes[parent].names.push(Arc::new(String::new()));
+                        }
+                    }
+                }
                 parents.push(nodes.len());
                 nodes.push(Node {
                     kind: kind,
@@ -338,7 +402,8 @@ pub fn convert_meta_data(
                     declaration: None,
                     op: None,
                     binops: vec![],
-                    lts: vec![]
+                    lts: vec![],
+                    generics: vec![]
                 });
             }
             MetaData::EndNode(_) => {
@@ -348,6 +413,21 @@ pub fn convert_meta_data(
                     node.source = d.range();
                     node.end = i + 1;
                 }
+                // Collect bindings of patterns in the match arm.
+                if nodes[ind].kind.is_pattern() {
+                    if let Some(&parent) = parents.last() {
+                        if nodes[parent].kind.is_pattern() ||
+                           nodes[parent].kind == Kind::MatchArm {
+                            for name in nodes[ind].bindings().to_vec() {
+                                if nodes[parent].bindings().contains(&name) {
+                                    return Err(nodes[ind].source.wrap(
+                                        format!("Duplicate binding `{}` in pattern", name)));
+                                }
+                                nodes[parent].names.push(name);
+                            }
+                        }
+                    }
+                }
                 match parents.last() {
                     Some(&parent) => {
                         nodes[parent].children.push(ind);
@@ -384,6 +464,33 @@ pub fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].lifetime = Some(val.clone());
                     }
+                    "enum" => {
+                        // The enum of a variant pattern is stored like the alias of a call.
+                        let i = *parents.last().unwrap();
+                        nodes[i].alias = Some(val.clone());
+                    }
+                    "variant" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].names.push(val.clone());
+                    }
+                    "generic" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].generics.push(val.clone());
+                    }
+                    "key" => {
+                        // Keys of object literals are checked against records.
+                        let i = *parents.last().unwrap();
+                        if nodes[i].kind == Kind::KeyValue {
+                            nodes[i].names.push(val.clone());
+                        }
+                    }
+                    "id" => {
+                        // Fields are checked against records.
+                        let i = *parents.last().unwrap();
+                        if nodes[i].kind == Kind::ItemExtra {
+                            nodes[i].names.push(val.clone());
+                        }
+                    }
                     "text" => {
                         let i = *parents.last().unwrap();
                         nodes[i].ty = Some(Type::Text);
@@ -481,6 +588,26 @@ pub fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].binops.push(BinOp::AndAlso);
                     }
+                    "&" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::BitAnd);
+                    }
+                    "|" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::BitOr);
+                    }
+                    "xor" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::BitXor);
+                    }
+                    "<<" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::Shl);
+                    }
+                    ">>" => {
+                        let i = *parents.last().unwrap();
+                        nodes[i].binops.push(BinOp::Shr);
+                    }
                     _ => {}
                 }
             }
@@ -490,6 +617,13 @@ pub fn convert_meta_data(
                         let i = *parents.last().unwrap();
                         nodes[i].ty = Some(Type::F64);
                     }
+                    "id" => {
+                        // Indices are not fields.
+                        let i = *parents.last().unwrap();
+                        if nodes[i].kind == Kind::ItemExtra {
+                            nodes[i].names.push(Arc::new(String::new()));
+                        }
+                    }
                     "grab_level" => {
                         if val < 1.0 {
                             return Err(d.range()
@@ -505,3 +639,137 @@ pub fn convert_meta_data(
     }
     Ok(())
 }
+
+/// A case of `opt`, `res` or an enum that must be covered by a match expression.
+struct Constructor {
+    /// The kind of pattern that matches the case.
+    kind: Kind,
+    /// The name used when the case is missing.
+    name: String,
+    /// The variant of an enum.
+    variant: Option<Arc<String>>,
+    /// The types of the inner values.
+    tys: Vec<Option<Type>>,
+}
+
+/// Returns the pattern of `some`, `none`, `ok`, `err` or an enum variant,
+/// or `None` if the pattern is not one of those.
+fn pattern_constructor(nodes: &[Node], pattern: usize) -> Option<usize> {
+    nodes[pattern].children.iter().cloned().find(|&ch| match nodes[ch].kind {
+        Kind::SomePattern | Kind::NonePattern | Kind::OkPattern |
+        Kind::ErrPattern | Kind::VariantPattern => true,
+        _ => false
+    })
+}
+
+/// Returns the cases that must be covered by the first patterns of rows.
+///
+/// Returns `None` when any value must be covered,
+/// which is the case for types other than `opt`, `res` and enums.
+fn constructors(
+    nodes: &[Node],
+    rows: &[Vec<Option<usize>>],
+    ty: &Option<Type>
+) -> Option<Vec<Constructor>> {
+    let ctor = |kind, name: &str, tys| Constructor {
+        kind: kind, name: name.into(), variant: None, tys: tys
+    };
+    let opt = |inner| vec![
+        ctor(Kind::SomePattern, "some", vec![inner]),
+        ctor(Kind::NonePattern, "none", vec![]),
+    ];
+    let res = |inner| vec![
+        ctor(Kind::OkPattern, "ok", vec![inner]),
+        // The pattern of `err` is matched against the error message.
+        ctor(Kind::ErrPattern, "err", vec![None]),
+    ];
+    match *ty {
+        Some(Type::Option(ref inner)) => return Some(opt(Some((**inner).clone()))),
+        Some(Type::Result(ref inner)) => return Some(res(Some((**inner).clone()))),
+        _ => {}
+    }
+    if let Some((name, variants)) = ty.as_ref().and_then(|ty| ty.variants()) {
+        return Some(variants.iter().map(|&(ref variant, ref tys)| Constructor {
+            kind: Kind::VariantPattern,
+            name: format!("{}::{}", name, variant),
+            variant: Some(variant.clone()),
+            tys: tys.iter().map(|ty| Some(ty.clone())).collect(),
+        }).collect());
+    }
+    for row in rows {
+        let ch = match row[0].and_then(|pattern| pattern_constructor(nodes, pattern)) {
+            None => continue,
+            Some(x) => x
+        };
+        match nodes[ch].kind {
+            Kind::SomePattern | Kind::NonePattern => return Some(opt(None)),
+            Kind::OkPattern | Kind::ErrPattern => return Some(res(None)),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns an example of values that are not matched by any row of patterns,
+/// with one value per type.
+///
+/// `None` in a row is a pattern that matches any value.
+fn missing_patterns(
+    nodes: &[Node],
+    rows: &[Vec<Option<usize>>],
+    tys: &[Option<Type>]
+) -> Option<Vec<String>> {
+    if tys.len() == 0 {
+        return if rows.len() == 0 { Some(vec![]) } else { None };
+    }
+
+    let ctors = match constructors(nodes, rows, &tys[0]) {
+        None => {
+            // Only patterns that match any value cover the first value.
+            let rows: Vec<Vec<Option<usize>>> = rows.iter()
+                .filter(|row| row[0].map(|p| nodes[p].is_irrefutable()).unwrap_or(true))
+                .map(|row| row[1..].to_vec()).collect();
+            return missing_patterns(nodes, &rows, &tys[1..]).map(|mut missing| {
+                missing.insert(0, "_".into());
+                missing
+            });
+        }
+        Some(x) => x
+    };
+    for ctor in &ctors {
+        // Replace the first pattern in each row matching the case with its inner patterns.
+        let mut inner_rows: Vec<Vec<Option<usize>>> = vec![];
+        for row in rows {
+            let inner: Vec<Option<usize>> = match row[0] {
+                Some(pattern) if !nodes[pattern].is_irrefutable() => {
+                    let ch = match pattern_constructor(nodes, pattern) {
+                        None => continue,
+                        Some(x) => x
+                    };
+                    if nodes[ch].kind != ctor.kind { continue; }
+                    match ctor.kind {
+                        Kind::NonePattern => vec![],
+                        Kind::VariantPattern => {
+                            if nodes[ch].name() != ctor.variant.as_ref() { continue; }
+                            nodes[ch].children.iter().cloned()
+                                .filter(|&p| nodes[p].kind == Kind::Pattern)
+                                .map(Some).collect()
+                        }
+                        _ => vec![Some(ch)],
+                    }
+                }
+                _ => vec![None; ctor.tys.len()],
+            };
+            if inner.len() != ctor.tys.len() { continue; }
+            inner_rows.push(inner.into_iter().chain(row[1..].iter().cloned()).collect());
+        }
+        let inner_tys: Vec<Option<Type>> = ctor.tys.iter().chain(tys[1..].iter()).cloned().collect();
+        if let Some(mut missing) = missing_patterns(nodes, &inner_rows, &inner_tys) {
+            let rest = missing.split_off(ctor.tys.len());
+            let mut res = vec![format!("{}({})", ctor.name, missing.join(", "))];
+            res.extend(rest);
+            return Some(res);
+        }
+    }
+    None
+}
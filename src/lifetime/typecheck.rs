@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use range::Range;
 use super::node::Node;
 use super::kind::Kind;
+use Dfn;
 use Prelude;
 use Type;
 use ast::UseLookup;
@@ -46,8 +50,17 @@ use ast::UseLookup;
 /// After type propagation, all blocks in the `if` expression should have some type information,
 /// but no further propagation is necessary, so it only need to check for consistency.
 pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) -> Result<(), Range<String>> {
+    resolve_generics(nodes);
+    let records = try!(resolve_records(nodes, prelude));
+
+    // Object literals passed as arguments, checked after type propagation.
+    let mut object_args: Vec<(usize, Type)> = vec![];
+
     // Type propagation.
     let mut changed;
+    // Whether calls to generic functions can ignore arguments of unknown type.
+    // This is enabled when nothing else can be inferred.
+    let mut partial_generics = false;
     loop {
         changed = false;
         'node: for i in 0..nodes.len() {
@@ -134,12 +147,15 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                                 let arg = nodes[decl].children[j];
                                 match (&expr_type, &nodes[arg].ty) {
                                     (&Some(ref ch_ty), &Some(ref arg_ty)) => {
-                                        if !arg_ty.goes_with(ch_ty) {
+                                        // Generic arguments are checked by the call.
+                                        if !arg_ty.has_generics() &&
+                                           !arg_ty.goes_with(ch_ty) {
                                             return Err(nodes[i].source.wrap(
                                                 format!("Type mismatch (#100):\n\
                                                     Expected `{}`, found `{}`",
                                                     arg_ty.description(), ch_ty.description())));
                                         }
+                                        object_args.push((ch, arg_ty.clone()));
                                     }
                                     (&None, _) | (_, &None) => {}
                                 }
@@ -148,26 +164,42 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                                 .and_then(|map| map.get(nodes[parent].name().unwrap())) {
                                     let f = &prelude.list[f];
                                     if let Some(ref ty) = expr_type {
-                                        if !f.tys[j].goes_with(ty) {
+                                        if !f.tys[j].has_generics() && !f.tys[j].goes_with(ty) {
                                             return Err(nodes[i].source.wrap(
                                                 format!("Type mismatch (#150):\n\
                                                     Expected `{}`, found `{}`",
                                                     f.tys[j].description(), ty.description())
                                             ))
                                         }
+                                        object_args.push((ch, f.tys[j].clone()));
+                                    }
+                                } else if let Some(tys) = variant_payload(
+                                    &records, alias, nodes[parent].name().unwrap()) {
+                                    if let Some(ref ty) = expr_type {
+                                        if !tys[j].goes_with(ty) {
+                                            return Err(nodes[i].source.wrap(
+                                                format!("Type mismatch (#2600):\n\
+                                                    Expected `{}`, found `{}`",
+                                                    tys[j].description(), ty.description())
+                                            ))
+                                        }
+                                        object_args.push((ch, tys[j].clone()));
                                     }
                                 }
                             } else if let Some(&f) = prelude.functions.get(
                                     nodes[parent].name().unwrap()) {
+                                // Overloaded functions are checked by the call.
+                                if prelude.overloads.contains_key(&f) { continue; }
                                 let f = &prelude.list[f];
                                 if let Some(ref ty) = expr_type {
-                                    if !f.tys[j].goes_with(ty) {
+                                    if !f.tys[j].has_generics() && !f.tys[j].goes_with(ty) {
                                         return Err(nodes[i].source.wrap(
                                             format!("Type mismatch (#200):\n\
                                                 Expected `{}`, found `{}`",
                                                 f.tys[j].description(), ty.description())
                                         ))
                                     }
+                                    object_args.push((ch, f.tys[j].clone()));
                                 }
                             }
                         }
@@ -175,17 +207,49 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                     this_ty = expr_type;
                 }
                 Kind::Call => {
+                    // Argument types of generic functions.
+                    let mut generic_tys: Option<Vec<Type>> = None;
                     if let Some(decl) = nodes[i].declaration {
                         if let Some(ref ty) = nodes[decl].ty {
                             this_ty = Some(ty.clone());
+                            let tys: Vec<Type> = nodes[decl].children.iter()
+                                .filter(|&&arg| nodes[arg].kind == Kind::Arg)
+                                .map(|&arg| nodes[arg].ty.clone().unwrap_or(Type::Any))
+                                .collect();
+                            if ty.has_generics() || tys.iter().any(|ty| ty.has_generics()) {
+                                generic_tys = Some(tys);
+                            }
                         }
                     } else if let Some(ref alias) = nodes[i].alias {
                         if let Some(&f) = use_lookup.aliases.get(alias)
                         .and_then(|map| map.get(nodes[i].name().unwrap())) {
-                            this_ty = Some(prelude.list[f].ret.clone());
+                            let f = &prelude.list[f];
+                            this_ty = Some(f.ret.clone());
+                            if f.is_generic() { generic_tys = Some(f.tys.clone()); }
+                        } else if let Some(ty) = records.get(alias) {
+                            // Constructs an enum variant.
+                            this_ty = Some(ty.clone());
                         }
                     } else if let Some(&f) = prelude.functions.get(nodes[i].name().unwrap()) {
-                        this_ty = Some(prelude.list[f].ret.clone());
+                        if prelude.overloads.contains_key(&f) {
+                            let signatures = prelude.signatures(f);
+                            match try!(check_overloads(i, &signatures, nodes, partial_generics)) {
+                                None => continue 'node,
+                                Some(ty) => this_ty = Some(ty),
+                            }
+                        } else {
+                            let f = &prelude.list[f];
+                            this_ty = Some(f.ret.clone());
+                            if f.is_generic() { generic_tys = Some(f.tys.clone()); }
+                        }
+                    }
+                    if let Some(tys) = generic_tys {
+                        match try!(bind_generics(i, &tys, nodes, partial_generics)) {
+                            None => continue 'node,
+                            Some(bindings) => {
+                                this_ty = this_ty.map(|ty| ty.substitute(&bindings));
+                            }
+                        }
                     }
                 }
                 Kind::CallClosure => {
@@ -234,6 +298,31 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                     if nodes[i].item_ids() { continue 'node; }
                     if let Some(decl) = nodes[i].declaration {
                         match nodes[decl].kind {
+                            _ if nodes[decl].kind.is_decl_loop() &&
+                                 nodes[decl].find_child_by_kind(nodes, Kind::Collection)
+                                    .is_some() => {
+                                let coll = nodes[decl]
+                                    .find_child_by_kind(nodes, Kind::Collection).unwrap();
+                                let (key_ty, value_ty) = match nodes[coll].ty {
+                                    None => continue 'node,
+                                    Some(Type::Array(ref ty)) => (Type::F64, (**ty).clone()),
+                                    Some(Type::Object) => (Type::Text, Type::Any),
+                                    Some(Type::Map(ref key, ref value)) =>
+                                        ((**key).clone(), (**value).clone()),
+                                    Some(Type::Link) => (Type::F64, Type::Any),
+                                    Some(Type::Any) => (Type::Any, Type::Any),
+                                    Some(ref ty) => return Err(nodes[coll].source.wrap(
+                                        format!("Type mismatch (#3000):\n\
+                                        Expected `array`, `object`, `map` or `link`, found `{}`",
+                                        ty.description())))
+                                };
+                                // The value is the last name, the key comes before it.
+                                this_ty = Some(if nodes[decl].names.last() == nodes[i].name() {
+                                    value_ty
+                                } else {
+                                    key_ty
+                                });
+                            }
                             Kind::Sum | Kind::Min | Kind::Max |
                             Kind::Any | Kind::All | Kind::Sift |
                             Kind::Vec4UnLoop |
@@ -250,6 +339,10 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                                 this_ty = Some(nodes[i].inner_type(nodes[decl].ty.as_ref()
                                     .unwrap_or(&Type::Any)));
                             }
+                            Kind::MatchArm => {
+                                // Bindings in patterns can have any type.
+                                this_ty = Some(Type::Any);
+                            }
                             _ => {
                                 if let Some(ref ty) = nodes[decl].ty {
                                     this_ty = Some(nodes[i].inner_type(ty));
@@ -276,7 +369,8 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                 }
                 Kind::Return | Kind::Val | Kind::Expr | Kind::Cond |
                 Kind::Exp | Kind::Base | Kind::Left | Kind::Right |
-                Kind::ElseIfCond | Kind::UnOp | Kind::Grab
+                Kind::ElseIfCond | Kind::UnOp | Kind::Grab | Kind::Guard |
+                Kind::Collection
                  => {
                      // TODO: Report error for expected unary operator.
                     if nodes[i].children.len() == 0 { continue 'node; }
@@ -345,7 +439,7 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                     }
                     this_ty = it_ty;
                 }
-                Kind::Mul => {
+                Kind::Mul | Kind::Bitwise => {
                     if nodes[i].binops.len() + 1 != nodes[i].children.len() {
                         return Err(nodes[i].source.wrap(
                             format!("Type mismatch (#450):\n\
@@ -478,6 +572,20 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
 
                     this_ty = Some(true_type);
                 }
+                Kind::MatchArm => {
+                    if let Some(ch) = nodes[i].find_child_by_kind(nodes, Kind::Expr) {
+                        if nodes[ch].item_ids() { continue 'node; }
+                        this_ty = nodes[ch].ty.clone();
+                    }
+                }
+                Kind::Match => {
+                    // The type of matches are inferred from the first arm.
+                    let arm = match nodes[i].find_child_by_kind(nodes, Kind::MatchArm) {
+                        None => continue 'node,
+                        Some(arm) => arm
+                    };
+                    this_ty = nodes[arm].ty.clone();
+                }
                 Kind::Arg => {
                     this_ty = Some(Type::Any);
                 }
@@ -519,10 +627,18 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
                 changed = true;
             }
         }
-        if !changed { break; }
+        if !changed {
+            if partial_generics { break; }
+            partial_generics = true;
+        } else {
+            partial_generics = false;
+        }
     }
 
     // After type propagation.
+    for &(obj, ref ty) in &object_args {
+        try!(check_object(obj, ty, nodes));
+    }
     for i in 0..nodes.len() {
         let kind = nodes[i].kind;
         match kind {
@@ -578,6 +694,15 @@ pub fn run(nodes: &mut Vec<Node>, prelude: &Prelude, use_lookup: &UseLookup) ->
             Kind::If => {
                 try!(check_if(i, nodes))
             }
+            Kind::Match => {
+                try!(check_match(i, nodes))
+            }
+            Kind::VariantPattern => {
+                try!(check_variant_pattern(i, nodes, &records))
+            }
+            Kind::Item => {
+                try!(check_fields(i, nodes))
+            }
             Kind::Assign => {
                 use ast::AssignOp;
 
@@ -660,6 +785,9 @@ fn check_fn(
                                 ty.description(), ret_ty.description())));
                     }
                 }
+                if let Some(&obj) = nodes[ch].children.first() {
+                    try!(check_object(obj, ty, nodes));
+                }
                 *found_return = true;
             }
             Kind::ReturnVoid => {
@@ -740,3 +868,359 @@ fn check_if(n: usize, nodes: &Vec<Node>) -> Result<(), Range<String>> {
 
     Ok(())
 }
+
+fn check_match(n: usize, nodes: &Vec<Node>) -> Result<(), Range<String>> {
+    for &ch in &nodes[n].children {
+        if nodes[ch].kind != Kind::MatchArm { continue; }
+        if let Some(guard) = nodes[ch].find_child_by_kind(nodes, Kind::Guard) {
+            if let Some(ref guard_ty) = nodes[guard].ty {
+                if !Type::Bool.goes_with(guard_ty) {
+                    return Err(nodes[guard].source.wrap(
+                        format!("Type mismatch (#1800):\nExpected `{}`, found `{}`",
+                            Type::Bool.description(), guard_ty.description())));
+                }
+            }
+        }
+        if let (&Some(ref match_type), &Some(ref arm_type)) = (&nodes[n].ty, &nodes[ch].ty) {
+            if !arm_type.goes_with(match_type) {
+                return Err(nodes[ch].source.wrap(
+                    format!("Type mismatch (#1900):\nExpected `{}`, found `{}`",
+                        match_type.description(), arm_type.description())));
+            }
+        }
+    }
+
+    if let Some(expr) = nodes[n].find_child_by_kind(nodes, Kind::Expr) {
+        if let Some(ref ty) = nodes[expr].ty {
+            if let Some((name, _)) = ty.variants() {
+                for &arm in &nodes[n].children {
+                    let pattern = match nodes[arm].find_child_by_kind(nodes, Kind::Pattern) {
+                        None => continue,
+                        Some(x) => x
+                    };
+                    let vp = match nodes[pattern].find_child_by_kind(nodes, Kind::VariantPattern) {
+                        None => continue,
+                        Some(x) => x
+                    };
+                    if let Some(ref alias) = nodes[vp].alias {
+                        if alias != name {
+                            return Err(nodes[vp].source.wrap(
+                                format!("Type mismatch (#2900):\nExpected `{}`, found `{}`",
+                                    name, alias)));
+                        }
+                    }
+                }
+            }
+            if let Some(missing) = nodes[n].missing_match_arm(nodes, Some(ty)) {
+                return Err(nodes[n].source.wrap(
+                    format!("Type mismatch (#2000):\n\
+                        Non-exhaustive `match` on `{}`, missing `{}`",
+                        ty.description(), missing)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the payload types of an enum variant.
+fn variant_payload<'a>(
+    records: &'a HashMap<Arc<String>, Type>,
+    name: &Arc<String>,
+    variant: &Arc<String>
+) -> Option<&'a [Type]> {
+    records.get(name).and_then(|ty| ty.variants())
+        .and_then(|(_, variants)| variants.iter().find(|&&(ref v, _)| v == variant))
+        .map(|&(_, ref tys)| &tys[..])
+}
+
+/// Checks that a variant pattern refers to a variant with the same payload length.
+fn check_variant_pattern(
+    i: usize,
+    nodes: &Vec<Node>,
+    records: &HashMap<Arc<String>, Type>
+) -> Result<(), Range<String>> {
+    let name = nodes[i].alias.as_ref().expect("Expected enum");
+    let variant = nodes[i].name().expect("Expected variant");
+    if records.get(name).and_then(|ty| ty.variants()).is_none() {
+        return Err(nodes[i].source.wrap(
+            format!("Type mismatch (#2700):\nCould not find enum `{}`", name)));
+    }
+    let tys = match variant_payload(records, name, variant) {
+        None => return Err(nodes[i].source.wrap(
+            format!("Type mismatch (#2700):\n`{}` has no variant `{}`", name, variant))),
+        Some(x) => x
+    };
+    let n = nodes[i].children.iter().filter(|&&ch| nodes[ch].kind == Kind::Pattern).count();
+    if n != tys.len() {
+        return Err(nodes[i].source.wrap(
+            format!("Type mismatch (#2800):\n`{}::{}` expects {} values, found {}",
+                name, variant, tys.len(), n)));
+    }
+    Ok(())
+}
+
+/// Resolves types referring to records and enums declared in this script or in the prelude.
+///
+/// Returns the declared types by name.
+fn resolve_records(
+    nodes: &mut Vec<Node>,
+    prelude: &Prelude
+) -> Result<HashMap<Arc<String>, Type>, Range<String>> {
+    let mut records = prelude.records.clone();
+    let mut declared: Vec<Arc<String>> = vec![];
+    for i in 0..nodes.len() {
+        if nodes[i].kind == Kind::Enum {
+            let name = nodes[i].name().expect("Expected name").clone();
+            if declared.contains(&name) {
+                return Err(nodes[i].source.wrap(
+                    format!("Duplicate declaration of enum `{}`", name)));
+            }
+            let mut variants: Vec<(Arc<String>, Vec<Type>)> = vec![];
+            for &ch in &nodes[i].children {
+                if nodes[ch].kind != Kind::Variant { continue; }
+                let variant = nodes[ch].name().expect("Expected name").clone();
+                if variants.iter().any(|&(ref v, _)| v == &variant) {
+                    return Err(nodes[ch].source.wrap(
+                        format!("Duplicate variant `{}` in enum `{}`", variant, name)));
+                }
+                let tys = nodes[ch].children.iter()
+                    .filter(|&&p| nodes[p].kind == Kind::Payload)
+                    .map(|&p| nodes[p].ty.clone().unwrap_or(Type::Any))
+                    .collect();
+                variants.push((variant, tys));
+            }
+            declared.push(name.clone());
+            records.insert(name.clone(), Type::Enum(name, Arc::new(variants)));
+            continue;
+        }
+        if nodes[i].kind != Kind::Record { continue; }
+        let name = nodes[i].name().expect("Expected name").clone();
+        if declared.contains(&name) {
+            return Err(nodes[i].source.wrap(
+                format!("Duplicate declaration of record `{}`", name)));
+        }
+        let mut fields: Vec<(Arc<String>, Type)> = vec![];
+        for &ch in &nodes[i].children {
+            if nodes[ch].kind != Kind::Field { continue; }
+            let key = nodes[ch].name().expect("Expected name").clone();
+            if fields.iter().any(|&(ref k, _)| k == &key) {
+                return Err(nodes[ch].source.wrap(
+                    format!("Duplicate field `{}` in record `{}`", key, name)));
+            }
+            fields.push((key, nodes[ch].ty.clone().unwrap_or(Type::Any)));
+        }
+        declared.push(name.clone());
+        records.insert(name.clone(), Type::Record(name, Arc::new(fields)));
+    }
+    if records.len() == 0 { return Ok(records); }
+
+    Type::resolve_record_fields(&mut records);
+    for node in nodes.iter_mut() {
+        if let Some(ref mut ty) = node.ty {
+            ty.resolve_records(&records);
+        }
+    }
+    Ok(records)
+}
+
+/// Checks keys and values of an object literal against a record type.
+fn check_object(obj: usize, ty: &Type, nodes: &Vec<Node>) -> Result<(), Range<String>> {
+    if nodes[obj].kind != Kind::Object { return Ok(()); }
+    let (name, fields) = match ty.record() {
+        None => return Ok(()),
+        Some(x) => x
+    };
+    let mut found = vec![];
+    for &kv in &nodes[obj].children {
+        let key = match nodes[kv].name() {
+            None => continue,
+            Some(x) => x
+        };
+        let field_ty = match fields.iter().find(|&&(ref k, _)| k == key) {
+            None => return Err(nodes[kv].source.wrap(
+                format!("Type mismatch (#2100):\n`{}` has no field `{}`", name, key))),
+            Some(&(_, ref ty)) => ty
+        };
+        found.push(key);
+        if let Some(val) = nodes[kv].find_child_by_kind(nodes, Kind::Val) {
+            if let Some(ref val_ty) = nodes[val].ty {
+                if !field_ty.goes_with(val_ty) {
+                    return Err(nodes[val].source.wrap(
+                        format!("Type mismatch (#2200):\n\
+                            Expected `{}` for field `{}`, found `{}`",
+                            field_ty.description(), key, val_ty.description())));
+                }
+            }
+            if let Some(&ch) = nodes[val].children.first() {
+                try!(check_object(ch, field_ty, nodes));
+            }
+        }
+    }
+    for &(ref key, _) in fields {
+        if !found.contains(&key) {
+            return Err(nodes[obj].source.wrap(
+                format!("Type mismatch (#2300):\nMissing field `{}` of `{}`", key, name)));
+        }
+    }
+    Ok(())
+}
+
+/// Checks fields accessed on an item with record type.
+fn check_fields(i: usize, nodes: &Vec<Node>) -> Result<(), Range<String>> {
+    let extra = match nodes[i].find_child_by_kind(nodes, Kind::ItemExtra) {
+        None => return Ok(()),
+        Some(x) => x
+    };
+    let mut ty = match nodes[i].declaration.and_then(|decl| nodes[decl].ty.as_ref()) {
+        None => return Ok(()),
+        Some(ty) => nodes[i].inner_type(ty)
+    };
+    for id in &nodes[extra].names {
+        // Stop at indices, since the key is not known.
+        if id.len() == 0 { break; }
+        ty = match ty.record() {
+            None => break,
+            Some((name, fields)) => {
+                match fields.iter().find(|&&(ref k, _)| k == id) {
+                    None => return Err(nodes[extra].source.wrap(
+                        format!("Type mismatch (#2400):\n`{}` has no field `{}`", name, id))),
+                    Some(&(_, ref ty)) => ty.clone()
+                }
+            }
+        };
+    }
+    Ok(())
+}
+
+/// Resolves types referring to type variables of generic functions.
+fn resolve_generics(nodes: &mut Vec<Node>) {
+    for i in 0..nodes.len() {
+        if nodes[i].ty.is_none() { continue; }
+        // Find the generic function containing the node.
+        let mut p = Some(i);
+        while let Some(j) = p {
+            if nodes[j].kind == Kind::Fn { break; }
+            p = nodes[j].parent;
+        }
+        let f = match p {
+            None => continue,
+            Some(f) => f
+        };
+        if nodes[f].generics.len() == 0 { continue; }
+        let generics = nodes[f].generics.clone();
+        if let Some(ref mut ty) = nodes[i].ty {
+            ty.resolve_generics(&generics);
+        }
+    }
+}
+
+/// Binds type variables of a generic function to the types of call arguments.
+///
+/// Returns `None` when the types of the arguments are not known yet,
+/// unless `partial` is set, which ignores such arguments.
+fn bind_generics(
+    call: usize,
+    tys: &[Type],
+    nodes: &Vec<Node>,
+    partial: bool
+) -> Result<Option<Vec<(Arc<String>, Type)>>, Range<String>> {
+    let mut bindings = vec![];
+    let args = nodes[call].children.iter()
+        .filter(|&&ch| nodes[ch].kind == Kind::CallArg);
+    for (&arg, ty) in args.zip(tys.iter()) {
+        if let Some(&ch) = nodes[arg].children.first() {
+            // Swizzles cover several arguments.
+            if nodes[ch].kind == Kind::Swizzle { break; }
+            // The type of items with ids is not known.
+            if nodes[ch].item_ids() { continue; }
+        }
+        let arg_ty = match nodes[arg].ty {
+            None if partial => continue,
+            None => return Ok(None),
+            Some(ref x) => x
+        };
+        if !ty.bind_generics(arg_ty, &mut bindings) {
+            // Show type variables that are not bound yet.
+            let mut names = vec![];
+            ty.generics(&mut names);
+            for name in names {
+                if !bindings.iter().any(|&(ref n, _)| n == &name) {
+                    bindings.push((name.clone(), Type::Generic(name)));
+                }
+            }
+            return Err(nodes[arg].source.wrap(
+                format!("Type mismatch (#2500):\nExpected `{}`, found `{}`",
+                    ty.substitute(&bindings).description(), arg_ty.description())));
+        }
+    }
+    Ok(Some(bindings))
+}
+
+/// Checks a call against the signatures of an overloaded function.
+///
+/// Returns the return type of the signatures that go with the arguments,
+/// or `any` when their return types differ.
+/// Returns `None` when the types of the arguments are not known yet,
+/// unless `partial` is set, which ignores such arguments.
+fn check_overloads(
+    call: usize,
+    signatures: &[&Dfn],
+    nodes: &Vec<Node>,
+    partial: bool
+) -> Result<Option<Type>, Range<String>> {
+    let mut arg_tys = vec![];
+    let args = nodes[call].children.iter()
+        .filter(|&&ch| nodes[ch].kind == Kind::CallArg);
+    for &arg in args {
+        if let Some(&ch) = nodes[arg].children.first() {
+            // Swizzles cover several `f64` arguments.
+            if nodes[ch].kind == Kind::Swizzle {
+                for &sw_ch in &nodes[ch].children {
+                    match nodes[sw_ch].kind {
+                        Kind::Sw0 | Kind::Sw1 | Kind::Sw2 | Kind::Sw3 => {
+                            arg_tys.push(Type::F64);
+                        }
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            // The type of items with ids is not known.
+            if nodes[ch].item_ids() {
+                arg_tys.push(Type::Any);
+                continue;
+            }
+        }
+        match nodes[arg].ty {
+            None if partial => arg_tys.push(Type::Any),
+            None => return Ok(None),
+            Some(ref ty) => arg_tys.push(ty.clone()),
+        }
+    }
+    let mut ret: Option<Type> = None;
+    for f in signatures {
+        let mut bindings = vec![];
+        if f.tys.len() == arg_tys.len() &&
+           f.tys.iter().zip(arg_tys.iter()).all(|(ty, arg)| ty.bind_generics(arg, &mut bindings)) {
+            let ty = f.ret.substitute(&bindings);
+            ret = Some(match ret {
+                Some(ref other) if *other != ty => Type::Any,
+                _ => ty
+            });
+        }
+    }
+    match ret {
+        Some(ty) => Ok(Some(ty)),
+        None => {
+            let expected: Vec<String> = signatures.iter().map(|f| {
+                let tys: Vec<String> = f.tys.iter().map(|ty| ty.description()).collect();
+                format!("`({})`", tys.join(", "))
+            }).collect();
+            let found: Vec<String> = arg_tys.iter().map(|ty| ty.description()).collect();
+            Err(nodes[call].source.wrap(
+                format!("Type mismatch (#3100):\nExpected {}, found `({})`",
+                    expected.join(" or "), found.join(", "))))
+        }
+    }
+}
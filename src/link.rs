@@ -163,6 +163,12 @@ pub struct Link {
     pub slices: Vec<Slice>,
 }
 
+impl PartialEq for Link {
+    fn eq(&self, other: &Link) -> bool {
+        self.items() == other.items()
+    }
+}
+
 impl Link {
     pub fn new() -> Link {
         Link {
@@ -229,6 +235,17 @@ impl Link {
 
     pub fn is_empty(&self) -> bool { self.slices.len() == 0 }
 
+    /// Returns the items of the link.
+    pub fn items(&self) -> Vec<Variable> {
+        let mut res = vec![];
+        for slice in &self.slices {
+            for i in slice.start..slice.end {
+                res.push(slice.block.var(i));
+            }
+        }
+        res
+    }
+
     pub fn add(&self, other: &Link) -> Link {
         let mut slices = Vec::with_capacity(self.slices.len() + other.slices.len());
         slices.extend_from_slice(&self.slices);
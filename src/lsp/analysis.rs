@@ -0,0 +1,290 @@
+//! Answers questions about documents using the parser and checkers.
+
+use std::sync::Arc;
+
+use piston_meta::bootstrap::Convert;
+
+use ast::{Namespace, UseLookup, Uses};
+use prelude::{Dfn, Prelude};
+use {load_str, parse_str, DyonError, FnIndex, Module};
+
+use super::json::Json;
+
+/// Converts byte offset to zero-based line and UTF-16 character.
+pub fn position(text: &str, offset: usize) -> (usize, usize) {
+    let mut line = 0;
+    let mut character = 0;
+    for (i, c) in text.char_indices() {
+        if i >= offset { break }
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16();
+        }
+    }
+    (line, character)
+}
+
+/// Converts zero-based line and UTF-16 character to byte offset.
+pub fn offset(text: &str, line: usize, character: usize) -> usize {
+    let mut l = 0;
+    let mut ch = 0;
+    for (i, c) in text.char_indices() {
+        if l == line && (ch >= character || c == '\n') { return i }
+        if c == '\n' {
+            l += 1;
+            ch = 0;
+        } else if l == line {
+            ch += c.len_utf16();
+        }
+    }
+    text.len()
+}
+
+/// Creates a range from byte offsets.
+pub fn range(text: &str, start: usize, end: usize) -> Json {
+    let pos = |offset: usize| {
+        let (line, character) = position(text, offset);
+        Json::object(vec![
+            ("line", Json::Number(line as f64)),
+            ("character", Json::Number(character as f64)),
+        ])
+    };
+    Json::object(vec![("start", pos(start)), ("end", pos(end))])
+}
+
+fn is_ident(c: char) -> bool { c.is_alphanumeric() || c == '_' }
+
+/// Returns start of identifier ending at offset.
+fn ident_start(text: &str, offset: usize) -> usize {
+    text[..offset].char_indices().rev()
+        .take_while(|&(_, c)| is_ident(c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(offset)
+}
+
+/// A function name written in source, e.g. `add` or `m::add`.
+pub struct Word {
+    pub alias: Option<Arc<String>>,
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Word {
+    /// Returns the function name at offset.
+    pub fn at(text: &str, offset: usize) -> Option<Word> {
+        let offset = offset.min(text.len());
+        let start = ident_start(text, offset);
+        let end = text[offset..].char_indices()
+            .find(|&(_, c)| !is_ident(c))
+            .map(|(i, _)| offset + i)
+            .unwrap_or(text.len());
+        if start == end { return None }
+        let mut word = Word {
+            alias: None,
+            name: text[start..end].into(),
+            start: start,
+            end: end,
+        };
+        if text[..start].ends_with("::") {
+            let alias_start = ident_start(text, start - 2);
+            if alias_start < start - 2 {
+                word.alias = Some(Arc::new(text[alias_start..start - 2].into()));
+                word.start = alias_start;
+            }
+        }
+        Some(word)
+    }
+
+    /// Returns `true` if function name is called by this word,
+    /// taking named arguments and mutability information into account.
+    pub fn matches(&self, f_name: &str) -> bool {
+        if !f_name.starts_with(&self.name) { return false }
+        let rest = &f_name[self.name.len()..];
+        rest == "" || rest.starts_with("__") || rest.starts_with("(")
+    }
+}
+
+/// Loads documents with a namespace, which other documents can import.
+///
+/// Documents that depend on each other are loaded when their dependencies are.
+pub fn load_namespaces(docs: &[(&str, &Arc<String>)]) -> Module {
+    let mut module = Module::new();
+    let mut pending: Vec<&(&str, &Arc<String>)> = docs.iter()
+        .filter(|&&(file, text)| {
+            match parse_str(file, text) {
+                Ok(data) => {
+                    let mut ignored = vec![];
+                    Namespace::from_meta_data(Convert::new(&data), &mut ignored).is_ok()
+                }
+                Err(_) => false
+            }
+        })
+        .collect();
+    loop {
+        let n = pending.len();
+        pending.retain(|&&(file, text)| {
+            let mut m = module.clone();
+            match load_str(file, text.clone(), &mut m) {
+                Ok(()) => {
+                    module = m;
+                    false
+                }
+                Err(_) => true
+            }
+        });
+        if pending.len() == n { break }
+    }
+    module
+}
+
+/// Returns imported functions of a document.
+pub fn use_lookup(file: &str, text: &Arc<String>, module: &Module) -> UseLookup {
+    let data = match parse_str(file, text) {
+        Ok(data) => data,
+        Err(_) => return UseLookup::new()
+    };
+    let mut ignored = vec![];
+    let mut convert = Convert::new(&data);
+    if let Ok((range, _)) = Namespace::from_meta_data(convert, &mut ignored) {
+        convert.update(range);
+    }
+    match Uses::from_meta_data(convert, &mut ignored) {
+        Ok((_, uses)) => UseLookup::from_uses_module(&uses, module),
+        Err(_) => UseLookup::new()
+    }
+}
+
+/// Returns diagnostics of an error in a document.
+pub fn diagnostics(file: &str, text: &str, err: Option<&DyonError>) -> Json {
+    let err = match err {
+        None => return Json::Array(vec![]),
+        Some(err) => err
+    };
+    let (start, end) = match (err.range, err.file.as_ref()) {
+        (Some(r), Some(f)) if &**f == file => (r.offset, r.next_offset()),
+        _ => (0, 0)
+    };
+    let mut message = err.message.clone();
+    for note in &err.notes {
+        message.push('\n');
+        message.push_str(note);
+    }
+    Json::Array(vec![Json::object(vec![
+        ("range", range(text, start.min(text.len()), end.min(text.len()))),
+        ("severity", Json::Number(1.0)),
+        ("source", Json::str("dyon")),
+        ("message", Json::String(message)),
+    ])])
+}
+
+/// Returns the signature of a function.
+pub fn signature(name: &str, f: &Dfn) -> String {
+    let args: Vec<String> = f.tys.iter().map(|ty| ty.description()).collect();
+    if f.returns() {
+        format!("{}({}) -> {}", name, args.join(", "), f.ret.description())
+    } else {
+        format!("{}({})", name, args.join(", "))
+    }
+}
+
+/// Returns indices of loaded functions called by word.
+fn loaded_functions(file: &str, text: &Arc<String>, module: &Module, word: &Word) -> Vec<usize> {
+    let mut res = vec![];
+    if let Some(ref alias) = word.alias {
+        let lookup = use_lookup(file, text, module);
+        if let Some(fns) = lookup.aliases.get(alias) {
+            for (name, &i) in fns {
+                if word.matches(name) { res.push(i) }
+            }
+        }
+    } else {
+        for f in &module.functions {
+            if !word.matches(&f.name) { continue }
+            if let FnIndex::Loaded(i) = module.find_function(&f.name, 0) {
+                res.push(i as usize);
+            }
+        }
+    }
+    res.sort();
+    res.dedup();
+    res
+}
+
+/// Returns locations of functions called by word at offset.
+pub fn definition(file: &str, text: &Arc<String>, module: &Module, offset: usize) -> Json {
+    let word = match Word::at(text, offset) {
+        None => return Json::Null,
+        Some(word) => word
+    };
+    let locations: Vec<Json> = loaded_functions(file, text, module, &word).into_iter()
+        .map(|i| {
+            let f = &module.functions[i];
+            let start = f.source_range.offset;
+            Json::object(vec![
+                ("uri", Json::String((*f.file).clone())),
+                ("range", range(&f.source, start, start)),
+            ])
+        })
+        .collect();
+    if locations.len() == 0 { Json::Null } else { Json::Array(locations) }
+}
+
+/// Returns signatures of functions called by word at offset.
+pub fn hover(file: &str, text: &Arc<String>, module: &Module, offset: usize) -> Json {
+    let word = match Word::at(text, offset) {
+        None => return Json::Null,
+        Some(word) => word
+    };
+    let mut signatures = vec![];
+    if word.alias.is_some() {
+        for i in loaded_functions(file, text, module, &word) {
+            let f = &module.functions[i];
+            signatures.push(signature(&f.name, &Dfn::new(f)));
+        }
+    } else {
+        let prelude = Prelude::from_module(module);
+        let mut names: Vec<&Arc<String>> = prelude.functions.keys()
+            .filter(|name| word.matches(name))
+            .collect();
+        names.sort();
+        for name in names {
+            for f in prelude.signatures(prelude.functions[name]) {
+                signatures.push(signature(name, f));
+            }
+        }
+    }
+    if signatures.len() == 0 { return Json::Null }
+    Json::object(vec![
+        ("contents", Json::object(vec![
+            ("kind", Json::str("markdown")),
+            ("value", Json::String(format!("```dyon\n{}\n```", signatures.join("\n")))),
+        ])),
+        ("range", range(text, word.start, word.end)),
+    ])
+}
+
+/// Returns functions starting with the name before offset.
+pub fn completion(text: &str, module: &Module, offset: usize) -> Json {
+    let offset = offset.min(text.len());
+    let prefix = &text[ident_start(text, offset)..offset];
+    let prelude = Prelude::from_module(module);
+    let mut names: Vec<&Arc<String>> = prelude.functions.keys()
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+    names.sort();
+    Json::Array(names.into_iter().map(|name| {
+        // Named arguments are written in the call.
+        let insert = name.split("__").next().unwrap_or(name);
+        let insert = insert.split('(').next().unwrap_or(insert);
+        Json::object(vec![
+            ("label", Json::String((**name).clone())),
+            ("kind", Json::Number(3.0)),
+            ("detail", Json::String(signature(name, &prelude.list[prelude.functions[name]]))),
+            ("insertText", Json::str(insert)),
+        ])
+    }).collect())
+}
@@ -0,0 +1,216 @@
+//! Minimal JSON values for the language server protocol.
+
+use std::fmt;
+
+use read_token::{NumberSettings, ReadToken};
+
+/// A JSON value.
+///
+/// Objects keep the order of their keys.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+static NUMBER_SETTINGS: NumberSettings = NumberSettings {
+    allow_underscore: false,
+};
+
+impl Json {
+    /// Parses JSON from text.
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut read = ReadToken::new(text, 0);
+        let val = try!(value(&mut read));
+        whitespace(&mut read);
+        if read.src.len() > 0 {
+            return Err(format!("Expected end of JSON at {}", read.offset));
+        }
+        Ok(val)
+    }
+
+    /// Creates an object from key-value pairs.
+    pub fn object(items: Vec<(&str, Json)>) -> Json {
+        Json::Object(items.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// Creates a string.
+    pub fn str(text: &str) -> Json {
+        Json::String(text.into())
+    }
+
+    /// Returns the value of a key in an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref obj) => obj.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v),
+            _ => None
+        }
+    }
+
+    /// Returns the value at a path of keys in nested objects.
+    pub fn path(&self, keys: &[&str]) -> Option<&Json> {
+        let mut val = self;
+        for key in keys {
+            val = match val.get(key) {
+                None => return None,
+                Some(x) => x
+            };
+        }
+        Some(val)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(x) => Some(x),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match *self {
+            Json::Array(ref arr) => Some(arr),
+            _ => None
+        }
+    }
+}
+
+fn whitespace(read: &mut ReadToken) {
+    let range = read.whitespace();
+    *read = read.consume(range.length);
+}
+
+fn value(read: &mut ReadToken) -> Result<Json, String> {
+    whitespace(read);
+    if let Some(range) = read.tag("{") {
+        *read = read.consume(range.length);
+        let mut obj = vec![];
+        loop {
+            whitespace(read);
+            if let Some(range) = read.tag("}") {
+                *read = read.consume(range.length);
+                return Ok(Json::Object(obj));
+            }
+            if obj.len() > 0 {
+                try!(expect(read, ","));
+                whitespace(read);
+            }
+            let key = try!(string(read));
+            whitespace(read);
+            try!(expect(read, ":"));
+            let val = try!(value(read));
+            obj.push((key, val));
+        }
+    }
+    if let Some(range) = read.tag("[") {
+        *read = read.consume(range.length);
+        let mut arr = vec![];
+        loop {
+            whitespace(read);
+            if let Some(range) = read.tag("]") {
+                *read = read.consume(range.length);
+                return Ok(Json::Array(arr));
+            }
+            if arr.len() > 0 {
+                try!(expect(read, ","));
+            }
+            arr.push(try!(value(read)));
+        }
+    }
+    if read.tag("\"").is_some() {
+        return string(read).map(Json::String);
+    }
+    for &(tag, ref val) in &[
+        ("null", Json::Null),
+        ("true", Json::Bool(true)),
+        ("false", Json::Bool(false)),
+    ] {
+        if let Some(range) = read.tag(tag) {
+            *read = read.consume(range.length);
+            return Ok(val.clone());
+        }
+    }
+    if let Some(range) = read.number(&NUMBER_SETTINGS) {
+        let val = try!(read.parse_number(&NUMBER_SETTINGS, range.length)
+            .map_err(|err| format!("{} at {}", err, read.offset)));
+        *read = read.consume(range.length);
+        return Ok(Json::Number(val));
+    }
+    Err(format!("Expected JSON value at {}", read.offset))
+}
+
+fn expect(read: &mut ReadToken, tag: &str) -> Result<(), String> {
+    match read.tag(tag) {
+        Some(range) => {
+            *read = read.consume(range.length);
+            Ok(())
+        }
+        None => Err(format!("Expected `{}` at {}", tag, read.offset))
+    }
+}
+
+fn string(read: &mut ReadToken) -> Result<String, String> {
+    match read.string() {
+        Some(range) => {
+            let s = try!(read.parse_string(range.length)
+                .map_err(|err| format!("{} at {}", err.data, err.offset)));
+            *read = read.consume(range.length);
+            Ok(s)
+        }
+        None => Err(format!("Expected string at {}", read.offset))
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    try!(write!(f, "\""));
+    for c in s.chars() {
+        match c {
+            '"' => try!(write!(f, "\\\"")),
+            '\\' => try!(write!(f, "\\\\")),
+            '\n' => try!(write!(f, "\\n")),
+            '\r' => try!(write!(f, "\\r")),
+            '\t' => try!(write!(f, "\\t")),
+            c if (c as u32) < 0x20 => try!(write!(f, "\\u{:04x}", c as u32)),
+            c => try!(write!(f, "{}", c)),
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(x) => write!(f, "{}", x),
+            Json::Number(x) => write!(f, "{}", x),
+            Json::String(ref s) => write_string(f, s),
+            Json::Array(ref arr) => {
+                try!(write!(f, "["));
+                for (i, it) in arr.iter().enumerate() {
+                    if i > 0 { try!(write!(f, ",")); }
+                    try!(write!(f, "{}", it));
+                }
+                write!(f, "]")
+            }
+            Json::Object(ref obj) => {
+                try!(write!(f, "{{"));
+                for (i, &(ref key, ref val)) in obj.iter().enumerate() {
+                    if i > 0 { try!(write!(f, ",")); }
+                    try!(write_string(f, key));
+                    try!(write!(f, ":{}", val));
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
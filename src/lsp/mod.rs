@@ -0,0 +1,246 @@
+//! Language server for Dyon files, using JSON-RPC over stdio.
+//!
+//! Supports diagnostics, go-to-definition, hover and completion of function names.
+//! Documents are checked in memory when opened or changed,
+//! using the same parser and checkers as when loading a module.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+use {load_str, Module};
+
+pub use self::json::Json;
+
+mod analysis;
+mod json;
+
+/// Error code when a method is not supported.
+const METHOD_NOT_FOUND: f64 = -32601.0;
+/// Error code when a message is not valid JSON.
+const PARSE_ERROR: f64 = -32700.0;
+
+/// Stores open documents.
+pub struct Server {
+    /// Text of open documents by uri.
+    documents: BTreeMap<String, Arc<String>>,
+    /// The last module that loaded without errors, by uri.
+    ///
+    /// Used to look up functions while the document is edited.
+    modules: HashMap<String, Module>,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            documents: BTreeMap::new(),
+            modules: HashMap::new(),
+        }
+    }
+
+    /// Handles a request or notification.
+    /// Returns responses and notifications to send to the client.
+    pub fn handle(&mut self, msg: &Json) -> Vec<Json> {
+        let method = msg.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        let id = msg.get("id").cloned();
+        let params = msg.get("params").cloned().unwrap_or(Json::Null);
+        let text_document = |params: &Json| -> Option<(String, usize, usize)> {
+            let uri = match params.path(&["textDocument", "uri"]).and_then(|x| x.as_str()) {
+                None => return None,
+                Some(x) => x
+            };
+            let line = params.path(&["position", "line"])
+                .and_then(|x| x.as_f64()).unwrap_or(0.0);
+            let character = params.path(&["position", "character"])
+                .and_then(|x| x.as_f64()).unwrap_or(0.0);
+            Some((uri.into(), line as usize, character as usize))
+        };
+
+        let result = match method {
+            "initialize" => Json::object(vec![
+                ("capabilities", Json::object(vec![
+                    ("textDocumentSync", Json::Number(1.0)),
+                    ("hoverProvider", Json::Bool(true)),
+                    ("definitionProvider", Json::Bool(true)),
+                    ("completionProvider", Json::object(vec![])),
+                ])),
+                ("serverInfo", Json::object(vec![
+                    ("name", Json::str("dyon-lsp")),
+                    ("version", Json::str(env!("CARGO_PKG_VERSION"))),
+                ])),
+            ]),
+            "shutdown" => Json::Null,
+            "textDocument/didOpen" => {
+                let uri = params.path(&["textDocument", "uri"]).and_then(|x| x.as_str());
+                let text = params.path(&["textDocument", "text"]).and_then(|x| x.as_str());
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    return vec![self.update(uri, text)];
+                }
+                return vec![];
+            }
+            "textDocument/didChange" => {
+                let uri = params.path(&["textDocument", "uri"]).and_then(|x| x.as_str());
+                // The server asks for full text, so the last change has the whole document.
+                let text = params.get("contentChanges")
+                    .and_then(|x| x.as_array())
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(|x| x.as_str());
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    return vec![self.update(uri, text)];
+                }
+                return vec![];
+            }
+            "textDocument/didClose" => {
+                if let Some(uri) = params.path(&["textDocument", "uri"]).and_then(|x| x.as_str()) {
+                    self.documents.remove(uri);
+                    self.modules.remove(uri);
+                    return vec![publish_diagnostics(uri, Json::Array(vec![]))];
+                }
+                return vec![];
+            }
+            "textDocument/definition" | "textDocument/hover" | "textDocument/completion" => {
+                match text_document(&params) {
+                    None => Json::Null,
+                    Some((uri, line, character)) => {
+                        match self.documents.get(&uri) {
+                            None => Json::Null,
+                            Some(text) => {
+                                let module = match self.modules.get(&uri) {
+                                    Some(m) => m.clone(),
+                                    None => self.namespaces(&uri)
+                                };
+                                let offset = analysis::offset(text, line, character);
+                                match method {
+                                    "textDocument/definition" =>
+                                        analysis::definition(&uri, text, &module, offset),
+                                    "textDocument/hover" =>
+                                        analysis::hover(&uri, text, &module, offset),
+                                    _ => analysis::completion(text, &module, offset),
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {
+                // Notifications without an id are ignored.
+                return match id {
+                    None => vec![],
+                    Some(id) => vec![error_response(id, METHOD_NOT_FOUND,
+                        &format!("Unknown method `{}`", method))],
+                };
+            }
+        };
+        match id {
+            None => vec![],
+            Some(id) => vec![Json::object(vec![
+                ("jsonrpc", Json::str("2.0")),
+                ("id", id),
+                ("result", result),
+            ])],
+        }
+    }
+
+    /// Loads documents with a namespace, except the one with uri.
+    fn namespaces(&self, uri: &str) -> Module {
+        let docs: Vec<(&str, &Arc<String>)> = self.documents.iter()
+            .filter(|&(u, _)| u != uri)
+            .map(|(u, text)| (&**u, text))
+            .collect();
+        analysis::load_namespaces(&docs)
+    }
+
+    /// Checks new text of document and returns diagnostics.
+    fn update(&mut self, uri: &str, text: &str) -> Json {
+        let text = Arc::new(String::from(text));
+        self.documents.insert(uri.into(), text.clone());
+        let mut module = self.namespaces(uri);
+        let err = match load_str(uri, text.clone(), &mut module) {
+            Ok(()) => {
+                self.modules.insert(uri.into(), module);
+                None
+            }
+            Err(err) => {
+                if !self.modules.contains_key(uri) {
+                    self.modules.insert(uri.into(), self.namespaces(uri));
+                }
+                Some(err)
+            }
+        };
+        publish_diagnostics(uri, analysis::diagnostics(uri, &text, err.as_ref()))
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("textDocument/publishDiagnostics")),
+        ("params", Json::object(vec![
+            ("uri", Json::str(uri)),
+            ("diagnostics", diagnostics),
+        ])),
+    ])
+}
+
+fn error_response(id: Json, code: f64, message: &str) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("id", id),
+        ("error", Json::object(vec![
+            ("code", Json::Number(code)),
+            ("message", Json::str(message)),
+        ])),
+    ])
+}
+
+/// Reads a message with a `Content-Length` header.
+/// Returns `None` at end of input.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if try!(input.read_line(&mut line)) == 0 { return Ok(None) }
+        let line = line.trim();
+        if line == "" {
+            if length.is_some() { break } else { continue }
+        }
+        let mut parts = line.splitn(2, ':');
+        if let (Some(key), Some(val)) = (parts.next(), parts.next()) {
+            if key.trim().eq_ignore_ascii_case("Content-Length") {
+                length = val.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut buf = vec![0; length.unwrap()];
+    try!(input.read_exact(&mut buf));
+    String::from_utf8(buf)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Writes a message with a `Content-Length` header.
+pub fn write_message<W: Write>(output: &mut W, msg: &Json) -> io::Result<()> {
+    let text = format!("{}", msg);
+    try!(write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text));
+    output.flush()
+}
+
+/// Runs the language server until the client sends `exit` or closes the input.
+pub fn run<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let mut server = Server::new();
+    while let Some(text) = try!(read_message(&mut input)) {
+        let msg = match Json::parse(&text) {
+            Ok(msg) => msg,
+            Err(err) => {
+                try!(write_message(&mut output, &error_response(Json::Null, PARSE_ERROR, &err)));
+                continue;
+            }
+        };
+        if msg.get("method").and_then(|m| m.as_str()) == Some("exit") { break }
+        for reply in server.handle(&msg) {
+            try!(write_message(&mut output, &reply));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+//! Keys of maps.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use Variable;
+
+/// Key of a map.
+///
+/// Maps are keyed by `bool`, `f64`, `str` or `vec4`.
+/// Numbers are equal when they have the same value, so `-0` and `0` is the same key.
+/// NaN is not equal to itself and can not be used as key,
+/// neither in `f64` nor in any component of `vec4`.
+/// Components of `vec4` are stored as `f32`, so keys that round to the same `f32` are equal.
+///
+/// Keys are ordered by type, `bool` < `f64` < `str` < `vec4`, then by value.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Bool(bool),
+    /// Stores the bits of the number.
+    F64(u64),
+    Text(Arc<String>),
+    /// Stores the bits of the components.
+    Vec4([u32; 4]),
+}
+
+impl MapKey {
+    /// Converts a variable to a key.
+    ///
+    /// The variable must be resolved.
+    pub fn from_variable(v: &Variable) -> Result<MapKey, String> {
+        fn check(x: f64) -> Result<f64, String> {
+            if x.is_nan() {
+                Err("NaN can not be used as key".into())
+            } else if x == 0.0 {
+                // Use the same key for `-0` and `0`.
+                Ok(0.0)
+            } else {
+                Ok(x)
+            }
+        }
+
+        match *v {
+            Variable::Bool(x, _) => Ok(MapKey::Bool(x)),
+            Variable::F64(x, _) => {
+                let x = try!(check(x));
+                Ok(MapKey::F64(x.to_bits()))
+            }
+            Variable::Text(ref x) => Ok(MapKey::Text(x.clone())),
+            Variable::Vec4(x) => {
+                let mut bits = [0; 4];
+                for i in 0..4 {
+                    let x = try!(check(x[i] as f64)) as f32;
+                    bits[i] = x.to_bits();
+                }
+                Ok(MapKey::Vec4(bits))
+            }
+            _ => Err("Expected `bool`, `f64`, `str` or `vec4` as key".into())
+        }
+    }
+
+    /// Converts key to a variable.
+    pub fn to_variable(&self) -> Variable {
+        match *self {
+            MapKey::Bool(x) => Variable::bool(x),
+            MapKey::F64(x) => Variable::f64(f64::from_bits(x)),
+            MapKey::Text(ref x) => Variable::Text(x.clone()),
+            MapKey::Vec4(x) => Variable::Vec4(vec4_from_bits(x)),
+        }
+    }
+
+    fn type_order(&self) -> u8 {
+        match *self {
+            MapKey::Bool(_) => 0,
+            MapKey::F64(_) => 1,
+            MapKey::Text(_) => 2,
+            MapKey::Vec4(_) => 3,
+        }
+    }
+}
+
+fn vec4_from_bits(x: [u32; 4]) -> [f32; 4] {
+    [f32::from_bits(x[0]), f32::from_bits(x[1]), f32::from_bits(x[2]), f32::from_bits(x[3])]
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &MapKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MapKey {
+    fn cmp(&self, other: &MapKey) -> Ordering {
+        // Keys never contain NaN, so numbers have a total order.
+        match (self, other) {
+            (&MapKey::Bool(a), &MapKey::Bool(b)) => a.cmp(&b),
+            (&MapKey::F64(a), &MapKey::F64(b)) => {
+                f64::from_bits(a).partial_cmp(&f64::from_bits(b)).unwrap()
+            }
+            (&MapKey::Text(ref a), &MapKey::Text(ref b)) => a.cmp(b),
+            (&MapKey::Vec4(a), &MapKey::Vec4(b)) => {
+                let (a, b) = (vec4_from_bits(a), vec4_from_bits(b));
+                a.partial_cmp(&b).unwrap()
+            }
+            _ => self.type_order().cmp(&other.type_order())
+        }
+    }
+}
+
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapKey::Bool(x) => write!(f, "{}", x),
+            MapKey::F64(x) => write!(f, "{}", f64::from_bits(x)),
+            MapKey::Text(ref x) => write!(f, "{:?}", x),
+            MapKey::Vec4(x) => {
+                let v = vec4_from_bits(x);
+                write!(f, "({}, {}, {}, {})", v[0], v[1], v[2], v[3])
+            }
+        }
+    }
+}
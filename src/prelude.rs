@@ -3,6 +3,7 @@ use std::collections::HashMap;
 
 use ast;
 use intrinsics;
+use Capabilities;
 use Module;
 use Type;
 
@@ -53,12 +54,23 @@ impl Dfn {
     }
 
     pub fn returns(&self) -> bool { self.ret != Type::Void }
+
+    /// Returns `true` if the signature uses type variables.
+    pub fn is_generic(&self) -> bool {
+        self.tys.iter().any(|ty| ty.has_generics()) || self.ret.has_generics()
+    }
 }
 
 pub struct Prelude {
     pub functions: HashMap<Arc<String>, usize>,
     pub list: Vec<Dfn>,
     pub namespaces: Vec<(Arc<Vec<Arc<String>>>, Arc<String>)>,
+    /// Capabilities allowed when calling intrinsics.
+    pub capabilities: Capabilities,
+    /// Record and enum types declared in loaded scripts.
+    pub records: HashMap<Arc<String>, Type>,
+    /// Other signatures of intrinsics, by function index.
+    pub overloads: HashMap<usize, Vec<Dfn>>,
 }
 
 impl Prelude {
@@ -77,11 +89,30 @@ impl Prelude {
         self.namespaces.push((Arc::new(vec![]), name));
     }
 
+    /// Adds another signature to an intrinsic.
+    ///
+    /// A call type checks when its arguments go with any of the signatures.
+    pub fn overload(&mut self, index: usize, f: Dfn) {
+        self.overloads.entry(index).or_insert(vec![]).push(f);
+    }
+
+    /// Returns all signatures of a function.
+    pub fn signatures(&self, index: usize) -> Vec<&Dfn> {
+        let mut res = vec![&self.list[index]];
+        if let Some(overloads) = self.overloads.get(&index) {
+            res.extend(overloads.iter());
+        }
+        res
+    }
+
     pub fn new() -> Prelude {
         Prelude {
             functions: HashMap::new(),
             list: vec![],
             namespaces: vec![],
+            capabilities: Capabilities::all(),
+            records: HashMap::new(),
+            overloads: HashMap::new(),
         }
     }
 
@@ -93,6 +124,8 @@ impl Prelude {
 
     pub fn from_module(module: &Module) -> Prelude {
         let mut prelude = Prelude::new();
+        prelude.capabilities = module.capabilities;
+        prelude.records = module.records.clone();
         intrinsics::standard(&mut prelude);
         for f in &*module.ext_prelude {
             prelude.insert(Arc::new(vec![]), f.name.clone(), f.p.clone());
@@ -0,0 +1,1402 @@
+//! Compiles function bodies to a flat list of instructions.
+//!
+//! Values are computed on a separate operand stack, while the runtime stack
+//! keeps the same layout as when walking the tree.
+//! This is required because locals are resolved by their distance
+//! from the top of the runtime stack.
+//! Expressions that are not compiled are evaluated by walking the tree.
+
+use std::sync::Arc;
+use std::collections::HashMap;
+use range::Range;
+
+use ast;
+use intrinsics;
+
+use DyonError;
+use FnIndex;
+use Module;
+use Variable;
+use super::{Flow, Runtime, Side};
+
+/// Stores instructions for a function body.
+#[derive(Debug)]
+pub struct Code {
+    pub instructions: Vec<Instr>,
+    /// Loops in the function, indexed by instructions.
+    pub loops: Vec<LoopInfo>,
+    /// Calls in the function, indexed by instructions.
+    pub calls: Vec<ast::Call>,
+    /// Object keys, indexed by instructions.
+    pub objects: Vec<Vec<Arc<String>>>,
+    /// Items with ids, indexed by instructions.
+    pub items: Vec<ast::Item>,
+    /// Loops and value expressions enclosing an evaluated expression.
+    /// Used to handle `break` and `continue` from evaluated expressions.
+    pub contexts: Vec<Vec<Target>>,
+}
+
+// Required because `ast::Call` and `Variable` are not `Sync`.
+unsafe impl Sync for Code {}
+
+/// Where an instruction reads a value from.
+#[derive(Debug)]
+pub enum Operand {
+    /// Pops value from the operand stack.
+    Stack,
+    /// Reference to local, by distance from top of the stack.
+    Local(usize),
+    Const(Variable),
+}
+
+/// An instruction.
+#[derive(Debug)]
+pub enum Instr {
+    /// Pushes a constant value.
+    Const(Variable),
+    /// Pushes no value.
+    Void,
+    /// Discards the top value.
+    Pop,
+    /// Pushes reference to local, by distance from top of the stack.
+    Local(usize),
+    /// Evaluates expression by walking the tree, with flow context.
+    Eval(Box<ast::Expression>, usize),
+    /// Reports an error if the top value is missing.
+    Expect(Range, &'static str),
+    /// Reports an error if the top value is not missing, and pops it.
+    ExpectVoid(Range, &'static str),
+    /// Reports an error.
+    Fail(Range, &'static str),
+    /// Returns value from function.
+    Return(Operand),
+    /// Returns from function without a value.
+    ReturnVoid,
+    /// Leaves function with flow not handled inside it.
+    Escape(Flow),
+    /// Breaks loop.
+    Break(usize),
+    /// Continues loop.
+    Continue(usize),
+    Jump(usize),
+    /// Pops condition and jumps if it is `false`.
+    JumpIfNot(usize, Range, &'static str),
+    /// Skips evaluating the right argument of a lazy binary operator.
+    Lazy(ast::BinOp, usize),
+    BinOp(ast::BinOp, Range, Operand, Operand),
+    UnOp(ast::UnOp, Range, Operand),
+    Norm(Range, Operand),
+    Compare(ast::CompareOp, Range, Operand, Operand),
+    /// Looks up item with ids, where expressions for ids are on the stack.
+    Item(usize, usize, Side),
+    /// Replaces reference on top with a shallow clone.
+    Shallow,
+    /// Pops reference and sets it to value below.
+    Insert,
+    /// Declares a new local, with flag for current object.
+    Declare(Arc<String>, bool, Operand),
+    /// Assigns to left operand with operator, using range of left side.
+    AssignOp(ast::AssignOp, Range, Operand, Operand),
+    Try(Range),
+    /// Pops items into new array.
+    Array(usize, Range),
+    /// Pops values into new object with keys.
+    Object(usize, Range),
+    ArrayFill(Range),
+    /// Pops four arguments from the stack.
+    Vec4(Box<[Range; 4]>),
+    /// Moves value to stack, unless it is missing.
+    PushArg(Operand),
+    /// Reserves return value and checks arguments for loaded function.
+    BeginCall(usize),
+    /// Calls loaded function.
+    Call(usize),
+    /// Calls intrinsic.
+    Intrinsic(usize, usize),
+    /// Calls external function.
+    External(usize),
+    EnterScope,
+    ExitScope,
+    BeginLoop(usize),
+    /// Marks where the loop body starts on the stack.
+    LoopMark(usize),
+    /// Pops `for` condition and exits loop if it is `false`.
+    ForCond(usize),
+    /// Jumps to `for` condition after step.
+    Reset(usize),
+    /// Pops top value and checks that it is a number.
+    Number(Range, &'static str),
+    /// Pops start and end, then pushes the counter.
+    ForNInit(usize),
+    /// Exits loop when counter reaches end.
+    ForNCond(usize),
+    /// Pops value of loop body.
+    Accumulate(usize),
+    /// Increments counter and jumps to condition.
+    ForNStep(usize),
+    /// Pops loop and pushes its value.
+    EndLoop,
+}
+
+/// The kind of loop.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoopKind {
+    For,
+    ForN,
+    Sum,
+    Prod,
+    Min,
+    Max,
+    Any,
+    All,
+    Sift,
+}
+
+/// Stores information about a loop.
+#[derive(Debug)]
+pub struct LoopInfo {
+    pub kind: LoopKind,
+    pub label: Option<Arc<String>>,
+    /// Nesting depth of loop within function.
+    pub depth: usize,
+    /// Name of counter.
+    pub name: Arc<String>,
+    /// Where the condition is checked.
+    pub cond: usize,
+    /// Where `continue` jumps to.
+    pub next: usize,
+    /// Where `break` jumps to.
+    pub exit: usize,
+    pub source_range: Range,
+    pub cond_range: Range,
+    pub block_range: Range,
+    /// Names of key and value when iterating over a collection.
+    pub for_in: Option<ast::ForIn>,
+}
+
+/// Handles `break` and `continue` when unwinding.
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// Jumps to loop.
+    Loop(usize),
+    /// Reports an error, because a value is expected.
+    Value(Range, &'static str),
+}
+
+/// Stores state of running instructions.
+pub struct Stacks {
+    pub values: Vec<Option<Variable>>,
+    /// Stack, local and current lengths of scopes.
+    pub marks: Vec<(usize, usize, usize)>,
+    pub loops: Vec<LoopState>,
+    /// Function index and stack, local and current lengths of calls.
+    pub calls: Vec<(usize, usize, usize, usize)>,
+}
+
+impl Stacks {
+    pub fn new() -> Stacks {
+        Stacks {
+            values: vec![],
+            marks: vec![],
+            loops: vec![],
+            calls: vec![],
+        }
+    }
+}
+
+/// Stores state of running loop.
+pub struct LoopState {
+    /// Stack and local lengths before loop.
+    pub prev: (usize, usize),
+    /// Stack, local and current lengths of loop body.
+    pub body: (usize, usize, usize),
+    pub marks: usize,
+    pub values: usize,
+    pub end: f64,
+    /// Counter when the loop body started.
+    pub ind: f64,
+    pub acc: Option<Variable>,
+    /// Keys and values when iterating over a collection.
+    pub items: Option<Vec<(Variable, Variable)>>,
+}
+
+impl LoopState {
+    /// Returns the key of current item, or the counter when not iterating over a collection.
+    fn key(&self) -> Variable {
+        match self.items {
+            Some(ref items) => items[self.ind as usize].0.clone(),
+            None => Variable::f64(self.ind)
+        }
+    }
+}
+
+struct Compiler {
+    code: Code,
+    targets: Vec<Target>,
+    depth: usize,
+}
+
+impl Code {
+    /// Compiles function body.
+    ///
+    /// Locals must be resolved before compiling.
+    pub fn compile(f: &ast::Function) -> Code {
+        let mut c = Compiler {
+            code: Code {
+                instructions: vec![],
+                loops: vec![],
+                calls: vec![],
+                objects: vec![],
+                items: vec![],
+                contexts: vec![],
+            },
+            targets: vec![],
+            depth: 0,
+        };
+        if f.block.expressions.len() > 0 {
+            c.block(&f.block);
+        }
+        c.code
+    }
+}
+
+impl Compiler {
+    fn emit(&mut self, instr: Instr) {
+        self.code.instructions.push(instr);
+    }
+
+    fn pos(&self) -> usize {
+        self.code.instructions.len()
+    }
+
+    fn patch(&mut self, at: usize, target: usize) {
+        match self.code.instructions[at] {
+            Instr::Jump(ref mut pos) |
+            Instr::JumpIfNot(ref mut pos, _, _) |
+            Instr::Lazy(_, ref mut pos) => *pos = target,
+            _ => panic!("Expected jump instruction")
+        }
+    }
+
+    fn eval(&mut self, expr: &ast::Expression) {
+        let context = self.code.contexts.len();
+        self.code.contexts.push(self.targets.clone());
+        self.emit(Instr::Eval(Box::new(expr.clone()), context));
+    }
+
+    fn block(&mut self, block: &ast::Block) {
+        if block.expressions.len() == 0 {
+            self.emit(Instr::Void);
+            return;
+        }
+        // Only declarations directly in the block leave variables on the stack.
+        let scope = block.expressions.iter().any(|e| {
+            if let ast::Expression::Assign(ref assign) = *e {
+                assign.op == ast::AssignOp::Assign
+            } else { false }
+        });
+        if scope { self.emit(Instr::EnterScope); }
+        let n = block.expressions.len();
+        for (i, e) in block.expressions.iter().enumerate() {
+            self.expr(e);
+            if i + 1 < n { self.emit(Instr::Pop); }
+        }
+        if scope { self.emit(Instr::ExitScope); }
+    }
+
+    /// Compiles expression where a value is required.
+    fn value(&mut self, expr: &ast::Expression, range: Range, msg: &'static str) {
+        self.targets.push(Target::Value(range, msg));
+        let some = self.expr(expr);
+        self.targets.pop();
+        if !some { self.emit(Instr::Expect(range, msg)); }
+    }
+
+    /// Returns operand that is read directly by the instruction using it.
+    fn leaf(&self, expr: &ast::Expression) -> Option<Operand> {
+        match *expr {
+            ast::Expression::Variable(_, ref v) => Some(Operand::Const(v.clone())),
+            ast::Expression::Item(ref item) if item.ids.len() == 0 && !item.try =>
+                item.static_stack_id.get().map(Operand::Local),
+            _ => None
+        }
+    }
+
+    /// Compiles operand where a value is required.
+    fn operand(&mut self, expr: &ast::Expression, range: Range, msg: &'static str) -> Operand {
+        match self.leaf(expr) {
+            Some(operand) => operand,
+            None => {
+                self.value(expr, range, msg);
+                Operand::Stack
+            }
+        }
+    }
+
+    /// Compiles operand where a value is optional.
+    fn optional_operand(
+        &mut self,
+        expr: &ast::Expression,
+        range: Range,
+        msg: &'static str
+    ) -> Operand {
+        match self.leaf(expr) {
+            Some(operand) => operand,
+            None => {
+                self.optional(expr, range, msg);
+                Operand::Stack
+            }
+        }
+    }
+
+    /// Compiles item with ids.
+    fn item(&mut self, item: &ast::Item, side: Side) {
+        let mut n = 0;
+        for id in &item.ids {
+            if let ast::Id::Expression(ref expr) = *id {
+                let arg = self.operand(expr, expr.source_range(),
+                    "Expected something for index");
+                self.emit(Instr::PushArg(arg));
+                n += 1;
+            }
+        }
+        let index = self.code.items.len();
+        self.code.items.push(item.clone());
+        self.emit(Instr::Item(index, n, side));
+    }
+
+    /// Compiles expression where a value is optional,
+    /// but `break` and `continue` are not allowed.
+    fn optional(&mut self, expr: &ast::Expression, range: Range, msg: &'static str) {
+        self.targets.push(Target::Value(range, msg));
+        self.expr(expr);
+        self.targets.pop();
+    }
+
+    /// Compiles `break` or `continue`.
+    fn jump_loop(&mut self, label: &Option<Arc<String>>, is_break: bool) {
+        let mut instr = None;
+        for target in self.targets.iter().rev() {
+            match *target {
+                Target::Loop(id) => {
+                    if label.is_none() || self.code.loops[id].label == *label {
+                        instr = Some(if is_break { Instr::Break(id) }
+                                     else { Instr::Continue(id) });
+                        break;
+                    }
+                }
+                Target::Value(range, msg) => {
+                    instr = Some(Instr::Fail(range, msg));
+                    break;
+                }
+            }
+        }
+        let instr = instr.unwrap_or_else(|| Instr::Escape(if is_break {
+            Flow::Break(label.clone())
+        } else {
+            Flow::ContinueLoop(label.clone())
+        }));
+        self.emit(instr);
+    }
+
+    fn begin_loop(
+        &mut self,
+        kind: LoopKind,
+        label: &Option<Arc<String>>,
+        name: Arc<String>,
+        source_range: Range,
+        cond_range: Range,
+        block_range: Range
+    ) -> usize {
+        let id = self.code.loops.len();
+        self.code.loops.push(LoopInfo {
+            kind: kind,
+            label: label.clone(),
+            depth: self.depth,
+            name: name,
+            cond: 0,
+            next: 0,
+            exit: 0,
+            source_range: source_range,
+            cond_range: cond_range,
+            block_range: block_range,
+            for_in: None,
+        });
+        self.emit(Instr::BeginLoop(id));
+        id
+    }
+
+    fn for_expr(&mut self, for_expr: &ast::For) {
+        let id = self.begin_loop(LoopKind::For, &for_expr.label, Arc::new(String::new()),
+            for_expr.source_range, for_expr.cond.source_range(),
+            for_expr.block.source_range);
+        let init_range = for_expr.init.source_range();
+        self.optional(&for_expr.init, init_range, "Expected nothing from for init");
+        self.emit(Instr::ExpectVoid(init_range, "Expected nothing from for init"));
+        self.emit(Instr::LoopMark(id));
+        let cond = self.pos();
+        let cond_range = for_expr.cond.source_range();
+        self.optional(&for_expr.cond, cond_range, "Expected bool from for condition");
+        self.emit(Instr::ForCond(id));
+        self.depth += 1;
+        self.targets.push(Target::Loop(id));
+        self.block(&for_expr.block);
+        self.targets.pop();
+        self.depth -= 1;
+        self.emit(Instr::Pop);
+        let next = self.pos();
+        let step_range = for_expr.step.source_range();
+        self.optional(&for_expr.step, step_range, "Expected nothing from for step");
+        self.emit(Instr::ExpectVoid(step_range, "Expected nothing from for step"));
+        self.emit(Instr::Reset(id));
+        let exit = self.pos();
+        self.emit(Instr::EndLoop);
+        let info = &mut self.code.loops[id];
+        info.cond = cond;
+        info.next = next;
+        info.exit = exit;
+    }
+
+    fn for_n_expr(&mut self, kind: LoopKind, for_n_expr: &ast::ForN) {
+        let end_range = for_n_expr.end.source_range();
+        let id = self.begin_loop(kind, &for_n_expr.label, for_n_expr.name.clone(),
+            for_n_expr.source_range, end_range, for_n_expr.block.source_range);
+        self.code.loops[id].for_in = for_n_expr.for_in.clone();
+        if let Some(ref start) = for_n_expr.start {
+            self.optional(start, end_range, "Expected number from for start");
+            self.emit(Instr::Number(end_range, "Expected number from for start"));
+        } else {
+            self.emit(Instr::Const(Variable::f64(0.0)));
+        }
+        self.optional(&for_n_expr.end, end_range, "Expected number from for end");
+        if for_n_expr.for_in.is_none() {
+            // The collection is checked when initializing the loop.
+            self.emit(Instr::Number(end_range, "Expected number from for end"));
+        }
+        self.emit(Instr::ForNInit(id));
+        let cond = self.pos();
+        self.emit(Instr::ForNCond(id));
+        self.depth += 1;
+        self.targets.push(Target::Loop(id));
+        self.block(&for_n_expr.block);
+        self.targets.pop();
+        self.depth -= 1;
+        self.emit(Instr::Accumulate(id));
+        let next = self.pos();
+        self.emit(Instr::ForNStep(id));
+        let exit = self.pos();
+        self.emit(Instr::EndLoop);
+        let info = &mut self.code.loops[id];
+        info.cond = cond;
+        info.next = next;
+        info.exit = exit;
+    }
+
+    fn if_expr(&mut self, if_expr: &ast::If) {
+        let mut ends = vec![];
+        let cond_range = if_expr.cond.source_range();
+        self.optional(&if_expr.cond, cond_range, "Expected bool from if condition");
+        let mut skip = self.pos();
+        self.emit(Instr::JumpIfNot(0, cond_range, "Expected bool from if condition"));
+        self.block(&if_expr.true_block);
+        ends.push(self.pos());
+        self.emit(Instr::Jump(0));
+        for (cond, body) in if_expr.else_if_conds.iter()
+            .zip(if_expr.else_if_blocks.iter()) {
+            let pos = self.pos();
+            self.patch(skip, pos);
+            let cond_range = cond.source_range();
+            self.optional(cond, cond_range, "Expected bool from else if condition");
+            skip = self.pos();
+            self.emit(Instr::JumpIfNot(0, cond_range, "Expected bool from else if condition"));
+            self.block(body);
+            ends.push(self.pos());
+            self.emit(Instr::Jump(0));
+        }
+        let pos = self.pos();
+        self.patch(skip, pos);
+        if let Some(ref block) = if_expr.else_block {
+            self.block(block);
+        } else {
+            self.emit(Instr::Void);
+        }
+        let end = self.pos();
+        for pos in ends {
+            self.patch(pos, end);
+        }
+    }
+
+    fn call(&mut self, call: &ast::Call) -> bool {
+        match call.f_index.get() {
+            FnIndex::Loaded(_) => {
+                let ci = self.code.calls.len();
+                self.code.calls.push(call.clone());
+                self.emit(Instr::BeginCall(ci));
+                for arg in &call.args {
+                    let arg = self.optional_operand(arg, arg.source_range(),
+                        "Expected something. Check that expression returns a value.");
+                    self.emit(Instr::PushArg(arg));
+                }
+                self.emit(Instr::Call(ci));
+                false
+            }
+            FnIndex::Intrinsic(index) => {
+                for arg in &call.args {
+                    let arg = self.operand(arg, arg.source_range(),
+                        "Expected something. Expression did not return a value.");
+                    self.emit(Instr::PushArg(arg));
+                }
+                let ci = self.code.calls.len();
+                self.code.calls.push(call.clone());
+                self.emit(Instr::Intrinsic(index, ci));
+                false
+            }
+            FnIndex::ExternalVoid(_) | FnIndex::ExternalReturn(_) => {
+                for arg in &call.args {
+                    let arg = self.operand(arg, arg.source_range(),
+                        "Expected something. Expression did not return a value.");
+                    self.emit(Instr::PushArg(arg));
+                }
+                let ci = self.code.calls.len();
+                self.code.calls.push(call.clone());
+                self.emit(Instr::External(ci));
+                false
+            }
+            FnIndex::None | FnIndex::Variant => {
+                self.eval(&ast::Expression::Call(call.clone()));
+                false
+            }
+        }
+    }
+
+    /// Compiles expression that pushes one value.
+    ///
+    /// Returns `true` if the value is never missing.
+    fn expr(&mut self, expr: &ast::Expression) -> bool {
+        use ast::Expression as E;
+
+        match *expr {
+            E::Variable(_, ref v) => {
+                self.emit(Instr::Const(v.clone()));
+                true
+            }
+            E::Item(ref item) => {
+                match item.static_stack_id.get() {
+                    Some(id) if item.ids.len() == 0 && !item.try => {
+                        self.emit(Instr::Local(id));
+                        true
+                    }
+                    Some(_) if item.ids.len() > 0 => {
+                        self.item(item, Side::Right);
+                        true
+                    }
+                    _ => {
+                        self.eval(expr);
+                        false
+                    }
+                }
+            }
+            E::Block(ref block) => {
+                self.block(block);
+                false
+            }
+            E::Return(ref ret) => {
+                let ret = self.operand(ret, expr.source_range(), "Expected something");
+                self.emit(Instr::Return(ret));
+                false
+            }
+            E::ReturnVoid(_) => {
+                self.emit(Instr::ReturnVoid);
+                false
+            }
+            E::Break(ref b) => {
+                self.jump_loop(&b.label, true);
+                false
+            }
+            E::Continue(ref b) => {
+                self.jump_loop(&b.label, false);
+                false
+            }
+            E::Call(ref call) => self.call(call),
+            E::BinOp(ref binop) => {
+                use ast::BinOp::*;
+
+                let (left, lazy) = match binop.op {
+                    OrElse | AndAlso => {
+                        self.value(&binop.left, binop.source_range,
+                            "Expected something from left argument");
+                        let pos = self.pos();
+                        self.emit(Instr::Lazy(binop.op, 0));
+                        (Operand::Stack, Some(pos))
+                    }
+                    _ => (self.operand(&binop.left, binop.source_range,
+                            "Expected something from left argument"), None)
+                };
+                let right = self.operand(&binop.right, binop.source_range,
+                    "Expected something from right argument");
+                self.emit(Instr::BinOp(binop.op, binop.source_range, left, right));
+                if let Some(pos) = lazy {
+                    let end = self.pos();
+                    self.patch(pos, end);
+                }
+                true
+            }
+            E::UnOp(ref unop) => {
+                let arg = self.operand(&unop.expr, unop.source_range,
+                    "Expected something from unary argument");
+                self.emit(Instr::UnOp(unop.op, unop.source_range, arg));
+                true
+            }
+            E::Norm(ref norm) => {
+                let arg = self.operand(&norm.expr, norm.source_range,
+                    "Expected something from unary argument");
+                self.emit(Instr::Norm(norm.source_range, arg));
+                true
+            }
+            E::Compare(ref compare) => {
+                let left = self.operand(&compare.left, compare.left.source_range(),
+                    "Expected something from the left argument");
+                let right = self.operand(&compare.right, compare.right.source_range(),
+                    "Expected something from the right argument");
+                self.emit(Instr::Compare(compare.op, compare.source_range, left, right));
+                true
+            }
+            E::Assign(ref assign) => {
+                let left = match assign.left {
+                    E::Item(ref item) => item,
+                    _ => {
+                        self.eval(expr);
+                        return false;
+                    }
+                };
+                let right_range = assign.right.source_range();
+                let msg = "Expected something from the right side";
+                if assign.op == ast::AssignOp::Assign && left.ids.len() == 0 {
+                    let right = self.operand(&assign.right, right_range, msg);
+                    self.emit(Instr::Declare(left.name.clone(), left.current, right));
+                } else if left.static_stack_id.get().is_none() ||
+                          left.ids.len() == 0 && left.try {
+                    self.eval(expr);
+                    return false;
+                } else if assign.op == ast::AssignOp::Assign {
+                    self.value(&assign.right, right_range, msg);
+                    self.emit(Instr::Shallow);
+                    self.item(left, Side::LeftInsert(true));
+                    self.emit(Instr::Insert);
+                } else {
+                    let right = self.operand(&assign.right, right_range, msg);
+                    let left_operand = if left.ids.len() == 0 {
+                        Operand::Local(left.static_stack_id.get().unwrap())
+                    } else {
+                        self.item(left, Side::LeftInsert(false));
+                        Operand::Stack
+                    };
+                    self.emit(Instr::AssignOp(assign.op, left.source_range,
+                                              left_operand, right));
+                }
+                false
+            }
+            E::Try(ref expr) => {
+                let range = expr.source_range();
+                self.value(expr, range, "Expected something");
+                self.emit(Instr::Try(range));
+                true
+            }
+            E::Array(ref arr) => {
+                for item in &arr.items {
+                    self.value(item, item.source_range(), "Expected something");
+                }
+                self.emit(Instr::Array(arr.items.len(), arr.source_range));
+                true
+            }
+            E::Object(ref obj) => {
+                let mut keys: Vec<Arc<String>> = vec![];
+                for &(ref key, _) in &obj.key_values {
+                    if keys.contains(key) {
+                        // Report duplicate key when walking the tree.
+                        self.eval(expr);
+                        return false;
+                    }
+                    keys.push(key.clone());
+                }
+                for &(_, ref e) in &obj.key_values {
+                    self.value(e, e.source_range(), "Expected something");
+                }
+                let index = self.code.objects.len();
+                self.code.objects.push(keys);
+                self.emit(Instr::Object(index, obj.source_range));
+                true
+            }
+            E::ArrayFill(ref array_fill) => {
+                self.value(&array_fill.fill, array_fill.fill.source_range(),
+                    "Expected something");
+                self.value(&array_fill.n, array_fill.n.source_range(),
+                    "Expected something");
+                self.emit(Instr::ArrayFill(array_fill.n.source_range()));
+                true
+            }
+            E::Vec4(ref vec4) => {
+                let swizzle = vec4.args.iter().any(|arg| {
+                    if let E::Swizzle(_) = *arg { true } else { false }
+                });
+                if swizzle || vec4.args.len() != 4 {
+                    self.eval(expr);
+                    return false;
+                }
+                for arg in &vec4.args {
+                    let arg = self.optional_operand(arg, arg.source_range(),
+                        "Expected something from vec4 argument");
+                    self.emit(Instr::PushArg(arg));
+                }
+                self.emit(Instr::Vec4(Box::new([
+                    vec4.args[0].source_range(),
+                    vec4.args[1].source_range(),
+                    vec4.args[2].source_range(),
+                    vec4.args[3].source_range(),
+                ])));
+                true
+            }
+            E::If(ref if_expr) => {
+                self.if_expr(if_expr);
+                false
+            }
+            E::For(ref for_expr) => {
+                self.for_expr(for_expr);
+                false
+            }
+            E::ForN(ref for_n_expr) => {
+                self.for_n_expr(LoopKind::ForN, for_n_expr);
+                false
+            }
+            E::Sum(ref for_n_expr) => {
+                self.for_n_expr(LoopKind::Sum, for_n_expr);
+                true
+            }
+            E::Prod(ref for_n_expr) => {
+                self.for_n_expr(LoopKind::Prod, for_n_expr);
+                true
+            }
+            E::Min(ref for_n_expr) => {
+                self.for_n_expr(LoopKind::Min, for_n_expr);
+                true
+            }
+            E::Max(ref for_n_expr) => {
+                self.for_n_expr(LoopKind::Max, for_n_expr);
+                true
+            }
+            E::Any(ref for_n_expr) => {
+                self.for_n_expr(LoopKind::Any, for_n_expr);
+                true
+            }
+            E::All(ref for_n_expr) => {
+                self.for_n_expr(LoopKind::All, for_n_expr);
+                true
+            }
+            E::Sift(ref for_n_expr) => {
+                self.for_n_expr(LoopKind::Sift, for_n_expr);
+                true
+            }
+            _ => {
+                self.eval(expr);
+                false
+            }
+        }
+    }
+}
+
+/// Finds the target of `break` or `continue` when unwinding.
+fn find_target<'a>(
+    targets: &'a [Target],
+    loops: &[LoopInfo],
+    label: &Option<Arc<String>>
+) -> Option<&'a Target> {
+    for target in targets.iter().rev() {
+        match *target {
+            Target::Loop(id) => {
+                if label.is_none() || loops[id].label == *label {
+                    return Some(target);
+                }
+            }
+            Target::Value(_, _) => return Some(target),
+        }
+    }
+    None
+}
+
+/// Runs compiled function body.
+#[inline]
+pub fn exec(
+    rt: &mut Runtime,
+    code: &Code,
+    module: &Arc<Module>
+) -> Result<(Option<Variable>, Flow), DyonError> {
+    if code.instructions.len() == 0 {
+        return Ok((None, Flow::Continue));
+    }
+    let values = rt.vm.values.len();
+    let marks = rt.vm.marks.len();
+    let loops = rt.vm.loops.len();
+    let calls = rt.vm.calls.len();
+    let res = dispatch(rt, code, loops, module);
+    rt.vm.values.truncate(values);
+    rt.vm.marks.truncate(marks);
+    rt.vm.loops.truncate(loops);
+    rt.vm.calls.truncate(calls);
+    res
+}
+
+fn error(rt: &Runtime, module: &Module, range: Range, msg: &str) -> DyonError {
+    module.error(range, msg, rt)
+}
+
+/// Unwinds to loop and returns where to continue.
+fn jump_loop(rt: &mut Runtime, code: &Code, loops: usize, id: usize, is_break: bool) -> usize {
+    let info = &code.loops[id];
+    let ind = loops + info.depth;
+    rt.vm.loops.truncate(ind + 1);
+    let (marks, values, (st, lc, cu)) = {
+        let state = &rt.vm.loops[ind];
+        (state.marks, state.values, state.body)
+    };
+    rt.vm.marks.truncate(marks);
+    rt.vm.values.truncate(values);
+    rt.stack.truncate(st);
+    rt.local_stack.truncate(lc);
+    rt.current_stack.truncate(cu);
+    if is_break { info.exit } else { info.next }
+}
+
+#[inline(always)]
+fn pop(rt: &mut Runtime) -> Option<Variable> {
+    rt.vm.values.pop().expect("Expected value")
+}
+
+/// Adds counter or key to secret of `min`, `max`, `any` and `all` loops.
+fn secret(sec: &Option<Box<Vec<Variable>>>, key: Variable) -> Box<Vec<Variable>> {
+    match *sec {
+        None => Box::new(vec![key]),
+        Some(ref arr) => {
+            let mut arr = arr.clone();
+            arr.push(key);
+            arr
+        }
+    }
+}
+
+/// Resolves local by distance from top of the stack with length `len`.
+#[inline(always)]
+fn local(rt: &Runtime, len: usize, id: usize) -> usize {
+    let stack_id = len - id;
+    if let Variable::Ref(ref_id) = rt.stack[stack_id] { ref_id } else { stack_id }
+}
+
+#[inline(always)]
+fn operand(rt: &mut Runtime, operand: &Operand) -> Option<Variable> {
+    match *operand {
+        Operand::Stack => pop(rt),
+        Operand::Local(id) => Some(Variable::Ref(local(rt, rt.stack.len(), id))),
+        Operand::Const(ref v) => Some(v.clone()),
+    }
+}
+
+fn dispatch(
+    rt: &mut Runtime,
+    code: &Code,
+    loops: usize,
+    module: &Arc<Module>
+) -> Result<(Option<Variable>, Flow), DyonError> {
+    use FnExternalRef;
+
+    let instructions = &code.instructions;
+    let mut pc = 0;
+    while pc < instructions.len() {
+        match instructions[pc] {
+            Instr::Const(ref v) => rt.vm.values.push(Some(v.clone())),
+            Instr::Void => rt.vm.values.push(None),
+            Instr::Pop => { rt.vm.values.pop(); }
+            Instr::Local(id) => {
+                let stack_id = local(rt, rt.stack.len(), id);
+                rt.vm.values.push(Some(Variable::Ref(stack_id)));
+            }
+            Instr::Eval(ref expr, context) => {
+                match try!(rt.expression(expr, Side::Right, module)) {
+                    (x, Flow::Continue) => rt.vm.values.push(x),
+                    (x, Flow::Return) => return Ok((x, Flow::Return)),
+                    (_, flow) => {
+                        let (label, is_break) = match flow {
+                            Flow::Break(ref label) => (label, true),
+                            Flow::ContinueLoop(ref label) => (label, false),
+                            _ => unreachable!()
+                        };
+                        match find_target(&code.contexts[context], &code.loops, label) {
+                            None => return Ok((None, flow)),
+                            Some(&Target::Value(range, msg)) =>
+                                return Err(error(rt, module, range, msg)),
+                            Some(&Target::Loop(id)) => {
+                                pc = jump_loop(rt, code, loops, id, is_break);
+                                continue;
+                            }
+                        }
+                    }
+                }
+            }
+            Instr::Expect(range, msg) => {
+                if let Some(&None) = rt.vm.values.last() {
+                    return Err(error(rt, module, range, msg));
+                }
+            }
+            Instr::ExpectVoid(range, msg) => {
+                if pop(rt).is_some() {
+                    return Err(error(rt, module, range, msg));
+                }
+            }
+            Instr::Fail(range, msg) => return Err(error(rt, module, range, msg)),
+            Instr::Return(ref v) => return Ok((operand(rt, v), Flow::Return)),
+            Instr::ReturnVoid => return Ok((None, Flow::Return)),
+            Instr::Escape(ref flow) => return Ok((None, flow.clone())),
+            Instr::Break(id) => {
+                pc = jump_loop(rt, code, loops, id, true);
+                continue;
+            }
+            Instr::Continue(id) => {
+                pc = jump_loop(rt, code, loops, id, false);
+                continue;
+            }
+            Instr::Jump(pos) => {
+                pc = pos;
+                continue;
+            }
+            Instr::JumpIfNot(pos, range, msg) => {
+                let cond = match pop(rt) {
+                    Some(x) => x,
+                    None => return Err(error(rt, module, range, msg))
+                };
+                match *rt.resolve(&cond) {
+                    Variable::Bool(true, _) => {}
+                    Variable::Bool(false, _) => {
+                        pc = pos;
+                        continue;
+                    }
+                    _ => return Err(error(rt, module, range, msg))
+                }
+            }
+            Instr::Lazy(op, pos) => {
+                let v = match (op, rt.resolve(rt.vm.values.last().unwrap().as_ref().unwrap())) {
+                    (ast::BinOp::OrElse, &Variable::Bool(true, ref sec)) =>
+                        Variable::Bool(true, sec.clone()),
+                    (ast::BinOp::AndAlso, &Variable::Bool(false, ref sec)) =>
+                        Variable::Bool(false, sec.clone()),
+                    _ => {
+                        pc += 1;
+                        continue;
+                    }
+                };
+                *rt.vm.values.last_mut().unwrap() = Some(v);
+                pc = pos;
+                continue;
+            }
+            Instr::BinOp(op, range, ref a, ref b) => {
+                let right = operand(rt, b).unwrap();
+                let left = operand(rt, a).unwrap();
+                let v = try!(rt.binop_values(op, range, &left, &right, module));
+                try!(rt.alloc_var(&v, range, module));
+                rt.vm.values.push(Some(v));
+            }
+            Instr::UnOp(op, range, ref a) => {
+                let val = operand(rt, a).unwrap();
+                let v = try!(rt.unop_value(op, range, &val, module));
+                rt.vm.values.push(Some(v));
+            }
+            Instr::Norm(range, ref a) => {
+                let val = operand(rt, a).unwrap();
+                let v = try!(rt.norm_value(range, &val, module));
+                rt.vm.values.push(Some(v));
+            }
+            Instr::Compare(op, range, ref a, ref b) => {
+                let right = operand(rt, b).unwrap();
+                let left = operand(rt, a).unwrap();
+                let v = try!(rt.compare_values(op, range, &left, &right, module));
+                rt.vm.values.push(Some(v));
+            }
+            Instr::Item(index, n, side) => {
+                let item = &code.items[index];
+                let start = rt.stack.len() - n;
+                let stack_id = local(rt, start, item.static_stack_id.get().unwrap());
+                match try!(rt.item_ids(item, side, stack_id, start, module)) {
+                    (x, Flow::Return) => return Ok((x, Flow::Return)),
+                    (x, _) => rt.vm.values.push(x),
+                }
+            }
+            Instr::Shallow => {
+                let v = match rt.vm.values.last() {
+                    Some(&Some(Variable::Ref(ind))) => rt.stack[ind].clone(),
+                    _ => {
+                        pc += 1;
+                        continue;
+                    }
+                };
+                *rt.vm.values.last_mut().unwrap() = Some(v);
+            }
+            Instr::Insert => {
+                let r = pop(rt).unwrap();
+                let v = pop(rt).unwrap();
+                match r {
+                    Variable::UnsafeRef(r) => unsafe { *r.0 = v },
+                    _ => panic!("Expected unsafe reference")
+                }
+                rt.vm.values.push(None);
+            }
+            Instr::Declare(ref name, current, ref a) => {
+                let v = match operand(rt, a).unwrap() {
+                    // Use a shallow clone of a reference.
+                    Variable::Ref(ind) => rt.stack[ind].clone(),
+                    x => x
+                };
+                rt.local_stack.push((name.clone(), rt.stack.len()));
+                if current {
+                    rt.current_stack.push((name.clone(), rt.stack.len()));
+                }
+                rt.stack.push(v);
+                rt.vm.values.push(None);
+            }
+            Instr::AssignOp(op, range, ref a, ref b) => {
+                let a = operand(rt, a).unwrap();
+                let b = operand(rt, b).unwrap();
+                try!(rt.assign_values(op, range, a, &b, module));
+                if op == ast::AssignOp::Add {
+                    try!(rt.alloc_var(&b, range, module));
+                }
+                rt.vm.values.push(None);
+            }
+            Instr::Try(range) => {
+                let v = pop(rt).unwrap();
+                match try!(rt.try_value(&v, range, module)) {
+                    (x, Flow::Return) => return Ok((x, Flow::Return)),
+                    (x, _) => rt.vm.values.push(x),
+                }
+            }
+            Instr::Array(n, range) => {
+                let start = rt.vm.values.len() - n;
+                let arr: Vec<Variable> = rt.vm.values.drain(start..)
+                    .map(|v| v.unwrap()).collect();
+                let v = Variable::Array(Arc::new(arr));
+                try!(rt.alloc_var(&v, range, module));
+                rt.vm.values.push(Some(v));
+            }
+            Instr::Object(index, range) => {
+                let keys = &code.objects[index];
+                let start = rt.vm.values.len() - keys.len();
+                let mut obj: HashMap<_, _> = HashMap::new();
+                for (key, v) in keys.iter().zip(rt.vm.values.drain(start..)) {
+                    obj.insert(key.clone(), v.unwrap());
+                }
+                let v = Variable::Object(Arc::new(obj));
+                try!(rt.alloc_var(&v, range, module));
+                rt.vm.values.push(Some(v));
+            }
+            Instr::ArrayFill(range) => {
+                let n = pop(rt).unwrap();
+                let fill = pop(rt).unwrap();
+                let n = match *rt.resolve(&n) {
+                    Variable::F64(n, _) => n as usize,
+                    _ => return Err(error(rt, module, range,
+                        "Expected number for length in `[value; length]`"))
+                };
+                try!(rt.alloc_items(n, range, module));
+                let v = Variable::Array(Arc::new(vec![rt.resolve(&fill).clone(); n]));
+                rt.vm.values.push(Some(v));
+            }
+            Instr::Vec4(ref ranges) => {
+                let mut v = [0.0; 4];
+                for i in (0..4).rev() {
+                    let x = rt.stack.pop().expect(::TINVOTS);
+                    v[i] = match *rt.resolve(&x) {
+                        Variable::F64(val, _) => val as f32,
+                        ref x => return Err(module.error(ranges[i],
+                            &rt.expected(x, "number"), rt))
+                    };
+                }
+                rt.vm.values.push(Some(Variable::Vec4(v)));
+            }
+            Instr::PushArg(ref a) => {
+                if let Some(v) = operand(rt, a) {
+                    rt.stack.push(v);
+                }
+            }
+            Instr::BeginCall(ci) => {
+                let call = &code.calls[ci];
+                if rt.step() {
+                    try!(rt.check_limits(call.source_range, module));
+                }
+                let f_index = match call.f_index.get() {
+                    FnIndex::Loaded(f_index) => f_index,
+                    _ => unreachable!()
+                };
+                let loader = false;
+                let state = try!(rt.begin_call(call, f_index, loader, module));
+                rt.vm.calls.push(state);
+            }
+            Instr::Call(ci) => {
+                let call = &code.calls[ci];
+                let (new_index, st, lc, cu) = rt.vm.calls.pop().unwrap();
+                try!(rt.enter_call(call, new_index, st, lc, cu, module));
+                let (x, flow) = try!(rt.call_body(new_index, module));
+                let (x, _) = try!(rt.end_call(call, new_index, x, flow, module));
+                rt.vm.values.push(x);
+            }
+            Instr::Intrinsic(index, ci) => {
+                if rt.step() {
+                    try!(rt.check_limits(code.calls[ci].source_range, module));
+                }
+                let v = try!(intrinsics::call_standard_pushed(rt, index, &code.calls[ci], module));
+                rt.vm.values.push(v);
+            }
+            Instr::External(ci) => {
+                let call = &code.calls[ci];
+                if rt.step() {
+                    try!(rt.check_limits(call.source_range, module));
+                }
+                match call.f_index.get() {
+                    FnIndex::ExternalVoid(FnExternalRef(f)) => {
+                        try!((f)(rt).map_err(|err|
+                            module.error(call.source_range, &err, rt)));
+                        rt.vm.values.push(None);
+                    }
+                    FnIndex::ExternalReturn(FnExternalRef(f)) => {
+                        try!((f)(rt).map_err(|err|
+                            module.error(call.source_range, &err, rt)));
+                        let v = rt.stack.pop().expect(::TINVOTS);
+                        rt.vm.values.push(Some(v));
+                    }
+                    _ => unreachable!()
+                }
+            }
+            Instr::EnterScope => {
+                rt.vm.marks.push((rt.stack.len(), rt.local_stack.len(),
+                                  rt.current_stack.len()));
+            }
+            Instr::ExitScope => {
+                let (st, lc, cu) = rt.vm.marks.pop().unwrap();
+                rt.stack.truncate(st);
+                rt.local_stack.truncate(lc);
+                rt.current_stack.truncate(cu);
+            }
+            Instr::BeginLoop(id) => {
+                let acc = match code.loops[id].kind {
+                    LoopKind::For | LoopKind::ForN => None,
+                    LoopKind::Sum => Some(Variable::f64(0.0)),
+                    LoopKind::Prod => Some(Variable::f64(1.0)),
+                    LoopKind::Min | LoopKind::Max => Some(Variable::f64(::std::f64::NAN)),
+                    LoopKind::Any => Some(Variable::bool(false)),
+                    LoopKind::All => Some(Variable::bool(true)),
+                    LoopKind::Sift => Some(Variable::Array(Arc::new(vec![]))),
+                };
+                let st = rt.stack.len();
+                let lc = rt.local_stack.len();
+                let cu = rt.current_stack.len();
+                let state = LoopState {
+                    prev: (st, lc),
+                    body: (st, lc, cu),
+                    marks: rt.vm.marks.len(),
+                    values: rt.vm.values.len(),
+                    end: 0.0,
+                    ind: 0.0,
+                    acc: acc,
+                    items: None,
+                };
+                rt.vm.loops.push(state);
+            }
+            Instr::LoopMark(_) => {
+                let body = (rt.stack.len(), rt.local_stack.len(), rt.current_stack.len());
+                rt.vm.loops.last_mut().unwrap().body = body;
+            }
+            Instr::ForCond(id) => {
+                let info = &code.loops[id];
+                let val = match pop(rt) {
+                    Some(Variable::Bool(val, _)) => val,
+                    Some(_) => return Err(error(rt, module, info.cond_range, "Expected bool")),
+                    None => return Err(error(rt, module, info.cond_range,
+                        "Expected bool from for condition"))
+                };
+                if !val {
+                    pc = info.exit;
+                    continue;
+                }
+            }
+            Instr::Reset(id) => {
+                if rt.step() {
+                    try!(rt.check_limits(code.loops[id].source_range, module));
+                }
+                let (st, lc, _) = rt.vm.loops.last().unwrap().body;
+                rt.stack.truncate(st);
+                rt.local_stack.truncate(lc);
+                pc = code.loops[id].cond;
+                continue;
+            }
+            Instr::Number(range, msg) => {
+                let v = match pop(rt) {
+                    Some(x) => x,
+                    None => return Err(error(rt, module, range, msg))
+                };
+                let v = match *rt.resolve(&v) {
+                    Variable::F64(val, _) => val,
+                    ref x => return Err(module.error(range, &rt.expected(x, "number"), rt))
+                };
+                rt.vm.values.push(Some(Variable::f64(v)));
+            }
+            Instr::ForNInit(id) => {
+                let info = &code.loops[id];
+                let (end, items) = if info.for_in.is_some() {
+                    let v = match pop(rt) {
+                        Some(x) => x,
+                        None => return Err(error(rt, module, info.cond_range,
+                            "Expected number from for end"))
+                    };
+                    let items = try!(rt.for_in_items(&v, info.cond_range, module));
+                    (items.len() as f64, Some(items))
+                } else {
+                    match pop(rt) {
+                        Some(Variable::F64(val, _)) => (val, None),
+                        _ => unreachable!()
+                    }
+                };
+                let start = pop(rt).unwrap();
+                if info.for_in.is_none() {
+                    rt.local_stack.push((info.name.clone(), rt.stack.len()));
+                }
+                rt.stack.push(start);
+                let body = (rt.stack.len(), rt.local_stack.len(), rt.current_stack.len());
+                let state = rt.vm.loops.last_mut().unwrap();
+                state.body = body;
+                state.end = end;
+                state.items = items;
+            }
+            Instr::ForNCond(id) => {
+                let (st, end) = {
+                    let state = rt.vm.loops.last().unwrap();
+                    (state.body.0, state.end)
+                };
+                match rt.stack[st - 1] {
+                    Variable::F64(val, _) => {
+                        if val < end {
+                            rt.vm.loops.last_mut().unwrap().ind = val;
+                            if let Some(ref for_in) = code.loops[id].for_in {
+                                let (key, value) = rt.vm.loops.last().unwrap()
+                                    .items.as_ref().unwrap()[val as usize].clone();
+                                if let Some(ref name) = for_in.key {
+                                    rt.local_stack.push((name.clone(), rt.stack.len()));
+                                    rt.stack.push(key);
+                                }
+                                rt.local_stack.push((for_in.value.clone(), rt.stack.len()));
+                                rt.stack.push(value);
+                            }
+                        } else {
+                            pc = code.loops[id].exit;
+                            continue;
+                        }
+                    }
+                    ref x => return Err(module.error(code.loops[id].source_range,
+                                        &rt.expected(x, "number"), rt))
+                }
+            }
+            Instr::Accumulate(id) => {
+                let info = &code.loops[id];
+                let v = pop(rt);
+                match info.kind {
+                    LoopKind::For | LoopKind::ForN => {}
+                    LoopKind::Sum | LoopKind::Prod => {
+                        let val = match v {
+                            None => return Err(module.error(info.block_range,
+                                "Expected `number`", rt)),
+                            Some(ref x) => match *rt.resolve(x) {
+                                Variable::F64(val, _) => val,
+                                ref x => return Err(module.error(info.block_range,
+                                    &rt.expected(x, "number"), rt))
+                            }
+                        };
+                        if let Some(Variable::F64(ref mut acc, _)) =
+                            rt.vm.loops.last_mut().unwrap().acc {
+                            if info.kind == LoopKind::Sum { *acc += val } else { *acc *= val }
+                        }
+                    }
+                    LoopKind::Min | LoopKind::Max => {
+                        let (val, sec) = match v {
+                            None => return Err(module.error(info.block_range,
+                                "Expected `number or option`", rt)),
+                            Some(ref x) => match *rt.resolve(x) {
+                                Variable::F64(val, ref sec) => (val, sec.clone()),
+                                ref x => return Err(module.error(info.block_range,
+                                    &rt.expected(x, "number"), rt))
+                            }
+                        };
+                        let state = rt.vm.loops.last_mut().unwrap();
+                        let key = state.key();
+                        if let Some(Variable::F64(ref mut acc, ref mut acc_sec)) = state.acc {
+                            let better = if info.kind == LoopKind::Min { *acc > val }
+                                         else { *acc < val };
+                            if acc.is_nan() || better {
+                                *acc = val;
+                                *acc_sec = Some(secret(&sec, key));
+                            }
+                        }
+                    }
+                    LoopKind::Any | LoopKind::All => {
+                        let (val, sec) = match v {
+                            None => return Err(module.error(info.block_range,
+                                "Expected `boolean`", rt)),
+                            Some(ref x) => match *rt.resolve(x) {
+                                Variable::Bool(val, ref sec) => (val, sec.clone()),
+                                ref x => return Err(module.error(info.block_range,
+                                    &rt.expected(x, "boolean"), rt))
+                            }
+                        };
+                        if val == (info.kind == LoopKind::Any) {
+                            let state = rt.vm.loops.last_mut().unwrap();
+                            let key = state.key();
+                            state.acc = Some(Variable::Bool(val, Some(secret(&sec, key))));
+                            pc = info.exit;
+                            continue;
+                        }
+                    }
+                    LoopKind::Sift => {
+                        let v = match v {
+                            None => return Err(module.error(info.block_range,
+                                "Expected variable", rt)),
+                            Some(x) => x
+                        };
+                        if let Some(Variable::Array(ref mut arr)) =
+                            rt.vm.loops.last_mut().unwrap().acc {
+                            Arc::make_mut(arr).push(v);
+                        }
+                    }
+                }
+            }
+            Instr::ForNStep(id) => {
+                if rt.step() {
+                    try!(rt.check_limits(code.loops[id].source_range, module));
+                }
+                let (st, lc, _) = rt.vm.loops.last().unwrap().body;
+                let error = if let Variable::F64(ref mut val, _) = rt.stack[st - 1] {
+                    *val += 1.0;
+                    false
+                } else { true };
+                if error {
+                    return Err(module.error(code.loops[id].source_range,
+                               &rt.expected(&rt.stack[st - 1], "number"), rt))
+                }
+                rt.stack.truncate(st);
+                rt.local_stack.truncate(lc);
+                pc = code.loops[id].cond;
+                continue;
+            }
+            Instr::EndLoop => {
+                let state = rt.vm.loops.pop().unwrap();
+                rt.stack.truncate(state.prev.0);
+                rt.local_stack.truncate(state.prev.1);
+                rt.vm.values.push(state.acc);
+            }
+        }
+        pc += 1;
+    }
+    Ok((pop(rt), Flow::Continue))
+}
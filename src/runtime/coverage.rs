@@ -0,0 +1,384 @@
+//! Records which parts of programs are executed.
+//!
+//! The runtime records the range of each evaluated expression,
+//! calls to loaded functions and whether `?` continued or returned an error.
+//! Lines, functions and branches of `if` and `match` are found by parsing the source again,
+//! such that code that never ran is reported too.
+//!
+//! Coverage can be written in the lcov format or as a plain-text summary.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use piston_meta::MetaData;
+use range::Range;
+
+use ast;
+use parse_str;
+use Module;
+
+/// Coverage of a source file.
+pub struct FileCoverage {
+    pub file: Arc<String>,
+    pub source: Arc<String>,
+    /// Number of times each expression was evaluated, by range in source.
+    pub expressions: HashMap<Range, u64>,
+    /// Number of times `?` continued and returned an error, by offset in source.
+    pub tries: BTreeMap<usize, (u64, u64)>,
+    /// Number of calls by function name.
+    pub functions: HashMap<Arc<String>, u64>,
+}
+
+impl FileCoverage {
+    fn new(file: Arc<String>, source: Arc<String>) -> FileCoverage {
+        FileCoverage {
+            file: file,
+            source: source,
+            expressions: HashMap::new(),
+            tries: BTreeMap::new(),
+            functions: HashMap::new(),
+        }
+    }
+
+    /// Returns the largest number of times an expression starting at offset was evaluated.
+    fn hits_at(&self, offset: usize) -> u64 {
+        self.expressions.iter()
+            .filter(|&(range, _)| range.offset == offset)
+            .map(|(_, &hits)| hits)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the number of times the first expression within range was evaluated.
+    fn first_hits(&self, range: Range) -> u64 {
+        let first = self.expressions.keys()
+            .filter(|r| r.offset >= range.offset && r.offset < range.next_offset())
+            .map(|r| r.offset)
+            .min();
+        match first {
+            None => 0,
+            Some(offset) => self.hits_at(offset)
+        }
+    }
+
+    /// Computes coverage of lines, functions and branches.
+    fn summary(&self) -> Summary {
+        let code = Code::from_source(&self.file, &self.source);
+        let mut starts = vec![0];
+        starts.extend(self.source.match_indices('\n').map(|(i, _)| i + 1));
+        let line = |offset: usize| match starts.binary_search(&offset) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+
+        let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+        for &offset in &code.statements {
+            lines.insert(line(offset), 0);
+        }
+        for (range, &hits) in &self.expressions {
+            let n = lines.entry(line(range.offset)).or_insert(0);
+            if hits > *n { *n = hits }
+        }
+
+        let functions = code.functions.iter()
+            .map(|&(ref name, offset)| {
+                (name.clone(), line(offset), self.functions.get(name).cloned().unwrap_or(0))
+            })
+            .collect();
+
+        let mut branches = vec![];
+        for &(offset, ref blocks) in &code.branches {
+            let evaluated = self.hits_at(offset) > 0;
+            let taken = blocks.iter().map(|first| {
+                if !evaluated { return None }
+                Some(first.map(|first| self.first_hits(first)).unwrap_or(0))
+            }).collect();
+            branches.push((line(offset), taken));
+        }
+        for (&offset, &(ok, err)) in &self.tries {
+            branches.push((line(offset), vec![Some(ok), Some(err)]));
+        }
+        branches.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Summary {
+            lines: lines,
+            functions: functions,
+            branches: branches,
+        }
+    }
+}
+
+/// Lines, functions and branches of a file with number of times executed.
+struct Summary {
+    lines: BTreeMap<usize, u64>,
+    /// Name, line and number of calls.
+    functions: Vec<(Arc<String>, usize, u64)>,
+    /// Line and number of times each branch was taken,
+    /// or `None` when the branching expression never ran.
+    branches: Vec<(usize, Vec<Option<u64>>)>,
+}
+
+impl Summary {
+    fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|&&n| n > 0).count()
+    }
+
+    fn functions_hit(&self) -> usize {
+        self.functions.iter().filter(|f| f.2 > 0).count()
+    }
+
+    fn branches_found(&self) -> usize {
+        self.branches.iter().map(|b| b.1.len()).sum()
+    }
+
+    fn branches_hit(&self) -> usize {
+        self.branches.iter()
+            .map(|b| b.1.iter().filter(|&&n| n.unwrap_or(0) > 0).count())
+            .sum()
+    }
+}
+
+/// Code that can be executed, found by parsing the source.
+struct Code {
+    /// Offsets of statements and conditions.
+    statements: Vec<usize>,
+    /// Names of functions and offsets where they start.
+    functions: Vec<(Arc<String>, usize)>,
+    /// Offsets of `if` and `match` expressions and the range from the first statement
+    /// to the end of each block or arm, or `None` when the block is empty.
+    branches: Vec<(usize, Vec<Option<Range>>)>,
+}
+
+impl Code {
+    fn from_source(file: &str, source: &Arc<String>) -> Code {
+        struct Open {
+            name: Arc<String>,
+            offset: usize,
+            first: Option<usize>,
+        }
+
+        let mut code = Code {
+            statements: vec![],
+            functions: vec![],
+            branches: vec![],
+        };
+        // Only executed code is reported when the source has errors.
+        let data = match parse_str(file, source) {
+            Ok(x) => x,
+            Err(_) => return code,
+        };
+        let mut stack: Vec<Open> = vec![];
+        let mut ifs: Vec<(usize, Vec<Option<Range>>)> = vec![];
+        for d in &data {
+            match d.data {
+                MetaData::StartNode(ref name) => {
+                    let statement = match (&***name, stack.last().map(|p| &**p.name)) {
+                        ("cond", _) | ("else_if_cond", _) | ("guard", _) => true,
+                        ("expr", Some("block")) | ("expr", Some("true_block")) |
+                        ("expr", Some("else_if_block")) | ("expr", Some("else_block")) |
+                        ("expr", Some("match_arm")) |
+                        ("expr", Some("fn")) | ("expr", Some("closure")) => {
+                            let parent = stack.last_mut().unwrap();
+                            if parent.first.is_none() {
+                                parent.first = Some(d.offset);
+                            }
+                            true
+                        }
+                        _ => false
+                    };
+                    if statement {
+                        code.statements.push(d.offset);
+                    }
+                    if &***name == "if" || &***name == "match" {
+                        ifs.push((d.offset, vec![]));
+                    }
+                    stack.push(Open {
+                        name: name.clone(),
+                        offset: d.offset,
+                        first: None,
+                    });
+                }
+                MetaData::EndNode(_) => {
+                    let node = match stack.pop() {
+                        None => continue,
+                        Some(x) => x
+                    };
+                    match &**node.name {
+                        "true_block" | "else_if_block" | "else_block" | "match_arm" => {
+                            let end = d.range().next_offset();
+                            if let Some(x) = ifs.last_mut() {
+                                x.1.push(node.first.map(|first| Range::new(first, end - first)));
+                            }
+                        }
+                        "if" | "match" => {
+                            if let Some(x) = ifs.pop() {
+                                code.branches.push(x);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                MetaData::String(ref name, ref val) => {
+                    if &***name == "name" {
+                        if let Some(parent) = stack.last() {
+                            if &**parent.name == "fn" {
+                                code.functions.push((val.clone(), parent.offset));
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        code
+    }
+}
+
+/// Records executed code by file.
+///
+/// Threads started with `go` are not recorded.
+pub struct Coverage {
+    pub files: Vec<FileCoverage>,
+    /// The file recorded last, to avoid looking it up for every expression.
+    last: usize,
+}
+
+impl Coverage {
+    /// Creates a new coverage recorder.
+    pub fn new() -> Coverage {
+        Coverage {
+            files: vec![],
+            last: 0,
+        }
+    }
+
+    /// Adds the files of functions in module,
+    /// such that files where no code ran are reported.
+    pub fn add_module(&mut self, module: &Module) {
+        for f in &module.functions {
+            self.file_mut(&f.file, &f.source);
+        }
+    }
+
+    fn file_mut(&mut self, file: &Arc<String>, source: &Arc<String>) -> &mut FileCoverage {
+        let found = match self.files.get(self.last) {
+            Some(x) => Arc::ptr_eq(&x.file, file) || x.file == *file,
+            None => false,
+        };
+        if !found {
+            self.last = match self.files.iter().position(|x| x.file == *file) {
+                Some(i) => i,
+                None => {
+                    self.files.push(FileCoverage::new(file.clone(), source.clone()));
+                    self.files.len() - 1
+                }
+            };
+        }
+        &mut self.files[self.last]
+    }
+
+    /// Records a call to function.
+    pub fn enter(&mut self, f: &ast::Function) {
+        *self.file_mut(&f.file, &f.source).functions.entry(f.name.clone()).or_insert(0) += 1;
+    }
+
+    /// Records that an expression is evaluated.
+    pub fn expression(&mut self, file: &Arc<String>, source: &Arc<String>, range: Range) {
+        *self.file_mut(file, source).expressions.entry(range).or_insert(0) += 1;
+    }
+
+    /// Records whether `?` returned an error.
+    pub fn try_result(&mut self, file: &Arc<String>, source: &Arc<String>, offset: usize, err: bool) {
+        let n = self.file_mut(file, source).tries.entry(offset).or_insert((0, 0));
+        if err { n.1 += 1 } else { n.0 += 1 }
+    }
+
+    fn sorted_files(&self) -> Vec<&FileCoverage> {
+        let mut files: Vec<&FileCoverage> = self.files.iter().collect();
+        files.sort_by(|a, b| a.file.cmp(&b.file));
+        files
+    }
+
+    /// Writes coverage in the lcov tracefile format.
+    ///
+    /// Branches are the blocks of `if` expressions
+    /// and whether `?` continued or returned an error.
+    pub fn write_lcov<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for file in self.sorted_files() {
+            let summary = file.summary();
+            try!(writeln!(w, "TN:"));
+            try!(writeln!(w, "SF:{}", file.file));
+            for &(ref name, line, _) in &summary.functions {
+                try!(writeln!(w, "FN:{},{}", line, name));
+            }
+            for &(ref name, _, calls) in &summary.functions {
+                try!(writeln!(w, "FNDA:{},{}", calls, name));
+            }
+            try!(writeln!(w, "FNF:{}", summary.functions.len()));
+            try!(writeln!(w, "FNH:{}", summary.functions_hit()));
+            for (block, &(line, ref taken)) in summary.branches.iter().enumerate() {
+                for (branch, n) in taken.iter().enumerate() {
+                    match *n {
+                        None => try!(writeln!(w, "BRDA:{},{},{},-", line, block, branch)),
+                        Some(n) => try!(writeln!(w, "BRDA:{},{},{},{}", line, block, branch, n)),
+                    }
+                }
+            }
+            try!(writeln!(w, "BRF:{}", summary.branches_found()));
+            try!(writeln!(w, "BRH:{}", summary.branches_hit()));
+            for (line, hits) in &summary.lines {
+                try!(writeln!(w, "DA:{},{}", line, hits));
+            }
+            try!(writeln!(w, "LF:{}", summary.lines.len()));
+            try!(writeln!(w, "LH:{}", summary.lines_hit()));
+            try!(writeln!(w, "end_of_record"));
+        }
+        Ok(())
+    }
+
+    /// Returns a summary of lines, branches and functions by file,
+    /// followed by the lines that never ran.
+    pub fn report(&self) -> String {
+        fn percent(hit: usize, found: usize) -> String {
+            if found == 0 { return "-".into() }
+            format!("{:.1}%", 100.0 * hit as f64 / found as f64)
+        }
+
+        let mut s = format!("{:>8} {:>8} {:>9}  {}\n", "lines", "branches", "functions", "file");
+        let mut missed = String::new();
+        for file in self.sorted_files() {
+            let summary = file.summary();
+            s.push_str(&format!("{:>8} {:>8} {:>9}  {}\n",
+                percent(summary.lines_hit(), summary.lines.len()),
+                percent(summary.branches_hit(), summary.branches_found()),
+                percent(summary.functions_hit(), summary.functions.len()),
+                file.file));
+
+            // Group lines that never ran into ranges, e.g. `3-5`.
+            let mut ranges: Vec<(usize, usize)> = vec![];
+            let mut open = false;
+            for (&line, &hits) in &summary.lines {
+                if hits > 0 {
+                    open = false;
+                } else if open {
+                    ranges.last_mut().unwrap().1 = line;
+                } else {
+                    ranges.push((line, line));
+                    open = true;
+                }
+            }
+            if ranges.len() > 0 {
+                let ranges: Vec<String> = ranges.iter()
+                    .map(|&(a, b)| if a == b { format!("{}", a) } else { format!("{}-{}", a, b) })
+                    .collect();
+                missed.push_str(&format!("{}: {}\n", file.file, ranges.join(", ")));
+            }
+        }
+        if missed.len() > 0 {
+            s.push_str("\nNot run:\n");
+            s.push_str(&missed);
+        }
+        s
+    }
+}
@@ -0,0 +1,206 @@
+//! Hooks for stepping through programs.
+//!
+//! A debugger is called before each expression is evaluated,
+//! with access to the paused runtime.
+//! `Stepper` keeps track of breakpoints and stepping,
+//! such that front-ends only need to decide what to do when pausing.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use range::Range;
+
+use error;
+use load_str;
+use DyonError;
+use Module;
+use Variable;
+use super::{Call, Runtime};
+
+/// The function used to evaluate expressions in a paused frame.
+const EVAL_FN: &'static str = "__debug_eval";
+
+/// Called by the runtime before each expression.
+///
+/// Threads started with `go` run without a debugger.
+pub trait Debugger: Send {
+    /// Called before evaluating an expression.
+    ///
+    /// Returning an error stops the program with a runtime error.
+    fn before_expression(&mut self, paused: &mut Paused) -> Result<(), String>;
+}
+
+/// The state of a runtime before evaluating an expression.
+pub struct Paused<'a> {
+    pub runtime: &'a mut Runtime,
+    pub module: &'a Arc<Module>,
+    /// The range of the expression in source.
+    pub range: Range,
+}
+
+impl<'a> Paused<'a> {
+    /// Returns the function call being evaluated.
+    pub fn call(&self) -> Option<&Call> {
+        self.runtime.call_stack.last()
+    }
+
+    /// Returns the number of function calls deep.
+    pub fn depth(&self) -> usize {
+        self.runtime.call_stack.len()
+    }
+
+    /// Returns the source file of the expression.
+    pub fn file(&self) -> Option<&Arc<String>> {
+        self.call().and_then(|call| call.file.as_ref())
+    }
+
+    /// Returns the source text of the expression.
+    pub fn source(&self) -> Option<&Arc<String>> {
+        self.call().and_then(|call| self.module.functions.get(call.index))
+            .map(|f| &f.source)
+    }
+
+    /// Returns line and column where the expression starts, counting from 1.
+    pub fn line_column(&self) -> Option<(usize, usize)> {
+        self.source().map(|source| error::line_column(source, self.range.offset))
+    }
+
+    /// Returns local variables of the current function, in the order they were declared.
+    ///
+    /// Only the last variable with the same name is included.
+    pub fn locals(&self) -> Vec<(Arc<String>, &Variable)> {
+        let start = self.call().map(|call| call.local_len).unwrap_or(0);
+        self.variables(&self.runtime.local_stack[start..])
+    }
+
+    /// Returns current objects, in the order they were declared.
+    pub fn currents(&self) -> Vec<(Arc<String>, &Variable)> {
+        self.variables(&self.runtime.current_stack)
+    }
+
+    fn variables(&self, names: &[(Arc<String>, usize)]) -> Vec<(Arc<String>, &Variable)> {
+        let mut res = vec![];
+        for (i, &(ref name, ind)) in names.iter().enumerate() {
+            if name == &self.runtime.ret { continue }
+            if names[i + 1..].iter().any(|&(ref n, _)| n == name) { continue }
+            res.push((name.clone(), self.runtime.resolve(&self.runtime.stack[ind])));
+        }
+        res
+    }
+
+    /// Looks up a local variable or current object by name.
+    pub fn variable(&self, name: &str) -> Option<&Variable> {
+        let start = self.call().map(|call| call.local_len).unwrap_or(0);
+        let locals = self.runtime.local_stack[start..].iter().rev();
+        let currents = self.runtime.current_stack.iter().rev();
+        for &(ref n, ind) in locals.chain(currents) {
+            if &***n == name {
+                return Some(self.runtime.resolve(&self.runtime.stack[ind]));
+            }
+        }
+        None
+    }
+
+    /// Evaluates an expression in the paused frame.
+    ///
+    /// Local variables and current objects are copied,
+    /// so changing them has no effect on the program.
+    pub fn eval(&mut self, expr: &str) -> Result<Variable, DyonError> {
+        let (args, values): (Vec<Arc<String>>, Vec<Variable>) = self.locals().into_iter()
+            .map(|(name, v)| (name, v.deep_clone(&self.runtime.stack)))
+            .unzip();
+        let currents: Vec<String> = self.currents().into_iter()
+            .filter(|&(ref name, _)| !args.contains(name))
+            .map(|(name, _)| (*name).clone())
+            .collect();
+        let args: Vec<String> = args.iter().map(|name| (**name).clone()).collect();
+        let currents = if currents.is_empty() { String::new() }
+            else { format!(" ~ {}", currents.join(", ")) };
+        let source = format!("fn {}({}){} -> {{\n    return clone({})\n}}",
+            EVAL_FN, args.join(", "), currents, expr);
+        let mut module = (**self.module).clone();
+        try!(load_str("debugger", Arc::new(source), &mut module));
+        self.runtime.call_str_ret(EVAL_FN, &values, &Arc::new(module))
+    }
+}
+
+/// How to continue after pausing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Runs until a breakpoint.
+    Continue,
+    /// Pauses at the next line, entering function calls.
+    In,
+    /// Pauses at the next line in the same function or after it returns.
+    Over,
+    /// Pauses at the next line after the current function returns.
+    Out,
+}
+
+/// Decides when to pause, using line breakpoints and stepping.
+///
+/// A program pauses at most once per line,
+/// when the first expression on the line is evaluated.
+pub struct Stepper {
+    /// Lines with breakpoints, counting from 1, by file.
+    pub breakpoints: HashMap<Arc<String>, HashSet<usize>>,
+    step: Step,
+    /// The depth of function calls when stepping started.
+    depth: usize,
+    /// The current line at each depth of function calls.
+    lines: Vec<usize>,
+}
+
+impl Stepper {
+    /// Creates a new stepper that pauses at the first line.
+    pub fn new() -> Stepper {
+        Stepper {
+            breakpoints: HashMap::new(),
+            step: Step::In,
+            depth: 0,
+            lines: vec![],
+        }
+    }
+
+    /// Adds a breakpoint at line, counting from 1.
+    pub fn add_breakpoint(&mut self, file: &str, line: usize) {
+        self.breakpoints.entry(Arc::new(file.into()))
+            .or_insert_with(HashSet::new)
+            .insert(line);
+    }
+
+    /// Removes a breakpoint at line, counting from 1.
+    pub fn remove_breakpoint(&mut self, file: &str, line: usize) {
+        if let Some(lines) = self.breakpoints.get_mut(&Arc::new(file.into())) {
+            lines.remove(&line);
+        }
+    }
+
+    /// Sets how to continue from the paused depth of function calls.
+    pub fn resume(&mut self, step: Step, paused: &Paused) {
+        self.step = step;
+        self.depth = paused.depth();
+    }
+
+    /// Returns `true` if the program should pause before the expression.
+    pub fn should_pause(&mut self, paused: &Paused) -> bool {
+        let line = match paused.line_column() {
+            None => return false,
+            Some((line, _)) => line
+        };
+        let depth = paused.depth();
+        self.lines.resize(depth + 1, 0);
+        if self.lines[depth] == line { return false }
+        self.lines[depth] = line;
+
+        let breakpoint = match paused.file().and_then(|file| self.breakpoints.get(file)) {
+            None => false,
+            Some(lines) => lines.contains(&line)
+        };
+        breakpoint || match self.step {
+            Step::Continue => false,
+            Step::In => true,
+            Step::Over => depth <= self.depth,
+            Step::Out => depth < self.depth,
+        }
+    }
+}
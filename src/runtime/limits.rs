@@ -0,0 +1,160 @@
+//! Limits on running programs.
+
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::time::Instant;
+
+use link::Block;
+use MapKey;
+use Variable;
+
+/// Number of steps between checking the deadline.
+pub const CHECK_INTERVAL: u32 = 1024;
+
+/// Stores limits on running programs.
+///
+/// A limit set to `None` is not checked.
+/// Threads started with `go` get the same limits.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Maximum number of steps.
+    ///
+    /// Each evaluated expression, function call and loop iteration is a step.
+    pub instructions: Option<u64>,
+    /// Stops the program when this time is reached.
+    ///
+    /// Checked every `CHECK_INTERVAL` steps.
+    pub deadline: Option<Instant>,
+    /// Maximum number of variables on the stack.
+    pub stack: Option<usize>,
+    /// Maximum depth of function calls.
+    ///
+    /// Deep recursion can overflow the native stack before other limits are reached.
+    pub call_stack: Option<usize>,
+    /// Approximate maximum bytes used by arrays, objects, maps, links, text and bytes.
+    pub heap: Option<usize>,
+}
+
+impl Limits {
+    /// Creates new limits where nothing is limited.
+    pub fn new() -> Limits {
+        Limits {
+            instructions: None,
+            deadline: None,
+            stack: None,
+            call_stack: None,
+            heap: None,
+        }
+    }
+}
+
+/// The limit that stopped a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// Used up the steps.
+    Instructions,
+    /// Reached the deadline.
+    Deadline,
+    /// Too many variables on the stack.
+    Stack,
+    /// Too deep function calls.
+    CallStack,
+    /// Used too much memory.
+    Heap,
+}
+
+/// Bytes used per object entry, in addition to the key and value.
+const OBJECT_ENTRY: usize = 16;
+
+/// Bytes used per map entry, in addition to the value and text in keys.
+const MAP_ENTRY: usize = 40;
+
+/// Returns approximate bytes allocated for a new variable,
+/// without counting shared data inside it.
+pub fn alloc_size(v: &Variable) -> usize {
+    match *v {
+        Variable::Text(ref text) => text.len(),
+        Variable::Bytes(ref bytes) => bytes.len(),
+        Variable::Array(ref arr) => arr.len() * size_of::<Variable>(),
+        Variable::Object(ref obj) => obj.len() * (size_of::<Variable>() + OBJECT_ENTRY),
+        Variable::Map(ref map) => map.len() * (size_of::<Variable>() + MAP_ENTRY),
+        Variable::Link(ref link) => link.slices.len() * size_of::<Block>(),
+        _ => 0
+    }
+}
+
+/// Returns approximate bytes used by arrays, objects, maps, links, text and bytes
+/// reachable from variables, counting shared data once.
+pub fn heap_size<'a, I>(vars: I) -> usize
+    where I: IntoIterator<Item = &'a Variable>
+{
+    let mut visited: HashSet<usize> = HashSet::new();
+    let mut size = 0;
+    for v in vars {
+        add_heap_size(v, &mut visited, &mut size);
+    }
+    size
+}
+
+fn add_heap_size(v: &Variable, visited: &mut HashSet<usize>, size: &mut usize) {
+    match *v {
+        Variable::Text(ref text) => {
+            if visited.insert(&**text as *const _ as usize) {
+                *size += text.len();
+            }
+        }
+        Variable::Bytes(ref bytes) => {
+            if visited.insert(&**bytes as *const _ as usize) {
+                *size += bytes.len();
+            }
+        }
+        Variable::Array(ref arr) => {
+            if visited.insert(&**arr as *const _ as usize) {
+                *size += arr.len() * size_of::<Variable>();
+                for it in arr.iter() {
+                    add_heap_size(it, visited, size);
+                }
+            }
+        }
+        Variable::Object(ref obj) => {
+            if visited.insert(&**obj as *const _ as usize) {
+                for (key, it) in obj.iter() {
+                    *size += size_of::<Variable>() + OBJECT_ENTRY;
+                    if visited.insert(&**key as *const _ as usize) {
+                        *size += key.len();
+                    }
+                    add_heap_size(it, visited, size);
+                }
+            }
+        }
+        Variable::Map(ref map) => {
+            if visited.insert(&**map as *const _ as usize) {
+                for (key, it) in map.iter() {
+                    *size += size_of::<Variable>() + MAP_ENTRY;
+                    if let MapKey::Text(ref key) = *key {
+                        if visited.insert(&**key as *const _ as usize) {
+                            *size += key.len();
+                        }
+                    }
+                    add_heap_size(it, visited, size);
+                }
+            }
+        }
+        Variable::Link(ref link) => {
+            for slice in &link.slices {
+                if visited.insert(&*slice.block as *const _ as usize) {
+                    *size += size_of::<Block>();
+                }
+            }
+        }
+        Variable::Bool(_, Some(ref sec)) | Variable::F64(_, Some(ref sec)) => {
+            for it in sec.iter() {
+                add_heap_size(it, visited, size);
+            }
+        }
+        Variable::Option(Some(ref it)) => add_heap_size(it, visited, size),
+        Variable::Result(Ok(ref it)) => add_heap_size(it, visited, size),
+        Variable::Result(Err(ref err)) => add_heap_size(&err.message, visited, size),
+        _ => {}
+    }
+}
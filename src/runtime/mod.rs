@@ -1,18 +1,29 @@
 use std::sync::Arc;
 use std::collections::HashMap;
-use rand;
 use range::Range;
 
 use ast;
 use intrinsics;
 use embed;
 
+use DyonError;
+use ErrorKind;
 use FnIndex;
 use Module;
+use MapKey;
 use Variable;
+use ClosureEnvironment;
 use UnsafeRef;
+use Variant;
 use TINVOTS;
 
+pub mod bytecode;
+pub mod debugger;
+pub mod limits;
+pub mod profiler;
+pub mod coverage;
+pub mod rng;
+
 /// Which side an expression is evaluated.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Side {
@@ -21,7 +32,7 @@ pub enum Side {
     Right
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Flow {
     /// Continues execution.
     Continue,
@@ -54,13 +65,16 @@ pub struct Runtime {
     pub local_stack: Vec<(Arc<String>, usize)>,
     pub current_stack: Vec<(Arc<String>, usize)>,
     pub ret: Arc<String>,
-    pub rng: rand::StdRng,
+    pub rng: rng::Rng,
     pub text_type: Variable,
     pub f64_type: Variable,
+    pub i64_type: Variable,
+    pub bytes_type: Variable,
     pub vec4_type: Variable,
     pub return_type: Variable,
     pub bool_type: Variable,
     pub object_type: Variable,
+    pub map_type: Variable,
     pub array_type: Variable,
     pub link_type: Variable,
     pub ref_type: Variable,
@@ -71,6 +85,24 @@ pub struct Runtime {
     pub thread_type: Variable,
     pub closure_type: Variable,
     pub in_type: Variable,
+    pub iter_type: Variable,
+    /// Stores state of running compiled functions.
+    pub vm: bytecode::Stacks,
+    /// Limits on running programs.
+    pub limits: limits::Limits,
+    /// Number of steps taken, counted against `limits.instructions`.
+    pub instructions: u64,
+    /// Approximate bytes used by arrays, objects, maps, links, text and bytes,
+    /// counted against `limits.heap`.
+    pub heap: usize,
+    /// Called before each expression when debugging.
+    pub debugger: Option<Box<dyn debugger::Debugger>>,
+    /// Records time spent in functions when profiling.
+    pub profiler: Option<profiler::Profiler>,
+    /// Records executed code when measuring coverage.
+    pub coverage: Option<coverage::Coverage>,
+    /// Number of steps when limits are checked next time.
+    next_check: u64,
 }
 
 #[inline(always)]
@@ -92,7 +124,8 @@ fn item_lookup(
     expr_j: &mut usize,
     insert: bool, // Whether to insert key in object.
     last: bool,   // Whether it is the last property.
-) -> Result<*mut Variable, String> {
+    byte: Option<&mut Variable>, // Stores value read from bytes.
+) -> Result<*mut Variable, DyonError> {
     use ast::Id;
     use std::collections::hash_map::Entry;
 
@@ -114,16 +147,12 @@ fn item_lookup(
                                 *expr_j += 1;
                                 id.clone()
                             }
-                            _ => return Err(module.error_fnindex(prop.source_range(),
-                                &format!("{}\nExpected string",
-                                    stack_trace(call_stack)),
-                                    call_stack.last().unwrap().index))
+                            _ => return Err(module.error_call_stack(prop.source_range(),
+                                "Expected string", call_stack))
                         }
                     }
-                    &Id::F64(range, _) => return Err(module.error_fnindex(range,
-                        &format!("{}\nExpected string",
-                            stack_trace(call_stack)),
-                            call_stack.last().unwrap().index))
+                    &Id::F64(range, _) => return Err(module.error_call_stack(range,
+                        "Expected string", call_stack))
                 };
                 let v = match Arc::make_mut(obj).entry(id.clone()) {
                     Entry::Vacant(vac) => {
@@ -131,10 +160,8 @@ fn item_lookup(
                             // Insert a key to overwrite with new value.
                             vac.insert(Variable::Return)
                         } else {
-                            return Err(module.error_fnindex(prop.source_range(),
-                                &format!("{}\nObject has no key `{}`",
-                                    stack_trace(call_stack), id),
-                                    call_stack.last().unwrap().index));
+                            return Err(module.error_call_stack(prop.source_range(),
+                                &format!("Object has no key `{}`", id), call_stack));
                         }
                     }
                     Entry::Occupied(v) => v.into_mut()
@@ -152,9 +179,14 @@ fn item_lookup(
                     Ok(v)
                 }
             }
-            Variable::Array(ref mut arr) => {
-                let id = match prop {
-                    &Id::F64(_, id) => id,
+            Variable::Map(ref mut map) => {
+                let key = match prop {
+                    &Id::String(_, ref id) => MapKey::Text(id.clone()),
+                    &Id::F64(range, id) => match MapKey::from_variable(&Variable::f64(id)) {
+                        Ok(key) => key,
+                        Err(err) => return Err(module.error_call_stack(range,
+                                                &err, call_stack))
+                    },
                     &Id::Expression(_) => {
                         let id = start_stack_len + *expr_j;
                         // Resolve reference of computed expression.
@@ -163,27 +195,46 @@ fn item_lookup(
                             } else {
                                 id
                             };
-                        match &mut stack[id] {
-                            &mut Variable::F64(id, _) => {
+                        match MapKey::from_variable(&stack[id]) {
+                            Ok(key) => {
                                 *expr_j += 1;
-                                id
+                                key
                             }
-                            _ => return Err(module.error_fnindex(prop.source_range(),
-                                            &format!("{}\nExpected number",
-                                                stack_trace(call_stack)),
-                                                call_stack.last().unwrap().index))
+                            Err(err) => return Err(module.error_call_stack(
+                                prop.source_range(), &err, call_stack))
+                        }
+                    }
+                };
+                let v = match Arc::make_mut(map).entry(key) {
+                    Entry::Vacant(vac) => {
+                        if insert && last {
+                            // Insert a key to overwrite with new value.
+                            vac.insert(Variable::Return)
+                        } else {
+                            return Err(module.error_call_stack(prop.source_range(),
+                                &format!("Map has no key `{}`", vac.key()), call_stack));
                         }
                     }
-                    &Id::String(range, _) => return Err(module.error_fnindex(range,
-                        &format!("{}\nExpected number",
-                            stack_trace(call_stack)),
-                            call_stack.last().unwrap().index))
+                    Entry::Occupied(v) => v.into_mut()
                 };
+                // Resolve reference.
+                if let &mut Variable::Ref(id) = v {
+                    // Do not resolve if last, because references should be
+                    // copy-on-write.
+                    if last {
+                        Ok(v)
+                    } else {
+                        Ok(&mut stack[id])
+                    }
+                } else {
+                    Ok(v)
+                }
+            }
+            Variable::Array(ref mut arr) => {
+                let id = try!(index(module, stack, call_stack, prop, start_stack_len, expr_j));
                 let v = match Arc::make_mut(arr).get_mut(id as usize) {
-                    None => return Err(module.error_fnindex(prop.source_range(),
-                                       &format!("{}\nOut of bounds `{}`",
-                                                stack_trace(call_stack), id),
-                                                call_stack.last().unwrap().index)),
+                    None => return Err(module.error_call_stack(prop.source_range(),
+                                       &format!("Out of bounds `{}`", id), call_stack)),
                     Some(x) => x
                 };
                 // Resolve reference.
@@ -199,14 +250,64 @@ fn item_lookup(
                     Ok(v)
                 }
             }
-            _ => return Err(module.error_fnindex(prop.source_range(),
-                            &format!("{}\nLook up requires object or array",
-                            stack_trace(call_stack)),
-                            call_stack.last().unwrap().index))
+            Variable::Bytes(ref bytes) => {
+                let id = try!(index(module, stack, call_stack, prop, start_stack_len, expr_j));
+                let v = match bytes.get(id as usize) {
+                    None => return Err(module.error_call_stack(prop.source_range(),
+                                       &format!("Out of bounds `{}`", id), call_stack)),
+                    Some(&x) => x
+                };
+                match byte {
+                    Some(byte) => {
+                        *byte = Variable::f64(v as f64);
+                        Ok(byte)
+                    }
+                    None => Err(module.error_call_stack(prop.source_range(),
+                            "Can not assign to index of bytes, use `write_int` instead",
+                            call_stack))
+                }
+            }
+            _ => return Err(module.error_call_stack(prop.source_range(),
+                            "Look up requires object, map, array or bytes", call_stack))
         }
     }
 }
 
+// Looks up a number used as index.
+fn index(
+    module: &Module,
+    stack: &mut [Variable],
+    call_stack: &[Call],
+    prop: &ast::Id,
+    start_stack_len: usize,
+    expr_j: &mut usize,
+) -> Result<f64, DyonError> {
+    use ast::Id;
+
+    match prop {
+        &Id::F64(_, id) => Ok(id),
+        &Id::Expression(_) => {
+            let id = start_stack_len + *expr_j;
+            // Resolve reference of computed expression.
+            let id = if let &Variable::Ref(ref_id) = &stack[id] {
+                    ref_id
+                } else {
+                    id
+                };
+            match &mut stack[id] {
+                &mut Variable::F64(id, _) => {
+                    *expr_j += 1;
+                    Ok(id)
+                }
+                _ => return Err(module.error_call_stack(prop.source_range(),
+                                "Expected number", call_stack))
+            }
+        }
+        &Id::String(range, _) => return Err(module.error_call_stack(range,
+            "Expected number", call_stack))
+    }
+}
+
 impl Runtime {
     pub fn new() -> Runtime {
         Runtime {
@@ -215,13 +316,16 @@ impl Runtime {
             local_stack: vec![],
             current_stack: vec![],
             ret: Arc::new("return".into()),
-            rng: rand::StdRng::new().unwrap(),
+            rng: rng::Rng::from_os(),
             text_type: Variable::Text(Arc::new("string".into())),
             f64_type: Variable::Text(Arc::new("number".into())),
+            i64_type: Variable::Text(Arc::new("i64".into())),
+            bytes_type: Variable::Text(Arc::new("bytes".into())),
             vec4_type: Variable::Text(Arc::new("vec4".into())),
             return_type: Variable::Text(Arc::new("return".into())),
             bool_type: Variable::Text(Arc::new("boolean".into())),
             object_type: Variable::Text(Arc::new("object".into())),
+            map_type: Variable::Text(Arc::new("map".into())),
             link_type: Variable::Text(Arc::new("link".into())),
             array_type: Variable::Text(Arc::new("array".into())),
             ref_type: Variable::Text(Arc::new("ref".into())),
@@ -232,9 +336,208 @@ impl Runtime {
             thread_type: Variable::Text(Arc::new("thread".into())),
             closure_type: Variable::Text(Arc::new("closure".into())),
             in_type: Variable::Text(Arc::new("in".into())),
+            iter_type: Variable::Text(Arc::new("iter".into())),
+            vm: bytecode::Stacks::new(),
+            limits: limits::Limits::new(),
+            instructions: 0,
+            heap: 0,
+            debugger: None,
+            profiler: None,
+            coverage: None,
+            next_check: 0,
         }
     }
 
+    /// Creates a new runtime with a seeded random number generator.
+    ///
+    /// Runs that use the same seed get the same random numbers,
+    /// also in threads started with `go`.
+    pub fn with_seed(seed: u64) -> Runtime {
+        let mut rt = Runtime::new();
+        rt.rng = rng::Rng::new(seed);
+        rt
+    }
+
+    /// Sets limits on running programs and resets the counters.
+    pub fn set_limits(&mut self, limits: limits::Limits) {
+        self.limits = limits;
+        self.instructions = 0;
+        self.heap = 0;
+        self.next_check = 0;
+    }
+
+    /// Sets the debugger called before each expression.
+    ///
+    /// Functions are evaluated by walking the tree while debugging.
+    pub fn set_debugger(&mut self, debugger: Option<Box<dyn debugger::Debugger>>) {
+        self.debugger = debugger;
+        self.next_check = 0;
+    }
+
+    /// Sets the profiler, which records time spent in functions.
+    ///
+    /// Take the profiler when the program is done to get the results.
+    pub fn set_profiler(&mut self, profiler: Option<profiler::Profiler>) {
+        self.profiler = profiler;
+        self.next_check = 0;
+    }
+
+    /// Sets the coverage recorder, which records executed code.
+    ///
+    /// Take the coverage when the program is done to get the results.
+    pub fn set_coverage(&mut self, coverage: Option<coverage::Coverage>) {
+        self.coverage = coverage;
+        self.next_check = 0;
+    }
+
+    /// Returns `true` if a debugger, line profiler or coverage recorder
+    /// is called before each expression.
+    #[inline(always)]
+    fn trace_expressions(&self) -> bool {
+        self.debugger.is_some() || self.coverage.is_some() ||
+        self.profiler.as_ref().map(|p| p.lines.is_some()).unwrap_or(false)
+    }
+
+    /// Clears the stacks left behind by a call that failed,
+    /// such that the runtime can be used again.
+    pub fn clear_stacks(&mut self) {
+        self.stack.clear();
+        self.call_stack.clear();
+        self.local_stack.clear();
+        self.current_stack.clear();
+        self.vm = bytecode::Stacks::new();
+    }
+
+    /// Counts a step, returns `true` when limits should be checked.
+    #[inline(always)]
+    pub fn step(&mut self) -> bool {
+        self.instructions += 1;
+        self.instructions >= self.next_check
+    }
+
+    /// Checks limits on steps, deadline and stack.
+    #[cold]
+    #[inline(never)]
+    pub fn check_limits(&mut self, range: Range, module: &Module) -> Result<(), DyonError> {
+        use std::time::Instant;
+        use self::limits::{Limit, CHECK_INTERVAL};
+
+        let mut next_check = self.instructions + CHECK_INTERVAL as u64;
+        if let Some(max) = self.limits.instructions {
+            if self.instructions > max {
+                return Err(self.limit_error(Limit::Instructions, range,
+                    &format!("Reached limit of {} instructions", max), module));
+            }
+            next_check = ::std::cmp::min(next_check, max + 1);
+        }
+        if let Some(deadline) = self.limits.deadline {
+            if Instant::now() >= deadline {
+                return Err(self.limit_error(Limit::Deadline, range,
+                    "Reached deadline", module));
+            }
+        }
+        try!(self.check_stack(range, module));
+        if self.trace_expressions() {
+            // Check every step.
+            next_check = self.instructions + 1;
+        }
+        self.next_check = next_check;
+        Ok(())
+    }
+
+    /// Checks limits on depth of stack and call stack.
+    #[inline(always)]
+    fn check_stack(&self, range: Range, module: &Module) -> Result<(), DyonError> {
+        use self::limits::Limit;
+
+        if let Some(max) = self.limits.stack {
+            if self.stack.len() > max {
+                return Err(self.limit_error(Limit::Stack, range,
+                    &format!("Reached limit of {} variables on the stack", max), module));
+            }
+        }
+        if let Some(max) = self.limits.call_stack {
+            if self.call_stack.len() >= max {
+                return Err(self.limit_error(Limit::CallStack, range,
+                    &format!("Reached limit of {} function calls deep", max), module));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds approximate bytes allocated for a new variable.
+    #[inline(always)]
+    pub fn alloc_var(&mut self, v: &Variable, range: Range, module: &Module) -> Result<(), DyonError> {
+        if self.limits.heap.is_none() {return Ok(())};
+        let bytes = limits::alloc_size(self.resolve(v));
+        self.alloc(bytes, range, module)
+    }
+
+    /// Adds approximate bytes allocated.
+    ///
+    /// When the heap limit is exceeded, the heap is measured
+    /// to account for memory freed since last time.
+    #[inline(always)]
+    pub fn alloc(&mut self, bytes: usize, range: Range, module: &Module) -> Result<(), DyonError> {
+        if let Some(max) = self.limits.heap {
+            self.heap = self.heap.saturating_add(bytes);
+            if self.heap > max {
+                return self.measure_heap(bytes, max, range, module);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds approximate bytes allocated for an array of `n` items.
+    ///
+    /// Reports an error for the heap limit when the size overflows.
+    pub fn alloc_items(&mut self, n: usize, range: Range, module: &Module) -> Result<(), DyonError> {
+        use self::limits::Limit;
+
+        match n.checked_mul(::std::mem::size_of::<Variable>()) {
+            Some(bytes) if bytes <= ::std::isize::MAX as usize => self.alloc(bytes, range, module),
+            _ => Err(self.limit_error(Limit::Heap, range,
+                &format!("Can not allocate array of {} items", n), module))
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn measure_heap(
+        &mut self,
+        bytes: usize,
+        max: usize,
+        range: Range,
+        module: &Module
+    ) -> Result<(), DyonError> {
+        use self::limits::Limit;
+
+        self.heap = bytes + limits::heap_size(self.stack.iter()
+            .chain(self.vm.values.iter().filter_map(|v| v.as_ref())));
+        if self.heap > max {
+            Err(self.limit_error(Limit::Heap, range,
+                &format!("Reached limit of {} bytes of memory", max), module))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn limit_error(
+        &self,
+        limit: limits::Limit,
+        range: Range,
+        msg: &str,
+        module: &Module
+    ) -> DyonError {
+        let mut err = if self.call_stack.len() > 0 {
+            module.error(range, msg, self)
+        } else {
+            DyonError::new(ErrorKind::Runtime, msg.into())
+        };
+        err.kind = ErrorKind::Limit(limit);
+        err
+    }
+
     pub fn pop<T: embed::PopVariable>(&mut self) -> Result<T, String> {
         let v = self.stack.pop().unwrap_or_else(|| panic!(TINVOTS));
         T::pop_var(self, self.resolve(&v))
@@ -278,7 +581,7 @@ impl Runtime {
 
     pub fn expected(&self, var: &Variable, ty: &str) -> String {
         let found_ty = self.typeof_var(var);
-        format!("{}\nExpected `{}`, found `{}`", self.stack_trace(), ty, found_ty)
+        format!("Expected `{}`, found `{}`", ty, found_ty)
     }
 
     #[inline(always)]
@@ -291,14 +594,14 @@ impl Runtime {
         call: &ast::Call,
         module: &Module,
         f: F
-    ) -> Result<Option<Variable>, String> {
+    ) -> Result<Option<Variable>, DyonError> {
         let x = self.stack.pop().expect(TINVOTS);
         Ok(Some(match self.resolve(&x) {
             &Variable::F64(a, _) => {
                 Variable::f64(f(a))
             }
             _ => return Err(module.error(call.args[0].source_range(),
-                    &format!("{}\nExpected number", self.stack_trace()), self))
+                    "Expected number", self))
         }))
     }
 
@@ -340,9 +643,25 @@ impl Runtime {
         expr: &ast::Expression,
         side: Side,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         use ast::Expression::*;
 
+        if self.step() {
+            try!(self.check_limits(expr.source_range(), module));
+            if self.debugger.is_some() {
+                try!(self.debug(expr.source_range(), module));
+            }
+            if self.profiler.is_some() {
+                self.profile_expression(expr.source_range(), module);
+            }
+            if self.coverage.is_some() {
+                match *expr {
+                    // Uses the range of the inner expression, which is recorded instead.
+                    Return(_) | Try(_) => {}
+                    _ => self.cover_expression(expr.source_range(), module),
+                }
+            }
+        }
         match *expr {
             Link(ref link) => self.link(link, module),
             Object(ref obj) => self.object(obj, module),
@@ -354,8 +673,7 @@ impl Runtime {
                     (Some(x), Flow::Continue) => x,
                     (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                     _ => return Err(module.error(expr.source_range(),
-                                    &format!("{}\nExpected something",
-                                        self.stack_trace()), self))
+                                    "Expected something", self))
                 };
                 Ok((Some(x), Flow::Return))
             }
@@ -386,6 +704,7 @@ impl Runtime {
             All(ref for_n_expr) => self.all_n_expr(for_n_expr, module),
             LinkFor(ref for_n_expr) => self.link_for_n_expr(for_n_expr, module),
             If(ref if_expr) => self.if_expr(if_expr, module),
+            Match(ref match_expr) => self.match_expr(match_expr, module),
             Compare(ref compare) => self.compare(compare, module),
             Variable(_, ref var) => Ok((Some(var.clone()), Flow::Continue)),
             Try(ref expr) => self.try(expr, side, module),
@@ -396,15 +715,65 @@ impl Runtime {
             Closure(ref closure) => self.closure(closure, module),
             CallClosure(ref call) => self.call_closure(call, module),
             Grab(ref expr) => Err(module.error(expr.source_range,
-                    &format!("{}\n`grab` expressions must be inside a closure",
-                        self.stack_trace()), self)),
+                    "`grab` expressions must be inside a closure", self)),
             TryExpr(ref try_expr) => self.try_expr(try_expr, module),
             In(ref in_expr) => self.in_expr(in_expr, module),
         }
     }
 
+    /// Calls the debugger before evaluating an expression.
+    #[cold]
+    #[inline(never)]
+    fn debug(&mut self, range: Range, module: &Arc<Module>) -> Result<(), DyonError> {
+        // Take the debugger to not call it while evaluating expressions for it.
+        let mut debugger = match self.debugger.take() {
+            None => return Ok(()),
+            Some(x) => x
+        };
+        let res = debugger.before_expression(&mut debugger::Paused {
+            runtime: self,
+            module: module,
+            range: range,
+        });
+        self.debugger = Some(debugger);
+        res.map_err(|err| module.error(range, &err, self))
+    }
+
+    /// Records the line of an expression when profiling lines.
+    #[cold]
+    #[inline(never)]
+    fn profile_expression(&mut self, range: Range, module: &Arc<Module>) {
+        if let (Some(profiler), Some(call)) = (self.profiler.as_mut(), self.call_stack.last()) {
+            let f = &module.functions[call.index];
+            let file = call.file.as_ref().unwrap_or(&f.file);
+            profiler.expression(file, &f.source, range.offset);
+        }
+    }
+
+    /// Records that an expression is evaluated when measuring coverage.
+    #[cold]
+    #[inline(never)]
+    fn cover_expression(&mut self, range: Range, module: &Arc<Module>) {
+        if let (Some(coverage), Some(call)) = (self.coverage.as_mut(), self.call_stack.last()) {
+            let f = &module.functions[call.index];
+            let file = call.file.as_ref().unwrap_or(&f.file);
+            coverage.expression(file, &f.source, range);
+        }
+    }
+
+    /// Records whether `?` returned an error when measuring coverage.
+    #[cold]
+    #[inline(never)]
+    fn cover_try(&mut self, range: Range, err: bool, module: &Arc<Module>) {
+        if let (Some(coverage), Some(call)) = (self.coverage.as_mut(), self.call_stack.last()) {
+            let f = &module.functions[call.index];
+            let file = call.file.as_ref().unwrap_or(&f.file);
+            coverage.try_result(file, &f.source, range.offset, err);
+        }
+    }
+
     fn in_expr(&mut self, in_expr: &ast::In, module: &Arc<Module>)
-    -> Result<(Option<Variable>, Flow), String> {
+    -> Result<(Option<Variable>, Flow), DyonError> {
         use std::sync::mpsc::channel;
         use std::sync::Mutex;
         use std::sync::atomic::Ordering;
@@ -423,13 +792,12 @@ impl Runtime {
                 Ok((Some(::Variable::In(Arc::new(Mutex::new(rx)))), Flow::Continue))
             }
             _ => Err(module.error(in_expr.source_range,
-                    &format!("{}\nExpected loaded function",
-                        self.stack_trace()), self)),
+                    "Expected loaded function", self)),
         }
     }
 
     fn try_expr(&mut self, try_expr: &ast::TryExpr, module: &Arc<Module>)
-    -> Result<(Option<Variable>, Flow), String> {
+    -> Result<(Option<Variable>, Flow), DyonError> {
         use Error;
 
         let cs = self.call_stack.len();
@@ -442,7 +810,7 @@ impl Runtime {
                 Flow::Continue
             )),
             Ok((None, Flow::Continue)) => Err(module.error(try_expr.source_range,
-                &format!("{}\nExpected something", self.stack_trace()), self)),
+                "Expected something", self)),
             Ok((x, flow)) => Ok((x, flow)),
             Err(err) => {
                 self.call_stack.truncate(cs);
@@ -451,7 +819,7 @@ impl Runtime {
                 self.current_stack.truncate(cu);
                 Ok((
                     Some(Variable::Result(Err(Box::new(Error {
-                        message: Variable::Text(Arc::new(err)),
+                        message: Variable::Text(Arc::new(err.to_string())),
                         trace: vec![],
                     }
                     )))),
@@ -462,9 +830,8 @@ impl Runtime {
     }
 
     fn closure(&mut self, closure: &ast::Closure, module: &Arc<Module>)
-    -> Result<(Option<Variable>, Flow), String> {
+    -> Result<(Option<Variable>, Flow), DyonError> {
         use grab::{self, Grabbed};
-        use ClosureEnvironment;
 
         // Create closure.
         let relative = self.call_stack.last().map(|c| c.index).unwrap_or(0);
@@ -473,8 +840,7 @@ impl Runtime {
             (Grabbed::Expression(x), Flow::Continue) => x,
             (Grabbed::Variable(x), Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(closure.expr.source_range(),
-                            &format!("{}\nExpected something",
-                                self.stack_trace()), self))
+                            "Expected something", self))
         };
 
         Ok((Some(::Variable::Closure(Arc::new(ast::Closure {
@@ -564,22 +930,32 @@ impl Runtime {
         expr: &ast::Expression,
         side: Side,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let v = match try!(self.expression(expr, side, module)) {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(expr.source_range(),
-                            &format!("{}\nExpected something",
-                                self.stack_trace()), self))
+                            "Expected something", self))
         };
-        let v = match Runtime::try_msg(self.resolve(&v)) {
+        self.try_value(&v, expr.source_range(), module)
+    }
+
+    fn try_value(
+        &mut self,
+        v: &Variable,
+        source_range: Range,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
+        let v = match Runtime::try_msg(self.resolve(v)) {
             Some(v) => v,
             None => {
-                return Err(module.error(expr.source_range(),
-                    &format!("{}\nExpected `ok(_)`, `err(_)`, `bool`, `f64`",
-                        self.stack_trace()), self));
+                return Err(module.error(source_range,
+                    "Expected `ok(_)`, `err(_)`, `bool`, `f64`", self));
             }
         };
+        if self.coverage.is_some() {
+            self.cover_try(source_range, v.is_err(), module);
+        }
         match v {
             Ok(ok) => {
                 Ok((Some(*ok), Flow::Continue))
@@ -587,31 +963,27 @@ impl Runtime {
             Err(mut err) => {
                 let call = self.call_stack.last().unwrap();
                 if call.stack_len == 0 {
-                    return Err(module.error(expr.source_range(),
-                        &format!("{}\nRequires `->` on function `{}`",
-                        self.stack_trace(),
-                        &call.fn_name), self));
+                    return Err(module.error(source_range,
+                        &format!("Requires `->` on function `{}`", &call.fn_name), self));
                 }
                 if let Variable::Return = self.stack[call.stack_len - 1] {}
                 else {
-                    return Err(module.error(expr.source_range(),
-                        &format!("{}\nRequires `->` on function `{}`",
-                        self.stack_trace(),
-                        &call.fn_name), self));
+                    return Err(module.error(source_range,
+                        &format!("Requires `->` on function `{}`", &call.fn_name), self));
                 }
                 let file = match call.file {
                     None => "".into(),
                     Some(ref f) => format!(" ({})", f)
                 };
-                err.trace.push(module.error(expr.source_range(),
+                err.trace.push(module.error_trace(source_range,
                     &format!("In function `{}`{}",
-                    &call.fn_name, file), self));
+                    &call.fn_name, file), &self.call_stack));
                 Ok((Some(Variable::Result(Err(err))), Flow::Return))
             }
         }
     }
 
-    pub fn run(&mut self, module: &Arc<Module>) -> Result<(), String> {
+    pub fn run(&mut self, module: &Arc<Module>) -> Result<(), DyonError> {
         use std::cell::Cell;
 
         let name: Arc<String> = Arc::new("main".into());
@@ -643,7 +1015,7 @@ impl Runtime {
         &mut self,
         block: &ast::Block,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let mut expect = None;
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -666,7 +1038,7 @@ impl Runtime {
         Ok((expect, Flow::Continue))
     }
 
-    pub fn go(&mut self, go: &ast::Go, module: &Arc<Module>) -> Result<(Option<Variable>, Flow), String> {
+    pub fn go(&mut self, go: &ast::Go, module: &Arc<Module>) -> Result<(Option<Variable>, Flow), DyonError> {
         use std::thread::{self, JoinHandle};
         use std::cell::Cell;
         use Thread;
@@ -689,9 +1061,8 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(arg.source_range(),
-                                &format!("{}\nExpected something. \
-                                Expression did not return a value.",
-                                self.stack_trace()), self))
+                                "Expected something. \
+                                Expression did not return a value.", self))
             };
             stack.push(v.deep_clone(&self.stack));
             fake_call.args.push(ast::Expression::Variable(
@@ -699,6 +1070,7 @@ impl Runtime {
         }
         stack.reverse();
 
+        let rng = self.rng.child();
         let last_call = self.call_stack.last().unwrap();
         let new_rt = Runtime {
             stack: stack,
@@ -714,7 +1086,7 @@ impl Runtime {
                 local_len: 0,
                 current_len: 0,
             }],
-            rng: self.rng.clone(),
+            rng: rng,
             ret: self.ret.clone(),
             ref_type: self.ref_type.clone(),
             option_type: self.option_type.clone(),
@@ -722,8 +1094,11 @@ impl Runtime {
             link_type: self.link_type.clone(),
             bool_type: self.bool_type.clone(),
             object_type: self.object_type.clone(),
+            map_type: self.map_type.clone(),
             text_type: self.text_type.clone(),
             f64_type: self.f64_type.clone(),
+            i64_type: self.i64_type.clone(),
+            bytes_type: self.bytes_type.clone(),
             thread_type: self.thread_type.clone(),
             unsafe_ref_type: self.unsafe_ref_type.clone(),
             return_type: self.return_type.clone(),
@@ -732,9 +1107,18 @@ impl Runtime {
             result_type: self.result_type.clone(),
             closure_type: self.closure_type.clone(),
             in_type: self.in_type.clone(),
+            iter_type: self.iter_type.clone(),
+            vm: bytecode::Stacks::new(),
+            limits: self.limits.clone(),
+            instructions: 0,
+            heap: 0,
+            debugger: None,
+            profiler: None,
+            coverage: None,
+            next_check: 0,
         };
         let new_module = module.clone();
-        let handle: JoinHandle<Result<Variable, String>> = thread::spawn(move || {
+        let handle: JoinHandle<Result<Variable, DyonError>> = thread::spawn(move || {
             let mut new_rt = new_rt;
             let new_module = new_module;
             let fake_call = fake_call;
@@ -754,15 +1138,14 @@ impl Runtime {
         &mut self,
         call: &ast::CallClosure,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         // Find item.
         let item = match try!(self.item(&call.item, Side::Right, module)) {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(call.item.source_range,
-                            &format!("{}\nExpected something. \
-                            Check that item returns a value.",
-                            self.stack_trace()), self))
+                            "Expected something. \
+                            Check that item returns a value.", self))
         };
 
         let (f, env) = match self.resolve(&item) {
@@ -773,9 +1156,7 @@ impl Runtime {
 
         if call.arg_len() != f.args.len() {
             return Err(module.error(call.source_range,
-                &format!("{}\nExpected {} arguments but found {}",
-                self.stack_trace(),
-                f.args.len(),
+                &format!("Expected {} arguments but found {}", f.args.len(),
                 call.arg_len()), self));
         }
         // Arguments must be computed.
@@ -793,12 +1174,57 @@ impl Runtime {
                 (None, Flow::Continue) => {}
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(arg.source_range(),
-                                &format!("{}\nExpected something. \
-                                Check that expression returns a value.",
-                                self.stack_trace()), self))
+                                "Expected something. \
+                                Check that expression returns a value.", self))
             };
         }
 
+        self.closure_body(&f, &env, call.item.name.clone(), st, lc, cu,
+                          call.source_range, module)
+    }
+
+    /// Calls closure with arguments, e.g. from intrinsics taking closures.
+    pub fn call_closure_args(
+        &mut self,
+        closure: &Variable,
+        args: &[Variable],
+        name: Arc<String>,
+        range: Range,
+        module: &Arc<Module>
+    ) -> Result<Option<Variable>, DyonError> {
+        let (f, env) = match self.resolve(closure) {
+            &Variable::Closure(ref f, ref env) => (f.clone(), env.clone()),
+            x => return Err(module.error(range, &self.expected(x, "closure"), self))
+        };
+        if args.len() != f.args.len() {
+            return Err(module.error(range,
+                &format!("Expected closure with {} arguments but found {}", args.len(),
+                f.args.len()), self));
+        }
+        if f.returns() {
+            self.stack.push(Variable::Return);
+        }
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let cu = self.current_stack.len();
+        for arg in args {
+            self.stack.push(arg.clone());
+        }
+        Ok(try!(self.closure_body(&f, &env, name, st, lc, cu, range, module)).0)
+    }
+
+    /// Runs closure body with arguments pushed on the stack.
+    fn closure_body(
+        &mut self,
+        f: &Arc<ast::Closure>,
+        env: &ClosureEnvironment,
+        name: Arc<String>,
+        st: usize,
+        lc: usize,
+        cu: usize,
+        range: Range,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         // Look for variable in current stack.
         if f.currents.len() > 0 {
             for current in &f.currents {
@@ -813,14 +1239,12 @@ impl Runtime {
                     self.local_stack.push((current.name.clone(), self.stack.len()));
                     self.stack.push(Variable::Ref(ind));
                 } else {
-                    return Err(module.error(call.source_range, &format!(
-                        "{}\nCould not find current variable `{}`",
-                            self.stack_trace(), current.name), self));
+                    return Err(module.error(range, &format!("Could not find current variable `{}`", current.name), self));
                 }
             }
         }
 
-        self.push_fn(call.item.name.clone(), env.relative, Some(f.file.clone()), st, lc, cu);
+        self.push_fn(name.clone(), env.relative, Some(f.file.clone()), st, lc, cu);
         if f.returns() {
             self.local_stack.push((self.ret.clone(), st - 1));
         }
@@ -831,33 +1255,26 @@ impl Runtime {
         let (x, flow) = try!(self.expression(&f.expr, Side::Right, &env.module));
         match flow {
             Flow::Break(None) =>
-                return Err(module.error(call.source_range,
-                           &format!("{}\nCan not break from function",
-                                self.stack_trace()), self)),
+                return Err(module.error(range,
+                           "Can not break from function", self)),
             Flow::ContinueLoop(None) =>
-                return Err(module.error(call.source_range,
-                           &format!("{}\nCan not continue from function",
-                                self.stack_trace()), self)),
+                return Err(module.error(range,
+                           "Can not continue from function", self)),
             Flow::Break(Some(ref label)) =>
-                return Err(module.error(call.source_range,
-                    &format!("{}\nThere is no loop labeled `{}`",
-                             self.stack_trace(), label), self)),
+                return Err(module.error(range,
+                    &format!("There is no loop labeled `{}`", label), self)),
             Flow::ContinueLoop(Some(ref label)) =>
-                return Err(module.error(call.source_range,
-                    &format!("{}\nThere is no loop labeled `{}`",
-                            self.stack_trace(), label), self)),
+                return Err(module.error(range,
+                    &format!("There is no loop labeled `{}`", label), self)),
             _ => {}
         }
-        self.pop_fn(call.item.name.clone());
+        self.pop_fn(name.clone());
         match (f.returns(), x) {
             (true, None) => {
                 match self.stack.pop().expect(TINVOTS) {
                     Variable::Return => {
                         return Err(module.error(
-                            call.source_range, &format!(
-                            "{}\nFunction `{}` did not return a value",
-                            self.stack_trace(),
-                            call.item.name), self))
+                            range, &format!("Function `{}` did not return a value", name), self))
                     }
                     x => {
                         // This happens when return is only
@@ -867,21 +1284,15 @@ impl Runtime {
                 };
             }
             (false, Some(_)) => {
-                return Err(module.error(call.source_range,
-                    &format!(
-                        "{}\nFunction `{}` should not return a value",
-                        self.stack_trace(),
-                        call.item.name), self))
+                return Err(module.error(range,
+                    &format!("Function `{}` should not return a value", name), self))
             }
             (true, Some(Variable::Return)) => {
                 // TODO: Could return the last value on the stack.
                 //       Requires .pop_fn delayed after.
-                return Err(module.error(call.source_range,
-                    &format!(
-                    "{}\nFunction `{}` did not return a value. \
-                    Did you forget a `return`?",
-                        self.stack_trace(),
-                        call.item.name), self))
+                return Err(module.error(range,
+                    &format!("Function `{}` did not return a value. \
+                    Did you forget a `return`?", name), self))
             }
             (returns, b) => {
                 if returns { self.stack.pop(); }
@@ -895,7 +1306,7 @@ impl Runtime {
         &mut self,
         call: &ast::Call,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         self.call_internal(call, true, module)
     }
 
@@ -909,7 +1320,7 @@ impl Runtime {
         call: &ast::Call,
         loader: bool,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         use FnExternalRef;
 
         match call.f_index.get() {
@@ -922,9 +1333,8 @@ impl Runtime {
                         (Some(x), Flow::Continue) => self.stack.push(x),
                         (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                         _ => return Err(module.error(arg.source_range(),
-                                        &format!("{}\nExpected something. \
-                                        Expression did not return a value.",
-                                        self.stack_trace()), self))
+                                        "Expected something. \
+                                        Expression did not return a value.", self))
                     };
                 }
                 try!((f)(self).map_err(|err|
@@ -937,9 +1347,8 @@ impl Runtime {
                         (Some(x), Flow::Continue) => self.stack.push(x),
                         (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                         _ => return Err(module.error(arg.source_range(),
-                                        &format!("{}\nExpected something. \
-                                        Expression did not return a value.",
-                                        self.stack_trace()), self))
+                                        "Expected something. \
+                                        Expression did not return a value.", self))
                     };
                 }
                 try!((f)(self).map_err(|err|
@@ -947,174 +1356,252 @@ impl Runtime {
                 return Ok((Some(self.stack.pop().expect(TINVOTS)), Flow::Continue));
             }
             FnIndex::Loaded(f_index) => {
-                use std::sync::atomic::Ordering;
-
-                let relative = if loader {0} else {
-                    self.call_stack.last().map(|c| c.index).unwrap_or(0)
-                };
-                let new_index = (f_index + relative as isize) as usize;
-                let f = &module.functions[new_index];
-                if call.arg_len() != f.args.len() {
-                    return Err(module.error(call.source_range,
-                        &format!("{}\nExpected {} arguments but found {}",
-                        self.stack_trace(),
-                        f.args.len(),
-                        call.arg_len()), self));
-                }
-                // Arguments must be computed.
-                if f.returns() {
-                    // Add return value before arguments on the stack.
-                    // The stack value should remain, but the local should not.
-                    self.stack.push(Variable::Return);
+                let (new_index, st, lc, cu) =
+                    try!(self.begin_call(call, f_index, loader, module));
+                for arg in &call.args {
+                    match try!(self.expression(arg, Side::Right, module)) {
+                        (Some(x), Flow::Continue) => self.stack.push(x),
+                        (None, Flow::Continue) => {}
+                        (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+                        _ => return Err(module.error(arg.source_range(),
+                                        "Expected something. \
+                                        Check that expression returns a value.", self))
+                    };
                 }
+                try!(self.enter_call(call, new_index, st, lc, cu, module));
+                let (x, flow) = try!(self.call_body(new_index, module));
+                self.end_call(call, new_index, x, flow, module)
+            }
+            FnIndex::Variant => {
                 let st = self.stack.len();
-                let lc = self.local_stack.len();
-                let cu = self.current_stack.len();
-
                 for arg in &call.args {
                     match try!(self.expression(arg, Side::Right, module)) {
                         (Some(x), Flow::Continue) => self.stack.push(x),
-                        (None, Flow::Continue) => {}
                         (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                         _ => return Err(module.error(arg.source_range(),
-                                        &format!("{}\nExpected something. \
-                                        Check that expression returns a value.",
-                                        self.stack_trace()), self))
+                                        "Expected something. \
+                                        Expression did not return a value.", self))
                     };
                 }
+                let payload = self.stack[st..].iter()
+                    .map(|v| self.resolve(v).deep_clone(&self.stack))
+                    .collect();
+                self.stack.truncate(st);
+                Ok((Some(Variable::Variant(Box::new(Variant {
+                    ty: call.alias.clone().expect("Expected enum name"),
+                    name: call.name.clone(),
+                    payload: payload,
+                }))), Flow::Continue))
+            }
+            FnIndex::None => {
+                return Err(module.error(call.source_range,
+                    &format!("Unknown function `{}`", call.name), self))
+            }
+        }
+    }
 
-                // Look for variable in current stack.
-                if f.currents.len() > 0 {
-                    for current in &f.currents {
-                        let mut res = None;
-                        for &(ref cname, ind) in self.current_stack.iter().rev() {
-                            if cname == &current.name {
-                                res = Some(ind);
-                                break;
-                            }
-                        }
-                        if let Some(ind) = res {
-                            self.local_stack.push((current.name.clone(), self.stack.len()));
-                            self.stack.push(Variable::Ref(ind));
-                        } else {
-                            return Err(module.error(call.source_range, &format!(
-                                "{}\nCould not find current variable `{}`",
-                                    self.stack_trace(), current.name), self));
-                        }
+    /// Checks the arguments of a call to a loaded function and reserves
+    /// the return slot.
+    ///
+    /// Returns the absolute function index and the lengths of the stacks
+    /// before arguments are pushed.
+    #[inline(always)]
+    fn begin_call(
+        &mut self,
+        call: &ast::Call,
+        f_index: isize,
+        loader: bool,
+        module: &Arc<Module>
+    ) -> Result<(usize, usize, usize, usize), DyonError> {
+        let relative = if loader {0} else {
+            self.call_stack.last().map(|c| c.index).unwrap_or(0)
+        };
+        let new_index = (f_index + relative as isize) as usize;
+        try!(self.check_stack(call.source_range, module));
+        let f = &module.functions[new_index];
+        if call.arg_len() != f.args.len() {
+            return Err(module.error(call.source_range,
+                &format!("Expected {} arguments but found {}", f.args.len(),
+                call.arg_len()), self));
+        }
+        // Arguments must be computed.
+        if f.returns() {
+            // Add return value before arguments on the stack.
+            // The stack value should remain, but the local should not.
+            self.stack.push(Variable::Return);
+        }
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let cu = self.current_stack.len();
+        Ok((new_index, st, lc, cu))
+    }
+
+    /// Binds currents, notifies senders and pushes the function
+    /// on the call stack after the arguments are pushed.
+    #[inline(always)]
+    fn enter_call(
+        &mut self,
+        call: &ast::Call,
+        new_index: usize,
+        st: usize,
+        lc: usize,
+        cu: usize,
+        module: &Arc<Module>
+    ) -> Result<(), DyonError> {
+        use std::sync::atomic::Ordering;
+
+        let f = &module.functions[new_index];
+        // Look for variable in current stack.
+        if f.currents.len() > 0 {
+            for current in &f.currents {
+                let mut res = None;
+                for &(ref cname, ind) in self.current_stack.iter().rev() {
+                    if cname == &current.name {
+                        res = Some(ind);
+                        break;
                     }
                 }
+                if let Some(ind) = res {
+                    self.local_stack.push((current.name.clone(), self.stack.len()));
+                    self.stack.push(Variable::Ref(ind));
+                } else {
+                    return Err(module.error(call.source_range, &format!("Could not find current variable `{}`", current.name), self));
+                }
+            }
+        }
 
-                // Send arguments to senders.
-                if f.senders.0.load(Ordering::Relaxed) {
-                    let n = self.stack.len();
-                    let mut msg = Vec::with_capacity(n - st);
-                    for i in st..n {
-                        msg.push(self.stack[i].deep_clone(&self.stack));
-                    }
-                    let msg = Arc::new(msg);
-                    // Uses smart swapping of channels to put the closed ones at the end.
-                    let ref mut channels = f.senders.1.lock().unwrap();
-                    let mut open = channels.len();
-                    for i in (0..channels.len()).rev() {
-                        match channels[i].send(Variable::Array(msg.clone())) {
-                            Ok(_) => {}
-                            Err(_) => {
-                                open -= 1;
-                                channels.swap(i, open);
-                            }
-                        }
-                    }
-                    channels.truncate(open);
-                    if channels.len() == 0 {
-                        // Change of flag is guarded by the mutex.
-                        f.senders.0.store(false, Ordering::Relaxed);
+        // Send arguments to senders.
+        if f.senders.0.load(Ordering::Relaxed) {
+            let n = self.stack.len();
+            let mut msg = Vec::with_capacity(n - st);
+            for i in st..n {
+                msg.push(self.stack[i].deep_clone(&self.stack));
+            }
+            let msg = Arc::new(msg);
+            // Uses smart swapping of channels to put the closed ones at the end.
+            let ref mut channels = f.senders.1.lock().unwrap();
+            let mut open = channels.len();
+            for i in (0..channels.len()).rev() {
+                match channels[i].send(Variable::Array(msg.clone())) {
+                    Ok(_) => {}
+                    Err(_) => {
+                        open -= 1;
+                        channels.swap(i, open);
                     }
-                    drop(channels);
                 }
+            }
+            channels.truncate(open);
+            if channels.len() == 0 {
+                // Change of flag is guarded by the mutex.
+                f.senders.0.store(false, Ordering::Relaxed);
+            }
+            drop(channels);
+        }
 
-                self.push_fn(call.name.clone(), new_index, Some(f.file.clone()), st, lc, cu);
-                if f.returns() {
-                    self.local_stack.push((self.ret.clone(), st - 1));
-                }
-                for (i, arg) in f.args.iter().enumerate() {
-                    // Do not resolve locals to keep fixed length from end of stack.
-                    self.local_stack.push((arg.name.clone(), st + i));
-                }
-                let (x, flow) = try!(self.block(&f.block, module));
-                match flow {
-                    Flow::Break(None) =>
-                        return Err(module.error(call.source_range,
-                                   &format!("{}\nCan not break from function",
-                                        self.stack_trace()), self)),
-                    Flow::ContinueLoop(None) =>
-                        return Err(module.error(call.source_range,
-                                   &format!("{}\nCan not continue from function",
-                                        self.stack_trace()), self)),
-                    Flow::Break(Some(ref label)) =>
-                        return Err(module.error(call.source_range,
-                            &format!("{}\nThere is no loop labeled `{}`",
-                                     self.stack_trace(), label), self)),
-                    Flow::ContinueLoop(Some(ref label)) =>
-                        return Err(module.error(call.source_range,
-                            &format!("{}\nThere is no loop labeled `{}`",
-                                    self.stack_trace(), label), self)),
-                    _ => {}
-                }
-                self.pop_fn(call.name.clone());
-                match (f.returns(), x) {
-                    (true, None) => {
-                        match self.stack.pop().expect(TINVOTS) {
-                            Variable::Return => {
-                                let source = call.custom_source.as_ref().unwrap_or(
-                                    &module.functions[
-                                        self.call_stack.last().unwrap().index
-                                    ].source
-                                );
-                                return Err(module.error_source(
-                                call.source_range, &format!(
-                                "{}\nFunction `{}` did not return a value",
-                                self.stack_trace(),
-                                f.name), source))
-                            }
-                            x => {
-                                // This happens when return is only
-                                // assigned to `return = x`.
-                                return Ok((Some(x), Flow::Continue))
-                            }
-                        };
-                    }
-                    (false, Some(_)) => {
-                        let source = call.custom_source.as_ref().unwrap_or(
-                            &module.functions[self.call_stack.last().unwrap().index].source
-                        );
-                        return Err(module.error_source(call.source_range,
-                            &format!(
-                                "{}\nFunction `{}` should not return a value",
-                                self.stack_trace(),
-                                f.name), source))
-                    }
-                    (true, Some(Variable::Return)) => {
-                        // TODO: Could return the last value on the stack.
-                        //       Requires .pop_fn delayed after.
+        self.push_fn(call.name.clone(), new_index, Some(f.file.clone()), st, lc, cu);
+        if f.returns() {
+            self.local_stack.push((self.ret.clone(), st - 1));
+        }
+        for (i, arg) in f.args.iter().enumerate() {
+            // Do not resolve locals to keep fixed length from end of stack.
+            self.local_stack.push((arg.name.clone(), st + i));
+        }
+        Ok(())
+    }
+
+    /// Runs the body of a loaded function after entering the call.
+    ///
+    /// Compiled functions are evaluated by walking the tree
+    /// when expressions are traced by a debugger, line profiler or coverage.
+    #[inline(always)]
+    fn call_body(
+        &mut self,
+        new_index: usize,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
+        let f = &module.functions[new_index];
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.enter(f);
+        }
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.enter(f);
+        }
+        let res = match f.code {
+            Some(ref code) if !self.trace_expressions() => bytecode::exec(self, code, module),
+            _ => self.block(&f.block, module),
+        };
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.exit();
+        }
+        res
+    }
+
+    /// Pops the function from the call stack and checks the return value.
+    #[inline(always)]
+    fn end_call(
+        &mut self,
+        call: &ast::Call,
+        new_index: usize,
+        x: Option<Variable>,
+        flow: Flow,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
+        let f = &module.functions[new_index];
+        match flow {
+            Flow::Break(None) =>
+                return Err(module.error(call.source_range,
+                           "Can not break from function", self)),
+            Flow::ContinueLoop(None) =>
+                return Err(module.error(call.source_range,
+                           "Can not continue from function", self)),
+            Flow::Break(Some(ref label)) =>
+                return Err(module.error(call.source_range,
+                    &format!("There is no loop labeled `{}`", label), self)),
+            Flow::ContinueLoop(Some(ref label)) =>
+                return Err(module.error(call.source_range,
+                    &format!("There is no loop labeled `{}`", label), self)),
+            _ => {}
+        }
+        self.pop_fn(call.name.clone());
+        match (f.returns(), x) {
+            (true, None) => {
+                match self.stack.pop().expect(TINVOTS) {
+                    Variable::Return => {
                         let source = call.custom_source.as_ref().unwrap_or(
-                            &module.functions[self.call_stack.last().unwrap().index].source
+                            &module.functions[
+                                self.call_stack.last().unwrap().index
+                            ].source
                         );
-                        return Err(module.error_source(call.source_range,
-                            &format!(
-                            "{}\nFunction `{}` did not return a value. \
-                            Did you forget a `return`?",
-                                self.stack_trace(),
-                                f.name), source))
+                        return Err(module.error_source(
+                        call.source_range, &format!("Function `{}` did not return a value", f.name), source, &self.call_stack))
                     }
-                    (returns, b) => {
-                        if returns { self.stack.pop(); }
-                        return Ok((b, Flow::Continue))
+                    x => {
+                        // This happens when return is only
+                        // assigned to `return = x`.
+                        return Ok((Some(x), Flow::Continue))
                     }
-                }
+                };
             }
-            FnIndex::None => {
-                return Err(module.error(call.source_range,
-                    &format!("{}\nUnknown function `{}`", self.stack_trace(), call.name), self))
+            (false, Some(_)) => {
+                let source = call.custom_source.as_ref().unwrap_or(
+                    &module.functions[self.call_stack.last().unwrap().index].source
+                );
+                return Err(module.error_source(call.source_range,
+                    &format!("Function `{}` should not return a value", f.name), source, &self.call_stack))
+            }
+            (true, Some(Variable::Return)) => {
+                // TODO: Could return the last value on the stack.
+                //       Requires .pop_fn delayed after.
+                let source = call.custom_source.as_ref().unwrap_or(
+                    &module.functions[self.call_stack.last().unwrap().index].source
+                );
+                return Err(module.error_source(call.source_range,
+                    &format!("Function `{}` did not return a value. \
+                    Did you forget a `return`?", f.name), source, &self.call_stack))
+            }
+            (returns, b) => {
+                if returns { self.stack.pop(); }
+                return Ok((b, Flow::Continue))
             }
         }
     }
@@ -1125,7 +1612,7 @@ impl Runtime {
         function: &str,
         args: &[Variable],
         module: &Arc<Module>
-    ) -> Result<(), String> {
+    ) -> Result<(), DyonError> {
         use std::cell::Cell;
 
         let name: Arc<String> = Arc::new(function.into());
@@ -1144,7 +1631,8 @@ impl Runtime {
                 try!(self.call(&call, &module));
                 Ok(())
             }
-            _ => return Err(format!("Could not find function `{}`",function))
+            _ => return Err(DyonError::new(ErrorKind::Runtime,
+                format!("Could not find function `{}`",function)))
         }
     }
 
@@ -1154,7 +1642,7 @@ impl Runtime {
         function: &str,
         args: &[Variable],
         module: &Arc<Module>
-    ) -> Result<Variable, String> {
+    ) -> Result<Variable, DyonError> {
         use std::cell::Cell;
 
         let name: Arc<String> = Arc::new(function.into());
@@ -1174,21 +1662,20 @@ impl Runtime {
                     Ok((Some(val), Flow::Continue)) => Ok(val),
                     Err(err) => Err(err),
                     _ => return Err(module.error(call.source_range,
-                                    &format!("{}\nExpected something",
-                                        self.stack_trace()), self))
+                                    "Expected something", self))
                 }
             }
-            _ => return Err(format!("Could not find function `{}`",function))
+            _ => return Err(DyonError::new(ErrorKind::Runtime,
+                format!("Could not find function `{}`",function)))
         }
     }
 
-    fn swizzle(&mut self, sw: &ast::Swizzle, module: &Arc<Module>) -> Result<Flow, String> {
+    fn swizzle(&mut self, sw: &ast::Swizzle, module: &Arc<Module>) -> Result<Flow, DyonError> {
         let v = match try!(self.expression(&sw.expr, Side::Right, module)) {
             (Some(x), Flow::Continue) => x,
             (_, Flow::Return) => { return Ok(Flow::Return); }
             _ => return Err(module.error(sw.expr.source_range(),
-                            &format!("{}\nExpected something",
-                                self.stack_trace()), self))
+                            "Expected something", self))
         };
         let v = match self.resolve(&v) {
             &Variable::Vec4(v) => v,
@@ -1210,7 +1697,7 @@ impl Runtime {
         &mut self,
         link: &ast::Link,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         use Link;
 
         Ok((Some(if link.items.len() == 0 {
@@ -1229,8 +1716,7 @@ impl Runtime {
                 match new_link.push(self.resolve(&v)) {
                     Err(err) => {
                         return Err(module.error(item.source_range(),
-                            &format!("{}\n{}", self.stack_trace(),
-                            err), self))
+                            &format!("{}", err), self))
                     }
                     Ok(()) => {}
                 }
@@ -1238,7 +1724,9 @@ impl Runtime {
             self.stack.truncate(st);
             self.local_stack.truncate(lc);
             self.current_stack.truncate(cu);
-            Variable::Link(Box::new(new_link))
+            let v = Variable::Link(Box::new(new_link));
+            try!(self.alloc_var(&v, link.source_range, module));
+            v
         }), Flow::Continue))
     }
 
@@ -1246,71 +1734,69 @@ impl Runtime {
         &mut self,
         obj: &ast::Object,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let mut object: HashMap<_, _> = HashMap::new();
         for &(ref key, ref expr) in &obj.key_values {
             let x = match try!(self.expression(expr, Side::Right, module)) {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(expr.source_range(),
-                                &format!("{}\nExpected something",
-                                    self.stack_trace()), self))
+                                "Expected something", self))
             };
             match object.insert(key.clone(), x) {
                 None => {}
                 Some(_) => return Err(module.error(expr.source_range(),
-                    &format!("{}\nDuplicate key in object `{}`",
-                        self.stack_trace(), key), self))
+                    &format!("Duplicate key in object `{}`", key), self))
             }
         }
-        Ok((Some(Variable::Object(Arc::new(object))), Flow::Continue))
+        let v = Variable::Object(Arc::new(object));
+        try!(self.alloc_var(&v, obj.source_range, module));
+        Ok((Some(v), Flow::Continue))
     }
 
     fn array(
         &mut self,
         arr: &ast::Array,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let mut array: Vec<Variable> = Vec::new();
         for item in &arr.items {
             array.push(match try!(self.expression(item, Side::Right, module)) {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => return Ok((x, Flow::Return)),
                 _ => return Err(module.error(item.source_range(),
-                    &format!("{}\nExpected something",
-                        self.stack_trace()), self))
+                    "Expected something", self))
             });
         }
-        Ok((Some(Variable::Array(Arc::new(array))), Flow::Continue))
+        let v = Variable::Array(Arc::new(array));
+        try!(self.alloc_var(&v, arr.source_range, module));
+        Ok((Some(v), Flow::Continue))
     }
 
     fn array_fill(
         &mut self,
         array_fill: &ast::ArrayFill,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let fill = match try!(self.expression(&array_fill.fill, Side::Right, module)) {
             (x, Flow::Return) => return Ok((x, Flow::Return)),
             (Some(x), Flow::Continue) => x,
             _ => return Err(module.error(array_fill.fill.source_range(),
-                            &format!("{}\nExpected something",
-                                self.stack_trace()), self))
+                            "Expected something", self))
         };
         let n = match try!(self.expression(&array_fill.n, Side::Right, module)) {
             (x, Flow::Return) => return Ok((x, Flow::Return)),
             (Some(x), Flow::Continue) => x,
             _ => return Err(module.error(array_fill.n.source_range(),
-                            &format!("{}\nExpected something",
-                                self.stack_trace()), self))
+                            "Expected something", self))
         };
-        let v = match (self.resolve(&fill), self.resolve(&n)) {
-            (x, &Variable::F64(n, _)) => {
-                Variable::Array(Arc::new(vec![x.clone(); n as usize]))
-            }
+        let n = match *self.resolve(&n) {
+            Variable::F64(n, _) => n as usize,
             _ => return Err(module.error(array_fill.n.source_range(),
-                &format!("{}\nExpected number for length in `[value; length]`",
-                    self.stack_trace()), self))
+                "Expected number for length in `[value; length]`", self))
         };
+        try!(self.alloc_items(n, array_fill.source_range, module));
+        let v = Variable::Array(Arc::new(vec![self.resolve(&fill).clone(); n]));
         Ok((Some(v), Flow::Continue))
     }
 
@@ -1320,7 +1806,7 @@ impl Runtime {
         left: &ast::Expression,
         right: &ast::Expression,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         use ast::AssignOp::*;
         use ast::Expression;
 
@@ -1332,475 +1818,596 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => return Ok((x, Flow::Return)),
                 _ => return Err(module.error(right.source_range(),
-                        &format!("{}\nExpected something from the right side",
-                            self.stack_trace()), self))
+                        "Expected something from the right side", self))
             };
             let a = match try!(self.expression(left, Side::LeftInsert(false), module)) {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => return Ok((x, Flow::Return)),
                 _ => return Err(module.error(left.source_range(),
-                        &format!("{}\nExpected something from the left side",
-                            self.stack_trace()), self))
+                        "Expected something from the left side", self))
             };
-            let r = match a {
-                Variable::UnsafeRef(r) => {
-                    // If reference, use a shallow clone to type check,
-                    // without affecting the original object.
-                    unsafe {
-                        if let Variable::Ref(ind) = *r.0 {
-                            *r.0 = self.stack[ind].clone()
+            try!(self.assign_values(op, left.source_range(), a, &b, module));
+            if op == Add {
+                try!(self.alloc_var(&b, left.source_range(), module));
+            }
+            Ok((None, Flow::Continue))
+        } else {
+            return match *left {
+                Expression::Item(ref item) => {
+                    let x = match try!(self.expression(right, Side::Right, module)) {
+                        (x, Flow::Return) => return Ok((x, Flow::Return)),
+                        (Some(x), Flow::Continue) => x,
+                        _ => return Err(module.error(right.source_range(),
+                                    "Expected something from the right side", self))
+                    };
+                    let v = match x {
+                        // Use a shallow clone of a reference.
+                        Variable::Ref(ind) => self.stack[ind].clone(),
+                        x => x
+                    };
+                    if item.ids.len() != 0 {
+                        let x = match try!(self.expression(left, Side::LeftInsert(true),
+                                                   module)) {
+                            (Some(x), Flow::Continue) => x,
+                            (x, Flow::Return) => return Ok((x, Flow::Return)),
+                            _ => return Err(module.error(left.source_range(),
+                                    "Expected something from the left side", self))
+                        };
+                        match x {
+                            Variable::UnsafeRef(r) => {
+                                unsafe { *r.0 = v }
+                            }
+                            _ => panic!("Expected unsafe reference")
+                        }
+                    } else {
+                        self.local_stack.push((item.name.clone(), self.stack.len()));
+                        if item.current {
+                            self.current_stack.push((item.name.clone(), self.stack.len()));
                         }
+                        self.stack.push(v);
                     }
-                    r
+                    Ok((None, Flow::Continue))
                 }
-                Variable::Ref(ind) => {
-                    UnsafeRef(&mut self.stack[ind] as *mut Variable)
+                _ => return Err(module.error(left.source_range(),
+                                "Expected item", self))
+            }
+        }
+    }
+
+    fn assign_values(
+        &mut self,
+        op: ast::AssignOp,
+        left_range: Range,
+        a: Variable,
+        b: &Variable,
+        module: &Arc<Module>
+    ) -> Result<(), DyonError> {
+        use ast::AssignOp::*;
+
+        let r = match a {
+            Variable::UnsafeRef(r) => {
+                // If reference, use a shallow clone to type check,
+                // without affecting the original object.
+                unsafe {
+                    if let Variable::Ref(ind) = *r.0 {
+                        *r.0 = self.stack[ind].clone()
+                    }
                 }
-                x => panic!("Expected reference, found `{}`", self.typeof_var(&x))
-            };
+                r
+            }
+            Variable::Ref(ind) => {
+                UnsafeRef(&mut self.stack[ind] as *mut Variable)
+            }
+            x => panic!("Expected reference, found `{}`", self.typeof_var(&x))
+        };
 
-            match *self.resolve(&b) {
-                Variable::F64(b, ref sec) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::F64(ref mut n, ref mut n_sec) => {
-                                match op {
-                                    Set => *n = b,
-                                    Add => *n += b,
-                                    Sub => *n -= b,
-                                    Mul => *n *= b,
-                                    Div => *n /= b,
-                                    Rem => *n %= b,
-                                    Pow => *n = n.powf(b),
-                                    Assign => {}
-                                };
-                                *n_sec = sec.clone()
+        match *self.resolve(b) {
+            Variable::F64(b, ref sec) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::F64(ref mut n, ref mut n_sec) => {
+                            match op {
+                                Set => *n = b,
+                                Add => *n += b,
+                                Sub => *n -= b,
+                                Mul => *n *= b,
+                                Div => *n /= b,
+                                Rem => *n %= b,
+                                Pow => *n = n.powf(b),
+                                Assign => {}
+                            };
+                            *n_sec = sec.clone()
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::F64(b, sec.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::F64(b, sec.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Link(ref mut n) => {
+                            if let Add = op {
+                                try!(n.push(&Variable::f64(b)).map_err(|err|
+                                    module.error(left_range, &err, self)));
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Can not use this assignment \
+                                    operator with `link` and `number`", self));
                             }
-                            Variable::Link(ref mut n) => {
-                                if let Add = op {
-                                    try!(n.push(&Variable::f64(b)));
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nCan not use this assignment \
-                                        operator with `link` and `number`",
-                                            self.stack_trace()), self));
-                                }
+                        }
+                        _ => return Err(module.error(
+                                left_range,
+                                "Expected assigning to a number", self))
+                    };
+                }
+            }
+            Variable::I64(b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::I64(ref mut n) => {
+                            let binop = match op {
+                                Set => {*n = b; return Ok(())}
+                                Assign => return Ok(()),
+                                Add => ast::BinOp::Add,
+                                Sub => ast::BinOp::Sub,
+                                Mul => ast::BinOp::Mul,
+                                Div => ast::BinOp::Div,
+                                Rem => ast::BinOp::Rem,
+                                Pow => ast::BinOp::Pow,
+                            };
+                            if let Variable::I64(x) = try!(self.binop_values(binop, left_range,
+                                    &Variable::I64(*n), &Variable::I64(b), module)) {
+                                *n = x;
                             }
-                            _ => return Err(module.error(
-                                    left.source_range(),
-                                    &format!("{}\nExpected assigning to a number",
-                                        self.stack_trace()), self))
-                        };
-                    }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::I64(b)
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
+                            }
+                        }
+                        _ => return Err(module.error(
+                                left_range,
+                                "Expected assigning to an i64", self))
+                    };
                 }
-                Variable::Vec4(b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Vec4(ref mut n) => {
-                                match op {
-                                    Set => *n = b,
-                                    Add => *n = [n[0] + b[0], n[1] + b[1],
-                                                 n[2] + b[2], n[3] + b[3]],
-                                    Sub => *n = [n[0] - b[0], n[1] - b[1],
-                                                 n[2] - b[2], n[3] - b[3]],
-                                    Mul => *n = [n[0] * b[0], n[1] * b[1],
-                                                 n[2] * b[2], n[3] * b[3]],
-                                    Div => *n = [n[0] / b[0], n[1] / b[1],
-                                                 n[2] / b[2], n[3] / b[3]],
-                                    Rem => *n = [n[0] % b[0], n[1] % b[1],
-                                                 n[2] % b[2], n[3] % b[3]],
-                                    Pow => *n = [n[0].powf(b[0]), n[1].powf(b[1]),
-                                                 n[2].powf(b[2]), n[3].powf(b[3])],
-                                    Assign => {}
-                                }
+            }
+            Variable::Vec4(b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Vec4(ref mut n) => {
+                            match op {
+                                Set => *n = b,
+                                Add => *n = [n[0] + b[0], n[1] + b[1],
+                                             n[2] + b[2], n[3] + b[3]],
+                                Sub => *n = [n[0] - b[0], n[1] - b[1],
+                                             n[2] - b[2], n[3] - b[3]],
+                                Mul => *n = [n[0] * b[0], n[1] * b[1],
+                                             n[2] * b[2], n[3] * b[3]],
+                                Div => *n = [n[0] / b[0], n[1] / b[1],
+                                             n[2] / b[2], n[3] / b[3]],
+                                Rem => *n = [n[0] % b[0], n[1] % b[1],
+                                             n[2] % b[2], n[3] % b[3]],
+                                Pow => *n = [n[0].powf(b[0]), n[1].powf(b[1]),
+                                             n[2].powf(b[2]), n[3].powf(b[3])],
+                                Assign => {}
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Vec4(b)
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Vec4(b)
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            _ => return Err(module.error(
-                                    left.source_range(),
-                                    &format!("{}\nExpected assigning to a vec4",
-                                        self.stack_trace()), self))
-                        };
-                    }
+                        }
+                        _ => return Err(module.error(
+                                left_range,
+                                "Expected assigning to a vec4", self))
+                    };
                 }
-                Variable::Bool(b, ref sec) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Bool(ref mut n, ref mut n_sec) => {
-                                match op {
-                                    Set => *n = b,
-                                    _ => unimplemented!()
-                                };
-                                *n_sec = sec.clone();
+            }
+            Variable::Bool(b, ref sec) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Bool(ref mut n, ref mut n_sec) => {
+                            match op {
+                                Set => *n = b,
+                                _ => unimplemented!()
+                            };
+                            *n_sec = sec.clone();
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Bool(b, sec.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Bool(b, sec.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Link(ref mut n) => {
+                            if let Add = op {
+                                try!(n.push(&Variable::bool(b)).map_err(|err|
+                                    module.error(left_range, &err, self)));
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Can not use this assignment \
+                                    operator with `link` and `bool`", self));
                             }
-                            Variable::Link(ref mut n) => {
-                                if let Add = op {
-                                    try!(n.push(&Variable::bool(b)));
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nCan not use this assignment \
-                                        operator with `link` and `bool`",
-                                            self.stack_trace()), self));
-                                }
+                        }
+                        _ => return Err(module.error(
+                                left_range,
+                                "Expected assigning to a bool", self))
+                    };
+                }
+            }
+            Variable::Text(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Text(ref mut n) => {
+                            match op {
+                                Set => *n = b.clone(),
+                                Add => Arc::make_mut(n).push_str(b),
+                                _ => unimplemented!()
                             }
-                            _ => return Err(module.error(
-                                    left.source_range(),
-                                    &format!("{}\nExpected assigning to a bool",
-                                        self.stack_trace()), self))
-                        };
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Text(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
+                            }
+                        }
+                        Variable::Link(ref mut n) => {
+                            if let Add = op {
+                                try!(n.push(&Variable::Text(b.clone())).map_err(|err|
+                                    module.error(left_range, &err, self)));
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Can not use this assignment \
+                                    operator with `link` and `text`", self));
+                            }
+                        }
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to text", self))
                     }
                 }
-                Variable::Text(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Text(ref mut n) => {
-                                match op {
-                                    Set => *n = b.clone(),
-                                    Add => Arc::make_mut(n).push_str(b),
-                                    _ => unimplemented!()
+            }
+            Variable::Object(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Object(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
+                                    *r.0 = Variable::Object(b.clone())
                                 }
+                                // *n = obj.clone()
+                            } else {
+                                unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Text(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Object(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            Variable::Link(ref mut n) => {
-                                if let Add = op {
-                                    try!(n.push(&Variable::Text(b.clone())));
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nCan not use this assignment \
-                                        operator with `link` and `text`",
-                                            self.stack_trace()), self));
+                        }
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to object", self))
+                    }
+                }
+            }
+            Variable::Array(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Array(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
+                                    *r.0 = Variable::Array(b.clone())
                                 }
+                                // *n = arr.clone();
+                            } else {
+                                unimplemented!()
+                            }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Array(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to text",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to array", self))
                     }
                 }
-                Variable::Object(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Object(ref mut n) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::Object(b.clone())
-                                    }
-                                    // *n = obj.clone()
-                                } else {
-                                    unimplemented!()
-                                }
+            }
+            Variable::Link(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Link(ref mut n) => {
+                            match op {
+                                Set => *n = b.clone(),
+                                Add => **n = n.add(b),
+                                Sub => **n = b.add(n),
+                                _ => unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Object(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Link(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to object",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to link", self))
                     }
                 }
-                Variable::Array(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Array(ref mut n) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::Array(b.clone())
-                                    }
-                                    // *n = arr.clone();
-                                } else {
-                                    unimplemented!()
+            }
+            Variable::Option(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Option(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
+                                    *r.0 = Variable::Option(b.clone())
                                 }
+                            } else {
+                                unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Array(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Option(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to array",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to option", self))
                     }
                 }
-                Variable::Link(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Link(ref mut n) => {
-                                match op {
-                                    Set => *n = b.clone(),
-                                    Add => **n = n.add(b),
-                                    Sub => **n = b.add(n),
-                                    _ => unimplemented!()
+            }
+            Variable::Result(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Result(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
+                                    *r.0 = Variable::Result(b.clone())
                                 }
+                            } else {
+                                unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Link(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Result(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to link",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to result", self))
                     }
                 }
-                Variable::Option(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Option(ref mut n) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::Option(b.clone())
-                                    }
-                                } else {
-                                    unimplemented!()
+            }
+            Variable::RustObject(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::RustObject(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
+                                    *r.0 = Variable::RustObject(b.clone())
                                 }
+                            } else {
+                                unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Option(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::RustObject(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to option",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to rust_object", self))
                     }
                 }
-                Variable::Result(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Result(ref mut n) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::Result(b.clone())
-                                    }
-                                } else {
-                                    unimplemented!()
+            }
+            Variable::Closure(ref b, ref env) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Closure(ref mut n, _) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
+                                    *r.0 = Variable::Closure(b.clone(), env.clone())
                                 }
+                            } else {
+                                unimplemented!()
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Result(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Closure(b.clone(), env.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!("{}\nExpected assigning to result",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to closure", self))
                     }
                 }
-                Variable::RustObject(ref b) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::RustObject(ref mut n) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::RustObject(b.clone())
-                                    }
-                                } else {
-                                    unimplemented!()
+            }
+            Variable::Variant(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Variant(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
+                                    *r.0 = Variable::Variant(b.clone())
                                 }
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Can not use this assignment operator with `variant`", self))
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::RustObject(b.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Variant(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!(
-                                    "{}\nExpected assigning to rust_object",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to variant", self))
                     }
                 }
-                Variable::Closure(ref b, ref env) => {
-                    unsafe {
-                        match *r.0 {
-                            Variable::Closure(ref mut n, _) => {
-                                if let Set = op {
-                                    // Check address to avoid unsafe
-                                    // reading and writing to same memory.
-                                    let n_addr = n as *const _ as usize;
-                                    let b_addr = b as *const _ as usize;
-                                    if n_addr != b_addr {
-                                        *r.0 = Variable::Closure(b.clone(), env.clone())
-                                    }
-                                } else {
-                                    unimplemented!()
+            }
+            Variable::Bytes(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Bytes(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
+                                    *r.0 = Variable::Bytes(b.clone())
                                 }
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Can not use this assignment operator with `bytes`", self))
                             }
-                            Variable::Return => {
-                                if let Set = op {
-                                    *r.0 = Variable::Closure(b.clone(), env.clone())
-                                } else {
-                                    return Err(module.error(
-                                        left.source_range(),
-                                        &format!("{}\nReturn has no value",
-                                            self.stack_trace()), self))
-                                }
+                        }
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Bytes(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
                             }
-                            _ => return Err(module.error(
-                                left.source_range(),
-                                &format!(
-                                    "{}\nExpected assigning to closure",
-                                    self.stack_trace()), self))
                         }
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to bytes", self))
                     }
                 }
-                ref x => {
-                    return Err(module.error(
-                        left.source_range(),
-                        &format!("{}\nCan not use this assignment operator with `{}`",
-                            self.stack_trace(), self.typeof_var(x)), self));
-                }
-            };
-            Ok((None, Flow::Continue))
-        } else {
-            return match *left {
-                Expression::Item(ref item) => {
-                    let x = match try!(self.expression(right, Side::Right, module)) {
-                        (x, Flow::Return) => return Ok((x, Flow::Return)),
-                        (Some(x), Flow::Continue) => x,
-                        _ => return Err(module.error(right.source_range(),
-                                    &format!("{}\nExpected something from the right side",
-                                        self.stack_trace()), self))
-                    };
-                    let v = match x {
-                        // Use a shallow clone of a reference.
-                        Variable::Ref(ind) => self.stack[ind].clone(),
-                        x => x
-                    };
-                    if item.ids.len() != 0 {
-                        let x = match try!(self.expression(left, Side::LeftInsert(true),
-                                                   module)) {
-                            (Some(x), Flow::Continue) => x,
-                            (x, Flow::Return) => return Ok((x, Flow::Return)),
-                            _ => return Err(module.error(left.source_range(),
-                                    &format!("{}\nExpected something from the left side",
-                                        self.stack_trace()), self))
-                        };
-                        match x {
-                            Variable::UnsafeRef(r) => {
-                                unsafe { *r.0 = v }
+            }
+            Variable::Map(ref b) => {
+                unsafe {
+                    match *r.0 {
+                        Variable::Map(ref mut n) => {
+                            if let Set = op {
+                                // Check address to avoid unsafe
+                                // reading and writing to same memory.
+                                let n_addr = n as *const _ as usize;
+                                let b_addr = b as *const _ as usize;
+                                if n_addr != b_addr {
+                                    *r.0 = Variable::Map(b.clone())
+                                }
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Can not use this assignment operator with `map`", self))
                             }
-                            _ => panic!("Expected unsafe reference")
                         }
-                    } else {
-                        self.local_stack.push((item.name.clone(), self.stack.len()));
-                        if item.current {
-                            self.current_stack.push((item.name.clone(), self.stack.len()));
+                        Variable::Return => {
+                            if let Set = op {
+                                *r.0 = Variable::Map(b.clone())
+                            } else {
+                                return Err(module.error(
+                                    left_range,
+                                    "Return has no value", self))
+                            }
                         }
-                        self.stack.push(v);
+                        _ => return Err(module.error(
+                            left_range,
+                            "Expected assigning to map", self))
                     }
-                    Ok((None, Flow::Continue))
                 }
-                _ => return Err(module.error(left.source_range(),
-                                &format!("{}\nExpected item",
-                                    self.stack_trace()), self))
             }
-        }
+            ref x => {
+                return Err(module.error(
+                    left_range,
+                    &format!("Can not use this assignment operator with `{}`", self.typeof_var(x)), self));
+            }
+        };
+        Ok(())
     }
+
     // `insert` is true for `:=` and false for `=`.
     // This works only on objects, but does not have to check since it is
     // ignored for arrays.
@@ -1809,7 +2416,7 @@ impl Runtime {
         item: &ast::Item,
         side: Side,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         use Error;
 
         #[inline(always)]
@@ -1819,33 +2426,27 @@ impl Runtime {
             v: Result<Box<Variable>, Box<Error>>,
             source_range: Range,
             module: &Module
-        ) -> Result<(Option<Variable>, Flow), String> {
+        ) -> Result<(Option<Variable>, Flow), DyonError> {
             match v {
                 Ok(ok) => Ok((Some(*ok), Flow::Continue)),
                 Err(mut err) => {
                     let call = call_stack.last().unwrap();
                     if call.stack_len == 0 {
-                        return Err(module.error_fnindex(source_range,
-                            &format!("{}\nRequires `->` on function `{}`",
-                            stack_trace(call_stack),
-                            &call.fn_name), call.index));
+                        return Err(module.error_call_stack(source_range,
+                            &format!("Requires `->` on function `{}`", &call.fn_name), call_stack));
                     }
                     if let Variable::Return = stack[call.stack_len - 1] {}
                     else {
-                        return Err(module.error_fnindex(source_range,
-                            &format!("{}\nRequires `->` on function `{}`",
-                            stack_trace(call_stack),
-                            &call.fn_name),
-                            call.index));
+                        return Err(module.error_call_stack(source_range,
+                            &format!("Requires `->` on function `{}`", &call.fn_name), call_stack));
                     }
                     let file = match call.file {
                         None => "".into(),
                         Some(ref f) => format!(" ({})", f)
                     };
-                    err.trace.push(module.error_fnindex(
+                    err.trace.push(module.error_trace(
                         source_range,
-                        &format!("In function `{}`{}", call.fn_name, file),
-                        call.index));
+                        &format!("In function `{}`{}", call.fn_name, file), call_stack));
                     Ok((Some(Variable::Result(Err(err))), Flow::Return))
                 }
             }
@@ -1886,14 +2487,9 @@ impl Runtime {
                         if found {
                             self.stack.len() - item.stack_id.get().unwrap()
                         } else if name == "return" {
-                            return Err(module.error(item.source_range, &format!(
-                                "{}\nRequires `->` on function `{}`",
-                                self.stack_trace(),
-                                &self.call_stack.last().unwrap().fn_name), self));
+                            return Err(module.error(item.source_range, &format!("Requires `->` on function `{}`", &self.call_stack.last().unwrap().fn_name), self));
                         } else {
-                            return Err(module.error(item.source_range, &format!(
-                                "{}\nCould not find local or current variable `{}`",
-                                    self.stack_trace(), name), self));
+                            return Err(module.error(item.source_range, &format!("Could not find local or current variable `{}`", name), self));
                         }
                     }
                 }
@@ -1927,10 +2523,12 @@ impl Runtime {
                     Some(v) => v,
                     None => {
                         return Err(module.error(item.source_range,
-                            &format!("{}\nExpected `ok(_)`, `err(_)`, `bool`, `f64`",
-                                self.stack_trace()), self));
+                            "Expected `ok(_)`, `err(_)`, `bool`, `f64`", self));
                     }
                 };
+                if self.coverage.is_some() {
+                    self.cover_try(item.source_range, v.is_err(), module);
+                }
                 return try(&mut self.stack, &self.call_stack, v,
                            item.source_range, module);
             } else {
@@ -1946,11 +2544,23 @@ impl Runtime {
                     (x, Flow::Return) => return Ok((x, Flow::Return)),
                     (Some(x), Flow::Continue) => self.stack.push(x),
                     _ => return Err(module.error(expr.source_range(),
-                        &format!("{}\nExpected something for index",
-                            self.stack_trace()), self))
+                        "Expected something for index", self))
                 };
             }
         }
+        self.item_ids(item, side, stack_id, start_stack_len, module)
+    }
+
+    /// Looks up item with ids, where expressions for ids are already pushed
+    /// on the stack after `start_stack_len`.
+    pub fn item_ids(
+        &mut self,
+        item: &ast::Item,
+        side: Side,
+        stack_id: usize,
+        start_stack_len: usize,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let &mut Runtime {
             ref mut stack,
             ref mut call_stack,
@@ -1962,6 +2572,8 @@ impl Runtime {
             Side::LeftInsert(insert) => insert,
         };
 
+        // Bytes are read by value, which is stored here.
+        let mut byte = Variable::Return;
         let v = {
             let item_len = item.ids.len();
             // Get the first variable (a.x).y
@@ -1974,7 +2586,8 @@ impl Runtime {
                 start_stack_len,
                 &mut expr_j,
                 insert,
-                item_len == 1
+                item_len == 1,
+                if let Side::Right = side {Some(&mut byte)} else {None}
             ));
             let mut try_id_ind = 0;
             if item.try_ids.len() > 0 && item.try_ids[try_id_ind] == 0 {
@@ -1982,10 +2595,8 @@ impl Runtime {
                 let v = unsafe {match Runtime::try_msg(&*var) {
                     Some(v) => v,
                     None => {
-                        return Err(module.error_fnindex(item.ids[0].source_range(),
-                            &format!("{}\nExpected `ok(_)` or `err(_)`",
-                                stack_trace(call_stack)),
-                                call_stack.last().unwrap().index));
+                        return Err(module.error_call_stack(item.ids[0].source_range(),
+                            "Expected `ok(_)` or `err(_)`", call_stack));
                     }
                 }};
                 match v {
@@ -1996,32 +2607,25 @@ impl Runtime {
                     Err(ref err) => {
                         let call = call_stack.last().unwrap();
                         if call.stack_len == 0 {
-                            return Err(module.error_fnindex(
+                            return Err(module.error_call_stack(
                                 item.ids[0].source_range(),
-                                &format!("{}\nRequires `->` on function `{}`",
-                                stack_trace(call_stack),
-                                &call.fn_name),
-                                call.index));
+                                &format!("Requires `->` on function `{}`", &call.fn_name), call_stack));
                         }
                         if let Variable::Return = stack[call.stack_len - 1] {}
                         else {
-                            return Err(module.error_fnindex(
+                            return Err(module.error_call_stack(
                                 item.ids[0].source_range(),
-                                &format!("{}\nRequires `->` on function `{}`",
-                                stack_trace(call_stack),
-                                &call.fn_name),
-                                call.index));
+                                &format!("Requires `->` on function `{}`", &call.fn_name), call_stack));
                         }
                         let mut err = err.clone();
                         let file = match call.file.as_ref() {
                             None => "".into(),
                             Some(f) => format!(" ({})", f)
                         };
-                        err.trace.push(module.error_fnindex(
+                        err.trace.push(module.error_trace(
                             item.ids[0].source_range(),
                             &format!("In function `{}`{}",
-                                &call.fn_name, file),
-                                call.index));
+                                &call.fn_name, file), call_stack));
                         return Ok((Some(Variable::Result(Err(err))), Flow::Return));
                     }
                 }
@@ -2038,7 +2642,8 @@ impl Runtime {
                     &mut expr_j,
                     insert,
                     // `i` skips first index.
-                    i + 2 == item_len
+                    i + 2 == item_len,
+                    if let Side::Right = side {Some(&mut byte)} else {None}
                 ));
 
                 if item.try_ids.len() > try_id_ind &&
@@ -2047,10 +2652,8 @@ impl Runtime {
                     let v = unsafe {match Runtime::try_msg(&*var) {
                         Some(v) => v,
                         None => {
-                            return Err(module.error_fnindex(prop.source_range(),
-                                &format!("{}\nExpected `ok(_)`, `err(_)`, `bool`, `f64`",
-                                    stack_trace(call_stack)),
-                                    call_stack.last().unwrap().index));
+                            return Err(module.error_call_stack(prop.source_range(),
+                                "Expected `ok(_)`, `err(_)`, `bool`, `f64`", call_stack));
                         }
                     }};
                     match v {
@@ -2061,32 +2664,25 @@ impl Runtime {
                         Err(ref err) => {
                             let call = call_stack.last().unwrap();
                             if call.stack_len == 0 {
-                                return Err(module.error_fnindex(
+                                return Err(module.error_call_stack(
                                     prop.source_range(),
-                                    &format!("{}\nRequires `->` on function `{}`",
-                                        stack_trace(call_stack),
-                                        &call.fn_name),
-                                        call.index));
+                                    &format!("Requires `->` on function `{}`", &call.fn_name), call_stack));
                             }
                             if let Variable::Return = stack[call.stack_len - 1] {}
                             else {
-                                return Err(module.error_fnindex(
+                                return Err(module.error_call_stack(
                                     prop.source_range(),
-                                    &format!("{}\nRequires `->` on function `{}`",
-                                        stack_trace(call_stack),
-                                        &call.fn_name),
-                                        call.index));
+                                    &format!("Requires `->` on function `{}`", &call.fn_name), call_stack));
                             }
                             let mut err = err.clone();
                             let file = match call.file.as_ref() {
                                 None => "".into(),
                                 Some(f) => format!(" ({})", f)
                             };
-                            err.trace.push(module.error_fnindex(
+                            err.trace.push(module.error_trace(
                                 prop.source_range(),
                                 &format!("In function `{}`{}",
-                                    &call.fn_name, file),
-                                    call.index));
+                                    &call.fn_name, file), call_stack));
                             return Ok((Some(Variable::Result(Err(err))), Flow::Return));
                         }
                     }
@@ -2106,10 +2702,13 @@ impl Runtime {
         let v = match var {
             &Variable::Text(_) => self.text_type.clone(),
             &Variable::F64(_, _) => self.f64_type.clone(),
+            &Variable::I64(_) => self.i64_type.clone(),
+            &Variable::Bytes(_) => self.bytes_type.clone(),
             &Variable::Vec4(_) => self.vec4_type.clone(),
             &Variable::Return => self.return_type.clone(),
             &Variable::Bool(_, _) => self.bool_type.clone(),
             &Variable::Object(_) => self.object_type.clone(),
+            &Variable::Map(_) => self.map_type.clone(),
             &Variable::Array(_) => self.array_type.clone(),
             &Variable::Link(_) => self.link_type.clone(),
             &Variable::Ref(_) => self.ref_type.clone(),
@@ -2120,6 +2719,8 @@ impl Runtime {
             &Variable::Thread(_) => self.thread_type.clone(),
             &Variable::Closure(_, _) => self.closure_type.clone(),
             &Variable::In(_) => self.in_type.clone(),
+            &Variable::Iter(_) => self.iter_type.clone(),
+            &Variable::Variant(ref v) => Variable::Text(v.ty.clone()),
         };
         match v {
             Variable::Text(v) => v,
@@ -2131,184 +2732,230 @@ impl Runtime {
         &mut self,
         compare: &ast::Compare,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
-        fn sub_compare(
-            rt: &Runtime,
-            compare: &ast::Compare,
-            module: &Module,
-            a: &Variable,
-            b: &Variable
-        ) -> Result<Variable, String> {
-            use ast::CompareOp::*;
-
-            match (rt.resolve(&b), rt.resolve(&a)) {
-                (&Variable::F64(b, _), &Variable::F64(a, ref sec)) => {
-                    Ok(Variable::Bool(match compare.op {
-                        Less => a < b,
-                        LessOrEqual => a <= b,
-                        Greater => a > b,
-                        GreaterOrEqual => a >= b,
-                        Equal => a == b,
-                        NotEqual => a != b
-                    }, sec.clone()))
-                }
-                (&Variable::Text(ref b), &Variable::Text(ref a)) => {
-                    Ok(Variable::bool(match compare.op {
-                        Less => a < b,
-                        LessOrEqual => a <= b,
-                        Greater => a > b,
-                        GreaterOrEqual => a >= b,
-                        Equal => a == b,
-                        NotEqual => a != b
-                    }))
-                }
-                (&Variable::Bool(b, _), &Variable::Bool(a, ref sec)) => {
-                    Ok(Variable::Bool(match compare.op {
-                        Equal => a == b,
-                        NotEqual => a != b,
-                        x => return Err(module.error(compare.source_range,
-                            &format!("{}\n`{}` can not be used with bools",
-                                rt.stack_trace(),
-                                x.symbol()), rt))
-                    }, sec.clone()))
-                }
-                (&Variable::Vec4(ref b), &Variable::Vec4(ref a)) => {
-                    Ok(Variable::bool(match compare.op {
-                        Equal => a == b,
-                        NotEqual => a != b,
-                        x => return Err(module.error(compare.source_range,
-                            &format!("{}\n`{}` can not be used with vec4s",
-                                rt.stack_trace(),
-                                x.symbol()), rt))
-                    }))
-                }
-                (&Variable::Object(ref b), &Variable::Object(ref a)) => {
-                    Ok(Variable::bool(match compare.op {
-                        Equal => {
-                            a.len() == b.len() &&
-                            a.iter().all(|a| {
-                                if let Some(b_val) = b.get(a.0) {
-                                    if let Ok(Variable::Bool(true, _)) =
-                                        sub_compare(rt, compare, module, &a.1, b_val) {true}
-                                    else {false}
-                                } else {false}
-                            })
-                        }
-                        NotEqual => {
-                            a.len() != b.len() ||
-                            a.iter().any(|a| {
-                                if let Some(b_val) = b.get(a.0) {
-                                    if let Ok(Variable::Bool(false, _)) =
-                                        sub_compare(rt, compare, module, &a.1, b_val) {false}
-                                    else {true}
-                                } else {true}
-                            })
-                        }
-                        x => return Err(module.error(compare.source_range,
-                            &format!("{}\n`{}` can not be used with objects",
-                                rt.stack_trace(),
-                                x.symbol()), rt))
-                    }))
-                }
-                (&Variable::Array(ref b), &Variable::Array(ref a)) => {
-                    Ok(Variable::bool(match compare.op {
-                        Equal => {
-                            a.len() == b.len() &&
-                            a.iter().zip(b.iter()).all(|(a, b)| {
-                                if let Ok(Variable::Bool(true, _)) =
-                                    sub_compare(rt, compare, module, a, b) {true} else {false}
-                            })
-                        }
-                        NotEqual => {
-                            a.len() != b.len() ||
-                            a.iter().zip(b.iter()).any(|(a, b)| {
-                                if let Ok(Variable::Bool(false, _)) =
-                                    sub_compare(rt, compare, module, a, b) {false} else {true}
-                            })
-                        }
-                        x => return Err(module.error(compare.source_range,
-                            &format!("{}\n`{}` can not be used with arrays",
-                                rt.stack_trace(),
-                                x.symbol()), rt))
-                    }))
-                }
-                (&Variable::Option(None), &Variable::Option(None)) => {
-                    Ok(Variable::bool(match compare.op {
-                        Equal => true,
-                        NotEqual => false,
-                        x => return Err(module.error(compare.source_range,
-                            &format!("{}\n`{}` can not be used with options",
-                                rt.stack_trace(),
-                                x.symbol()), rt))
-                    }))
-                }
-                (&Variable::Option(None), &Variable::Option(_)) => {
-                    Ok(Variable::bool(match compare.op {
-                        Equal => false,
-                        NotEqual => true,
-                        x => return Err(module.error(compare.source_range,
-                            &format!("{}\n`{}` can not be used with options",
-                                rt.stack_trace(),
-                                x.symbol()), rt))
-                    }))
-                }
-                (&Variable::Option(_), &Variable::Option(None)) => {
-                    Ok(Variable::bool(match compare.op {
-                        Equal => false,
-                        NotEqual => true,
-                        x => return Err(module.error(compare.source_range,
-                            &format!("{}\n`{}` can not be used with options",
-                                rt.stack_trace(),
-                                x.symbol()), rt))
-                    }))
-                }
-                (&Variable::Option(Some(ref b)),
-                 &Variable::Option(Some(ref a))) => {
-                    sub_compare(rt, compare, module, a, b)
-                }
-                (b, a) => return Err(module.error(compare.source_range,
-                    &format!(
-                    "{}\n`{}` can not be used with `{}` and `{}`",
-                    rt.stack_trace(),
-                    compare.op.symbol(),
-                    rt.typeof_var(a),
-                    rt.typeof_var(b)), rt))
-            }
-        }
-
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let left = match try!(self.expression(&compare.left, Side::Right, module)) {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(compare.left.source_range(),
-                &format!("{}\nExpected something from the left argument",
-                    self.stack_trace()), self))
+                "Expected something from the left argument", self))
         };
         let right = match try!(self.expression(&compare.right, Side::Right, module)) {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => return Ok((x, Flow::Return)),
             _ => return Err(module.error(compare.right.source_range(),
-                &format!("{}\nExpected something from the right argument",
-                    self.stack_trace()), self))
+                "Expected something from the right argument", self))
         };
-        Ok((Some(try!(sub_compare(self, compare, module, &left, &right))), Flow::Continue))
+        Ok((Some(try!(self.compare_values(compare.op, compare.source_range,
+            &left, &right, module))), Flow::Continue))
+    }
+    fn compare_values(
+        &self,
+        op: ast::CompareOp,
+        source_range: Range,
+        a: &Variable,
+        b: &Variable,
+        module: &Arc<Module>
+    ) -> Result<Variable, DyonError> {
+        use ast::CompareOp::*;
+
+        match (self.resolve(b), self.resolve(a)) {
+            (&Variable::F64(b, _), &Variable::F64(a, ref sec)) => {
+                Ok(Variable::Bool(match op {
+                    Less => a < b,
+                    LessOrEqual => a <= b,
+                    Greater => a > b,
+                    GreaterOrEqual => a >= b,
+                    Equal => a == b,
+                    NotEqual => a != b
+                }, sec.clone()))
+            }
+            (&Variable::I64(b), &Variable::I64(a)) => {
+                Ok(Variable::bool(match op {
+                    Less => a < b,
+                    LessOrEqual => a <= b,
+                    Greater => a > b,
+                    GreaterOrEqual => a >= b,
+                    Equal => a == b,
+                    NotEqual => a != b
+                }))
+            }
+            (&Variable::Text(ref b), &Variable::Text(ref a)) => {
+                Ok(Variable::bool(match op {
+                    Less => a < b,
+                    LessOrEqual => a <= b,
+                    Greater => a > b,
+                    GreaterOrEqual => a >= b,
+                    Equal => a == b,
+                    NotEqual => a != b
+                }))
+            }
+            (&Variable::Bool(b, _), &Variable::Bool(a, ref sec)) => {
+                Ok(Variable::Bool(match op {
+                    Equal => a == b,
+                    NotEqual => a != b,
+                    x => return Err(module.error(source_range,
+                        &format!("`{}` can not be used with bools", x.symbol()), self))
+                }, sec.clone()))
+            }
+            (&Variable::Bytes(ref b), &Variable::Bytes(ref a)) => {
+                Ok(Variable::bool(match op {
+                    Equal => a == b,
+                    NotEqual => a != b,
+                    x => return Err(module.error(source_range,
+                        &format!("`{}` can not be used with bytes", x.symbol()), self))
+                }))
+            }
+            (&Variable::Vec4(ref b), &Variable::Vec4(ref a)) => {
+                Ok(Variable::bool(match op {
+                    Equal => a == b,
+                    NotEqual => a != b,
+                    x => return Err(module.error(source_range,
+                        &format!("`{}` can not be used with vec4s", x.symbol()), self))
+                }))
+            }
+            (&Variable::Object(ref b), &Variable::Object(ref a)) => {
+                Ok(Variable::bool(match op {
+                    Equal => {
+                        a.len() == b.len() &&
+                        a.iter().all(|a| {
+                            if let Some(b_val) = b.get(a.0) {
+                                if let Ok(Variable::Bool(true, _)) =
+                                    self.compare_values(op, source_range, &a.1, b_val, module) {true}
+                                else {false}
+                            } else {false}
+                        })
+                    }
+                    NotEqual => {
+                        a.len() != b.len() ||
+                        a.iter().any(|a| {
+                            if let Some(b_val) = b.get(a.0) {
+                                if let Ok(Variable::Bool(false, _)) =
+                                    self.compare_values(op, source_range, &a.1, b_val, module) {false}
+                                else {true}
+                            } else {true}
+                        })
+                    }
+                    x => return Err(module.error(source_range,
+                        &format!("`{}` can not be used with objects", x.symbol()), self))
+                }))
+            }
+            (&Variable::Map(ref b), &Variable::Map(ref a)) => {
+                Ok(Variable::bool(match op {
+                    Equal => {
+                        a.len() == b.len() &&
+                        a.iter().all(|a| {
+                            if let Some(b_val) = b.get(a.0) {
+                                if let Ok(Variable::Bool(true, _)) =
+                                    self.compare_values(op, source_range, &a.1, b_val, module) {true}
+                                else {false}
+                            } else {false}
+                        })
+                    }
+                    NotEqual => {
+                        a.len() != b.len() ||
+                        a.iter().any(|a| {
+                            if let Some(b_val) = b.get(a.0) {
+                                if let Ok(Variable::Bool(false, _)) =
+                                    self.compare_values(op, source_range, &a.1, b_val, module) {false}
+                                else {true}
+                            } else {true}
+                        })
+                    }
+                    x => return Err(module.error(source_range,
+                        &format!("`{}` can not be used with maps", x.symbol()), self))
+                }))
+            }
+            (&Variable::Array(ref b), &Variable::Array(ref a)) => {
+                Ok(Variable::bool(match op {
+                    Equal => {
+                        a.len() == b.len() &&
+                        a.iter().zip(b.iter()).all(|(a, b)| {
+                            if let Ok(Variable::Bool(true, _)) =
+                                self.compare_values(op, source_range, a, b, module) {true} else {false}
+                        })
+                    }
+                    NotEqual => {
+                        a.len() != b.len() ||
+                        a.iter().zip(b.iter()).any(|(a, b)| {
+                            if let Ok(Variable::Bool(false, _)) =
+                                self.compare_values(op, source_range, a, b, module) {false} else {true}
+                        })
+                    }
+                    x => return Err(module.error(source_range,
+                        &format!("`{}` can not be used with arrays", x.symbol()), self))
+                }))
+            }
+            (&Variable::Option(None), &Variable::Option(None)) => {
+                Ok(Variable::bool(match op {
+                    Equal => true,
+                    NotEqual => false,
+                    x => return Err(module.error(source_range,
+                        &format!("`{}` can not be used with options", x.symbol()), self))
+                }))
+            }
+            (&Variable::Option(None), &Variable::Option(_)) => {
+                Ok(Variable::bool(match op {
+                    Equal => false,
+                    NotEqual => true,
+                    x => return Err(module.error(source_range,
+                        &format!("`{}` can not be used with options", x.symbol()), self))
+                }))
+            }
+            (&Variable::Option(_), &Variable::Option(None)) => {
+                Ok(Variable::bool(match op {
+                    Equal => false,
+                    NotEqual => true,
+                    x => return Err(module.error(source_range,
+                        &format!("`{}` can not be used with options", x.symbol()), self))
+                }))
+            }
+            (&Variable::Option(Some(ref b)),
+             &Variable::Option(Some(ref a))) => {
+                self.compare_values(op, source_range, a, b, module)
+            }
+            (&Variable::Variant(ref b), &Variable::Variant(ref a)) => {
+                Ok(Variable::bool(match op {
+                    Equal => {
+                        a.ty == b.ty && a.name == b.name &&
+                        a.payload.len() == b.payload.len() &&
+                        a.payload.iter().zip(b.payload.iter()).all(|(a, b)| {
+                            if let Ok(Variable::Bool(true, _)) =
+                                self.compare_values(op, source_range, a, b, module) {true} else {false}
+                        })
+                    }
+                    NotEqual => {
+                        a.ty != b.ty || a.name != b.name ||
+                        a.payload.len() != b.payload.len() ||
+                        a.payload.iter().zip(b.payload.iter()).any(|(a, b)| {
+                            if let Ok(Variable::Bool(false, _)) =
+                                self.compare_values(op, source_range, a, b, module) {false} else {true}
+                        })
+                    }
+                    x => return Err(module.error(source_range,
+                        &format!("`{}` can not be used with enums", x.symbol()), self))
+                }))
+            }
+            (b, a) => return Err(module.error(source_range,
+                &format!("`{}` can not be used with `{}` and `{}`", op.symbol(),
+                self.typeof_var(a),
+                self.typeof_var(b)), self))
+        }
     }
     fn if_expr(
         &mut self,
         if_expr: &ast::If,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let cond = match try!(self.expression(&if_expr.cond, Side::Right, module)) {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(if_expr.cond.source_range(),
-                &format!("{}\nExpected bool from if condition",
-                    self.stack_trace()), self))
+                "Expected bool from if condition", self))
         };
         let val = match self.resolve(&cond) {
             &Variable::Bool(val, _) => val,
             _ => return Err(module.error(if_expr.cond.source_range(),
-                &format!("{}\nExpected bool from if condition",
-                    self.stack_trace()), self))
+                "Expected bool from if condition", self))
         };
         if val {
             return self.block(&if_expr.true_block, module);
@@ -2321,8 +2968,7 @@ impl Runtime {
                     return Ok((x, Flow::Return));
                 }
                 _ => return Err(module.error(cond.source_range(),
-                    &format!("{}\nExpected bool from else if condition",
-                        self.stack_trace()), self))
+                    "Expected bool from else if condition", self))
             };
             match self.resolve(&else_if_cond) {
                 &Variable::Bool(false, _) => {}
@@ -2330,8 +2976,7 @@ impl Runtime {
                     return self.block(body, module);
                 }
                 _ => return Err(module.error(cond.source_range(),
-                    &format!("{}\nExpected bool from else if condition",
-                        self.stack_trace()), self))
+                    "Expected bool from else if condition", self))
             }
         }
         if let Some(ref block) = if_expr.else_block {
@@ -2340,19 +2985,75 @@ impl Runtime {
             Ok((None, Flow::Continue))
         }
     }
+    fn match_expr(
+        &mut self,
+        match_expr: &ast::Match,
+        module: &Arc<Module>
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
+        let val = match try!(self.expression(&match_expr.expr, Side::Right, module)) {
+            (Some(x), Flow::Continue) => x,
+            (x, Flow::Return) => { return Ok((x, Flow::Return)); }
+            _ => return Err(module.error(match_expr.expr.source_range(),
+                "Expected something from match expression", self))
+        };
+        let val = self.resolve(&val).clone();
+        let st = self.stack.len();
+        let lc = self.local_stack.len();
+        let mut bound = vec![];
+        for arm in &match_expr.arms {
+            bound.clear();
+            if !arm.pattern.matches(&val, &mut bound) { continue; }
+            // Push bindings in the same order as when resolving locals.
+            for (name, v) in bound.drain(..) {
+                let len = self.stack.len();
+                self.local_stack.push((name, len));
+                self.stack.push(v);
+            }
+            if let Some(ref guard) = arm.guard {
+                let cond = match try!(self.expression(guard, Side::Right, module)) {
+                    (Some(x), Flow::Continue) => x,
+                    (x, Flow::Return) => {
+                        self.stack.truncate(st);
+                        self.local_stack.truncate(lc);
+                        return Ok((x, Flow::Return));
+                    }
+                    _ => return Err(module.error(guard.source_range(),
+                        "Expected bool from match guard", self))
+                };
+                let val = match self.resolve(&cond) {
+                    &Variable::Bool(val, _) => val,
+                    _ => return Err(module.error(guard.source_range(),
+                        "Expected bool from match guard", self))
+                };
+                if !val {
+                    self.stack.truncate(st);
+                    self.local_stack.truncate(lc);
+                    continue;
+                }
+            }
+            let (x, flow) = try!(self.expression(&arm.expr, Side::Right, module));
+            // The result might refer to a binding that is removed from the stack.
+            let x = x.map(|x| self.resolve(&x).clone());
+            self.stack.truncate(st);
+            self.local_stack.truncate(lc);
+            return Ok((x, flow));
+        }
+        Err(module.error(match_expr.source_range,
+            &format!("No match arm for `{}`", self.typeof_var(&val)), self))
+    }
+
     fn for_expr(
         &mut self,
         for_expr: &ast::For,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         match try!(self.expression(&for_expr.init, Side::Right, module)) {
         (None, Flow::Continue) => {}
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(for_expr.init.source_range(),
-                &format!("{}\nExpected nothing from for init",
-                    self.stack_trace()), self))
+                "Expected nothing from for init", self))
         };
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -2362,14 +3063,13 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => return Ok((x, Flow::Return)),
                 _ => return Err(module.error(for_expr.cond.source_range(),
-                    &format!("{}\nExpected bool from for condition",
-                        self.stack_trace()), self))
+                    "Expected bool from for condition", self))
             };
             let val = match val {
                 Variable::Bool(val, _) => val,
                 _ => return Err(module.error(
                     for_expr.cond.source_range(),
-                    &format!("{}\nExpected bool", self.stack_trace()), self))
+                    "Expected bool", self))
             };
             if !val { break }
             match try!(self.block(&for_expr.block, module)) {
@@ -2409,8 +3109,7 @@ impl Runtime {
                         (x, Flow::Return) => return Ok((x, Flow::Return)),
                         _ => return Err(module.error(
                             for_expr.step.source_range(),
-                            &format!("{}\nExpected nothing from for step",
-                                self.stack_trace()), self))
+                            "Expected nothing from for step", self))
                     };
                     continue;
                 }
@@ -2420,8 +3119,7 @@ impl Runtime {
                 (x, Flow::Return) => return Ok((x, Flow::Return)),
                 _ => return Err(module.error(
                     for_expr.step.source_range(),
-                    &format!("{}\nExpected nothing from for step",
-                        self.stack_trace()), self))
+                    "Expected nothing from for step", self))
             };
             self.stack.truncate(st);
             self.local_stack.truncate(lc);
@@ -2430,11 +3128,98 @@ impl Runtime {
         self.local_stack.truncate(prev_lc);
         Ok((None, flow))
     }
+    /// Returns the end of the counter and the items when iterating over a collection.
+    fn for_n_end(
+        &self,
+        for_n_expr: &ast::ForN,
+        end: &Variable,
+        module: &Module
+    ) -> Result<(f64, Option<Vec<(Variable, Variable)>>), DyonError> {
+        if for_n_expr.for_in.is_some() {
+            let items = try!(self.for_in_items(end, for_n_expr.end.source_range(), module));
+            Ok((items.len() as f64, Some(items)))
+        } else {
+            match self.resolve(end) {
+                &Variable::F64(val, _) => Ok((val, None)),
+                x => Err(module.error(for_n_expr.end.source_range(),
+                                &self.expected(x, "number"), self))
+            }
+        }
+    }
+
+    /// Pushes the counter of a loop.
+    fn for_n_counter(&mut self, for_n_expr: &ast::ForN, start: f64) {
+        if for_n_expr.for_in.is_none() {
+            self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
+        }
+        self.stack.push(Variable::f64(start));
+    }
+
+    /// Returns pairs of key and value of a collection.
+    ///
+    /// Arrays and links use the index as key.
+    /// Objects and maps are sorted by key.
+    pub fn for_in_items(
+        &self,
+        v: &Variable,
+        range: Range,
+        module: &Module
+    ) -> Result<Vec<(Variable, Variable)>, DyonError> {
+        Ok(match self.resolve(v) {
+            &Variable::Array(ref arr) => {
+                arr.iter().enumerate()
+                    .map(|(i, v)| (Variable::f64(i as f64), v.clone())).collect()
+            }
+            &Variable::Link(ref link) => {
+                link.items().into_iter().enumerate()
+                    .map(|(i, v)| (Variable::f64(i as f64), v)).collect()
+            }
+            &Variable::Object(ref obj) => {
+                let mut items: Vec<(Variable, Variable)> = obj.iter()
+                    .map(|(k, v)| (Variable::Text(k.clone()), v.clone())).collect();
+                items.sort_by(|a, b| match (&a.0, &b.0) {
+                    (&Variable::Text(ref a), &Variable::Text(ref b)) => a.cmp(b),
+                    _ => unreachable!()
+                });
+                items
+            }
+            &Variable::Map(ref map) => {
+                let mut items: Vec<(&MapKey, &Variable)> = map.iter().collect();
+                items.sort_by(|a, b| a.0.cmp(b.0));
+                items.into_iter().map(|(k, v)| (k.to_variable(), v.clone())).collect()
+            }
+            x => return Err(module.error(range,
+                    &self.expected(x, "array, object, map or link"), self))
+        })
+    }
+
+    /// Pushes key and value of current item when iterating over a collection.
+    fn for_in_bind(
+        &mut self,
+        for_n_expr: &ast::ForN,
+        items: &Option<Vec<(Variable, Variable)>>,
+        st: usize
+    ) {
+        if let (&Some(ref for_in), &Some(ref items)) = (&for_n_expr.for_in, items) {
+            let ind = match self.stack[st - 1] {
+                Variable::F64(val, _) => val as usize,
+                _ => return
+            };
+            let (ref key, ref value) = items[ind];
+            if let Some(ref name) = for_in.key {
+                self.local_stack.push((name.clone(), self.stack.len()));
+                self.stack.push(key.clone());
+            }
+            self.local_stack.push((for_in.value.clone(), self.stack.len()));
+            self.stack.push(value.clone());
+        }
+    }
+
     fn for_n_expr(
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -2444,8 +3229,7 @@ impl Runtime {
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 (Some(x), Flow::Continue) => x,
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        self.stack_trace()), self))
+                    "Expected number from for start", self))
             };
             let start = match self.resolve(&start) {
                 &Variable::F64(val, _) => val,
@@ -2460,18 +3244,12 @@ impl Runtime {
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             (Some(x), Flow::Continue) => x,
             _ => return Err(module.error(for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    self.stack_trace()), self))
-        };
-        let end = match self.resolve(&end) {
-            &Variable::F64(val, _) => val,
-            x => return Err(module.error(for_n_expr.end.source_range(),
-                            &self.expected(x, "number"), self))
+                "Expected number from for end", self))
         };
+        let (end, items) = try!(self.for_n_end(for_n_expr, &end, module));
 
         // Initialize counter.
-        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
-        self.stack.push(Variable::f64(start));
+        self.for_n_counter(for_n_expr, start);
 
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -2485,6 +3263,7 @@ impl Runtime {
                 x => return Err(module.error(for_n_expr.source_range,
                                 &self.expected(x, "number"), self))
             };
+            self.for_in_bind(for_n_expr, &items, st);
             match try!(self.block(&for_n_expr.block, module)) {
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 (_, Flow::Continue) => {}
@@ -2538,7 +3317,7 @@ impl Runtime {
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut sum = 0.0;
@@ -2549,8 +3328,7 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        self.stack_trace()), self))
+                    "Expected number from for start", self))
             };
             let start = match self.resolve(&start) {
                 &Variable::F64(val, _) => val,
@@ -2565,18 +3343,12 @@ impl Runtime {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    self.stack_trace()), self))
-        };
-        let end = match self.resolve(&end) {
-            &Variable::F64(val, _) => val,
-            x => return Err(module.error(for_n_expr.end.source_range(),
-                            &self.expected(x, "number"), self))
+                "Expected number from for end", self))
         };
+        let (end, items) = try!(self.for_n_end(for_n_expr, &end, module));
 
         // Initialize counter.
-        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
-        self.stack.push(Variable::f64(start));
+        self.for_n_counter(for_n_expr, start);
 
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -2590,6 +3362,7 @@ impl Runtime {
                 x => return Err(module.error(for_n_expr.source_range,
                                 &self.expected(x, "number"), self))
             };
+            self.for_in_bind(for_n_expr, &items, st);
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => {
                     match self.resolve(&x) {
@@ -2653,7 +3426,7 @@ impl Runtime {
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut sum: [f32; 4] = [0.0; 4];
@@ -2664,8 +3437,7 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        self.stack_trace()), self))
+                    "Expected number from for start", self))
             };
             let start = match self.resolve(&start) {
                 &Variable::F64(val, _) => val,
@@ -2680,18 +3452,12 @@ impl Runtime {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    self.stack_trace()), self))
-        };
-        let end = match self.resolve(&end) {
-            &Variable::F64(val, _) => val,
-            x => return Err(module.error(for_n_expr.end.source_range(),
-                            &self.expected(x, "number"), self))
+                "Expected number from for end", self))
         };
+        let (end, items) = try!(self.for_n_end(for_n_expr, &end, module));
 
         // Initialize counter.
-        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
-        self.stack.push(Variable::f64(start));
+        self.for_n_counter(for_n_expr, start);
 
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -2705,6 +3471,7 @@ impl Runtime {
                 x => return Err(module.error(for_n_expr.source_range,
                                 &self.expected(x, "number"), self))
             };
+            self.for_in_bind(for_n_expr, &items, st);
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => {
                     match self.resolve(&x) {
@@ -2772,7 +3539,7 @@ impl Runtime {
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut prod = 1.0;
@@ -2783,8 +3550,7 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        self.stack_trace()), self))
+                    "Expected number from for start", self))
             };
             let start = match self.resolve(&start) {
                 &Variable::F64(val, _) => val,
@@ -2799,18 +3565,12 @@ impl Runtime {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    self.stack_trace()), self))
-        };
-        let end = match self.resolve(&end) {
-            &Variable::F64(val, _) => val,
-            x => return Err(module.error(for_n_expr.end.source_range(),
-                            &self.expected(x, "number"), self))
+                "Expected number from for end", self))
         };
+        let (end, items) = try!(self.for_n_end(for_n_expr, &end, module));
 
         // Initialize counter.
-        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
-        self.stack.push(Variable::f64(start));
+        self.for_n_counter(for_n_expr, start);
 
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -2824,6 +3584,7 @@ impl Runtime {
                 x => return Err(module.error(for_n_expr.source_range,
                                 &self.expected(x, "number"), self))
             };
+            self.for_in_bind(for_n_expr, &items, st);
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => {
                     match self.resolve(&x) {
@@ -2887,7 +3648,7 @@ impl Runtime {
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut prod: [f32; 4] = [1.0; 4];
@@ -2898,8 +3659,7 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        self.stack_trace()), self))
+                    "Expected number from for start", self))
             };
             let start = match self.resolve(&start) {
                 &Variable::F64(val, _) => val,
@@ -2914,18 +3674,12 @@ impl Runtime {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    self.stack_trace()), self))
-        };
-        let end = match self.resolve(&end) {
-            &Variable::F64(val, _) => val,
-            x => return Err(module.error(for_n_expr.end.source_range(),
-                            &self.expected(x, "number"), self))
+                "Expected number from for end", self))
         };
+        let (end, items) = try!(self.for_n_end(for_n_expr, &end, module));
 
         // Initialize counter.
-        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
-        self.stack.push(Variable::f64(start));
+        self.for_n_counter(for_n_expr, start);
 
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -2939,6 +3693,7 @@ impl Runtime {
                 x => return Err(module.error(for_n_expr.source_range,
                                 &self.expected(x, "number"), self))
             };
+            self.for_in_bind(for_n_expr, &items, st);
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => {
                     match self.resolve(&x) {
@@ -3006,7 +3761,7 @@ impl Runtime {
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -3016,8 +3771,7 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        self.stack_trace()), self))
+                    "Expected number from for start", self))
             };
             let start = match self.resolve(&start) {
                 &Variable::F64(val, _) => val,
@@ -3032,20 +3786,14 @@ impl Runtime {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    self.stack_trace()), self))
-        };
-        let end = match self.resolve(&end) {
-            &Variable::F64(val, _) => val,
-            x => return Err(module.error(for_n_expr.end.source_range(),
-                            &self.expected(x, "number"), self))
+                "Expected number from for end", self))
         };
+        let (end, items) = try!(self.for_n_end(for_n_expr, &end, module));
 
         let mut min = ::std::f64::NAN;
         let mut sec = None;
         // Initialize counter.
-        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
-        self.stack.push(Variable::f64(start));
+        self.for_n_counter(for_n_expr, start);
         let st = self.stack.len();
         let lc = self.local_stack.len();
         let mut flow = Flow::Continue;
@@ -3059,6 +3807,7 @@ impl Runtime {
                 x => return Err(module.error(for_n_expr.source_range,
                                 &self.expected(x, "number"), self))
             };
+            self.for_in_bind(for_n_expr, &items, st);
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => {
                     match self.resolve(&x) {
@@ -3067,11 +3816,11 @@ impl Runtime {
                                 min = val;
                                 sec = match val_sec {
                                     &None => {
-                                        Some(Box::new(vec![Variable::f64(ind)]))
+                                        Some(Box::new(vec![for_in_key(&items, ind)]))
                                     }
                                     &Some(ref arr) => {
                                         let mut arr = arr.clone();
-                                        arr.push(Variable::f64(ind));
+                                        arr.push(for_in_key(&items, ind));
                                         Some(arr)
                                     }
                                 };
@@ -3136,7 +3885,7 @@ impl Runtime {
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -3146,8 +3895,7 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        self.stack_trace()), self))
+                    "Expected number from for start", self))
             };
             let start = match self.resolve(&start) {
                 &Variable::F64(val, _) => val,
@@ -3162,20 +3910,14 @@ impl Runtime {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    self.stack_trace()), self))
-        };
-        let end = match self.resolve(&end) {
-            &Variable::F64(val, _) => val,
-            x => return Err(module.error(for_n_expr.end.source_range(),
-                            &self.expected(x, "number"), self))
+                "Expected number from for end", self))
         };
+        let (end, items) = try!(self.for_n_end(for_n_expr, &end, module));
 
         let mut max = ::std::f64::NAN;
         let mut sec = None;
         // Initialize counter.
-        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
-        self.stack.push(Variable::f64(start));
+        self.for_n_counter(for_n_expr, start);
 
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -3190,6 +3932,7 @@ impl Runtime {
                 x => return Err(module.error(for_n_expr.source_range,
                                 &self.expected(x, "number"), self))
             };
+            self.for_in_bind(for_n_expr, &items, st);
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => {
                     match self.resolve(&x) {
@@ -3198,11 +3941,11 @@ impl Runtime {
                                 max = val;
                                 sec = match val_sec {
                                     &None => {
-                                        Some(Box::new(vec![Variable::f64(ind)]))
+                                        Some(Box::new(vec![for_in_key(&items, ind)]))
                                     }
                                     &Some(ref arr) => {
                                         let mut arr = arr.clone();
-                                        arr.push(Variable::f64(ind));
+                                        arr.push(for_in_key(&items, ind));
                                         Some(arr)
                                     }
                                 };
@@ -3267,7 +4010,7 @@ impl Runtime {
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -3277,8 +4020,7 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        self.stack_trace()), self))
+                    "Expected number from for start", self))
             };
             let start = match self.resolve(&start) {
                 &Variable::F64(val, _) => val,
@@ -3293,20 +4035,14 @@ impl Runtime {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    self.stack_trace()), self))
-        };
-        let end = match self.resolve(&end) {
-            &Variable::F64(val, _) => val,
-            x => return Err(module.error(for_n_expr.end.source_range(),
-                            &self.expected(x, "number"), self))
+                "Expected number from for end", self))
         };
+        let (end, items) = try!(self.for_n_end(for_n_expr, &end, module));
 
         let mut any = false;
         let mut sec = None;
         // Initialize counter.
-        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
-        self.stack.push(Variable::f64(start));
+        self.for_n_counter(for_n_expr, start);
 
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -3320,6 +4056,7 @@ impl Runtime {
                 x => return Err(module.error(for_n_expr.source_range,
                                 &self.expected(x, "number"), self))
             };
+            self.for_in_bind(for_n_expr, &items, st);
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => {
                     match self.resolve(&x) {
@@ -3328,11 +4065,11 @@ impl Runtime {
                                 any = true;
                                 sec = match val_sec {
                                     &None => {
-                                        Some(Box::new(vec![Variable::f64(ind)]))
+                                        Some(Box::new(vec![for_in_key(&items, ind)]))
                                     }
                                     &Some(ref arr) => {
                                         let mut arr = arr.clone();
-                                        arr.push(Variable::f64(ind));
+                                        arr.push(for_in_key(&items, ind));
                                         Some(arr)
                                     }
                                 };
@@ -3398,7 +4135,7 @@ impl Runtime {
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
 
@@ -3408,8 +4145,7 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        self.stack_trace()), self))
+                    "Expected number from for start", self))
             };
             let start = match self.resolve(&start) {
                 &Variable::F64(val, _) => val,
@@ -3424,20 +4160,14 @@ impl Runtime {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    self.stack_trace()), self))
-        };
-        let end = match self.resolve(&end) {
-            &Variable::F64(val, _) => val,
-            x => return Err(module.error(for_n_expr.end.source_range(),
-                            &self.expected(x, "number"), self))
+                "Expected number from for end", self))
         };
+        let (end, items) = try!(self.for_n_end(for_n_expr, &end, module));
 
         let mut all = true;
         let mut sec = None;
         // Initialize counter.
-        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
-        self.stack.push(Variable::f64(start));
+        self.for_n_counter(for_n_expr, start);
 
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -3451,6 +4181,7 @@ impl Runtime {
                 x => return Err(module.error(for_n_expr.source_range,
                                 &self.expected(x, "number"), self))
             };
+            self.for_in_bind(for_n_expr, &items, st);
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => {
                     match self.resolve(&x) {
@@ -3459,11 +4190,11 @@ impl Runtime {
                                 all = false;
                                 sec = match val_sec {
                                     &None => {
-                                        Some(Box::new(vec![Variable::f64(ind)]))
+                                        Some(Box::new(vec![for_in_key(&items, ind)]))
                                     }
                                     &Some(ref arr) => {
                                         let mut arr = arr.clone();
-                                        arr.push(Variable::f64(ind));
+                                        arr.push(for_in_key(&items, ind));
                                         Some(arr)
                                     }
                                 };
@@ -3529,7 +4260,7 @@ impl Runtime {
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         use Link;
 
         fn sub_link_for_n_expr(
@@ -3537,7 +4268,7 @@ impl Runtime {
             rt: &mut Runtime,
             for_n_expr: &ast::ForN,
             module: &Arc<Module>
-        ) -> Result<(Option<Variable>, Flow), String> {
+        ) -> Result<(Option<Variable>, Flow), DyonError> {
             let prev_st = rt.stack.len();
             let prev_lc = rt.local_stack.len();
 
@@ -3547,8 +4278,7 @@ impl Runtime {
                     (Some(x), Flow::Continue) => x,
                     (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                     _ => return Err(module.error(for_n_expr.end.source_range(),
-                        &format!("{}\nExpected number from for start",
-                            rt.stack_trace()), rt))
+                        "Expected number from for start", rt))
                 };
                 let start = match rt.resolve(&start) {
                     &Variable::F64(val, _) => val,
@@ -3563,18 +4293,12 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for end",
-                        rt.stack_trace()), rt))
-            };
-            let end = match rt.resolve(&end) {
-                &Variable::F64(val, _) => val,
-                x => return Err(module.error(for_n_expr.end.source_range(),
-                                &rt.expected(x, "number"), rt))
+                    "Expected number from for end", rt))
             };
+            let (end, items) = try!(rt.for_n_end(for_n_expr, &end, module));
 
             // Initialize counter.
-            rt.local_stack.push((for_n_expr.name.clone(), rt.stack.len()));
-            rt.stack.push(Variable::f64(start));
+            rt.for_n_counter(for_n_expr, start);
 
             let st = rt.stack.len();
             let lc = rt.local_stack.len();
@@ -3590,6 +4314,7 @@ impl Runtime {
                                     &rt.expected(x, "number"), rt))
                 };
 
+                rt.for_in_bind(for_n_expr, &items, st);
                 match for_n_expr.block.expressions[0] {
                     ast::Expression::Link(ref link) => {
                         // Evaluate link items directly.
@@ -3599,8 +4324,7 @@ impl Runtime {
                                     match res.push(rt.resolve(x)) {
                                         Err(err) => {
                                             return Err(module.error(for_n_expr.source_range,
-                                                &format!("{}\n{}", rt.stack_trace(),
-                                                err), rt))
+                                                &format!("{}", err), rt))
                                         }
                                         Ok(()) => {}
                                     }
@@ -3713,7 +4437,7 @@ impl Runtime {
         &mut self,
         for_n_expr: &ast::ForN,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let prev_st = self.stack.len();
         let prev_lc = self.local_stack.len();
         let mut res: Vec<Variable> = vec![];
@@ -3724,8 +4448,7 @@ impl Runtime {
                 (Some(x), Flow::Continue) => x,
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
                 _ => return Err(module.error(for_n_expr.end.source_range(),
-                    &format!("{}\nExpected number from for start",
-                        self.stack_trace()), self))
+                    "Expected number from for start", self))
             };
             let start = match self.resolve(&start) {
                 &Variable::F64(val, _) => val,
@@ -3740,18 +4463,12 @@ impl Runtime {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => { return Ok((x, Flow::Return)); }
             _ => return Err(module.error(for_n_expr.end.source_range(),
-                &format!("{}\nExpected number from for end",
-                    self.stack_trace()), self))
-        };
-        let end = match self.resolve(&end) {
-            &Variable::F64(val, _) => val,
-            x => return Err(module.error(for_n_expr.end.source_range(),
-                            &self.expected(x, "number"), self))
+                "Expected number from for end", self))
         };
+        let (end, items) = try!(self.for_n_end(for_n_expr, &end, module));
 
         // Initialize counter.
-        self.local_stack.push((for_n_expr.name.clone(), self.stack.len()));
-        self.stack.push(Variable::f64(start));
+        self.for_n_counter(for_n_expr, start);
 
         let st = self.stack.len();
         let lc = self.local_stack.len();
@@ -3765,6 +4482,7 @@ impl Runtime {
                 x => return Err(module.error(for_n_expr.source_range,
                                 &self.expected(x, "number"), self))
             };
+            self.for_in_bind(for_n_expr, &items, st);
             match try!(self.block(&for_n_expr.block, module)) {
                 (Some(x), Flow::Continue) => res.push(x),
                 (x, Flow::Return) => { return Ok((x, Flow::Return)); }
@@ -3823,7 +4541,7 @@ impl Runtime {
         vec4: &ast::Vec4,
         side: Side,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let st = self.stack.len();
         for expr in &vec4.args {
             match try!(self.expression(expr, side, module)) {
@@ -3831,8 +4549,7 @@ impl Runtime {
                 (Some(x), Flow::Continue) => self.stack.push(x),
                 (x, Flow::Return) => return Ok((x, Flow::Return)),
                 _ => return Err(module.error(expr.source_range(),
-                    &format!("{}\nExpected something from vec4 argument",
-                        self.stack_trace()), self))
+                    "Expected something from vec4 argument", self))
             };
             // Skip the rest if swizzling pushes arguments.
             if self.stack.len() - st > 3 { break; }
@@ -3868,72 +4585,95 @@ impl Runtime {
         norm: &ast::Norm,
         side: Side,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let val = match try!(self.expression(&norm.expr, side, module)) {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => return Ok((x, Flow::Return)),
             _ => return Err(module.error(norm.source_range,
-                &format!("{}\nExpected something from unary argument",
-                    self.stack_trace()), self))
+                "Expected something from unary argument", self))
         };
-        let v = match self.resolve(&val) {
+        let v = try!(self.norm_value(norm.source_range, &val, module));
+        Ok((Some(v), Flow::Continue))
+    }
+    fn norm_value(
+        &self,
+        source_range: Range,
+        val: &Variable,
+        module: &Arc<Module>
+    ) -> Result<Variable, DyonError> {
+        Ok(match self.resolve(val) {
             &Variable::Vec4(b) => {
                 Variable::f64((b[0] * b[0] + b[1] * b[1] + b[2] * b[2]).sqrt() as f64)
             }
-            x => return Err(module.error(norm.source_range,
+            x => return Err(module.error(source_range,
                 &self.expected(x, "vec4"), self))
-        };
-        Ok((Some(v), Flow::Continue))
+        })
     }
     fn unop(
         &mut self,
         unop: &ast::UnOpExpression,
         side: Side,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         let val = match try!(self.expression(&unop.expr, side, module)) {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => return Ok((x, Flow::Return)),
             _ => return Err(module.error(unop.source_range,
-                &format!("{}\nExpected something from unary argument",
-                    self.stack_trace()), self))
+                "Expected something from unary argument", self))
         };
-        let v = match self.resolve(&val) {
+        let v = try!(self.unop_value(unop.op, unop.source_range, &val, module));
+        Ok((Some(v), Flow::Continue))
+    }
+    fn unop_value(
+        &self,
+        op: ast::UnOp,
+        source_range: Range,
+        val: &Variable,
+        module: &Arc<Module>
+    ) -> Result<Variable, DyonError> {
+        Ok(match self.resolve(val) {
             &Variable::Bool(b, ref sec) => {
-                Variable::Bool(match unop.op {
+                Variable::Bool(match op {
                     ast::UnOp::Not => !b,
-                    _ => return Err(module.error(unop.source_range,
-                                    &format!("{}\nUnknown boolean unary operator",
-                                             self.stack_trace()), self))
+                    _ => return Err(module.error(source_range,
+                                    "Unknown boolean unary operator", self))
                 }, sec.clone())
             }
             &Variable::F64(v, ref sec) => {
-                Variable::F64(match unop.op {
+                Variable::F64(match op {
                     ast::UnOp::Neg => -v,
-                    _ => return Err(module.error(unop.source_range,
-                                    &format!("{}\nUnknown number unary operator",
-                                             self.stack_trace()), self))
+                    _ => return Err(module.error(source_range,
+                                    "Unknown number unary operator", self))
                 }, sec.clone())
             }
-            _ => return Err(module.error(unop.source_range,
-                &format!("{}\nInvalid type, expected bool", self.stack_trace()), self))
-        };
-        Ok((Some(v), Flow::Continue))
+            &Variable::I64(v) => {
+                Variable::I64(match op {
+                    ast::UnOp::Neg => match v.checked_neg() {
+                        Some(x) => x,
+                        None => return Err(module.error(source_range,
+                                    "Integer overflow in `-`", self))
+                    },
+                    // Bitwise not.
+                    ast::UnOp::Not => !v,
+                })
+            }
+            _ => return Err(module.error(source_range,
+                "Invalid type, expected bool", self))
+        })
     }
     fn binop(
         &mut self,
         binop: &ast::BinOpExpression,
         side: Side,
         module: &Arc<Module>
-    ) -> Result<(Option<Variable>, Flow), String> {
+    ) -> Result<(Option<Variable>, Flow), DyonError> {
         use ast::BinOp::*;
 
         let left = match try!(self.expression(&binop.left, side, module)) {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => return Ok((x, Flow::Return)),
             _ => return Err(module.error(binop.source_range,
-                &format!("{}\nExpected something from left argument",
-                    self.stack_trace()), self))
+                "Expected something from left argument", self))
         };
 
         // Check lazy boolean expressions.
@@ -3955,26 +4695,74 @@ impl Runtime {
             (Some(x), Flow::Continue) => x,
             (x, Flow::Return) => return Ok((x, Flow::Return)),
             _ => return Err(module.error(binop.source_range,
-                &format!("{}\nExpected something from right argument",
-                    self.stack_trace()), self))
+                "Expected something from right argument", self))
         };
-        let v = match (self.resolve(&left), self.resolve(&right)) {
+        let v = try!(self.binop_values(binop.op, binop.source_range, &left, &right, module));
+        try!(self.alloc_var(&v, binop.source_range, module));
+        Ok((Some(v), Flow::Continue))
+    }
+    fn binop_values(
+        &self,
+        op: ast::BinOp,
+        source_range: Range,
+        left: &Variable,
+        right: &Variable,
+        module: &Arc<Module>
+    ) -> Result<Variable, DyonError> {
+        use ast::BinOp::*;
+
+        Ok(match (self.resolve(left), self.resolve(right)) {
             (&Variable::F64(a, ref sec), &Variable::F64(b, _)) => {
-                Variable::F64(match binop.op {
+                Variable::F64(match op {
                     Add => a + b,
                     Sub => a - b,
                     Mul => a * b,
                     Div => a / b,
                     Rem => a % b,
                     Pow => a.powf(b),
-                    _ => return Err(module.error(binop.source_range,
-                        &format!("{}\nUnknown number operator `{:?}`",
-                            self.stack_trace(),
-                            binop.op.symbol()), self))
+                    _ => return Err(module.error(source_range,
+                        &format!("Unknown number operator `{:?}`", op.symbol()), self))
                 }, sec.clone())
             }
+            (&Variable::I64(a), &Variable::I64(b)) => {
+                if b == 0 {
+                    if let Div | Rem = op {
+                        return Err(module.error(source_range,
+                            "Integer division by zero", self));
+                    }
+                }
+                let res = match op {
+                    Add => a.checked_add(b),
+                    Sub => a.checked_sub(b),
+                    Mul => a.checked_mul(b),
+                    Div => a.checked_div(b),
+                    Rem => a.checked_rem(b),
+                    Pow => if b < 0 || b > u32::max_value() as i64 {None}
+                           else {a.checked_pow(b as u32)},
+                    BitAnd => Some(a & b),
+                    BitOr => Some(a | b),
+                    BitXor => Some(a ^ b),
+                    // Shifting back must give the same number, or bits were lost.
+                    Shl => if b < 0 || b >= 64 {None} else {
+                        a.checked_shl(b as u32).and_then(|x|
+                            if x >> b == a {Some(x)} else {None})
+                    },
+                    Shr => if b < 0 || b >= 64 {None} else {a.checked_shr(b as u32)},
+                    _ => return Err(module.error(source_range,
+                        &format!("Unknown integer operator `{:?}`", op.symbol()), self))
+                };
+                match res {
+                    Some(x) => Variable::I64(x),
+                    None => return Err(module.error(source_range,
+                        &format!("Integer overflow in `{}`", op.symbol()), self))
+                }
+            }
+            (&Variable::I64(_), &Variable::F64(_, _)) |
+            (&Variable::F64(_, _), &Variable::I64(_)) =>
+                return Err(module.error(source_range,
+                    "Can not mix `i64` and `f64`, use `i64(x)` or `f64(x)` to convert", self)),
             (&Variable::Vec4(a), &Variable::Vec4(b)) => {
-                match binop.op {
+                match op {
                     Add => Variable::Vec4([a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]]),
                     Sub => Variable::Vec4([a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3]]),
                     Mul => Variable::Vec4([a[0] * b[0], a[1] * b[1], a[2] * b[2], a[3] * b[3]]),
@@ -3987,36 +4775,33 @@ impl Runtime {
                     Rem => Variable::Vec4([a[0] % b[0], a[1] % b[1], a[2] % b[2], a[3] % b[3]]),
                     Pow => Variable::Vec4([a[0].powf(b[0]), a[1].powf(b[1]),
                                            a[2].powf(b[2]), a[3].powf(b[3])]),
-                    AndAlso | OrElse => return Err(module.error(binop.source_range,
-                        &format!("{}\nUnknown operator `{:?}` for `vec4` and `vec4`",
-                            self.stack_trace(),
-                            binop.op.symbol_bool()), self)),
+                    AndAlso | OrElse | BitAnd | BitOr | BitXor | Shl | Shr =>
+                        return Err(module.error(source_range,
+                        &format!("Unknown operator `{:?}` for `vec4` and `vec4`", op.symbol_bool()), self)),
                 }
             }
             (&Variable::Vec4(a), &Variable::F64(b, _)) => {
                 let b = b as f32;
-                match binop.op {
+                match op {
                     Add => Variable::Vec4([a[0] + b, a[1] + b, a[2] + b, a[3] + b]),
                     Sub => Variable::Vec4([a[0] - b, a[1] - b, a[2] - b, a[3] - b]),
                     Mul => Variable::Vec4([a[0] * b, a[1] * b, a[2] * b, a[3] * b]),
                     Dot => Variable::f64((a[0] * b + a[1] * b +
                                           a[2] * b + a[3] * b) as f64),
-                    Cross => return Err(module.error(binop.source_range,
-                        &format!("{}\nExpected two vec4 for `{:?}`",
-                            self.stack_trace(), binop.op.symbol()), self)),
+                    Cross => return Err(module.error(source_range,
+                        &format!("Expected two vec4 for `{:?}`", op.symbol()), self)),
                     Div => Variable::Vec4([a[0] / b, a[1] / b, a[2] / b, a[3] / b]),
                     Rem => Variable::Vec4([a[0] % b, a[1] % b, a[2] % b, a[3] % b]),
                     Pow => Variable::Vec4([a[0].powf(b), a[1].powf(b),
                                            a[2].powf(b), a[3].powf(b)]),
-                    AndAlso | OrElse => return Err(module.error(binop.source_range,
-                        &format!("{}\nUnknown operator `{:?}` for `vec4` and `f64`",
-                            self.stack_trace(),
-                            binop.op.symbol_bool()), self)),
+                    AndAlso | OrElse | BitAnd | BitOr | BitXor | Shl | Shr =>
+                        return Err(module.error(source_range,
+                        &format!("Unknown operator `{:?}` for `vec4` and `f64`", op.symbol_bool()), self)),
                 }
             }
             (&Variable::F64(a, _), &Variable::Vec4(b)) => {
                 let a = a as f32;
-                match binop.op {
+                match op {
                     Add => Variable::Vec4([a + b[0], a + b[1], a + b[2], a + b[3]]),
                     Sub => Variable::Vec4([a - b[0], a - b[1], a - b[2], a - b[3]]),
                     Mul => Variable::Vec4([a * b[0], a * b[1], a * b[2], a * b[3]]),
@@ -4026,69 +4811,66 @@ impl Runtime {
                     Rem => Variable::Vec4([a % b[0], a % b[1], a % b[2], a % b[3]]),
                     Pow => Variable::Vec4([a.powf(b[0]), a.powf(b[1]),
                                            a.powf(b[2]), a.powf(b[3])]),
-                    Cross => return Err(module.error(binop.source_range,
-                        &format!("{}\nExpected two vec4 for `{:?}`",
-                            self.stack_trace(), binop.op.symbol()), self)),
-                    AndAlso | OrElse => return Err(module.error(binop.source_range,
-                        &format!("{}\nUnknown operator `{:?}` for `f64` and `vec4`",
-                            self.stack_trace(),
-                            binop.op.symbol_bool()), self)),
+                    Cross => return Err(module.error(source_range,
+                        &format!("Expected two vec4 for `{:?}`", op.symbol()), self)),
+                    AndAlso | OrElse | BitAnd | BitOr | BitXor | Shl | Shr =>
+                        return Err(module.error(source_range,
+                        &format!("Unknown operator `{:?}` for `f64` and `vec4`", op.symbol_bool()), self)),
                 }
             }
             (&Variable::Bool(a, ref sec), &Variable::Bool(b, _)) => {
-                Variable::Bool(match binop.op {
+                Variable::Bool(match op {
                     Add | OrElse => a || b,
                     // Boolean subtraction with lazy precedence.
                     Sub => a && !b,
                     Mul | AndAlso => a && b,
                     Pow => a ^ b,
-                    _ => return Err(module.error(binop.source_range,
-                        &format!("{}\nUnknown boolean operator `{:?}`",
-                            self.stack_trace(),
-                            binop.op.symbol_bool()), self))
+                    _ => return Err(module.error(source_range,
+                        &format!("Unknown boolean operator `{:?}`", op.symbol_bool()), self))
                 }, sec.clone())
             }
             (&Variable::Text(ref a), &Variable::Text(ref b)) => {
-                match binop.op {
+                match op {
                     Add => {
                         let mut res = String::with_capacity(a.len() + b.len());
                         res.push_str(a);
                         res.push_str(b);
                         Variable::Text(Arc::new(res))
                     }
-                    _ => return Err(module.error(binop.source_range,
-                        &format!("{}\nThis operation can not be used with strings",
-                            self.stack_trace()), self))
+                    _ => return Err(module.error(source_range,
+                        "This operation can not be used with strings", self))
                 }
             }
             (&Variable::Text(_), _) =>
-                return Err(module.error(binop.source_range,
-                &format!("{}\nThe right argument must be a string. \
-                Try the `str` function", self.stack_trace()), self)),
+                return Err(module.error(source_range,
+                "The right argument must be a string. \
+                Try the `str` function", self)),
             (&Variable::Link(ref a), &Variable::Link(ref b)) => {
-                match binop.op {
+                match op {
                     Add => {
                         Variable::Link(Box::new(a.add(b)))
                     }
-                    _ => return Err(module.error(binop.source_range,
-                        &format!("{}\nThis operation can not be used with links",
-                            self.stack_trace()), self))
+                    _ => return Err(module.error(source_range,
+                        "This operation can not be used with links", self))
                 }
             }
-            _ => return Err(module.error(binop.source_range, &format!(
-                "{}\nInvalid type for binary operator `{:?}`, \
-                expected numbers, vec4s, bools or strings",
-                self.stack_trace(),
-                binop.op.symbol()), self))
-        };
-
-        Ok((Some(v), Flow::Continue))
+            _ => return Err(module.error(source_range, &format!("Invalid type for binary operator `{:?}`, \
+                expected numbers, vec4s, bools or strings", op.symbol()), self))
+        })
     }
     pub fn stack_trace(&self) -> String {
         stack_trace(&self.call_stack)
     }
 }
 
+/// Returns the key of an item, or the index when not iterating over a collection.
+fn for_in_key(items: &Option<Vec<(Variable, Variable)>>, ind: f64) -> Variable {
+    match *items {
+        Some(ref items) => items[ind as usize].0.clone(),
+        None => Variable::f64(ind)
+    }
+}
+
 fn stack_trace(call_stack: &[Call]) -> String {
     let mut s = String::new();
     for call in call_stack.iter() {
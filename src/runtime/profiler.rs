@@ -0,0 +1,355 @@
+//! Profiles time spent in functions.
+//!
+//! The profiler measures each call to a loaded function,
+//! recording the number of calls, inclusive and exclusive time.
+//! Exclusive time is also recorded by stack of calls,
+//! which can be written as folded stacks for flamegraph tools.
+//! Optionally, time is recorded per source line.
+//!
+//! In sampling mode, a timer thread counts ticks at a fixed interval.
+//! The ticks are added to the current stack of calls when a function is entered or exits,
+//! so the clock is not read for every call.
+//! Times are then estimated from the number of ticks.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ast;
+
+/// Identifies a function.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FnKey {
+    pub namespace: Arc<Vec<Arc<String>>>,
+    pub name: Arc<String>,
+    pub file: Arc<String>,
+}
+
+impl FnKey {
+    /// Returns the name with namespace, e.g. `foo::bar::baz`.
+    pub fn path(&self) -> String {
+        let mut s = String::new();
+        for ns in self.namespace.iter() {
+            s.push_str(ns);
+            s.push_str("::");
+        }
+        s.push_str(&self.name);
+        s
+    }
+}
+
+/// Statistics of a function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FnStats {
+    pub calls: u64,
+    /// Time from calls start until they return.
+    ///
+    /// Recursive calls are only counted once.
+    pub inclusive: Duration,
+    /// Time spent in the function, not counting calls to other functions.
+    pub exclusive: Duration,
+}
+
+/// Statistics of a source line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineStats {
+    /// Number of expressions evaluated on the line.
+    pub hits: u64,
+    /// Time from evaluating an expression on the line until the next expression.
+    pub time: Duration,
+}
+
+/// A function call being measured.
+struct Frame {
+    key: usize,
+    start: Instant,
+    /// Time spent in calls to other functions.
+    children: Duration,
+}
+
+/// Counts ticks from a timer thread.
+struct Sampler {
+    interval: Duration,
+    ticks: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    /// The ticks counted at the last sample.
+    last: usize,
+}
+
+/// Records time spent in functions.
+///
+/// Threads started with `go` are not profiled.
+pub struct Profiler {
+    /// Functions that have been called, indexed by `functions`.
+    pub keys: Vec<FnKey>,
+    /// Statistics of functions.
+    pub functions: Vec<FnStats>,
+    /// Exclusive time by stack of calls, using indices of functions.
+    pub stacks: HashMap<Vec<usize>, Duration>,
+    /// Statistics by file and line, counting from 1, when profiling lines.
+    pub lines: Option<HashMap<(Arc<String>, usize), LineStats>>,
+    index: HashMap<FnKey, usize>,
+    frames: Vec<Frame>,
+    path: Vec<usize>,
+    /// The last line and when its expression was evaluated.
+    last_line: Option<((Arc<String>, usize), Instant)>,
+    /// Offsets where lines start, by address of source.
+    line_starts: HashMap<usize, Vec<usize>>,
+    sampler: Option<Sampler>,
+}
+
+impl Profiler {
+    /// Creates a new profiler.
+    ///
+    /// Profiling lines calls the profiler before each expression,
+    /// which makes programs run slower.
+    pub fn new(lines: bool) -> Profiler {
+        Profiler {
+            keys: vec![],
+            functions: vec![],
+            stacks: HashMap::new(),
+            lines: if lines { Some(HashMap::new()) } else { None },
+            index: HashMap::new(),
+            frames: vec![],
+            path: vec![],
+            last_line: None,
+            line_starts: HashMap::new(),
+            sampler: None,
+        }
+    }
+
+    /// Creates a new profiler that samples the stack of calls at interval.
+    ///
+    /// Calls are still counted, but the time of a call is not measured.
+    /// Time spent in a function is estimated from the ticks
+    /// counted while it is on top of the stack of calls.
+    pub fn sampling(interval: Duration) -> Profiler {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        {
+            let ticks = ticks.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(interval);
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+        let mut profiler = Profiler::new(false);
+        profiler.sampler = Some(Sampler {
+            interval: interval,
+            ticks: ticks,
+            stop: stop,
+            last: 0,
+        });
+        profiler
+    }
+
+    /// Returns the sampling interval, or `None` if calls are measured.
+    pub fn interval(&self) -> Option<Duration> {
+        self.sampler.as_ref().map(|s| s.interval)
+    }
+
+    /// Starts measuring a call to function.
+    pub fn enter(&mut self, f: &ast::Function) {
+        let key = FnKey {
+            namespace: f.namespace.clone(),
+            name: f.name.clone(),
+            file: f.file.clone(),
+        };
+        let key = match self.index.get(&key) {
+            Some(&i) => i,
+            None => {
+                let i = self.keys.len();
+                self.index.insert(key.clone(), i);
+                self.keys.push(key);
+                self.functions.push(FnStats {
+                    calls: 0,
+                    inclusive: Duration::new(0, 0),
+                    exclusive: Duration::new(0, 0),
+                });
+                i
+            }
+        };
+        self.functions[key].calls += 1;
+        if self.sampler.is_some() {
+            self.sample();
+            self.path.push(key);
+            return;
+        }
+        self.path.push(key);
+        self.frames.push(Frame {
+            key: key,
+            start: Instant::now(),
+            children: Duration::new(0, 0),
+        });
+    }
+
+    /// Stops measuring the last call.
+    pub fn exit(&mut self) {
+        if self.sampler.is_some() {
+            self.sample();
+            self.path.pop();
+            return;
+        }
+        let frame = match self.frames.pop() {
+            None => return,
+            Some(x) => x
+        };
+        let elapsed = frame.start.elapsed();
+        let exclusive = if elapsed > frame.children { elapsed - frame.children }
+            else { Duration::new(0, 0) };
+        {
+            let stats = &mut self.functions[frame.key];
+            stats.exclusive += exclusive;
+            if !self.frames.iter().any(|f| f.key == frame.key) {
+                stats.inclusive += elapsed;
+            }
+        }
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += elapsed;
+        }
+        let found = match self.stacks.get_mut(&self.path[..]) {
+            None => false,
+            Some(time) => {
+                *time += exclusive;
+                true
+            }
+        };
+        if !found {
+            self.stacks.insert(self.path.clone(), exclusive);
+        }
+        self.path.pop();
+    }
+
+    /// Adds the time of ticks counted since the last sample to the current stack of calls.
+    fn sample(&mut self) {
+        let time = match self.sampler {
+            None => return,
+            Some(ref mut sampler) => {
+                let ticks = sampler.ticks.load(Ordering::Relaxed);
+                let n = ticks.wrapping_sub(sampler.last);
+                sampler.last = ticks;
+                if n == 0 { return }
+                sampler.interval * n as u32
+            }
+        };
+        let key = match self.path.last() {
+            None => return,
+            Some(&x) => x
+        };
+        self.functions[key].exclusive += time;
+        for (i, &f) in self.path.iter().enumerate() {
+            // Recursive calls are only counted once.
+            if !self.path[..i].contains(&f) {
+                self.functions[f].inclusive += time;
+            }
+        }
+        let found = match self.stacks.get_mut(&self.path[..]) {
+            None => false,
+            Some(x) => {
+                *x += time;
+                true
+            }
+        };
+        if !found {
+            self.stacks.insert(self.path.clone(), time);
+        }
+    }
+
+    /// Records that an expression is evaluated at offset in source.
+    pub fn expression(&mut self, file: &Arc<String>, source: &Arc<String>, offset: usize) {
+        let now = Instant::now();
+        let lines = match self.lines {
+            None => return,
+            Some(ref mut x) => x
+        };
+        let starts = self.line_starts.entry(&**source as *const String as usize)
+            .or_insert_with(|| {
+                let mut starts = vec![0];
+                starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+                starts
+            });
+        let line = match starts.binary_search(&offset) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        if let Some((key, start)) = self.last_line.take() {
+            if let Some(stats) = lines.get_mut(&key) {
+                stats.time += now - start;
+            }
+        }
+        let key = (file.clone(), line);
+        lines.entry(key.clone()).or_insert(LineStats {
+            hits: 0,
+            time: Duration::new(0, 0),
+        }).hits += 1;
+        self.last_line = Some((key, now));
+    }
+
+    /// Writes exclusive time in microseconds by stack of calls,
+    /// one stack per line, e.g. `main;foo;bar 120`.
+    ///
+    /// This is the folded format used by flamegraph tools.
+    pub fn write_folded<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self.stacks.iter().map(|(path, time)| {
+            let names: Vec<String> = path.iter().map(|&i| self.keys[i].path()).collect();
+            (names.join(";"), micros(*time))
+        }).collect();
+        stacks.sort();
+        for (stack, time) in stacks {
+            try!(writeln!(w, "{} {}", stack, time));
+        }
+        Ok(())
+    }
+
+    /// Returns a report of functions sorted by exclusive time,
+    /// followed by lines sorted by time when profiling lines.
+    pub fn report(&self) -> String {
+        let mut order: Vec<usize> = (0..self.keys.len()).collect();
+        order.sort_by(|&a, &b| self.functions[b].exclusive.cmp(&self.functions[a].exclusive)
+            .then_with(|| self.keys[a].path().cmp(&self.keys[b].path())));
+        let mut s = match self.interval() {
+            None => String::new(),
+            Some(interval) => format!("Sampled every {:.3} ms\n\n", millis(interval)),
+        };
+        s.push_str(&format!("{:>10} {:>14} {:>14}  {}\n",
+            "calls", "inclusive ms", "exclusive ms", "function"));
+        for i in order {
+            let stats = &self.functions[i];
+            s.push_str(&format!("{:>10} {:>14.3} {:>14.3}  {} ({})\n",
+                stats.calls, millis(stats.inclusive), millis(stats.exclusive),
+                self.keys[i].path(), self.keys[i].file));
+        }
+        if let Some(ref lines) = self.lines {
+            let mut lines: Vec<(&(Arc<String>, usize), &LineStats)> = lines.iter().collect();
+            lines.sort_by(|a, b| b.1.time.cmp(&a.1.time).then_with(|| a.0.cmp(b.0)));
+            s.push_str(&format!("\n{:>10} {:>14}  {}\n", "hits", "time ms", "line"));
+            for (&(ref file, line), stats) in lines {
+                s.push_str(&format!("{:>10} {:>14.3}  {}:{}\n",
+                    stats.hits, millis(stats.time), file, line));
+            }
+        }
+        s
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        if let Some(ref sampler) = self.sampler {
+            sampler.stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+fn micros(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000 + (d.subsec_nanos() / 1_000) as u64
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1_000_000.0
+}
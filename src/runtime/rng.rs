@@ -0,0 +1,48 @@
+//! Deterministic random number generator.
+
+use rand;
+
+/// Random number generator with a state that can be saved and restored.
+///
+/// Uses SplitMix64, which gives the same numbers on every platform.
+/// Replay a run by saving `state` and assigning it back later.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rng {
+    /// The state, which changes for each number.
+    pub state: u64,
+}
+
+impl Rng {
+    /// Creates a new generator from a seed.
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Creates a new generator seeded from the operating system.
+    pub fn from_os() -> Rng {
+        Rng::new(rand::random())
+    }
+
+    /// Creates a child generator with a seed derived from this generator.
+    ///
+    /// Used by threads started with `go`.
+    pub fn child(&mut self) -> Rng {
+        use rand::Rng as RandRng;
+
+        Rng::new(self.next_u64())
+    }
+}
+
+impl rand::Rng for Rng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
@@ -0,0 +1,178 @@
+//! Runs functions declared with `test fn`.
+//!
+//! Each test is called in a fresh runtime, so tests do not share state.
+//! A test fails when it reports a runtime error,
+//! or when it returns `err(_)`, `none()` or `false`.
+//! Errors returned with `?` include the trace of where they were propagated.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use range::Range;
+
+use ast;
+use write::{write_variable, EscapeString};
+use {DyonError, ErrorKind, FnIndex, Module, Runtime, Variable};
+
+/// The result of running a test.
+#[derive(Clone, Debug)]
+pub struct TestResult {
+    pub name: Arc<String>,
+    pub file: Arc<String>,
+    /// The error message when the test failed.
+    pub result: Result<(), String>,
+    pub duration: Duration,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool { self.result.is_ok() }
+}
+
+/// Returns indices of test functions with names containing filter,
+/// in the order they were loaded.
+pub fn collect(module: &Module, filter: &str) -> Vec<usize> {
+    module.functions.iter().enumerate()
+        .filter(|&(_, f)| f.test && f.name.contains(filter))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Runs a test function in a new runtime.
+pub fn run_test(module: &Arc<Module>, index: usize) -> Result<(), String> {
+    use std::cell::Cell;
+
+    let f = &module.functions[index];
+    if f.args.len() != 0 {
+        let err = DyonError::new(ErrorKind::Runtime,
+                                 "Test functions should not have arguments".into())
+            .range(f.args[0].source_range)
+            .source(f.file.clone(), f.source.clone());
+        return Err(format!("{}", err));
+    }
+    let call = ast::Call {
+        alias: None,
+        name: f.name.clone(),
+        f_index: Cell::new(FnIndex::Loaded(index as isize)),
+        args: vec![],
+        custom_source: None,
+        source_range: Range::empty(0),
+    };
+    let mut rt = Runtime::new();
+    let v = match rt.call(&call, module) {
+        Ok((v, _)) => v,
+        Err(err) => return Err(format!("{}", err)),
+    };
+    let v = match v {
+        None => return Ok(()),
+        Some(v) => v
+    };
+    match *rt.resolve(&v) {
+        Variable::Result(Err(ref err)) => {
+            let mut w: Vec<u8> = vec![];
+            write_variable(&mut w, &rt, &err.message, EscapeString::None, 0).unwrap();
+            let mut msg = String::from_utf8(w).unwrap();
+            for t in &err.trace {
+                msg.push('\n');
+                msg.push_str(t);
+            }
+            Err(msg)
+        }
+        Variable::Option(None) => Err("Returned `none()`".into()),
+        Variable::Bool(false, _) => Err("Returned `false`".into()),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the message of a panic.
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "Unknown panic".into()
+    };
+    format!("Test panicked: {}", msg)
+}
+
+/// Runs tests in parallel threads.
+///
+/// Returns results in the same order as the tests.
+/// A test that panics fails with the panic message, and the other tests continue.
+pub fn run(module: &Arc<Module>, tests: &[usize], threads: usize) -> Vec<TestResult> {
+    let next = Arc::new(Mutex::new(0));
+    let tests = Arc::new(tests.to_vec());
+    let (tx, rx) = channel();
+    let threads = threads.max(1).min(tests.len());
+    let mut handles = vec![];
+    for _ in 0..threads {
+        let next = next.clone();
+        let tests = tests.clone();
+        let module = module.clone();
+        let tx = tx.clone();
+        handles.push(thread::spawn(move || {
+            loop {
+                let i = {
+                    let mut next = next.lock().unwrap();
+                    let i = *next;
+                    *next += 1;
+                    i
+                };
+                let index = match tests.get(i) {
+                    None => break,
+                    Some(&x) => x
+                };
+                let start = Instant::now();
+                let result = match panic::catch_unwind(AssertUnwindSafe(|| {
+                    run_test(&module, index)
+                })) {
+                    Ok(result) => result,
+                    Err(payload) => Err(panic_message(payload)),
+                };
+                let f = &module.functions[index];
+                tx.send((i, TestResult {
+                    name: f.name.clone(),
+                    file: f.file.clone(),
+                    result: result,
+                    duration: start.elapsed(),
+                })).unwrap();
+            }
+        }));
+    }
+    drop(tx);
+    let mut results: Vec<(usize, TestResult)> = rx.iter().collect();
+    for handle in handles {
+        // Panics in tests are caught, so the threads only finish normally.
+        let _ = handle.join();
+    }
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, res)| res).collect()
+}
+
+/// Returns a report with a line per test,
+/// followed by the errors of failed tests and a summary.
+pub fn report(results: &[TestResult]) -> String {
+    let mut s = String::new();
+    for res in results {
+        s.push_str(&format!("test {} ... {}\n", res.name,
+            if res.passed() { "ok" } else { "FAILED" }));
+    }
+    let failed: Vec<&TestResult> = results.iter().filter(|res| !res.passed()).collect();
+    if failed.len() > 0 {
+        s.push_str("\nfailures:\n");
+        for res in &failed {
+            if let Err(ref msg) = res.result {
+                s.push_str(&format!("\n---- {} ({}) ----\n{}\n",
+                    res.name, res.file, msg.trim_end()));
+            }
+        }
+    }
+    s.push_str(&format!("\ntest result: {}. {} passed; {} failed\n",
+        if failed.len() == 0 { "ok" } else { "FAILED" },
+        results.len() - failed.len(), failed.len()));
+    s
+}
@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::collections::HashMap;
 
 use piston_meta::bootstrap::Convert;
 use range::Range;
@@ -13,20 +14,33 @@ pub enum Type {
     Any,
     Bool,
     F64,
+    /// Signed 64 bit integer.
+    I64,
     Vec4,
     Text,
+    /// Buffer of bytes.
+    Bytes,
     Link,
     Array(Box<Type>),
-    // Object(HashMap<Arc<String>, Type>),
     Object,
+    /// Map with key and value types.
+    Map(Box<Type>, Box<Type>),
+    /// Named record type with fields in declaration order.
+    Record(Arc<String>, Arc<Vec<(Arc<String>, Type)>>),
+    /// Named enum type with variants and their payload types in declaration order.
+    Enum(Arc<String>, Arc<Vec<(Arc<String>, Vec<Type>)>>),
     // Rust(Arc<String>),
     Option(Box<Type>),
     Result(Box<Type>),
     Secret(Box<Type>),
     Thread(Box<Type>),
     In(Box<Type>),
+    /// Lazy sequence with item type.
+    Iter(Box<Type>),
     AdHoc(Arc<String>, Box<Type>),
     Closure(Box<Dfn>),
+    /// Type variable of a generic function, e.g. `T` in `fn first<T>(xs: [T]) -> opt[T]`.
+    Generic(Arc<String>),
 }
 
 impl Type {
@@ -39,8 +53,10 @@ impl Type {
             &Any => "any".into(),
             &Bool => "bool".into(),
             &F64 => "f64".into(),
+            &I64 => "i64".into(),
             &Vec4 => "vec4".into(),
             &Text => "str".into(),
+            &Bytes => "bytes".into(),
             &Link => "link".into(),
             &Array(ref ty) => {
                 if let Any = **ty {
@@ -53,6 +69,20 @@ impl Type {
                 }
             }
             &Object => "{}".into(),
+            &Map(ref key, ref value) => {
+                if let (&Any, &Any) = (&**key, &**value) {
+                    "map".into()
+                } else {
+                    let mut res = String::from("map[");
+                    res.push_str(&key.description());
+                    res.push_str(", ");
+                    res.push_str(&value.description());
+                    res.push(']');
+                    res
+                }
+            }
+            &Record(ref name, _) => (**name).clone(),
+            &Enum(ref name, _) => (**name).clone(),
             &Option(ref ty) => {
                 if let Any = **ty {
                     "opt".into()
@@ -100,6 +130,16 @@ impl Type {
                     res
                 }
             }
+            &Iter(ref ty) => {
+                if let Any = **ty {
+                    "iter".into()
+                } else {
+                    let mut res = String::from("iter[");
+                    res.push_str(&ty.description());
+                    res.push(']');
+                    res
+                }
+            }
             &AdHoc(ref ad, ref ty) => {
                 (&**ad).clone() + " " + &ty.description()
             }
@@ -116,6 +156,7 @@ impl Type {
                 s.push_str(&closure.ret.description());
                 s
             }
+            &Generic(ref name) => (**name).clone(),
         }
     }
 
@@ -127,6 +168,10 @@ impl Type {
         Type::Object
     }
 
+    pub fn map() -> Type {
+        Type::Map(Box::new(Type::Any), Box::new(Type::Any))
+    }
+
     pub fn option() -> Type {
         Type::Option(Box::new(Type::Any))
     }
@@ -143,6 +188,271 @@ impl Type {
         Type::In(Box::new(Type::Any))
     }
 
+    pub fn iter() -> Type {
+        Type::Iter(Box::new(Type::Any))
+    }
+
+    /// Returns the name and fields of a record type, looking through ad-hoc types.
+    pub fn record(&self) -> Option<(&Arc<String>, &[(Arc<String>, Type)])> {
+        match self {
+            &Type::Record(ref name, ref fields) => Some((name, fields)),
+            &Type::AdHoc(_, ref ty) => ty.record(),
+            _ => None
+        }
+    }
+
+    /// Returns the name and variants of an enum type, looking through ad-hoc types.
+    pub fn variants(&self) -> Option<(&Arc<String>, &[(Arc<String>, Vec<Type>)])> {
+        match self {
+            &Type::Enum(ref name, ref variants) => Some((name, variants)),
+            &Type::AdHoc(_, ref ty) => ty.variants(),
+            _ => None
+        }
+    }
+
+    /// Replaces ad-hoc types without inner type by type variables of the same name.
+    pub fn resolve_generics(&mut self, generics: &[Arc<String>]) {
+        use self::Type::*;
+
+        match *self {
+            AdHoc(ref name, ref ty) if **ty == Object && generics.contains(name) => {
+                *self = Generic(name.clone());
+            }
+            Array(ref mut ty) | Option(ref mut ty) | Result(ref mut ty) |
+            Thread(ref mut ty) | In(ref mut ty) | Iter(ref mut ty) |
+            AdHoc(_, ref mut ty) => {
+                ty.resolve_generics(generics)
+            }
+            Map(ref mut key, ref mut value) => {
+                key.resolve_generics(generics);
+                value.resolve_generics(generics);
+            }
+            Closure(ref mut dfn) => {
+                for ty in &mut dfn.tys { ty.resolve_generics(generics) }
+                dfn.ret.resolve_generics(generics);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns `true` if the type contains type variables.
+    pub fn has_generics(&self) -> bool {
+        use self::Type::*;
+
+        match *self {
+            Generic(_) => true,
+            Array(ref ty) | Option(ref ty) | Result(ref ty) | Secret(ref ty) |
+            Thread(ref ty) | In(ref ty) | Iter(ref ty) | AdHoc(_, ref ty) => ty.has_generics(),
+            Map(ref key, ref value) => key.has_generics() || value.has_generics(),
+            Closure(ref dfn) => dfn.tys.iter().any(|ty| ty.has_generics()) ||
+                                dfn.ret.has_generics(),
+            _ => false
+        }
+    }
+
+    /// Collects names of type variables in the type.
+    pub fn generics(&self, names: &mut Vec<Arc<String>>) {
+        use self::Type::*;
+
+        match *self {
+            Generic(ref name) => {
+                if !names.contains(name) { names.push(name.clone()) }
+            }
+            Array(ref ty) | Option(ref ty) | Result(ref ty) | Secret(ref ty) |
+            Thread(ref ty) | In(ref ty) | Iter(ref ty) | AdHoc(_, ref ty) => ty.generics(names),
+            Map(ref key, ref value) => {
+                key.generics(names);
+                value.generics(names);
+            }
+            Closure(ref dfn) => {
+                for ty in &dfn.tys { ty.generics(names) }
+                dfn.ret.generics(names);
+            }
+            _ => {}
+        }
+    }
+
+    /// Binds type variables of a parameter type to an argument type.
+    ///
+    /// Returns `false` if the argument type does not go with the parameter type.
+    /// A type variable bound to `any` is rebound when a more specific type is found.
+    pub fn bind_generics(&self, other: &Type, bindings: &mut Vec<(Arc<String>, Type)>) -> bool {
+        use self::Type::*;
+
+        if !self.has_generics() { return self.goes_with(other); }
+        match (self, other) {
+            (_, &Void) => false,
+            (&Generic(ref name), _) => {
+                match bindings.iter().position(|&(ref n, _)| n == name) {
+                    None => {
+                        bindings.push((name.clone(), other.clone()));
+                        true
+                    }
+                    Some(i) => {
+                        if bindings[i].1 == Any {
+                            bindings[i].1 = other.clone();
+                            true
+                        } else {
+                            bindings[i].1.goes_with(other)
+                        }
+                    }
+                }
+            }
+            (_, &Any) | (_, &Unreachable) => true,
+            (&Array(ref a), &Array(ref b)) |
+            (&Option(ref a), &Option(ref b)) |
+            (&Result(ref a), &Result(ref b)) |
+            (&Thread(ref a), &Thread(ref b)) |
+            (&In(ref a), &In(ref b)) |
+            (&Iter(ref a), &Iter(ref b)) => a.bind_generics(b, bindings),
+            (&Map(ref a_key, ref a), &Map(ref b_key, ref b)) => {
+                a_key.bind_generics(b_key, bindings) && a.bind_generics(b, bindings)
+            }
+            (&AdHoc(ref name, ref a), &AdHoc(ref other_name, ref b)) => {
+                name == other_name && a.bind_generics(b, bindings)
+            }
+            (&AdHoc(_, ref a), _) => a.bind_generics(other, bindings),
+            (&Closure(ref a), &Closure(ref b)) => {
+                a.tys.len() == b.tys.len() &&
+                a.tys.iter().zip(b.tys.iter()).all(|(a, b)| a.bind_generics(b, bindings)) &&
+                a.ret.bind_generics(&b.ret, bindings)
+            }
+            _ => false
+        }
+    }
+
+    /// Replaces type variables with bound types, or `any` when not bound.
+    pub fn substitute(&self, bindings: &[(Arc<String>, Type)]) -> Type {
+        use self::Type::*;
+
+        match *self {
+            Generic(ref name) => {
+                bindings.iter().find(|&&(ref n, _)| n == name)
+                    .map(|&(_, ref ty)| ty.clone()).unwrap_or(Any)
+            }
+            Array(ref ty) => Array(Box::new(ty.substitute(bindings))),
+            Option(ref ty) => Option(Box::new(ty.substitute(bindings))),
+            Result(ref ty) => Result(Box::new(ty.substitute(bindings))),
+            Thread(ref ty) => Thread(Box::new(ty.substitute(bindings))),
+            In(ref ty) => In(Box::new(ty.substitute(bindings))),
+            Iter(ref ty) => Iter(Box::new(ty.substitute(bindings))),
+            Map(ref key, ref value) => Map(Box::new(key.substitute(bindings)),
+                                           Box::new(value.substitute(bindings))),
+            AdHoc(ref name, ref ty) => AdHoc(name.clone(), Box::new(ty.substitute(bindings))),
+            Closure(ref dfn) => Closure(Box::new(Dfn {
+                lts: dfn.lts.clone(),
+                tys: dfn.tys.iter().map(|ty| ty.substitute(bindings)).collect(),
+                ret: dfn.ret.substitute(bindings),
+            })),
+            ref x => x.clone()
+        }
+    }
+
+    /// Replaces ad-hoc types without inner type by records or enums of the same name.
+    ///
+    /// For example, `Player` is parsed as an ad-hoc type `Player {}`,
+    /// which refers to the record `Player` when it is declared.
+    pub fn resolve_records(&mut self, records: &HashMap<Arc<String>, Type>) {
+        use self::Type::*;
+
+        match *self {
+            AdHoc(ref name, ref ty) if **ty == Object => {
+                if let Some(record) = records.get(name) {
+                    *self = record.clone();
+                }
+            }
+            Array(ref mut ty) | Option(ref mut ty) | Result(ref mut ty) |
+            Thread(ref mut ty) | In(ref mut ty) | Iter(ref mut ty) |
+            AdHoc(_, ref mut ty) => {
+                ty.resolve_records(records)
+            }
+            Map(ref mut key, ref mut value) => {
+                key.resolve_records(records);
+                value.resolve_records(records);
+            }
+            Closure(ref mut dfn) => {
+                for ty in &mut dfn.tys { ty.resolve_records(records) }
+                dfn.ret.resolve_records(records);
+            }
+            _ => {}
+        }
+    }
+
+    /// Resolves field types of records and payload types of enums declared together.
+    ///
+    /// A record or enum referring to itself, directly or through other declarations,
+    /// keeps the inner reference as an ad-hoc type.
+    pub fn resolve_record_fields(records: &mut HashMap<Arc<String>, Type>) {
+        fn resolve(
+            name: &Arc<String>,
+            decls: &HashMap<Arc<String>, Type>,
+            visiting: &mut Vec<Arc<String>>
+        ) -> Type {
+            match decls.get(name) {
+                Some(&Type::Record(ref name, ref fields)) => {
+                    visiting.push(name.clone());
+                    let mut resolved = vec![];
+                    for &(ref key, ref ty) in fields.iter() {
+                        let mut ty = ty.clone();
+                        resolve_type(&mut ty, decls, visiting);
+                        resolved.push((key.clone(), ty));
+                    }
+                    visiting.pop();
+                    Type::Record(name.clone(), Arc::new(resolved))
+                }
+                Some(&Type::Enum(ref name, ref variants)) => {
+                    visiting.push(name.clone());
+                    let mut resolved = vec![];
+                    for &(ref variant, ref tys) in variants.iter() {
+                        let mut tys = tys.clone();
+                        for ty in &mut tys {
+                            resolve_type(ty, decls, visiting);
+                        }
+                        resolved.push((variant.clone(), tys));
+                    }
+                    visiting.pop();
+                    Type::Enum(name.clone(), Arc::new(resolved))
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        fn resolve_type(
+            ty: &mut Type,
+            decls: &HashMap<Arc<String>, Type>,
+            visiting: &mut Vec<Arc<String>>
+        ) {
+            use self::Type::*;
+
+            match *ty {
+                AdHoc(ref name, ref inner) if **inner == Object => {
+                    if decls.contains_key(name) && !visiting.contains(name) {
+                        *ty = resolve(name, decls, visiting);
+                    }
+                }
+                Array(ref mut ty) | Option(ref mut ty) | Result(ref mut ty) |
+                Thread(ref mut ty) | In(ref mut ty) | Iter(ref mut ty) |
+                AdHoc(_, ref mut ty) => {
+                    resolve_type(ty, decls, visiting)
+                }
+                Map(ref mut key, ref mut value) => {
+                    resolve_type(key, decls, visiting);
+                    resolve_type(value, decls, visiting);
+                }
+                Closure(ref mut dfn) => {
+                    for ty in &mut dfn.tys { resolve_type(ty, decls, visiting) }
+                    resolve_type(&mut dfn.ret, decls, visiting);
+                }
+                _ => {}
+            }
+        }
+
+        let decls = records.clone();
+        for (name, ty) in records.iter_mut() {
+            *ty = resolve(name, &decls, &mut vec![]);
+        }
+    }
+
     /// Returns `true` if a type goes with another type (directional check).
     ///
     /// - `bool` (argument) goes with `sec[bool]` (value)
@@ -183,12 +493,33 @@ impl Type {
                 }
             }
             &Object => {
-                if let &Object = other {
-                    true
-                } else if let &Any = other {
-                    true
-                } else {
-                    false
+                match other {
+                    &Object | &Record(_, _) | &Any => true,
+                    _ => false
+                }
+            }
+            &Map(ref key, ref value) => {
+                match other {
+                    &Map(ref other_key, ref other_value) => {
+                        key.goes_with(other_key) && value.goes_with(other_value)
+                    }
+                    &Any => true,
+                    _ => false
+                }
+            }
+            &Record(_, ref fields) => {
+                match other {
+                    // Opaque objects are checked when they are object literals.
+                    &Object | &Any => true,
+                    &Record(_, ref other_fields) => {
+                        fields.len() == other_fields.len() &&
+                        fields.iter().all(|&(ref key, ref ty)| {
+                            other_fields.iter().any(|&(ref other_key, ref other_ty)| {
+                                key == other_key && ty.goes_with(other_ty)
+                            })
+                        })
+                    }
+                    _ => false
                 }
             }
             &Option(ref opt) => {
@@ -227,6 +558,15 @@ impl Type {
                     false
                 }
             }
+            &Iter(ref iter_ty) => {
+                if let &Iter(ref other_ty) = other {
+                    iter_ty.goes_with(other_ty)
+                } else if let &Any = other {
+                    true
+                } else {
+                    false
+                }
+            }
             &Closure(ref cl) => {
                 if let &Closure(ref other_cl) = other {
                     if cl.tys.len() != other_cl.tys.len() { return false; }
@@ -250,7 +590,21 @@ impl Type {
                     ty.goes_with(other)
                 }
             }
-            // Bool, F64, Text, Vec4, AdHoc.
+            &Enum(ref name, _) => {
+                match other {
+                    &Enum(ref other_name, _) => name == other_name,
+                    &Any => true,
+                    _ => false
+                }
+            }
+            &Generic(ref name) => {
+                match other {
+                    &Generic(ref other_name) => name == other_name,
+                    &Any => true,
+                    _ => false
+                }
+            }
+            // Bool, F64, I64, Text, Bytes, Vec4, AdHoc.
             x if x == other => { true }
             _ if *other == Type::Any => { true }
             _ => { false }
@@ -284,12 +638,15 @@ impl Type {
                 Some(Secret(Box::new(F64))),
             (&Secret(ref a), &F64) if **a == Type::F64 => Some(Secret(Box::new(F64))),
             (&F64, &Secret(ref b)) if **b == Type::F64 => Some(F64),
+            (&I64, &I64) => Some(I64),
             (&Text, &Text) => Some(Text),
             (&Vec4, &F64) => Some(Vec4),
             (&F64, &Vec4) => Some(Vec4),
             (&Vec4, &Vec4) => Some(Vec4),
             (&Any, x) if x != &Type::Void => Some(Any),
             (x, &Any) if x != &Type::Void => Some(Any),
+            (&Generic(_), x) if x != &Type::Void => Some(Any),
+            (x, &Generic(_)) if x != &Type::Void => Some(Any),
             _ => None
         }
     }
@@ -315,6 +672,13 @@ impl Type {
         match (self, other) {
             (&Void, _) | (_, &Void) => None,
             (&Array(_), _) | (_, &Array(_)) => None,
+            (&I64, &I64) => match binop {
+                BinOp::Dot | BinOp::Cross => None,
+                _ => Some(I64)
+            },
+            (&Bool, _) | (&F64, _) | (&Vec4, _) | (&Secret(_), _) |
+            (_, &Bool) | (_, &F64) | (_, &Vec4) | (_, &Secret(_))
+            if binop.is_bitwise() => None,
             (&Bool, &Bool) => Some(Bool),
             (&Secret(ref a), &Secret(ref b))
             if **a == Type::Bool && **b == Type::Bool =>
@@ -338,6 +702,8 @@ impl Type {
             }
             (&Any, x) if x != &Type::Void => Some(Any),
             (x, &Any) if x != &Type::Void => Some(Any),
+            (&Generic(_), x) if x != &Type::Void => Some(Any),
+            (x, &Generic(_)) if x != &Type::Void => Some(Any),
             _ => None
         }
     }
@@ -348,6 +714,7 @@ impl Type {
         match (self, other) {
             (&Void, _) | (_, &Void) => None,
             (&Array(_), _) | (_, &Array(_)) => None,
+            (&I64, &I64) => Some(I64),
             (&Bool, &Bool) => Some(Bool),
             (&Secret(ref a), &Secret(ref b))
             if **a == Type::Bool && **b == Type::Bool =>
@@ -365,6 +732,8 @@ impl Type {
             (&Vec4, &Vec4) => Some(Vec4),
             (&Any, x) if x != &Type::Void => Some(Any),
             (x, &Any) if x != &Type::Void => Some(Any),
+            (&Generic(_), x) if x != &Type::Void => Some(Any),
+            (x, &Generic(_)) if x != &Type::Void => Some(Any),
             _ => None
         }
     }
@@ -392,12 +761,18 @@ impl Type {
             } else if let Ok((range, _)) = convert.meta_bool("f64") {
                 convert.update(range);
                 ty = Some(Type::F64);
+            } else if let Ok((range, _)) = convert.meta_bool("i64") {
+                convert.update(range);
+                ty = Some(Type::I64);
             } else if let Ok((range, _)) = convert.meta_bool("sec_f64") {
                 convert.update(range);
                 ty = Some(Type::Secret(Box::new(Type::F64)));
             } else if let Ok((range, _)) = convert.meta_bool("str") {
                 convert.update(range);
                 ty = Some(Type::Text);
+            } else if let Ok((range, _)) = convert.meta_bool("bytes") {
+                convert.update(range);
+                ty = Some(Type::Bytes);
             } else if let Ok((range, _)) = convert.meta_bool("vec4") {
                 convert.update(range);
                 ty = Some(Type::Vec4);
@@ -422,6 +797,12 @@ impl Type {
             } else if let Ok((range, _)) = convert.meta_bool("in_any") {
                 convert.update(range);
                 ty = Some(Type::In(Box::new(Type::Any)));
+            } else if let Ok((range, _)) = convert.meta_bool("iter_any") {
+                convert.update(range);
+                ty = Some(Type::iter());
+            } else if let Ok((range, _)) = convert.meta_bool("map_any") {
+                convert.update(range);
+                ty = Some(Type::map());
             } else if let Ok((range, val)) = Type::from_meta_data(
                     "opt", convert, ignored) {
                 convert.update(range);
@@ -442,6 +823,16 @@ impl Type {
                     "in", convert, ignored) {
                 convert.update(range);
                 ty = Some(Type::In(Box::new(val)));
+            } else if let Ok((range, val)) = Type::from_meta_data(
+                    "iter", convert, ignored) {
+                convert.update(range);
+                ty = Some(Type::Iter(Box::new(val)));
+            } else if let Ok((range, key)) = Type::from_meta_data(
+                    "map_key", convert, ignored) {
+                convert.update(range);
+                let (range, value) = try!(Type::from_meta_data("map_value", convert, ignored));
+                convert.update(range);
+                ty = Some(Type::Map(Box::new(key), Box::new(value)));
             } else if let Ok((range, val)) = convert.meta_string("ad_hoc") {
                 convert.update(range);
                 let inner_ty = if let Ok((range, val)) = Type::from_meta_data(
@@ -478,4 +869,89 @@ impl Type {
 
         Ok((convert.subtract(start), try!(ty.ok_or(()))))
     }
+
+    /// Reads a record declaration, e.g. `type Player {name: str, goals: f64}`.
+    ///
+    /// Field types are not resolved, see `Type::resolve_record_fields`.
+    pub fn record_from_meta_data(mut convert: Convert, ignored: &mut Vec<Range>)
+    -> Result<(Range, Type), ()> {
+        let start = convert.clone();
+        let node = "record";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut fields = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok(range) = convert.start_node("field") {
+                convert.update(range);
+                let (range, key) = try!(convert.meta_string("name"));
+                convert.update(range);
+                let (range, ty) = try!(Type::from_meta_data("type", convert, ignored));
+                convert.update(range);
+                let range = try!(convert.end_node("field"));
+                convert.update(range);
+                fields.push((key, ty));
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let name = try!(name.ok_or(()));
+        Ok((convert.subtract(start), Type::Record(name, Arc::new(fields))))
+    }
+
+    /// Reads an enum declaration, e.g. `enum Shape {Circle(f64), Rect(f64, f64), Empty}`.
+    ///
+    /// Payload types are not resolved, see `Type::resolve_record_fields`.
+    pub fn enum_from_meta_data(mut convert: Convert, ignored: &mut Vec<Range>)
+    -> Result<(Range, Type), ()> {
+        let start = convert.clone();
+        let node = "enum";
+        let start_range = try!(convert.start_node(node));
+        convert.update(start_range);
+
+        let mut name: Option<Arc<String>> = None;
+        let mut variants = vec![];
+        loop {
+            if let Ok(range) = convert.end_node(node) {
+                convert.update(range);
+                break;
+            } else if let Ok((range, val)) = convert.meta_string("name") {
+                convert.update(range);
+                name = Some(val);
+            } else if let Ok(range) = convert.start_node("variant") {
+                convert.update(range);
+                let (range, variant) = try!(convert.meta_string("name"));
+                convert.update(range);
+                let mut tys = vec![];
+                while let Ok(range) = convert.start_node("payload") {
+                    convert.update(range);
+                    let (range, ty) = try!(Type::from_meta_data("type", convert, ignored));
+                    convert.update(range);
+                    let range = try!(convert.end_node("payload"));
+                    convert.update(range);
+                    tys.push(ty);
+                }
+                let range = try!(convert.end_node("variant"));
+                convert.update(range);
+                variants.push((variant, tys));
+            } else {
+                let range = convert.ignore();
+                convert.update(range);
+                ignored.push(range);
+            }
+        }
+
+        let name = try!(name.ok_or(()));
+        Ok((convert.subtract(start), Type::Enum(name, Arc::new(variants))))
+    }
 }
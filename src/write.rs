@@ -33,6 +33,9 @@ pub fn write_variable<W>(
         Variable::F64(x, _) => {
             try!(write!(w, "{}", x));
         }
+        Variable::I64(x) => {
+            try!(write!(w, "{}", x));
+        }
         Variable::Vec4(v) => {
             try!(write!(w, "({}, {}", v[0], v[1]));
             if v[2] != 0.0 || v[3] != 0.0 {
@@ -93,6 +96,35 @@ pub fn write_variable<W>(
             }
             try!(write!(w, "}}"));
         }
+        Variable::Map(ref map) => {
+            // Uses the tagged encoding that is read by `load_data`,
+            // with entries sorted by key.
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            try!(write!(w, "{{\"$map\": ["));
+            for (i, &(k, v)) in entries.iter().enumerate() {
+                if i > 0 {
+                    try!(write!(w, ", "));
+                }
+                try!(write!(w, "["));
+                try!(write_variable(w, rt, &k.to_variable(), EscapeString::Json, tabs));
+                try!(write!(w, ", "));
+                try!(write_variable(w, rt, v, EscapeString::Json, tabs));
+                try!(write!(w, "]"));
+            }
+            try!(write!(w, "]}}"));
+        }
+        Variable::Bytes(ref bytes) => {
+            // Written as an array of numbers.
+            try!(write!(w, "["));
+            for (i, b) in bytes.iter().enumerate() {
+                if i > 0 {
+                    try!(write!(w, ", "));
+                }
+                try!(write!(w, "{}", b));
+            }
+            try!(write!(w, "]"));
+        }
         Variable::Array(ref arr) => {
             try!(write!(w, "["));
             let n = arr.len();
@@ -137,6 +169,23 @@ pub fn write_variable<W>(
         Variable::RustObject(_) => try!(write!(w, "_rust_object")),
         Variable::Closure(ref closure, _) => try!(write_closure(w, rt, closure, tabs)),
         Variable::In(_) => try!(write!(w, "_in")),
+        Variable::Iter(_) => try!(write!(w, "_iter")),
+        Variable::Variant(ref v) => {
+            // Uses the tagged encoding that is read by `load_data`.
+            try!(write!(w, "{{\"$enum\": "));
+            try!(json::write_string(w, &v.ty));
+            try!(write!(w, ", \"$variant\": "));
+            try!(json::write_string(w, &v.name));
+            try!(write!(w, ", \"$payload\": ["));
+            let n = v.payload.len();
+            for (i, v) in v.payload.iter().enumerate() {
+                try!(write_variable(w, rt, v, EscapeString::Json, tabs));
+                if i + 1 < n {
+                    try!(write!(w, ", "));
+                }
+            }
+            try!(write!(w, "]}}"));
+        }
         // ref x => panic!("Could not print out `{:?}`", x)
     }
     Ok(())
@@ -268,6 +317,7 @@ pub fn write_expr<W: io::Write>(
             try!(write_for_n(w, rt, for_n, tabs));
         }
         &E::If(ref if_expr) => try!(write_if(w, rt, if_expr, tabs)),
+        &E::Match(ref match_expr) => try!(write_match(w, rt, match_expr, tabs)),
         &E::Norm(ref norm) => try!(write_norm(w, rt, norm, tabs)),
         &E::UnOp(ref unop) => try!(write_unop(w, rt, unop, tabs)),
         &E::Try(ref expr) => {
@@ -323,7 +373,9 @@ fn binop_needs_parens(op: ast::BinOp, expr: &ast::Expression, right: bool) -> bo
         E::Compare(_) => true,
         E::BinOp(ref binop) => {
             match (op.precedence(), binop.op.precedence()) {
-                (3, _) => true,
+                (4, _) => true,
+                (3, 1) | (3, 2) => true,
+                (3, 3) if right => true,
                 (2, 1) => true,
                 (2, 2) if right => true,
                 (1, 1) if right => true,
@@ -467,6 +519,9 @@ pub fn write_call<W: io::Write>(
     call: &ast::Call,
     tabs: u32,
 ) -> Result<(), io::Error> {
+    if let Some(ref alias) = call.alias {
+        try!(write!(w, "{}::", alias));
+    }
     try!(write!(w, "{}(", call.name));
     for (i, arg) in call.args.iter().enumerate() {
         try!(write_expr(w, rt, arg, tabs));
@@ -673,6 +728,16 @@ pub fn write_for_n<W: io::Write>(
     for_n: &ast::ForN,
     tabs: u32
 ) -> Result<(), io::Error> {
+    if let Some(ref for_in) = for_n.for_in {
+        if let Some(ref key) = for_in.key {
+            try!(write!(w, "{}, ", key));
+        }
+        try!(write!(w, "{} in ", for_in.value));
+        try!(write_expr(w, rt, &for_n.end, tabs));
+        try!(write!(w, " "));
+        try!(write_block(w, rt, &for_n.block, tabs + 1));
+        return Ok(());
+    }
     try!(write!(w, "{} ", for_n.name));
     if let Some(ref start) = for_n.start {
         try!(write!(w, "["));
@@ -712,6 +777,97 @@ pub fn write_if<W: io::Write>(
     Ok(())
 }
 
+pub fn write_match<W: io::Write>(
+    w: &mut W,
+    rt: &Runtime,
+    match_expr: &ast::Match,
+    tabs: u32,
+) -> Result<(), io::Error> {
+    try!(write!(w, "match "));
+    try!(write_expr(w, rt, &match_expr.expr, tabs));
+    try!(writeln!(w, " {{"));
+    for arm in &match_expr.arms {
+        try!(write_tabs(w, tabs + 1));
+        try!(write_pattern(w, rt, &arm.pattern, tabs + 1));
+        if let Some(ref guard) = arm.guard {
+            try!(write!(w, " if "));
+            try!(write_expr(w, rt, guard, tabs + 1));
+        }
+        try!(write!(w, " => "));
+        try!(write_expr(w, rt, &arm.expr, tabs + 1));
+        try!(writeln!(w, ","));
+    }
+    try!(write_tabs(w, tabs));
+    try!(write!(w, "}}"));
+    Ok(())
+}
+
+pub fn write_pattern<W: io::Write>(
+    w: &mut W,
+    rt: &Runtime,
+    pattern: &ast::Pattern,
+    tabs: u32,
+) -> Result<(), io::Error> {
+    use ast::Pattern as P;
+
+    match *pattern {
+        P::Wildcard => try!(write!(w, "_")),
+        P::Bind(ref name) => try!(write!(w, "{}", name)),
+        P::Value(ref v) => try!(write_variable(w, rt, v, EscapeString::Json, tabs)),
+        P::Some(ref pat) => {
+            try!(write!(w, "some("));
+            try!(write_pattern(w, rt, pat, tabs));
+            try!(write!(w, ")"));
+        }
+        P::None => try!(write!(w, "none()")),
+        P::Ok(ref pat) => {
+            try!(write!(w, "ok("));
+            try!(write_pattern(w, rt, pat, tabs));
+            try!(write!(w, ")"));
+        }
+        P::Err(ref pat) => {
+            try!(write!(w, "err("));
+            try!(write_pattern(w, rt, pat, tabs));
+            try!(write!(w, ")"));
+        }
+        P::Object(ref key_patterns, rest) => {
+            try!(write!(w, "{{"));
+            for (i, &(ref key, ref pat)) in key_patterns.iter().enumerate() {
+                if i > 0 { try!(write!(w, ", ")); }
+                try!(write!(w, "{}: ", key));
+                try!(write_pattern(w, rt, pat, tabs));
+            }
+            if rest {
+                if key_patterns.len() > 0 { try!(write!(w, ", ")); }
+                try!(write!(w, ".."));
+            }
+            try!(write!(w, "}}"));
+        }
+        P::Array(ref items, rest) => {
+            try!(write!(w, "["));
+            for (i, pat) in items.iter().enumerate() {
+                if i > 0 { try!(write!(w, ", ")); }
+                if rest == Some(i) { try!(write!(w, ".., ")); }
+                try!(write_pattern(w, rt, pat, tabs));
+            }
+            if rest == Some(items.len()) {
+                if items.len() > 0 { try!(write!(w, ", ")); }
+                try!(write!(w, ".."));
+            }
+            try!(write!(w, "]"));
+        }
+        P::Variant(ref ty, ref name, ref items) => {
+            try!(write!(w, "{}::{}(", ty, name));
+            for (i, pat) in items.iter().enumerate() {
+                if i > 0 { try!(write!(w, ", ")); }
+                try!(write_pattern(w, rt, pat, tabs));
+            }
+            try!(write!(w, ")"));
+        }
+    }
+    Ok(())
+}
+
 pub fn write_grab<W: io::Write>(
     w: &mut W,
     rt: &Runtime,
@@ -15,13 +15,26 @@ pub fn test_fail_src(source: &str) {
     match load(source, &mut module) {
         Ok(_) => panic!("`{}` should fail", source),
         Err(err) => {
-            if err.starts_with(&format!("Could not open `{}`", source)) {
+            if err.kind == ErrorKind::Io {
                 panic!("{}", err)
             }
         }
     };
 }
 
+/// Runs `main` with setup followed by expression,
+/// and returns the message of the runtime error.
+pub fn run_fail_main(setup: &str, expr: &str) -> String {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(format!("fn main() {{\n    {}\n    {}\n}}\n", setup, expr)),
+        &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.call_str("main", &[], &module).unwrap_err().message
+}
+
 pub fn debug_src(source: &str) {
     let mut module = Module::new();
     load(source, &mut module).unwrap_or_else(|err| {
@@ -84,6 +97,12 @@ fn test_syntax() {
     test_src("source/syntax/accessor.dyon");
     test_src("source/syntax/sum.dyon");
     test_src("source/syntax/link_for.dyon");
+    test_src("source/syntax/for_in.dyon");
+    test_src("source/syntax/iter.dyon");
+    test_src("source/syntax/text.dyon");
+    test_src("source/syntax/array_utils.dyon");
+    test_fail_src("source/syntax/array_utils_2.dyon");
+    test_src("source/syntax/math.dyon");
     test_src("source/syntax/min_max.dyon");
     test_src("source/syntax/vec4.dyon");
     test_src("source/syntax/vec4_2.dyon");
@@ -116,6 +135,8 @@ fn test_syntax() {
     test_src("source/syntax/closure_6.dyon");
     test_src("source/syntax/or.dyon");
     test_src("source/syntax/try_expr.dyon");
+    test_fail_src("source/syntax/match_2.dyon");
+    test_fail_src("source/syntax/match_3.dyon");
     test_src("source/syntax/start_true.dyon");
     test_fail_src("source/syntax/push_ref.dyon");
 }
@@ -194,6 +215,50 @@ fn test_typechk() {
     test_src("source/typechk/dot.dyon");
     test_src("source/typechk/in.dyon");
     test_fail_src("source/typechk/in_2.dyon");
+    test_fail_src("source/typechk/match.dyon");
+    test_fail_src("source/typechk/match_2.dyon");
+    test_src("source/typechk/record.dyon");
+    test_fail_src("source/typechk/record_2.dyon");
+    test_fail_src("source/typechk/record_3.dyon");
+    test_fail_src("source/typechk/record_4.dyon");
+    test_src("source/typechk/generic.dyon");
+    test_fail_src("source/typechk/generic_2.dyon");
+    test_fail_src("source/typechk/generic_3.dyon");
+    test_fail_src("source/typechk/generic_4.dyon");
+    test_src("source/typechk/enum.dyon");
+    test_fail_src("source/typechk/enum_2.dyon");
+    test_fail_src("source/typechk/enum_3.dyon");
+    test_fail_src("source/typechk/enum_4.dyon");
+    test_src("source/typechk/enum_5.dyon");
+    test_fail_src("source/typechk/enum_6.dyon");
+    test_src("source/typechk/i64.dyon");
+    test_fail_src("source/typechk/i64_2.dyon");
+    test_fail_src("source/typechk/i64_3.dyon");
+    test_src("source/typechk/bytes.dyon");
+    test_fail_src("source/typechk/bytes_2.dyon");
+    test_src("source/typechk/map.dyon");
+    test_fail_src("source/typechk/map_2.dyon");
+    test_fail_src("source/typechk/map_3.dyon");
+    test_fail_src("source/typechk/map_4.dyon");
+    test_src("source/typechk/map_5.dyon");
+    test_fail_src("source/typechk/map_6.dyon");
+    test_fail_src("source/typechk/map_7.dyon");
+    test_src("source/typechk/for_in.dyon");
+    test_fail_src("source/typechk/for_in_2.dyon");
+    test_fail_src("source/typechk/for_in_3.dyon");
+    test_src("source/typechk/iter.dyon");
+    test_fail_src("source/typechk/iter_2.dyon");
+    test_fail_src("source/typechk/iter_3.dyon");
+    test_src("source/typechk/text.dyon");
+    test_fail_src("source/typechk/text_2.dyon");
+    test_fail_src("source/typechk/text_3.dyon");
+    test_fail_src("source/typechk/text_4.dyon");
+    test_fail_src("source/typechk/text_5.dyon");
+    test_fail_src("source/typechk/sort_by.dyon");
+    test_fail_src("source/typechk/math.dyon");
+    test_fail_src("source/typechk/math_2.dyon");
+    test_fail_src("source/typechk/math_3.dyon");
+    test_src("source/typechk/math_4.dyon");
 }
 
 #[test]
@@ -211,3 +276,990 @@ fn test_error() {
     test_src("source/error/unwrap_err.dyon");
     test_src("source/error/option.dyon");
 }
+
+#[test]
+fn test_bytecode() {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    load("source/bytecode/flow.dyon", &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    let mut check = |name: &str, expected: f64| {
+        let val: f64 = Call::new(name).run_ret(&mut rt, &module)
+            .unwrap_or_else(|err| panic!("{}", err));
+        assert_eq!(val, expected, "{}", name);
+    };
+    check("labeled_break", 15.0);
+    check("loops", 14.0 + 12.0 + 1.0 + 5.0 + 3.0 + 3.0);
+    check("try_op", 12.0);
+    check("grab_closure", 5.0);
+    check("items", 13.0 + 6.0 + 2.0 + 1.0);
+}
+
+#[test]
+fn test_error_kind() {
+    use std::sync::Arc;
+
+    let kind = |src: &str| {
+        let mut module = Module::new();
+        load_str("main.dyon", Arc::new(src.into()), &mut module).unwrap_err().kind
+    };
+    assert_eq!(kind("fn main() {"), ErrorKind::Syntax);
+    assert_eq!(kind("fn foo(a) -> { return a }"), ErrorKind::Lifetime);
+    assert_eq!(kind("fn foo() -> bool { return 2 }"), ErrorKind::Type);
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(r#"
+fn outer() -> { return inner([1]) }
+fn inner(a: []) -> { return clone(a[2]) }
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let err = Call::new("outer").run_ret::<f64>(&mut Runtime::new(), &module).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Runtime);
+    assert_eq!(err.file, Some(Arc::new("main.dyon".into())));
+    assert_eq!(err.line_column(), Some((3, 37)));
+    let names: Vec<&str> = err.call_stack.iter().map(|f| &**f.fn_name).collect();
+    assert_eq!(names, vec!["outer", "inner"]);
+
+    // Lines and columns count characters, not bytes.
+    let mut module = Module::new();
+    let err = load_str("main.dyon", Arc::new("// æøå\nfn foo() -> bool { return \"ø\" + 2 }".into()),
+        &mut module).unwrap_err();
+    assert_eq!(err.line_column(), Some((2, 33)), "{}", err);
+}
+
+#[test]
+fn test_limits() {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use dyon::runtime::coverage::Coverage;
+
+    let mut module = Module::new();
+    load_str("limits.dyon", Arc::new(r#"
+fn forever() { loop {} }
+fn deep() -> { return deep() }
+fn grow() {
+    a := []
+    loop { push(mut a, 1) }
+}
+fn fill() -> { return len([0; 1000000]) }
+fn huge() -> { return len([0; 1e300]) }
+fn concat() {
+    s := ""
+    loop { s += "abcdefgh" }
+}
+fn text_repeat() -> { return repeat("abcdefgh", 1000000) }
+fn text_pad() -> { return pad("a", 2000000) }
+fn text_join() -> { return join(["abcdefgh"; 1000], repeat("a", 1000)) }
+fn text_replace() -> { return replace("abcdefgh", "a", repeat("a", 1000000)) }
+fn text_replace_all() -> { return replace_all("abcdefgh", "a", repeat("a", 1000000)) }
+fn text_format() -> { return format("{0}{0}", [repeat("a", 1000000)]) }
+fn count() -> { return sum i 10 { i } }
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let check = |name: &str, limits: Limits, limit: Limit| {
+        let mut rt = Runtime::new();
+        rt.set_limits(limits);
+        let err = Call::new(name).run(&mut rt, &module).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Limit(limit), "{}", name);
+        assert!(err.call_stack.len() > 0);
+    };
+    check("forever", Limits {instructions: Some(10_000), ..Limits::new()},
+        Limit::Instructions);
+    check("forever", Limits {
+        deadline: Some(Instant::now() + Duration::from_millis(50)),
+        ..Limits::new()
+    }, Limit::Deadline);
+    check("deep", Limits {call_stack: Some(10), ..Limits::new()}, Limit::CallStack);
+    check("deep", Limits {stack: Some(10), ..Limits::new()}, Limit::Stack);
+    check("grow", Limits {heap: Some(1_000_000), ..Limits::new()}, Limit::Heap);
+    check("fill", Limits {heap: Some(1_000_000), ..Limits::new()}, Limit::Heap);
+    check("huge", Limits::new(), Limit::Heap);
+    check("concat", Limits {heap: Some(1_000_000), ..Limits::new()}, Limit::Heap);
+    for &name in &["text_repeat", "text_pad", "text_join", "text_replace", "text_replace_all",
+                   "text_format"] {
+        check(name, Limits {heap: Some(1_000_000), ..Limits::new()}, Limit::Heap);
+    }
+
+    // Coverage runs the functions without bytecode.
+    let mut coverage = Coverage::new();
+    coverage.add_module(&module);
+    let mut rt = Runtime::new();
+    rt.set_coverage(Some(coverage));
+    let err = Call::new("huge").run(&mut rt, &module).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Limit(Limit::Heap));
+
+    // Programs within limits run as usual.
+    let mut rt = Runtime::new();
+    rt.set_limits(Limits {instructions: Some(1000), heap: Some(1000), ..Limits::new()});
+    let val: f64 = Call::new("count").run_ret(&mut rt, &module)
+        .unwrap_or_else(|err| panic!("{}", err));
+    assert_eq!(val, 45.0);
+}
+
+#[test]
+fn test_capabilities() {
+    use std::sync::Arc;
+
+    let check = |source: &str, capabilities: Capabilities, cap: Capability| {
+        let mut module = Module::new();
+        module.capabilities = capabilities;
+        let err = load_str("main.dyon", Arc::new(source.into()), &mut module).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Capability(cap), "{}", err);
+        assert_eq!(err.line_column(), Some((2, 5)), "{}", err);
+    };
+    let mut no_file = Capabilities::all();
+    no_file.set(Capability::File, false);
+    check("fn main() {\n    load_string(file: \"a.txt\")\n}",
+        no_file, Capability::File);
+    check("fn main() {\n    download(url: \"a\", file: \"b\")\n}",
+        Capabilities {file: true, ..Capabilities::none()}, Capability::Net);
+    check("fn main() {\n    read_line()\n}",
+        Capabilities::none(), Capability::Stdin);
+    check("fn main() {\n    go f()\n}\nfn f() {}",
+        Capabilities::none(), Capability::Threads);
+    check("fn main() {\n    load(\"a.dyon\")\n}",
+        Capabilities::none(), Capability::Load);
+
+    // Functions declared by the script are allowed.
+    let mut module = Module::new();
+    module.capabilities = Capabilities::none();
+    load_str("main.dyon", Arc::new(r#"
+fn main() {}
+fn read_line() -> str { return "" }
+fn count() -> { return read_line() }
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+
+    // Loaded modules get the same capabilities.
+    let mut module = Module::new();
+    module.capabilities = no_file;
+    load_str("main.dyon", Arc::new(r#"
+fn main() {}
+fn denied() -> bool {
+    return is_err(module(in: "m.dyon",
+        string: "fn main() {}\nfn f() -> { return load_string(file: \"a\") }",
+        imports: []))
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let mut rt = Runtime::new();
+    let denied: bool = Call::new("denied").run_ret(&mut rt, &Arc::new(module))
+        .unwrap_or_else(|err| panic!("{}", err));
+    assert!(denied);
+}
+
+#[test]
+fn test_lsp() {
+    use std::io::Cursor;
+    use dyon::lsp::{self, Json};
+
+    let open = |uri: &str, text: &str| format!(r#"{{"jsonrpc": "2.0",
+        "method": "textDocument/didOpen", "params": {{"textDocument":
+        {{"uri": "{}", "languageId": "dyon", "version": 1, "text": "{}"}}}}}}"#,
+        uri, text.replace('\n', "\\n").replace('"', "\\\""));
+    let request = |id: u32, method: &str, line: u32, character: u32|
+        format!(r#"{{"jsonrpc": "2.0", "id": {}, "method": "textDocument/{}", "params":
+        {{"textDocument": {{"uri": "file:///main.dyon"}},
+        "position": {{"line": {}, "character": {}}}}}}}"#, id, method, line, character);
+    let msgs = vec![
+        r#"{"jsonrpc": "2.0", "id": 0, "method": "initialize",
+            "params": {"processId": null, "rootUri": null, "capabilities": {}}}"#.into(),
+        open("file:///math.dyon", "ns math\n\nfn add(a: f64, b: f64) -> f64 { return a + b }\n"),
+        open("file:///main.dyon", "use math as m\n\nfn main() {\n    x := m::add(1, 2)\n    \
+            println(foo(x))\n}\n\nfn foo(x: f64) -> f64 { return x + 1 }\n"),
+        open("file:///bad.dyon", "fn main() {\n    x := 1 + \"a\"\n}\n"),
+        request(1, "definition", 4, 13),
+        request(2, "definition", 3, 12),
+        request(3, "hover", 3, 12),
+        request(4, "completion", 4, 7),
+        r#"{"jsonrpc": "2.0", "id": 5, "method": "shutdown"}"#.into(),
+        r#"{"jsonrpc": "2.0", "method": "exit"}"#.into(),
+    ];
+    let mut input = vec![];
+    for msg in &msgs {
+        input.extend(format!("Content-Length: {}\r\n\r\n{}", msg.len(), msg).bytes());
+    }
+    let mut output = vec![];
+    lsp::run(Cursor::new(input), &mut output).unwrap();
+    let mut output = Cursor::new(output);
+    let mut replies = vec![];
+    while let Some(text) = lsp::read_message(&mut output).unwrap() {
+        replies.push(Json::parse(&text).unwrap());
+    }
+    assert_eq!(replies.len(), 9);
+
+    let diagnostics = |reply: &Json| reply.path(&["params", "diagnostics"])
+        .and_then(|x| x.as_array()).unwrap().to_vec();
+    assert_eq!(diagnostics(&replies[1]).len(), 0);
+    assert_eq!(diagnostics(&replies[2]).len(), 0);
+    let bad = diagnostics(&replies[3]);
+    assert_eq!(bad.len(), 1);
+    assert_eq!(bad[0].path(&["range", "start", "line"]), Some(&Json::Number(1.0)));
+
+    let location = |reply: &Json| {
+        let loc = &reply.get("result").and_then(|x| x.as_array()).unwrap()[0];
+        (loc.get("uri").and_then(|x| x.as_str()).unwrap().to_string(),
+         loc.path(&["range", "start", "line"]).and_then(|x| x.as_f64()).unwrap())
+    };
+    assert_eq!(location(&replies[4]), ("file:///main.dyon".into(), 7.0));
+    assert_eq!(location(&replies[5]), ("file:///math.dyon".into(), 2.0));
+    assert_eq!(replies[6].path(&["result", "contents", "value"]).and_then(|x| x.as_str()),
+        Some("```dyon\nadd(f64, f64) -> f64\n```"));
+    let labels: Vec<&str> = replies[7].get("result").and_then(|x| x.as_array()).unwrap()
+        .iter().map(|it| it.get("label").and_then(|x| x.as_str()).unwrap()).collect();
+    assert_eq!(labels, vec!["print", "println"]);
+    assert_eq!(replies[8].get("result"), Some(&Json::Null));
+}
+
+#[test]
+fn test_format() {
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use dyon::format::{format_str, is_formatted};
+
+    let source = Arc::new(r#"ns  test::fmt
+use   std::{a as b,c} as s
+// Adds numbers.
+fn add(a:f64,b:'a [f64]) ->f64 {return a+sum i{b[i]}}
+
+fn main( ) ~ mut w:{} {
+    x:=  (1+2)*3 // trailing
+    /* own line */
+
+
+    if x>2 {println("a")} else {println(
+        "b")}
+    s::c(foo: #ff0000,bar:[1,
+        2])
+}
+"#.to_string());
+    let formatted = format_str("main.dyon", &source).unwrap_or_else(|err| panic!("{}", err));
+    assert_eq!(formatted, r#"ns test::fmt
+use std::{a as b, c} as s
+// Adds numbers.
+fn add(a: f64, b: 'a [f64]) -> f64 { return a + sum i { b[i] } }
+
+fn main() ~ mut w: {} {
+    x := (1 + 2) * 3 // trailing
+    /* own line */
+
+    if x > 2 { println("a") } else {
+        println(
+            "b"
+        )
+    }
+    s::c(foo: #ff0000, bar: [
+        1,
+        2
+    ])
+}
+"#);
+    assert!(!is_formatted("main.dyon", &source).unwrap());
+    assert!(is_formatted("main.dyon", &Arc::new(formatted)).unwrap());
+    assert_eq!(format_str("main.dyon", &Arc::new("fn main() {".into())).unwrap_err().kind,
+               ErrorKind::Syntax);
+
+    // All example programs can be formatted, and formatting again changes nothing.
+    fn visit(dir: &Path) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                visit(&path);
+                continue;
+            }
+            if path.extension().and_then(|x| x.to_str()) != Some("dyon") { continue }
+            let mut text = String::new();
+            File::open(&path).unwrap().read_to_string(&mut text).unwrap();
+            let file = path.to_str().unwrap();
+            let text = Arc::new(text);
+            let formatted = match format_str(file, &text) {
+                Ok(x) => x,
+                Err(ref err) if err.kind == ErrorKind::Syntax => continue,
+                Err(err) => panic!("{}", err),
+            };
+            assert!(is_formatted(file, &Arc::new(formatted)).unwrap(), "`{}`", file);
+        }
+    }
+    visit(Path::new("source"));
+}
+
+#[test]
+fn test_debugger() {
+    use std::sync::{Arc, Mutex};
+    use dyon::runtime::debugger::{Paused, Step, Stepper};
+
+    struct Recorder {
+        stepper: Stepper,
+        steps: Vec<Step>,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Debugger for Recorder {
+        fn before_expression(&mut self, paused: &mut Paused) -> Result<(), String> {
+            if !self.stepper.should_pause(paused) { return Ok(()) }
+            let (line, _) = paused.line_column().unwrap();
+            let mut entry = format!("{}:{}", paused.call().unwrap().fn_name, line);
+            if let Some(&Variable::F64(x, _)) = paused.variable("x") {
+                entry.push_str(&format!(" x={}", x));
+            }
+            self.log.lock().unwrap().push(entry);
+            if line == 9 {
+                match paused.eval("x + y") {
+                    Ok(Variable::F64(v, _)) => self.log.lock().unwrap().push(format!("eval {}", v)),
+                    Ok(_) => return Err("Expected number".into()),
+                    Err(err) => return Err(format!("{}", err)),
+                }
+            }
+            let step = if self.steps.len() > 0 { self.steps.remove(0) } else { Step::Continue };
+            self.stepper.resume(step, paused);
+            Ok(())
+        }
+    }
+
+    let mut module = Module::new();
+    // Lines count characters, so text that is not ASCII does not move them.
+    load_str("main.dyon", Arc::new(r#"fn plus(a, b) -> { // Σ ≈ ∑ ∫ æøå
+    return a + b
+}
+
+fn main() {
+    x := 1
+    y := plus(x, 2)
+    x = y * 2
+    z := plus(x, y)
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+
+    let log = Arc::new(Mutex::new(vec![]));
+    let mut stepper = Stepper::new();
+    stepper.add_breakpoint("main.dyon", 9);
+    let mut rt = Runtime::new();
+    rt.set_debugger(Some(Box::new(Recorder {
+        stepper: stepper,
+        steps: vec![Step::Over, Step::In, Step::Out, Step::Continue],
+        log: log.clone(),
+    })));
+    rt.run(&module).unwrap_or_else(|err| panic!("{}", err));
+    assert_eq!(*log.lock().unwrap(), vec![
+        "main:6", "main:7 x=1", "plus:2", "main:8 x=1", "main:9 x=6", "eval 9"
+    ]);
+
+    struct Stop;
+
+    impl Debugger for Stop {
+        fn before_expression(&mut self, _: &mut Paused) -> Result<(), String> {
+            Err("Stopped by debugger".into())
+        }
+    }
+
+    rt.clear_stacks();
+    rt.set_debugger(Some(Box::new(Stop)));
+    let err = rt.run(&module).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Runtime);
+    assert_eq!(err.message, "Stopped by debugger");
+}
+
+#[test]
+fn test_profiler() {
+    use std::sync::Arc;
+    use dyon::runtime::profiler::Profiler;
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(r#"ns prof
+
+fn fib(n) -> {
+    return if n < 2 { clone(n) } else { fib(n - 1) + fib(n - 2) }
+}
+
+fn main() {
+    x := fib(10)
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+
+    let mut rt = Runtime::new();
+    rt.set_profiler(Some(Profiler::new(true)));
+    rt.run(&module).unwrap_or_else(|err| panic!("{}", err));
+    let profiler = rt.profiler.take().unwrap();
+
+    let stats = |name: &str| {
+        let i = profiler.keys.iter().position(|key| &**key.name == name).unwrap();
+        assert_eq!(&**profiler.keys[i].file, "main.dyon");
+        profiler.functions[i].clone()
+    };
+    assert_eq!(stats("main").calls, 1);
+    assert_eq!(stats("fib").calls, 177);
+    assert!(stats("main").inclusive >= stats("fib").inclusive);
+    assert!(stats("fib").inclusive >= stats("fib").exclusive);
+
+    let mut folded = vec![];
+    profiler.write_folded(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    let stacks: Vec<&str> = folded.lines()
+        .map(|line| line.rsplitn(2, ' ').nth(1).unwrap())
+        .collect();
+    assert_eq!(stacks[0], "prof::main");
+    assert_eq!(stacks[1], "prof::main;prof::fib");
+    assert_eq!(stacks.len(), 11);
+
+    let lines = profiler.lines.as_ref().unwrap();
+    assert!(lines[&(Arc::new("main.dyon".into()), 4)].hits > 177);
+    assert!(profiler.report().contains("prof::fib (main.dyon)"));
+}
+
+#[test]
+fn test_profiler_sampling() {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use dyon::runtime::profiler::Profiler;
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(r#"ns prof
+
+fn fib(n) -> {
+    return if n < 2 { clone(n) } else { fib(n - 1) + fib(n - 2) }
+}
+
+fn main() {
+    x := fib(10)
+    sleep(0.05)
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+
+    let mut rt = Runtime::new();
+    rt.set_profiler(Some(Profiler::sampling(Duration::from_millis(1))));
+    rt.run(&module).unwrap_or_else(|err| panic!("{}", err));
+    let profiler = rt.profiler.take().unwrap();
+    assert_eq!(profiler.interval(), Some(Duration::from_millis(1)));
+
+    let stats = |name: &str| {
+        let i = profiler.keys.iter().position(|key| &**key.name == name).unwrap();
+        profiler.functions[i].clone()
+    };
+    assert_eq!(stats("main").calls, 1);
+    assert_eq!(stats("fib").calls, 177);
+    // The time of `sleep` is sampled when `main` exits.
+    assert!(stats("main").exclusive > Duration::new(0, 0));
+    assert!(stats("main").inclusive >= stats("main").exclusive + stats("fib").inclusive);
+
+    let mut folded = vec![];
+    profiler.write_folded(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    assert!(folded.lines().all(|line| line.starts_with("prof::main")));
+    assert!(folded.lines().any(|line| line.starts_with("prof::main ")));
+
+    assert!(profiler.lines.is_none());
+    assert!(profiler.report().starts_with("Sampled every 1.000 ms"));
+}
+
+#[test]
+fn test_coverage() {
+    use std::sync::Arc;
+    use dyon::runtime::coverage::Coverage;
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(r#"fn sign(x) -> {
+    if x < 0 {
+        return -1
+    } else if x == 0 {
+        return 0
+    } else {
+        return 1
+    }
+}
+
+fn half(x) -> res {
+    return if (x % 2) == 0 { ok(x / 2) } else { err("odd") }
+}
+
+fn unused() {
+    println("never")
+}
+
+fn quarter(x) -> res {
+    return half(half(x)?)
+}
+
+fn main() {
+    a := sign(3)
+    b := sign(0)
+    c := quarter(8)
+    d := quarter(2)
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+
+    let mut coverage = Coverage::new();
+    coverage.add_module(&module);
+    let mut rt = Runtime::new();
+    rt.set_coverage(Some(coverage));
+    rt.run(&module).unwrap_or_else(|err| panic!("{}", err));
+    let coverage = rt.coverage.take().unwrap();
+
+    let mut lcov = vec![];
+    coverage.write_lcov(&mut lcov).unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    let lines: Vec<&str> = lcov.lines().collect();
+    assert_eq!(lines[0..2], ["TN:", "SF:main.dyon"]);
+    assert!(lines.contains(&"FNDA:2,sign"));
+    assert!(lines.contains(&"FNDA:0,unused"));
+    // `if x < 0` is never true, the other blocks run once.
+    assert!(lines.contains(&"BRDA:2,0,0,0"));
+    assert!(lines.contains(&"BRDA:2,0,1,1"));
+    assert!(lines.contains(&"BRDA:2,0,2,1"));
+    // `?` continues twice and never returns an error.
+    assert!(lines.contains(&"BRDA:20,2,0,2"));
+    assert!(lines.contains(&"BRDA:20,2,1,0"));
+    assert!(lines.contains(&"DA:3,0"));
+    assert!(lines.contains(&"DA:12,4"));
+    assert!(lines.contains(&"DA:16,0"));
+    assert_eq!(lines[lines.len() - 3..], ["LF:12", "LH:10", "end_of_record"]);
+
+    let report = coverage.report();
+    assert!(report.contains("83.3%    71.4%     80.0%  main.dyon"));
+    assert!(report.contains("main.dyon: 3, 16"));
+}
+
+#[test]
+fn test_testing() {
+    use std::sync::Arc;
+    use dyon::testing;
+
+    fn explode(_: &mut Runtime) -> Result<(), String> {
+        panic!("boom")
+    }
+
+    let mut module = Module::new();
+    module.add(Arc::new("explode".into()), explode, Dfn {
+        lts: vec![],
+        tys: vec![],
+        ret: Type::Void
+    });
+    load_str("main.dyon", Arc::new(r#"fn half(x) -> res {
+    return if (x % 2) == 0 { ok(x / 2) } else { err("odd") }
+}
+
+fn quarter(x) -> res {
+    return ok(half(half(x)?)?)
+}
+
+test fn quarter_even() -> {
+    return quarter(8)
+}
+
+test fn quarter_odd() -> {
+    return quarter(6)
+}
+
+test fn no_return() {
+    x := 1 + 1
+}
+
+test fn compare() -> {
+    return 1 == 2
+}
+
+test fn panics() {
+    explode()
+}
+
+test(x) = x + 1
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+
+    assert_eq!(testing::collect(&module, "").len(), 5);
+    let tests = testing::collect(&module, "quarter");
+    assert_eq!(tests.len(), 2);
+
+    let results = testing::run(&module, &testing::collect(&module, ""), 2);
+    let names: Vec<&str> = results.iter().map(|res| &**res.name).collect();
+    assert_eq!(names, ["quarter_even", "quarter_odd", "no_return", "compare", "panics"]);
+    let passed: Vec<bool> = results.iter().map(|res| res.passed()).collect();
+    assert_eq!(passed, [true, false, true, false, false]);
+    let err = results[1].result.as_ref().unwrap_err();
+    assert!(err.starts_with("odd\nIn function `quarter` (main.dyon)"));
+    assert_eq!(results[3].result, Err("Returned `false`".into()));
+    assert_eq!(results[4].result, Err("Test panicked: boom".into()));
+    assert!(testing::report(&results).ends_with("test result: FAILED. 2 passed; 3 failed\n"));
+}
+
+#[test]
+fn test_assert() {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(r#"fn pass() {
+    assert(1 < 2)
+    assert_eq([1, {x: some(2)}], [1, {x: some(2)}])
+    assert_eq((1, 2), (1, 2))
+    assert_eq(ok("a"), ok("a"))
+    assert_eq(err("a"), err("a"))
+    assert_eq(link { 1 "a" }, link { 1 "a" })
+    assert_ne(none(), some(1))
+    assert_ne(ok(1), err(1))
+}
+
+fn fail_assert() {
+    assert(1 > 2)
+}
+
+fn fail_eq() {
+    assert_eq([1, {x: 2}, {y: 3}, ok(4)], [1, {x: 3}, {z: 3}, ok(5), 6])
+}
+
+fn fail_eq_number() {
+    assert_eq(1, 2)
+}
+
+fn fail_ne() {
+    assert_ne([1], [1])
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+
+    let mut rt = Runtime::new();
+    rt.call_str("pass", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+    let fail = |name: &str| -> String {
+        let mut rt = Runtime::new();
+        let err = rt.call_str(name, &[], &module).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Runtime);
+        assert!(err.range.is_some());
+        err.message
+    };
+    assert_eq!(fail("fail_assert"), "Assertion failed");
+    assert_eq!(fail("fail_eq"), "Assertion `left == right` failed\n\
+        left:  [1, {x: 2}, {y: 3}, ok(4)]\n\
+        right: [1, {x: 3}, {z: 3}, ok(5), 6]\n\
+        at [1].x: 2 != 3\n\
+        at [2].y: 3 only in left\n\
+        at [2].z: 3 only in right\n\
+        at [3].ok: 4 != 5\n\
+        at [4]: 6 only in right");
+    assert_eq!(fail("fail_eq_number"), "Assertion `left == right` failed\nleft:  1\nright: 2");
+    assert_eq!(fail("fail_ne"), "Assertion `left != right` failed\nboth: [1]");
+}
+
+#[test]
+fn test_match() {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    load("source/syntax/match.dyon", &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.call_str("main", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(r#"fn main() {
+    println(match 2 { 1 => "one" })
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    let err = rt.call_str("main", &[], &module).unwrap_err();
+    assert!(err.message.contains("No match arm for `number`"));
+
+    let mut module = Module::new();
+    let err = load("source/syntax/match_3.dyon", &mut module).unwrap_err();
+    assert!(err.message.contains("Non-exhaustive `match`, missing `ok(none())`"));
+}
+
+#[test]
+fn test_record_across_scripts() {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    load_str("player.dyon", Arc::new(r#"type Player {name: str, goals: f64}
+
+fn new_player(name: str) -> Player {
+    return {name: clone(name), goals: 0}
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    assert!(load_str("main.dyon", Arc::new(r#"fn main() {
+    p := new_player("Ada")
+    println(p.name)
+}
+"#.into()), &mut module).is_ok());
+    let err = load_str("main.dyon", Arc::new(r#"fn goals(p: Player) -> f64 {
+    return clone(p.gols)
+}
+"#.into()), &mut module).unwrap_err();
+    assert!(err.message.contains("`Player` has no field `gols`"));
+}
+
+#[test]
+fn test_enum() {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    load("source/typechk/enum.dyon", &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.call_str("main", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+
+    // Enums declared in one script can be used in another.
+    let mut module = Module::new();
+    load_str("shape.dyon", Arc::new(r#"enum Shape {Circle(f64), Empty}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    assert!(load_str("main.dyon", Arc::new(r#"fn radius(s: Shape) -> f64 {
+    return match s {
+        Shape::Circle(r) => r + 0,
+        Shape::Empty() => 0,
+    }
+}
+"#.into()), &mut module).is_ok());
+    let err = load_str("main.dyon", Arc::new(r#"fn radius(s: Shape) -> f64 {
+    return match s {
+        Shape::Circle(r) => r + 0,
+    }
+}
+"#.into()), &mut module).unwrap_err();
+    assert!(err.message.contains("missing `Shape::Empty()`"));
+    let err = load_str("main.dyon", Arc::new(r#"fn main() {
+    s := Shape::Circle(1, 2)
+}
+"#.into()), &mut module).unwrap_err();
+    assert!(err.message.contains("Shape::Circle: Expected 1 arguments, found 2"));
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(r#"enum Shape {Circle(f64), Empty}
+
+fn add(mut a, b) { a += b }
+
+fn main() {
+    s := Shape::Empty()
+    add(mut s, Shape::Empty())
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    let err = rt.call_str("main", &[], &module).unwrap_err();
+    assert!(err.message.contains("Can not use this assignment operator with `variant`"));
+}
+
+#[test]
+fn test_i64() {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    load("source/typechk/i64.dyon", &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.call_str("main", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+
+    let fail = |expr: &str| run_fail_main("", &format!("println({})", expr));
+    assert!(fail(r#"unwrap(parse_i64("9223372036854775807")) + i64(1)"#)
+        .contains("Integer overflow in `+`"));
+    assert!(fail("i64(1) << i64(64)").contains("Integer overflow in `<<`"));
+    assert!(fail("i64(1) << i64(63)").contains("Integer overflow in `<<`"));
+    assert!(fail("i64(3) << i64(62)").contains("Integer overflow in `<<`"));
+    assert!(fail("i64(1) / i64(0)").contains("Integer division by zero"));
+    assert!(fail("i64(0.5)").contains("Can not convert `0.5` to `i64`"));
+
+    let mut module = Module::new();
+    let err = load_str("main.dyon", Arc::new(r#"fn main() {
+    println(i64(9007199254740993))
+}
+"#.into()), &mut module).unwrap_err();
+    assert!(err.message.contains(r#"use `unwrap(parse_i64("9007199254740993"))`"#));
+}
+
+#[test]
+fn test_bytes() {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    load("source/typechk/bytes.dyon", &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.call_str("main", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+
+    let file = ::std::env::temp_dir().join("dyon_test_bytes.bin");
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(format!(r#"fn main() {{
+    file := {:?}
+    _ := unwrap(save(bytes: bytes([0, 128, 255]), file: file))
+    assert_eq(hex(unwrap(load_bytes(file: file))), "0080ff")
+}}
+"#, file.to_str().unwrap())), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.call_str("main", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+    let _ = ::std::fs::remove_file(&file);
+
+    let fail = |expr: &str| run_fail_main("b := bytes([1, 2])", expr);
+    assert!(fail("b[0] = 3").contains("Can not assign to index of bytes"));
+    assert!(fail("println(b[2])").contains("Out of bounds `2`"));
+    assert!(fail(r#"println(read_int(b, 1, "u16le"))"#).contains("Out of bounds"));
+    assert!(fail(r#"write_int(mut b, 3, "u8", i64(0))"#).contains("Out of bounds"));
+    assert!(fail(r#"write_int(mut b, 0, "u8", i64(256))"#).contains("does not fit in 1 bytes"));
+    assert!(fail(r#"println(read_int(b, 0, "u16"))"#).contains("Expected `u16le` or `u16be`"));
+    assert!(fail(r#"println(slice(bytes: b, start: 1, end: 3))"#).contains("Out of bounds"));
+    assert!(fail("b += bytes([2])").contains("Can not use this assignment operator with `bytes`"));
+}
+
+#[test]
+fn test_map() {
+    use std::sync::Arc;
+
+    let mut module = Module::new();
+    load("source/typechk/map.dyon", &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.call_str("main", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+
+    let file = ::std::env::temp_dir().join("dyon_test_map.json");
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(format!(r#"fn main() {{
+    file := {:?}
+    m := new_map()
+    m[(1, 2)] := [1, 2]
+    m["a"] := {{b: 3}}
+    _ := unwrap(save(data: m, file: file))
+    assert_eq(unwrap(load_data(file: file)), m)
+}}
+"#, file.to_str().unwrap())), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.call_str("main", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+    let _ = ::std::fs::remove_file(&file);
+
+    let fail = |expr: &str| run_fail_main("m := new_map()", expr);
+    assert!(fail("println(m[1])").contains("Map has no key `1`"));
+    assert!(fail("x := 0 / 0\n    m[x] := 1").contains("NaN can not be used as key"));
+    assert!(fail("m[[1]] := 1").contains("Expected `bool`, `f64`, `str` or `vec4` as key"));
+    assert!(fail("println(remove(mut m, \"a\"))").contains("Map has no key `\"a\"`"));
+    assert!(fail("m += new_map()").contains("Can not use this assignment operator with `map`"));
+}
+
+#[test]
+fn test_for_in() {
+    use std::sync::Arc;
+    use dyon::runtime::coverage::Coverage;
+
+    let mut module = Module::new();
+    load("source/syntax/for_in.dyon", &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    rt.call_str("main", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+
+    // Coverage runs the functions without bytecode.
+    let mut coverage = Coverage::new();
+    coverage.add_module(&module);
+    let mut rt = Runtime::new();
+    rt.set_coverage(Some(coverage));
+    rt.call_str("main", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(r#"fn main() {
+    x := {a: 1}
+    for k, v in x.a { println(v) }
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    let err = rt.call_str("main", &[], &module).unwrap_err();
+    assert!(err.message.contains("Expected `array, object, map or link`, found `number`"));
+}
+
+#[test]
+fn test_iter() {
+    use std::sync::Arc;
+    use dyon::runtime::coverage::Coverage;
+
+    let mut module = Module::new();
+    load("source/syntax/iter.dyon", &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+
+    // Coverage runs the functions without bytecode.
+    let mut coverage = Coverage::new();
+    coverage.add_module(&module);
+    let mut rt = Runtime::new();
+    rt.set_coverage(Some(coverage));
+    rt.call_str("main", &[], &module).unwrap_or_else(|err| panic!("{}", err));
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(r#"fn main() {
+    x := {a: 1}
+    println(collect(iter(x.a)))
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let mut rt = Runtime::new();
+    let err = rt.call_str("main", &[], &module).unwrap_err();
+    assert!(err.message.contains("Expected `array, object, map, link or in`, found `number`"));
+}
+
+#[test]
+fn test_text() {
+    let fail = |expr: &str| run_fail_main("", &format!("x := {}", expr));
+    assert!(fail(r#"format("{} {}", [1])"#).contains("There is no argument `1`"));
+    assert!(fail(r#"format("{:x}", [1])"#).contains("Unknown format `{:x}`"));
+    assert!(fail(r#"format("{:.2}", ["a"])"#).contains("Precision requires `f64`"));
+    assert!(fail(r#"format("{:.4000000000}", [1])"#)
+        .contains("Precision in `{:.4000000000}` is larger than 1000"));
+    assert!(fail(r#"format("{:.99999999999}", [1])"#).contains("is larger than 1000"));
+    assert!(fail(r#"format("{a}", [1])"#).contains("Expected object for arguments by name"));
+    assert!(fail(r#"substr("abc", 2, 5)"#).contains("can not take `2..5` from 3 characters"));
+    assert!(fail(r#"split("abc", "")"#).contains("Expected non-empty separator"));
+    assert!(fail(r#"match("a", "(")"#).contains("Invalid regular expression"));
+    assert!(fail(r#"repeat("ab", 1e19)"#).contains("Text is too large"));
+    assert!(fail(r#"repeat("ab", 1e18)"#).contains("Can not allocate 2000000000000000000 bytes"));
+    assert!(fail(r#"pad("ab", 1e20)"#).contains("Text is too large"));
+    assert!(fail(r#"pad("ab", -1e18)"#).contains("Can not allocate"));
+}
+
+#[test]
+fn test_array_utils() {
+    let fail = |expr: &str| run_fail_main("a := [1, \"a\"]", expr);
+    assert!(fail("sort(mut a)").contains("Expected array of numbers or array of strings"));
+    assert!(fail("sort_by(mut a, \\(x, y) = clone(y))").contains("Expected `f64`, found `string`"));
+    assert!(fail("x := slice(a, 1, 3)").contains("can not slice `1..3` from 2 items"));
+    assert!(fail("x := chunks(a, 0)").contains("Expected chunk size greater than zero"));
+}
+
+#[test]
+fn test_seed() {
+    use std::sync::Arc;
+    use dyon::Variable;
+
+    let mut module = Module::new();
+    load_str("main.dyon", Arc::new(r#"fn roll() -> f64 { return random() }
+
+fn main() -> [f64] {
+    t := go roll()
+    a := random_range(0, 10)
+    b := unwrap(join(thread: t))
+    return [clone(a), clone(b)]
+}
+
+fn main_seed() -> [f64] {
+    seed(7)
+    return main()
+}
+"#.into()), &mut module).unwrap_or_else(|err| panic!("{}", err));
+    let module = Arc::new(module);
+    let run = |rt: &mut Runtime, name: &str| -> Variable {
+        rt.call_str_ret(name, &[], &module).unwrap_or_else(|err| panic!("{}", err))
+    };
+
+    // Threads started with `go` derive their seed from the parent.
+    let a = run(&mut Runtime::with_seed(7), "main");
+    assert_eq!(a, run(&mut Runtime::with_seed(7), "main"));
+    assert!(a != run(&mut Runtime::with_seed(8), "main"));
+    assert_eq!(a, run(&mut Runtime::new(), "main_seed"));
+
+    // Restoring the state replays the same numbers.
+    let mut rt = Runtime::with_seed(7);
+    run(&mut rt, "main");
+    let state = rt.rng;
+    let b = run(&mut rt, "main");
+    assert!(a != b);
+    rt.rng = state;
+    assert_eq!(b, run(&mut rt, "main"));
+}